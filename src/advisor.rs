@@ -0,0 +1,275 @@
+//! Advice on device paths that are vulnerable to renumbering.
+//!
+//! Provider names such as `ada0p2` depend on probe order: adding, removing, or re-cabling a disk can
+//! change which disk is `ada0`.  Filesystems, swap, and pools configured by such a name (in
+//! `fstab(5)`, say) may then fail to come up, or worse, come up on the wrong device.  Label
+//! providers (`gpt/`, `gptid/`, `ufs/`, ...) follow the media instead.
+use crate::graph::{
+    Edge, EdgeId, EdgeMetadata, GeomClass, GeomMetadata, Graph, PartInfo, PartScheme,
+};
+
+/// How a provider is in use.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum DeviceUse {
+    /// Mounted as a filesystem of the given type, e.g., `"ffs"`
+    Filesystem(String),
+    /// Active swap device
+    Swap,
+    /// ZFS pool member
+    ZfsVdev,
+}
+
+/// A use of a provider by an unstable name, with stable alternatives.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PathAdvice {
+    /// The consumer-provider `Edge` in use, e.g., from a VFS geom to `ada0p2`.
+    pub edge: EdgeId,
+    /// The device path in use, e.g., `"/dev/ada0p2"`
+    pub device: String,
+    /// What the device is used for
+    pub usage: DeviceUse,
+    /// Stable paths to the same media, e.g., `"/dev/gpt/rootfs"` and `"/dev/gptid/<uuid>"`:
+    /// existing label providers first, then labels derived from the partition entry.  Empty if
+    /// the media has no label; consider adding one.
+    pub alternatives: Vec<String>,
+}
+
+impl Graph {
+    /// Finds every filesystem, swap device, and ZFS vdev using a raw DISK or PART provider, i.e.,
+    /// a name subject to renumbering, and recommends stable alternatives.
+    ///
+    /// Alternatives are taken from LABEL providers on the same media.  While a provider is open
+    /// for writing, GEOM "withers" the other labels on it, so GPT labels and UUIDs are also
+    /// derived from the partition entry.
+    pub fn device_path_advice(&self) -> Vec<PathAdvice> {
+        return self
+            .edges
+            .iter()
+            .filter_map(|(eid, edge)| self.edge_path_advice(eid, edge))
+            .collect();
+    }
+
+    /// The advice of `Graph::device_path_advice()` for one edge, if any.
+    pub(crate) fn edge_path_advice(&self, eid: &EdgeId, edge: &Edge) -> Option<PathAdvice> {
+        let provider = self.nodes.get(&edge.provider_geom)?;
+        if provider.class != GeomClass::DISK && provider.class != GeomClass::PART {
+            return None;
+        }
+        let consumer = self.nodes.get(&edge.consumer_geom)?;
+        let usage = match (&consumer.class, consumer.metadata.as_deref()) {
+            (GeomClass::VFS, Some(GeomMetadata::VFS { fstype, .. })) => {
+                DeviceUse::Filesystem(fstype.to_owned())
+            }
+            (GeomClass::SWAP, _) => DeviceUse::Swap,
+            (GeomClass::ZFSVDEV, _) => DeviceUse::ZfsVdev,
+            _ => return None,
+        };
+
+        let mut alternatives: Vec<String> = Vec::new();
+        let mut add = |name: &str| {
+            let path = format!("/dev/{}", name);
+            if !alternatives.contains(&path) {
+                alternatives.push(path);
+            }
+        };
+        for (leid, ledge, label) in self.child_geoms_iter(&edge.provider_geom) {
+            if leid.1 != eid.1 || label.class != GeomClass::LABEL {
+                continue;
+            }
+            for (_, lprov) in self.child_edges_iter(&ledge.consumer_geom) {
+                add(&lprov.name);
+            }
+        }
+        let gpt = matches!(
+            provider.metadata.as_deref(),
+            Some(GeomMetadata::PART(m)) if matches!(m.scheme, PartScheme::GPT)
+        );
+        if let (true, Some(EdgeMetadata::PART(PartInfo { label, rawuuid, .. }))) =
+            (gpt, edge.metadata.as_deref())
+        {
+            if let Some(label) = label.as_deref().filter(|l| !l.is_empty()) {
+                add(&format!("gpt/{}", label));
+            }
+            if let Some(uuid) = rawuuid {
+                add(&format!("gptid/{}", uuid));
+            }
+        }
+
+        return Some(PathAdvice {
+            edge: *eid,
+            device: format!("/dev/{}", edge.name),
+            usage,
+            alternatives,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{advisor::DeviceUse, graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn sample_uses_labels() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        // Everything in the sample is mounted by label.
+        assert!(g.device_path_advice().is_empty());
+    }
+
+    #[test]
+    fn raw_partitions() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>PART</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>ada0</name>
+                <rank>2</rank>
+                <config>
+                  <scheme>GPT</scheme>
+                  <entries>128</entries>
+                  <first>40</first>
+                  <last>10000</last>
+                  <fwsectors>63</fwsectors>
+                  <fwheads>16</fwheads>
+                  <state>OK</state>
+                  <modified>false</modified>
+                </config>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r1w1e1</mode>
+                  <name>ada0p1</name>
+                  <mediasize>524288</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>20480</stripeoffset>
+                  <config>
+                    <start>40</start>
+                    <end>1063</end>
+                    <index>1</index>
+                    <type>freebsd-ufs</type>
+                    <offset>20480</offset>
+                    <length>524288</length>
+                    <label>rootfs</label>
+                    <rawuuid>11111111-1111-1111-1111-111111111111</rawuuid>
+                  </config>
+                </provider>
+                <provider id="0x31">
+                  <geom ref="0x20"/>
+                  <mode>r1w1e0</mode>
+                  <name>ada0p2</name>
+                  <mediasize>524288</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>544768</stripeoffset>
+                  <config>
+                    <start>1064</start>
+                    <end>2087</end>
+                    <index>2</index>
+                    <type>freebsd-swap</type>
+                    <offset>544768</offset>
+                    <length>524288</length>
+                    <rawuuid>22222222-2222-2222-2222-222222222222</rawuuid>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>LABEL</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>ada0p2</name>
+                <rank>3</rank>
+                <config>
+                </config>
+                <consumer id="0x40">
+                  <geom ref="0x21"/>
+                  <provider ref="0x31"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+                <provider id="0x32">
+                  <geom ref="0x21"/>
+                  <mode>r0w0e0</mode>
+                  <name>gptid/22222222-2222-2222-2222-222222222222</name>
+                  <mediasize>524288</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>544768</stripeoffset>
+                  <config>
+                    <index>0</index>
+                    <length>524288</length>
+                    <seclength>1024</seclength>
+                    <offset>0</offset>
+                    <secoffset>0</secoffset>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x12">
+              <name>DEV</name>
+              <geom id="0x22">
+                <class ref="0x12"/>
+                <name>gptid/22222222-2222-2222-2222-222222222222</name>
+                <rank>4</rank>
+                <consumer id="0x41">
+                  <geom ref="0x22"/>
+                  <provider ref="0x32"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+            <class id="0x13">
+              <name>VFS</name>
+              <geom id="0x23">
+                <class ref="0x13"/>
+                <name>ffs.ada0p1</name>
+                <rank>3</rank>
+                <consumer id="0x42">
+                  <geom ref="0x23"/>
+                  <provider ref="0x30"/>
+                  <mode>r1w1e1</mode>
+                </consumer>
+              </geom>
+            </class>
+            <class id="0x14">
+              <name>SWAP</name>
+              <geom id="0x24">
+                <class ref="0x14"/>
+                <name>swap</name>
+                <rank>3</rank>
+                <consumer id="0x43">
+                  <geom ref="0x24"/>
+                  <provider ref="0x31"/>
+                  <mode>r1w1e0</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let advice = g.device_path_advice();
+        assert_eq!(advice.len(), 2);
+
+        assert_eq!(advice[0].edge, (0x42, 0x30));
+        assert_eq!(advice[0].device, "/dev/ada0p1");
+        assert_eq!(advice[0].usage, DeviceUse::Filesystem("ffs".into()));
+        assert_eq!(
+            advice[0].alternatives,
+            vec![
+                "/dev/gpt/rootfs".to_owned(),
+                "/dev/gptid/11111111-1111-1111-1111-111111111111".to_owned()
+            ]
+        );
+
+        assert_eq!(advice[1].device, "/dev/ada0p2");
+        assert_eq!(advice[1].usage, DeviceUse::Swap);
+        assert_eq!(
+            advice[1].alternatives,
+            vec!["/dev/gptid/22222222-2222-2222-2222-222222222222".to_owned()]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Runtime discovery of what this build of the library can do on the current host.
+//!
+//! Agents that run on several platforms (or in jails) can call `capabilities()` once and adapt,
+//! rather than replicating this crate's `cfg` conditions and probing sysctls themselves.
+
+/// What this build of the library supports, and what is usable on the current host.
+///
+/// More fields may be added as optional subsystems are added.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Built for FreeBSD, so that `get_graph()` and friends are available at all.
+    pub freebsd: bool,
+    /// Built with the `rayon` feature, so `Graph::map_roots()` (and `Graph::full_report()`) runs
+    /// in parallel.
+    pub parallel: bool,
+    /// Built with the `eli` feature (linking `libcrypto`), so `eli::attach()`, `eli::init()`, and
+    /// `eli::EliMetadata::parse()` are available.
+    pub eli: bool,
+    /// Built with the `serde` feature, so graphs (and reports) can be serialized.
+    pub serde: bool,
+    /// Built with the `snapshot` feature, so `Graph::save()` and `Graph::load()` are available.
+    pub snapshot: bool,
+    /// Built with the `tokio` feature (for FreeBSD), so `watch::EventStream` and
+    /// `get_graph_async()` are available.
+    pub tokio: bool,
+    /// The `kern.geom.confxml` sysctl is readable, so `get_graph()` can work.
+    pub confxml: bool,
+    /// The `kern.geom.conftxt` sysctl is readable, so `conftxt::get_sizes()` can work.
+    pub conftxt: bool,
+    /// Whether the process is running in a jail (`security.jail.jailed`), if known.  Jails
+    /// typically see the host's GEOM graph but cannot change it.
+    pub jailed: Option<bool>,
+    /// The process runs with an effective user id of 0.  Reading the graph needs no privilege;
+    /// changing it (`ctl`, `part`, `eli`, `md`, ...) does.
+    pub root: bool,
+    /// `/dev/geom.ctl` can be opened for writing, which GEOM control requests require (usually
+    /// meaning the process is privileged and not jailed).
+    pub geom_ctl_writable: bool,
+    /// `/dev/mdctl` can be opened for writing, which `md::create()` and `md::destroy()` require.
+    pub md_ctl_writable: bool,
+    /// `geom(8)` and `mdconfig(8)` are installed, so `ctl::Backend::Command` can work.
+    pub ctl_commands: bool,
+    /// The `kern.devstat` sysctls exist, so `stats::get_devstat()` can work.
+    pub devstat: bool,
+    /// `devd(8)`'s socket exists, so `watch::Watcher::devd()` can connect.  Without it,
+    /// `watch::Watcher::poll()` still works.
+    pub devd: bool,
+    /// ZFS is loaded (the `vfs.zfs` sysctls exist), so ZFS vdevs and zvols may be in the graph.
+    pub zfs: bool,
+}
+
+#[cfg(target_os = "freebsd")]
+fn sysctl_readable(name: &str) -> bool {
+    use sysctl::Sysctl;
+
+    return sysctl::Ctl::new(name)
+        .and_then(|ctl| ctl.value_string())
+        .is_ok();
+}
+
+#[cfg(target_os = "freebsd")]
+fn sysctl_exists(name: &str) -> bool {
+    use sysctl::Sysctl;
+
+    return sysctl::Ctl::new(name).is_ok();
+}
+
+#[cfg(target_os = "freebsd")]
+fn writable(path: &str) -> bool {
+    return std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .is_ok();
+}
+
+#[cfg(target_os = "freebsd")]
+fn jailed() -> Option<bool> {
+    use sysctl::Sysctl;
+
+    let value = sysctl::Ctl::new("security.jail.jailed")
+        .and_then(|ctl| ctl.value_string())
+        .ok()?;
+    return Some(value.trim() != "0");
+}
+
+/// Probes the current host.  This reads a few sysctls and opens `/dev/geom.ctl` and `/dev/mdctl`,
+/// so callers should cache the result rather than call it in a loop.
+///
+/// # Examples
+///
+/// ```
+/// let caps = freebsd_geom::capabilities();
+/// if !caps.confxml {
+///     eprintln!("GEOM is not available here");
+/// }
+/// ```
+pub fn capabilities() -> Capabilities {
+    #[cfg(target_os = "freebsd")]
+    return Capabilities {
+        freebsd: true,
+        parallel: cfg!(feature = "rayon"),
+        eli: cfg!(feature = "eli"),
+        serde: cfg!(feature = "serde"),
+        snapshot: cfg!(feature = "snapshot"),
+        tokio: cfg!(feature = "tokio"),
+        confxml: sysctl_readable("kern.geom.confxml"),
+        conftxt: sysctl_readable("kern.geom.conftxt"),
+        jailed: jailed(),
+        // The effective user id cannot fail to be retrieved.
+        root: unsafe { libc::geteuid() } == 0,
+        geom_ctl_writable: writable(crate::ctl::GEOM_CTL_PATH),
+        md_ctl_writable: writable(crate::md::MDCTL_PATH),
+        ctl_commands: [crate::ctl::GEOM_PATH, crate::md::MDCONFIG_PATH]
+            .iter()
+            .all(|p| std::path::Path::new(p).is_file()),
+        devstat: sysctl_exists("kern.devstat.version"),
+        devd: std::path::Path::new(crate::watch::DEVD_SEQPACKET_PIPE).exists(),
+        zfs: sysctl_exists("vfs.zfs.version.module") || sysctl_exists("vfs.zfs.version.spa"),
+    };
+    #[cfg(not(target_os = "freebsd"))]
+    return Capabilities {
+        parallel: cfg!(feature = "rayon"),
+        eli: cfg!(feature = "eli"),
+        serde: cfg!(feature = "serde"),
+        snapshot: cfg!(feature = "snapshot"),
+        ..Default::default()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capabilities;
+
+    #[test]
+    fn compiled() {
+        let caps = capabilities();
+        assert_eq!(caps.freebsd, cfg!(target_os = "freebsd"));
+        assert_eq!(caps.parallel, cfg!(feature = "rayon"));
+        assert_eq!(caps.eli, cfg!(feature = "eli"));
+        assert_eq!(caps.serde, cfg!(feature = "serde"));
+        assert_eq!(caps.snapshot, cfg!(feature = "snapshot"));
+        if !caps.freebsd {
+            assert!(!caps.confxml && !caps.conftxt && !caps.geom_ctl_writable);
+            assert!(!caps.md_ctl_writable && !caps.devstat && !caps.devd && !caps.zfs);
+            assert!(!caps.tokio && !caps.root && !caps.ctl_commands);
+            assert_eq!(caps.jailed, None);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Creating `gconcat(8)` volumes.
+//!
+//! A CONCAT geom joins its providers end to end into one provider, `concat/<name>`, whose size is
+//! their sum (JBOD).  A volume can be labeled, writing metadata to the last sector of each
+//! provider so the kernel reassembles it whenever the providers appear, or created manually,
+//! lasting only until it is destroyed or the system reboots.  See `stripe` for the striped
+//! (RAID0) equivalent.
+#[cfg(target_os = "freebsd")]
+use crate::ctl::CtlResult;
+use crate::ctl::{CtlError, GctlRequest, EEXIST, EINVAL, ENOENT};
+use crate::graph::Graph;
+use crate::Error;
+
+/// `G_CONCAT_MAGIC`
+const G_CONCAT_MAGIC: &str = "GEOM::CONCAT";
+/// `G_CONCAT_VERSION`, the metadata version, which the kernel also checks against each
+/// request's `version`
+const G_CONCAT_VERSION: u32 = 4;
+/// The size of the `md_name` and `md_provider` fields, including the terminating NUL
+const NAME_LEN: usize = 16;
+
+/// Checks that a new `class` (`"concat"` or `"stripe"`) volume `name` can be assembled from
+/// `providers`, and returns their names (without `/dev/`), sizes, and sector sizes.
+pub(crate) fn check_volume<'a>(
+    graph: &Graph,
+    class: &str,
+    name: &str,
+    providers: &[&'a str],
+) -> Result<Vec<(&'a str, u64, u64)>, Error> {
+    let invalid = |what: String| -> Error { CtlError::new(EINVAL, what).into() };
+    if name.is_empty() || name.len() >= NAME_LEN || name.contains('/') {
+        return Err(invalid(format!("Invalid name {:?}", name)));
+    }
+    let volume = format!("{}/{}", class, name);
+    if graph.has_provider(&volume) {
+        return Err(CtlError::new(EEXIST, format!("Provider {} already exists", volume)).into());
+    }
+    if providers.len() < 2 {
+        return Err(invalid("Too few arguments.".to_owned()));
+    }
+
+    let mut members: Vec<(&str, u64, u64)> = Vec::new();
+    for provider in providers {
+        let provider = provider.trim_start_matches("/dev/");
+        if members.iter().any(|(p, _, _)| *p == provider) {
+            return Err(invalid(format!("Disk {} given twice", provider)));
+        }
+        let (mediasize, sectorsize) = graph
+            .provider_size(provider)
+            .ok_or_else(|| CtlError::new(ENOENT, format!("Disk {} is invalid.", provider)))?;
+        members.push((provider, mediasize, sectorsize));
+    }
+    return Ok(members);
+}
+
+/// Copies `s` into a NUL-padded `NAME_LEN`-byte metadata field.
+pub(crate) fn name_field(s: &str) -> [u8; NAME_LEN] {
+    let mut field = [0u8; NAME_LEN];
+    let len = s.len().min(NAME_LEN - 1);
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+    return field;
+}
+
+/// Encodes `struct g_concat_metadata` for member `no` of `all`.
+fn metadata(name: &str, id: u32, no: u16, all: u16, provsize: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(68);
+    buf.extend_from_slice(&name_field(G_CONCAT_MAGIC));
+    buf.extend_from_slice(&G_CONCAT_VERSION.to_le_bytes());
+    buf.extend_from_slice(&name_field(name));
+    buf.extend_from_slice(&id.to_le_bytes());
+    buf.extend_from_slice(&no.to_le_bytes());
+    buf.extend_from_slice(&all.to_le_bytes());
+    // `md_provider`, which is only set by `gconcat label -h`.
+    buf.extend_from_slice(&[0u8; NAME_LEN]);
+    buf.extend_from_slice(&provsize.to_le_bytes());
+    return buf;
+}
+
+/// Writes `encode(no, mediasize)`'s metadata to the last sector of each of `members`, as
+/// returned by `check_volume()`.  Fails, before writing anything, if any of them is open.
+#[cfg(target_os = "freebsd")]
+pub(crate) fn write_labels<F: Fn(u16, u64) -> Vec<u8>>(
+    graph: &Graph,
+    members: &[(&str, u64, u64)],
+    encode: F,
+) -> Result<(), Error> {
+    use crate::ctl::EBUSY;
+    use std::io::{Seek, SeekFrom, Write};
+
+    for (provider, _, _) in members {
+        let open = graph
+            .edges
+            .values()
+            .find(|e| e.name == *provider && e.mode.is_open());
+        if let Some(e) = open {
+            return Err(CtlError::new(EBUSY, format!("{} is open ({})", provider, e.mode)).into());
+        }
+    }
+    for (no, (provider, mediasize, sectorsize)) in members.iter().enumerate() {
+        let mut sector = encode(no as u16, *mediasize);
+        sector.resize(*sectorsize as usize, 0);
+        let mut dev = std::fs::OpenOptions::new()
+            .write(true)
+            .open(format!("/dev/{}", provider))?;
+        dev.seek(SeekFrom::Start(mediasize - sectorsize))?;
+        dev.write_all(&sector)?;
+    }
+    return Ok(());
+}
+
+/// A random volume ID, `md_id`.
+#[cfg(target_os = "freebsd")]
+pub(crate) fn random_id() -> u32 {
+    // SAFETY: `arc4random(3)` has no preconditions.
+    return unsafe { libc::arc4random() };
+}
+
+/// Returns the request to create the volume `concat/<name>` from `providers`, in order, like
+/// `gconcat create`.  The volume is not recorded on the providers; see `concat::label()`.
+///
+/// Fails if the volume already exists, if a provider does not exist or is given twice, or if
+/// there are fewer than two providers.
+pub fn create_request(graph: &Graph, name: &str, providers: &[&str]) -> Result<GctlRequest, Error> {
+    let members = check_volume(graph, "concat", name, providers)?;
+    let args: Vec<&str> = std::iter::once(name)
+        .chain(members.iter().map(|(p, _, _)| *p))
+        .collect();
+    return Ok(GctlRequest::new("CONCAT", "create")
+        .int("version", G_CONCAT_VERSION as i32)
+        .args(&args));
+}
+
+/// Creates the volume `concat/<name>` from `providers`, like `gconcat create`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn jbod() -> Result<(), geom::Error> {
+///     let res = geom::concat::create("data", &["da1", "da2", "da3"])?.refresh()?;
+///     for id in &res.new_graph_nodes {
+///         println!("{:?}", res.graph.nodes[id]);
+///     }
+///     assert_eq!(res.new_providers, vec!["concat/data"]);
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn create(name: &str, providers: &[&str]) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    create_request(&graph, name, providers)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Labels `providers` as the members of volume `concat/<name>`, in order, like `gconcat label`.
+/// The kernel assembles the volume once it has tasted all of the labeled providers, now and
+/// whenever they reappear.
+///
+/// Any data in the last sector of each provider is overwritten.  Fails, as `create_request()`
+/// does, or if any of the providers is open.
+#[cfg(target_os = "freebsd")]
+pub fn label(name: &str, providers: &[&str]) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    let members = check_volume(&graph, "concat", name, providers)?;
+    let id = random_id();
+    let args: Vec<&str> = std::iter::once(name)
+        .chain(members.iter().map(|(p, _, _)| *p))
+        .collect();
+    let req = GctlRequest::new("CONCAT", "label").args(&args);
+    if !crate::ctl::check_policy(&req)? {
+        return Ok(CtlResult::unrefreshed(graph, None));
+    }
+    let all = members.len() as u16;
+    crate::ctl::audited(&req, || {
+        write_labels(&graph, &members, |no, provsize| {
+            return metadata(name, id, no, all, provsize);
+        })?;
+        return Ok(crate::ctl::GctlResponse::default());
+    })?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concat::{create_request, metadata};
+    use crate::ctl::{CtlErrorKind, ParamValue};
+    use crate::{graph, raw, Error};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn create() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+
+        let req = create_request(&g, "data", &["/dev/nvd0p4", "nvd1p2"]).unwrap();
+        assert_eq!(req.class(), "CONCAT");
+        assert_eq!(req.get("version"), Some(&ParamValue::Int(4)));
+        assert_eq!(req.get("nargs"), Some(&ParamValue::Int(3)));
+        assert_eq!(req.get("arg0"), Some(&ParamValue::String("data".into())));
+        assert_eq!(req.get("arg1"), Some(&ParamValue::String("nvd0p4".into())));
+        assert_eq!(req.get("arg2"), Some(&ParamValue::String("nvd1p2".into())));
+
+        assert_eq!(
+            kind(create_request(&g, "data", &["nvd0p4"])),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            kind(create_request(&g, "data", &["nvd0p4", "nvd0p4"])),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            kind(create_request(&g, "data", &["nvd0p4", "nvd9"])),
+            CtlErrorKind::NotFound
+        );
+        assert_eq!(
+            kind(create_request(
+                &g,
+                "a-very-long-name",
+                &["nvd0p4", "nvd1p2"]
+            )),
+            CtlErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn label() {
+        let md = metadata("data", 0x1234_5678, 1, 3, 1 << 30);
+        assert_eq!(md.len(), 68);
+        assert_eq!(&md[..13], b"GEOM::CONCAT\0");
+        assert_eq!(&md[16..20], &[4, 0, 0, 0]);
+        assert_eq!(&md[20..25], b"data\0");
+        assert_eq!(&md[36..44], &[0x78, 0x56, 0x34, 0x12, 1, 0, 3, 0]);
+        assert_eq!(&md[44..60], &[0u8; 16]);
+        assert_eq!(&md[60..], &(1u64 << 30).to_le_bytes());
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn dry_run() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let providers = ["nvd0p4", "nvd1p2"];
+        let (res, requests) = crate::dry_run_on(&g, || -> Result<(), Error> {
+            super::create("data", &providers)?;
+            super::label("data", &providers)?;
+            return Ok(());
+        });
+        res.unwrap();
+        let verbs: Vec<&str> = requests.iter().map(|r| r.verb()).collect();
+        assert_eq!(verbs, vec!["create", "label"]);
+    }
+}
@@ -0,0 +1,142 @@
+//! A lightweight alternative to the XML mesh, based on `kern.geom.conftxt`.
+//!
+//! `kern.geom.conftxt` is a flat text dump with one line per provider, walked from every DISK
+//! and MD geom down through their consumers:
+//!
+//! ```text
+//! 0 DISK ada0 500107862016 512 hd 16 sc 63
+//! 1 PART ada0p1 524288 512 i 1 o 20480 ty freebsd-boot
+//! ```
+//!
+//! Each line carries the depth of the walk, the class, the provider name, its media and sector
+//! sizes, and class-specific `key value` pairs (which are not decoded here).  For tools that only
+//! need sizes, this avoids XML parsing entirely.
+use crate::{Error, GeomClass};
+use std::str::FromStr;
+
+/// One provider from `kern.geom.conftxt`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProviderSize {
+    /// Distance from the DISK or MD root, which is 0
+    pub level: u32,
+    /// The class of the geom owning the provider
+    pub class: GeomClass,
+    /// The provider name, e.g., `"ada0p1"`
+    pub name: String,
+    /// The size of the provider, in bytes
+    pub mediasize: u64,
+    /// The sector size of the provider, in bytes
+    pub sectorsize: u64,
+}
+
+/// Parses the contents of `kern.geom.conftxt`.
+///
+/// A provider reachable by several paths (e.g., a `gmirror(8)` of two disks) is listed once per
+/// path, as in the kernel's output.
+pub fn parse_conftxt(text: &str) -> Result<Vec<ProviderSize>, Error> {
+    let mut result = Vec::new();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let (level, class, name, mediasize, sectorsize) =
+            scan_fmt!(line, "{d} {} {} {d} {d}", u32, String, String, u64, u64)?;
+        result.push(ProviderSize {
+            level,
+            class: GeomClass::from_str(&class)?,
+            name,
+            mediasize,
+            sectorsize,
+        });
+    }
+    return Ok(result);
+}
+
+#[cfg(target_os = "freebsd")]
+fn get_conftxt() -> Result<String, Error> {
+    use sysctl::Sysctl;
+    const CTLNAME: &str = "kern.geom.conftxt";
+
+    let ctl = sysctl::Ctl::new(CTLNAME)?;
+    return Ok(ctl.value_string()?);
+}
+
+/// Returns the name, size, and sector size of every provider below a DISK or MD on the running
+/// system, without the cost of fetching and decoding the XML mesh.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// fn myfoo() -> Result<(), geom::Error> {
+///     for p in geom::conftxt::get_sizes()? {
+///         println!("{}: {} bytes", p.name, p.mediasize);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn get_sizes() -> Result<Vec<ProviderSize>, Error> {
+    return parse_conftxt(&get_conftxt()?);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{conftxt, graph, raw, GeomClass};
+    use std::time::Instant;
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+    const SAMPLE_CONFTXT: &str = include_str!("test/fullsample.conftxt");
+
+    #[test]
+    fn parse_sample() {
+        let sizes = conftxt::parse_conftxt(SAMPLE_CONFTXT).unwrap();
+        assert_eq!(
+            sizes[0],
+            conftxt::ProviderSize {
+                level: 0,
+                class: GeomClass::DISK,
+                name: "ada0".into(),
+                mediasize: 1000204886016,
+                sectorsize: 512,
+            }
+        );
+
+        // Agrees with the XML.
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        for p in &sizes {
+            let edge = g.edges.values().find(|e| e.name == p.name).unwrap();
+            assert_eq!(edge.mediasize, p.mediasize);
+            assert_eq!(edge.sectorsize, p.sectorsize);
+            assert_eq!(g.nodes[&edge.provider_geom].class, p.class);
+        }
+        assert_eq!(sizes.iter().filter(|p| p.level == 0).count(), 3);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(conftxt::parse_conftxt("").unwrap().is_empty());
+        assert!(conftxt::parse_conftxt("0 DISK ada0 big 512\n").is_err());
+    }
+
+    /// Compares the two paths; run with `cargo test -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_against_xml() {
+        const ITERS: u32 = 1000;
+
+        let start = Instant::now();
+        for _ in 0..ITERS {
+            conftxt::parse_conftxt(SAMPLE_CONFTXT).unwrap();
+        }
+        let txt = start.elapsed() / ITERS;
+
+        let start = Instant::now();
+        for _ in 0..ITERS {
+            let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+            graph::decode_graph(&rawmesh).unwrap();
+        }
+        let xml = start.elapsed() / ITERS;
+
+        println!("conftxt: {:?}/iter, confxml: {:?}/iter", txt, xml);
+        assert!(txt < xml);
+    }
+}
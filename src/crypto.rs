@@ -0,0 +1,385 @@
+//! The few cryptographic primitives `geli(8)` key handling needs, all from the system's
+//! `libcrypto`: HMAC-SHA-512 and PKCS #5 v2 (PBKDF2) key derivation, as in
+//! `sys/geom/eli/g_eli_hmac.c` and `pkcs5v2.c`; AES-CBC encryption, to protect Master Keys; and
+//! MD5, to checksum metadata.  Only the `EVP_*` and `PKCS5_*` interfaces are used, which OpenSSL
+//! 1.1 and 3 both provide without deprecation.
+//!
+//! This module, and so linking with `libcrypto`, is only built with the `eli` feature.  These are
+//! not meant for use outside this crate.
+use crate::wipe;
+use std::convert::TryFrom;
+use std::os::raw::{c_int, c_uchar, c_uint, c_void};
+
+/// The length of a SHA-512 digest, in bytes.
+pub(crate) const SHA512_MDLEN: usize = 64;
+
+/// OpenSSL's `EVP_MD_CTX`
+#[repr(C)]
+struct EvpMdCtx {
+    _private: [u8; 0],
+}
+
+/// OpenSSL's `EVP_MD`
+#[repr(C)]
+struct EvpMd {
+    _private: [u8; 0],
+}
+
+#[link(name = "crypto")]
+extern "C" {
+    fn EVP_sha512() -> *const EvpMd;
+    fn EVP_MD_CTX_new() -> *mut EvpMdCtx;
+    fn EVP_MD_CTX_free(ctx: *mut EvpMdCtx);
+    fn EVP_DigestInit_ex(ctx: *mut EvpMdCtx, type_: *const EvpMd, engine: *mut c_void) -> c_int;
+    fn EVP_DigestUpdate(ctx: *mut EvpMdCtx, data: *const c_void, count: usize) -> c_int;
+    fn EVP_DigestFinal_ex(ctx: *mut EvpMdCtx, md: *mut c_uchar, size: *mut c_uint) -> c_int;
+    fn PKCS5_PBKDF2_HMAC(
+        pass: *const c_uchar,
+        passlen: c_int,
+        salt: *const c_uchar,
+        saltlen: c_int,
+        iter: c_int,
+        digest: *const EvpMd,
+        keylen: c_int,
+        out: *mut c_uchar,
+    ) -> c_int;
+}
+
+/// The SHA-512 block size, in bytes.
+const SHA512_BLOCKLEN: usize = 128;
+
+/// An incremental SHA-512 computation.  `libcrypto` cleanses its state when it is dropped.
+struct Sha512(*mut EvpMdCtx);
+
+impl Sha512 {
+    fn new() -> Sha512 {
+        let ctx = Sha512(unsafe { EVP_MD_CTX_new() });
+        assert!(!ctx.0.is_null(), "EVP_MD_CTX_new failed");
+        let rc = unsafe { EVP_DigestInit_ex(ctx.0, EVP_sha512(), std::ptr::null_mut()) };
+        assert_eq!(rc, 1, "EVP_DigestInit_ex failed");
+        return ctx;
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        let rc = unsafe { EVP_DigestUpdate(self.0, data.as_ptr() as *const c_void, data.len()) };
+        assert_eq!(rc, 1, "EVP_DigestUpdate failed");
+    }
+
+    fn finalize(self) -> [u8; SHA512_MDLEN] {
+        let mut md = [0u8; SHA512_MDLEN];
+        let mut len = 0;
+        let rc = unsafe { EVP_DigestFinal_ex(self.0, md.as_mut_ptr(), &mut len) };
+        assert!(
+            rc == 1 && len as usize == SHA512_MDLEN,
+            "EVP_DigestFinal_ex failed"
+        );
+        return md;
+    }
+}
+
+impl Drop for Sha512 {
+    fn drop(&mut self) {
+        unsafe { EVP_MD_CTX_free(self.0) };
+    }
+}
+
+/// An incremental HMAC-SHA-512 computation (RFC 2104), built on SHA-512 as
+/// `g_eli_crypto_hmac_init()` and friends are.
+pub(crate) struct HmacSha512 {
+    inner: Sha512,
+    outer: Sha512,
+}
+
+impl HmacSha512 {
+    /// Starts an HMAC with `key`, which may be empty.
+    pub(crate) fn new(key: &[u8]) -> HmacSha512 {
+        let mut k = [0u8; SHA512_BLOCKLEN];
+        if key.len() > SHA512_BLOCKLEN {
+            let mut h = Sha512::new();
+            h.update(key);
+            let mut md = h.finalize();
+            k[..SHA512_MDLEN].copy_from_slice(&md);
+            wipe(&mut md);
+        } else {
+            k[..key.len()].copy_from_slice(key);
+        }
+
+        let mut pad = [0u8; SHA512_BLOCKLEN];
+        let mut result = HmacSha512 {
+            inner: Sha512::new(),
+            outer: Sha512::new(),
+        };
+        for (p, k) in pad.iter_mut().zip(k.iter()) {
+            *p = k ^ 0x36;
+        }
+        result.inner.update(&pad);
+        for (p, k) in pad.iter_mut().zip(k.iter()) {
+            *p = k ^ 0x5c;
+        }
+        result.outer.update(&pad);
+        wipe(&mut pad);
+        wipe(&mut k);
+        return result;
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub(crate) fn finalize(self) -> [u8; SHA512_MDLEN] {
+        let HmacSha512 { inner, mut outer } = self;
+        let mut digest = inner.finalize();
+        outer.update(&digest);
+        wipe(&mut digest);
+        return outer.finalize();
+    }
+}
+
+/// Derives `key.len()` bytes from `passphrase` and `salt` with PBKDF2-HMAC-SHA-512, like
+/// `pkcs5v2_genkey()`.  Returns `None` if `iterations` (or any length) does not fit in a C `int`,
+/// or `libcrypto` fails.
+pub(crate) fn pbkdf2_sha512(
+    key: &mut [u8],
+    salt: &[u8],
+    passphrase: &[u8],
+    iterations: u32,
+) -> Option<()> {
+    let int = |n: usize| c_int::try_from(n).ok();
+    let rc = unsafe {
+        PKCS5_PBKDF2_HMAC(
+            passphrase.as_ptr(),
+            int(passphrase.len())?,
+            salt.as_ptr(),
+            int(salt.len())?,
+            c_int::try_from(iterations).ok()?,
+            EVP_sha512(),
+            int(key.len())?,
+            key.as_mut_ptr(),
+        )
+    };
+    if rc != 1 {
+        return None;
+    }
+    return Some(());
+}
+
+/// OpenSSL's `EVP_CIPHER_CTX`
+#[repr(C)]
+struct EvpCipherCtx {
+    _private: [u8; 0],
+}
+
+/// OpenSSL's `EVP_CIPHER`
+#[repr(C)]
+struct EvpCipher {
+    _private: [u8; 0],
+}
+
+#[link(name = "crypto")]
+extern "C" {
+    fn EVP_md5() -> *const EvpMd;
+    fn EVP_Digest(
+        data: *const c_void,
+        count: usize,
+        md: *mut c_uchar,
+        size: *mut c_uint,
+        type_: *const EvpMd,
+        engine: *mut c_void,
+    ) -> c_int;
+    fn EVP_aes_128_cbc() -> *const EvpCipher;
+    fn EVP_aes_192_cbc() -> *const EvpCipher;
+    fn EVP_aes_256_cbc() -> *const EvpCipher;
+    fn EVP_CIPHER_CTX_new() -> *mut EvpCipherCtx;
+    fn EVP_CIPHER_CTX_free(ctx: *mut EvpCipherCtx);
+    fn EVP_CIPHER_CTX_set_padding(ctx: *mut EvpCipherCtx, padding: c_int) -> c_int;
+    fn EVP_EncryptInit_ex(
+        ctx: *mut EvpCipherCtx,
+        cipher: *const EvpCipher,
+        engine: *mut c_void,
+        key: *const c_uchar,
+        iv: *const c_uchar,
+    ) -> c_int;
+    fn EVP_EncryptUpdate(
+        ctx: *mut EvpCipherCtx,
+        out: *mut c_uchar,
+        outl: *mut c_int,
+        in_: *const c_uchar,
+        inl: c_int,
+    ) -> c_int;
+    fn EVP_EncryptFinal_ex(ctx: *mut EvpCipherCtx, out: *mut c_uchar, outl: *mut c_int) -> c_int;
+}
+
+/// The AES block size, in bytes.
+const AES_BLOCKLEN: usize = 16;
+
+/// Frees an `EVP_CIPHER_CTX`, which cleanses the key schedule, however encryption ends.
+struct CipherCtx(*mut EvpCipherCtx);
+
+impl Drop for CipherCtx {
+    fn drop(&mut self) {
+        unsafe { EVP_CIPHER_CTX_free(self.0) };
+    }
+}
+
+/// Encrypts `data` (a multiple of the block size) in place with AES-CBC and a zero IV, and no
+/// padding, like the userland `g_eli_crypto_encrypt()`.  Returns `None` for unsupported key
+/// lengths, a partial block, or if `libcrypto` fails.
+pub(crate) fn aes_cbc_encrypt(data: &mut [u8], key: &[u8]) -> Option<()> {
+    let cipher = match key.len() {
+        16 => unsafe { EVP_aes_128_cbc() },
+        24 => unsafe { EVP_aes_192_cbc() },
+        32 => unsafe { EVP_aes_256_cbc() },
+        _ => return None,
+    };
+    if !data.len().is_multiple_of(AES_BLOCKLEN) {
+        return None;
+    }
+    let inl = c_int::try_from(data.len()).ok()?;
+    let ctx = CipherCtx(unsafe { EVP_CIPHER_CTX_new() });
+    if ctx.0.is_null() {
+        return None;
+    }
+    let iv = [0u8; AES_BLOCKLEN];
+    let ok = unsafe {
+        EVP_EncryptInit_ex(
+            ctx.0,
+            cipher,
+            std::ptr::null_mut(),
+            key.as_ptr(),
+            iv.as_ptr(),
+        ) == 1
+            && EVP_CIPHER_CTX_set_padding(ctx.0, 0) == 1
+    };
+    if !ok {
+        return None;
+    }
+    // Without padding, the output is exactly as long as the input.
+    let mut out = vec![0u8; data.len()];
+    let (mut outl, mut finl) = (0, 0);
+    let ok = unsafe {
+        EVP_EncryptUpdate(ctx.0, out.as_mut_ptr(), &mut outl, data.as_ptr(), inl) == 1
+            && EVP_EncryptFinal_ex(ctx.0, out.as_mut_ptr().add(outl as usize), &mut finl) == 1
+    };
+    if ok && outl as usize + finl as usize == data.len() {
+        data.copy_from_slice(&out);
+    }
+    wipe(&mut out);
+    if !ok {
+        return None;
+    }
+    return Some(());
+}
+
+/// The MD5 digest of `data`, as stored in `md_hash`.
+pub(crate) fn md5(data: &[u8]) -> [u8; 16] {
+    let mut md = [0u8; 16];
+    let mut len = 0;
+    let rc = unsafe {
+        EVP_Digest(
+            data.as_ptr() as *const c_void,
+            data.len(),
+            md.as_mut_ptr(),
+            &mut len,
+            EVP_md5(),
+            std::ptr::null_mut(),
+        )
+    };
+    assert!(rc == 1 && len as usize == md.len(), "EVP_Digest failed");
+    return md;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{aes_cbc_encrypt, md5, pbkdf2_sha512, HmacSha512};
+
+    fn hex(bytes: &[u8]) -> String {
+        return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    }
+
+    #[test]
+    fn hmac_vectors() {
+        // RFC 4231, test case 2
+        let mut h = HmacSha512::new(b"Jefe");
+        h.update(b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&h.finalize()),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+        // RFC 4231, test case 6: a key longer than the block size
+        let mut h = HmacSha512::new(&[0xaa; 131]);
+        h.update(b"Test Using Larger Than Block-Size Key - Hash Key First");
+        assert_eq!(
+            hex(&h.finalize()),
+            "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352\
+             6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598"
+        );
+        // An empty key, as for the key file component of a user key; checked against Python's
+        // `hmac`.
+        assert_eq!(
+            hex(&HmacSha512::new(&[]).finalize()),
+            "b936cee86c9f87aa5d3c6f2e84cb5a4239a5fe50480a6ec66b70ab5b1f4ac673\
+             0c6c515421b327ec1d69402e53dfb49ad7381eb067b338fd7b0cb22247225d47"
+        );
+        // Updates may be split anywhere.
+        let mut h = HmacSha512::new(b"Jefe");
+        for chunk in b"what do ya want for nothing?".chunks(5) {
+            h.update(chunk);
+        }
+        assert!(hex(&h.finalize()).starts_with("164b7a7bfcf819e2"));
+    }
+
+    #[test]
+    fn pbkdf2_vectors() {
+        let mut key = [0u8; 64];
+        pbkdf2_sha512(&mut key, b"salt", b"password", 1).unwrap();
+        assert_eq!(
+            hex(&key),
+            "867f70cf1ade02cff3752599a3a53dc4af34c7a669815ae5d513554e1c8cf252\
+             c02d470a285a0501bad999bfe943c08f050235d7d68b1da55e63f73b60a57fce"
+        );
+        let mut key = [0u8; 80];
+        pbkdf2_sha512(&mut key, b"saltSALTsaltSALT", b"passwordPASSWORD", 3).unwrap();
+        assert_eq!(
+            hex(&key),
+            "d39779192b6c0e8ebc82a1a18a8b6b0c1dd28f9273527ef4d367c6fe38d6acac\
+             2a081899aac78700e33d1446c99297706eacc870b6354ab26d05a52cf9539d72\
+             4456403951997adf763ea789af9c26f1"
+        );
+        assert!(pbkdf2_sha512(&mut key, b"salt", b"password", 1 << 31).is_none());
+    }
+
+    #[test]
+    fn aes_vectors() {
+        // FIPS 197, appendix C; with a zero IV, the first CBC block is the bare cipher.
+        let plaintext: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        let key: Vec<u8> = (0..32).collect();
+        for (keylen, expected) in &[
+            (16, "69c4e0d86a7b0430d8cdb78070b4c55a"),
+            (24, "dda97ca4864cdfe06eaf70a0ec0d7191"),
+            (32, "8ea2b7ca516745bfeafc49904b496089"),
+        ] {
+            let mut block = plaintext.clone();
+            aes_cbc_encrypt(&mut block, &key[..*keylen]).unwrap();
+            assert_eq!(hex(&block), *expected);
+        }
+        assert!(aes_cbc_encrypt(&mut [0u8; 16], &key[..20]).is_none());
+        assert!(aes_cbc_encrypt(&mut [0u8; 15], &key[..16]).is_none());
+
+        // Checked against Python's `cryptography`.
+        let mut data: Vec<u8> = (0..48).collect();
+        aes_cbc_encrypt(&mut data, &key[..16]).unwrap();
+        assert_eq!(
+            hex(&data),
+            "0a940bb5416ef045f1c39458c653ea5a3cf456b4ca488aa383c79c98b34797cb\
+             7e163e30ea49d32152a51a08a10ec02d"
+        );
+    }
+
+    #[test]
+    fn md5_vectors() {
+        assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hex(&md5(&[0x61; 495])), "1f3ab8ff372bd73892c7597e6a116532");
+    }
+}
@@ -0,0 +1,1521 @@
+//! GEOM control requests.
+//!
+//! Changes to the GEOM graph (creating a partition, attaching a `geli(8)` provider, ...) are made
+//! by sending a *control request* to a GEOM class through `/dev/geom.ctl`: a verb, such as
+//! `"add"`, and named parameters, which the class interprets.  This is what `geom(8)`, `gpart(8)`
+//! and friends do through `libgeom(3)`'s `gctl_*()` functions; `GctlRequest` is the equivalent.
+//!
+//! # Examples
+//!
+//! ```
+//! use freebsd_geom as geom;
+//!
+//! // Like `gpart add -t freebsd-ufs -s 1G da0`.
+//! let req = geom::ctl::GctlRequest::new("PART", "add")
+//!     .args(&["da0"])
+//!     .string("type", "freebsd-ufs")
+//!     .string("size", "1G")
+//!     .string("flags", "x")
+//!     .output("output", 1024);
+//!
+//! #[cfg(target_os = "freebsd")]
+//! fn myfoo(req: &geom::ctl::GctlRequest) -> Result<(), geom::Error> {
+//!     let resp = req.issue()?;
+//!     println!("{}", resp.get_str("output").unwrap_or_default());
+//!     Ok(())
+//! }
+//! ```
+use crate::diff::{geom_keys, Change, GeomKey, GraphDelta};
+use crate::graph::{Edge, EdgeId, Graph, NodeId};
+use crate::Error;
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::raw::{c_char, c_int, c_uint, c_ulong, c_void};
+use std::sync::{Arc, RwLock};
+
+/// The GEOM control device.
+pub const GEOM_CTL_PATH: &str = "/dev/geom.ctl";
+
+/// Verbs that overwrite or tear down geoms, providers, or metadata; see
+/// `GctlRequest::is_destructive()`.
+const DESTRUCTIVE_VERBS: &[&str] = &[
+    "bootcode", "clear", "commit", "delete", "delkey", "destroy", "forget", "init", "kill",
+    "label", "modify", "remove", "resize", "setkey", "stop",
+];
+
+/// `GCTL_VERSION` from `<geom/geom_ctl.h>`
+const GCTL_VERSION: c_uint = 2;
+
+const GCTL_PARAM_RD: c_int = 1;
+const GCTL_PARAM_WR: c_int = 2;
+const GCTL_PARAM_ASCII: c_int = 4;
+
+/// The size of the kernel's error message buffer, `BUFSIZ` in `libgeom(3)`.
+const ERROR_LEN: usize = 1024;
+
+/// `struct gctl_req_arg`
+#[repr(C)]
+struct GctlReqArg {
+    nlen: c_uint,
+    name: *mut c_char,
+    offset: i64,
+    flag: c_int,
+    len: c_int,
+    value: *mut c_void,
+    kvalue: *mut c_void,
+}
+
+/// `struct gctl_req`
+#[repr(C)]
+struct GctlReq {
+    version: c_uint,
+    serial: c_uint,
+    narg: c_uint,
+    arg: *mut GctlReqArg,
+    lerror: c_uint,
+    error: *mut c_char,
+    reqt: *mut c_void,
+    nerror: c_int,
+    serror: *mut c_void,
+}
+
+/// `_IOW('G', GCTL_VERSION, struct gctl_req)`
+const GEOM_CTL: c_ulong = {
+    const IOC_IN: c_ulong = 0x8000_0000;
+    const IOCPARM_MASK: c_ulong = (1 << 13) - 1;
+    IOC_IN
+        | ((std::mem::size_of::<GctlReq>() as c_ulong & IOCPARM_MASK) << 16)
+        | ((b'G' as c_ulong) << 8)
+        | GCTL_VERSION as c_ulong
+};
+
+/// FreeBSD `errno` values found in control request errors, their `CtlErrorKind`s, and their
+/// `strerror(3)` messages.
+const ERRNOS: &[(i32, CtlErrorKind, &str)] = &[
+    (1, CtlErrorKind::PermissionDenied, "Operation not permitted"),
+    (2, CtlErrorKind::NotFound, "No such file or directory"),
+    (6, CtlErrorKind::NotFound, "Device not configured"),
+    (13, CtlErrorKind::PermissionDenied, "Permission denied"),
+    (16, CtlErrorKind::Busy, "Device busy"),
+    (17, CtlErrorKind::AlreadyExists, "File exists"),
+    (22, CtlErrorKind::InvalidArgument, "Invalid argument"),
+    (28, CtlErrorKind::NoSpace, "No space left on device"),
+    (87, CtlErrorKind::MissingParameter, "Attribute not found"),
+];
+
+pub(crate) const EPERM: i32 = 1;
+pub(crate) const ENOENT: i32 = 2;
+pub(crate) const EBUSY: i32 = 16;
+pub(crate) const EEXIST: i32 = 17;
+pub(crate) const EINVAL: i32 = 22;
+pub(crate) const ENOSPC: i32 = 28;
+
+/// The broad reason a control request failed; see `CtlError::kind()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CtlErrorKind {
+    /// `EPERM`, `EACCES`
+    PermissionDenied,
+    /// `ENOENT`, `ENXIO`: no such geom, provider, or partition entry
+    NotFound,
+    /// `EBUSY`: e.g., the provider is open
+    Busy,
+    /// `EEXIST`
+    AlreadyExists,
+    /// `EINVAL`: a parameter has a bad value
+    InvalidArgument,
+    /// `ENOSPC`: e.g., no room for a partition
+    NoSpace,
+    /// `ENOATTR`: a required parameter is missing
+    MissingParameter,
+    /// Any other error, including those the class reported without an `errno`
+    Other,
+}
+
+/// Why a control request failed, as reported by the class (or found by this library before
+/// issuing it).
+///
+/// Some classes (notably PART) begin their messages with an `errno` value, e.g.,
+/// `"22 index '9'"`; it is split off into `errno`.  `Display` formats such errors the way
+/// `libgeom(3)` does: `"index '9': Invalid argument"`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CtlError {
+    /// The `errno` value, if any
+    pub errno: Option<i32>,
+    /// The rest of the message, e.g., `"index '9'"`; may be empty
+    pub message: String,
+}
+
+impl CtlError {
+    pub(crate) fn new(errno: i32, message: String) -> CtlError {
+        return CtlError {
+            errno: Some(errno),
+            message,
+        };
+    }
+
+    pub(crate) fn other(message: String) -> CtlError {
+        return CtlError {
+            errno: None,
+            message,
+        };
+    }
+
+    /// Parses a class's error message.
+    pub fn parse(msg: &str) -> CtlError {
+        let msg = msg.trim_end();
+        let (number, rest) = match msg.find(' ') {
+            Some(i) => (&msg[..i], msg[i + 1..].trim_start()),
+            None => (msg, ""),
+        };
+        return match number.parse::<i32>() {
+            Ok(errno) => CtlError::new(errno, rest.to_owned()),
+            Err(_) => CtlError::other(msg.to_owned()),
+        };
+    }
+
+    /// The broad reason for the error, from `errno`.
+    pub fn kind(&self) -> CtlErrorKind {
+        return ERRNOS
+            .iter()
+            .find(|(e, _, _)| Some(*e) == self.errno)
+            .map(|(_, kind, _)| *kind)
+            .unwrap_or(CtlErrorKind::Other);
+    }
+}
+
+impl std::fmt::Display for CtlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errno = match self.errno {
+            Some(errno) => errno,
+            None => return write!(f, "{}", self.message),
+        };
+        if !self.message.is_empty() {
+            write!(f, "{}: ", self.message)?;
+        }
+        return match ERRNOS.iter().find(|(e, _, _)| *e == errno) {
+            Some((_, _, desc)) => write!(f, "{}", desc),
+            None => write!(f, "Unknown error: {}", errno),
+        };
+    }
+}
+
+/// The value of a control request parameter.  Which type a class expects for each parameter is
+/// up to the class; see the `geom(8)` class modules (e.g., `geom_part.c`) for the types
+/// `geom(8)` passes.
+///
+/// `Binary` values may be key material: they are formatted (by `Debug`, too) only as their
+/// length, and zeroed when dropped.
+#[derive(Clone, Eq, PartialEq)]
+pub enum ParamValue {
+    /// A NUL-terminated string, e.g., a provider name or a size such as `"1G"`
+    String(String),
+    /// A C `int`, e.g., `nargs`, or a boolean option (0 or 1)
+    Int(i32),
+    /// An `intmax_t`, e.g., `gpart`'s `index`
+    Number(i64),
+    /// Arbitrary bytes, e.g., `geli(8)` key material
+    Binary(Vec<u8>),
+}
+
+impl ParamValue {
+    /// The parameter's bytes, as passed to the kernel, and its `GCTL_PARAM_*` flags.
+    fn encode(&self) -> (Vec<u8>, c_int) {
+        return match self {
+            Self::String(s) => {
+                let mut bytes = s.as_bytes().to_vec();
+                bytes.push(0);
+                (bytes, GCTL_PARAM_RD | GCTL_PARAM_ASCII)
+            }
+            Self::Int(i) => (i.to_ne_bytes().to_vec(), GCTL_PARAM_RD),
+            Self::Number(n) => (n.to_ne_bytes().to_vec(), GCTL_PARAM_RD),
+            Self::Binary(b) => (b.clone(), GCTL_PARAM_RD),
+        };
+    }
+}
+
+impl std::fmt::Debug for ParamValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Self::String(s) => f.debug_tuple("String").field(s).finish(),
+            Self::Int(i) => f.debug_tuple("Int").field(i).finish(),
+            Self::Number(n) => f.debug_tuple("Number").field(n).finish(),
+            Self::Binary(b) => write!(f, "Binary(<{} bytes>)", b.len()),
+        };
+    }
+}
+
+impl Drop for ParamValue {
+    fn drop(&mut self) {
+        if let Self::Binary(b) = self {
+            crate::wipe(b);
+        }
+    }
+}
+
+impl std::fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Self::String(s) => write!(f, "{:?}", s),
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Binary(b) => write!(f, "<{} bytes>", b.len()),
+        };
+    }
+}
+
+/// A request parameter: a value for the class, or a buffer for its reply.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Param {
+    In(ParamValue),
+    Out(usize),
+}
+
+/// A GEOM control request, built up with the parameters of a verb and sent with
+/// `GctlRequest::issue()`.
+///
+/// The `class` and `verb` parameters are added by `issue()`; any others are the caller's.
+/// Setting a parameter again replaces its value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GctlRequest {
+    class: String,
+    verb: String,
+    params: Vec<(String, Param)>,
+}
+
+impl GctlRequest {
+    /// A request for `verb` (e.g., `"add"`) of `class` (e.g., `"PART"`), without parameters.
+    pub fn new(class: &str, verb: &str) -> GctlRequest {
+        return GctlRequest {
+            class: class.to_owned(),
+            verb: verb.to_owned(),
+            params: Vec::new(),
+        };
+    }
+
+    fn set(mut self, name: &str, param: Param) -> Self {
+        match self.params.iter_mut().find(|(n, _)| n == name) {
+            Some((_, p)) => *p = param,
+            None => self.params.push((name.to_owned(), param)),
+        }
+        return self;
+    }
+
+    /// Adds (or replaces) a parameter.
+    pub fn param(self, name: &str, value: ParamValue) -> Self {
+        return self.set(name, Param::In(value));
+    }
+
+    /// Adds a string parameter.
+    pub fn string(self, name: &str, value: &str) -> Self {
+        return self.param(name, ParamValue::String(value.to_owned()));
+    }
+
+    /// Adds an `int` parameter.
+    pub fn int(self, name: &str, value: i32) -> Self {
+        return self.param(name, ParamValue::Int(value));
+    }
+
+    /// Adds an `intmax_t` parameter.
+    pub fn number(self, name: &str, value: i64) -> Self {
+        return self.param(name, ParamValue::Number(value));
+    }
+
+    /// Adds a binary parameter.
+    pub fn binary(self, name: &str, value: &[u8]) -> Self {
+        return self.param(name, ParamValue::Binary(value.to_vec()));
+    }
+
+    /// Adds the positional arguments of the verb, as `geom(8)` does: string parameters `arg0`,
+    /// `arg1`, ..., and their count, `nargs`.
+    pub fn args<S: AsRef<str>>(mut self, args: &[S]) -> Self {
+        for (i, arg) in args.iter().enumerate() {
+            self = self.string(&format!("arg{}", i), arg.as_ref());
+        }
+        return self.int("nargs", args.len() as i32);
+    }
+
+    /// Adds an output parameter: a `len`-byte buffer for the class to write its reply to, such
+    /// as `gpart(8)`'s `"output"`.  See `GctlResponse`.
+    pub fn output(self, name: &str, len: usize) -> Self {
+        return self.set(name, Param::Out(len));
+    }
+
+    /// The class the request is for
+    pub fn class(&self) -> &str {
+        return &self.class;
+    }
+
+    /// The verb requested
+    pub fn verb(&self) -> &str {
+        return &self.verb;
+    }
+
+    /// The value of the parameter `name`, if it was set (and is not an output parameter).
+    pub fn get(&self, name: &str) -> Option<&ParamValue> {
+        return self.params.iter().find_map(|(n, p)| match p {
+            Param::In(v) if n == name => Some(v),
+            _ => None,
+        });
+    }
+
+    /// The parameters, in the order they were first set, without the output parameters.
+    pub fn params(&self) -> impl Iterator<Item = (&str, &ParamValue)> + '_ {
+        return self.params.iter().filter_map(|(n, p)| match p {
+            Param::In(v) => Some((&n[..], v)),
+            Param::Out(_) => None,
+        });
+    }
+
+    /// Checks that the request can be passed to the kernel: no names or string values contain
+    /// NUL, and no value is too long.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, param) in &self.params {
+            if name.contains('\0') {
+                return Err(CtlError::new(
+                    EINVAL,
+                    format!("parameter name {:?} contains NUL", name),
+                )
+                .into());
+            }
+            let len = match param {
+                Param::In(ParamValue::String(s)) if s.contains('\0') => {
+                    return Err(
+                        CtlError::new(EINVAL, format!("parameter {} contains NUL", name)).into(),
+                    );
+                }
+                Param::In(v) => v.encode().0.len(),
+                Param::Out(len) => *len,
+            };
+            if len > c_int::MAX as usize {
+                return Err(
+                    CtlError::new(EINVAL, format!("parameter {} is too long", name)).into(),
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    /// A copy of the request with its `Binary` parameters (e.g., keys) zeroed, to keep around.
+    fn redacted(&self) -> GctlRequest {
+        let mut result = self.clone();
+        for (_, param) in &mut result.params {
+            if let Param::In(ParamValue::Binary(b)) = param {
+                crate::wipe(b);
+            }
+        }
+        return result;
+    }
+
+    /// Whether the verb overwrites or tears down something, e.g., `"destroy"`, `"delete"`, or
+    /// `"label"`, and so needs confirmation under a `CtlPolicy` that requires it.
+    pub fn is_destructive(&self) -> bool {
+        return DESTRUCTIVE_VERBS.contains(&self.verb.as_str());
+    }
+
+    /// The token confirming this request to a `CtlPolicy`: the class, verb, and positional
+    /// arguments, e.g., `"PART destroy da0"`.
+    pub fn confirmation_token(&self) -> String {
+        let mut token = format!("{} {}", self.class, self.verb);
+        for i in 0.. {
+            match self.get(&format!("arg{}", i)) {
+                Some(ParamValue::String(arg)) => {
+                    token.push(' ');
+                    token.push_str(arg);
+                }
+                _ => break,
+            }
+        }
+        return token;
+    }
+
+    /// The `geom(8)` command line equivalent to the request, for `Backend::Command`, e.g.,
+    /// `["/sbin/geom", "part", "add", "-t", "freebsd-ufs", "--", "da0"]`.
+    ///
+    /// Positional arguments follow the options; `version` and `nargs` are left to `geom(8)`,
+    /// which sets them itself.  Fails with `EINVAL` if a parameter has no command-line option in
+    /// its class (see `COMMAND_OPTIONS`), or cannot be given on a command line at all, e.g.,
+    /// `geli attach`'s binary key.
+    pub fn command(&self) -> Result<Vec<String>, Error> {
+        let unsupported = |name: &str| -> Error {
+            let msg = format!(
+                "{} {}: parameter {} cannot be passed to geom(8)",
+                self.class, self.verb, name
+            );
+            return CtlError::new(EINVAL, msg).into();
+        };
+        let options = COMMAND_OPTIONS
+            .iter()
+            .find(|(class, _)| *class == self.class)
+            .map_or(&[][..], |(_, options)| options);
+
+        let mut argv = vec![
+            GEOM_PATH.to_owned(),
+            self.class.to_lowercase(),
+            self.verb.clone(),
+        ];
+        let mut args = BTreeMap::new();
+        for (name, value) in self.params() {
+            if name == "version" || name == "nargs" {
+                continue;
+            }
+            if let Some(i) = name.strip_prefix("arg").and_then(|i| i.parse::<u32>().ok()) {
+                match value {
+                    ParamValue::String(arg) => args.insert(i, arg.clone()),
+                    _ => return Err(unsupported(name)),
+                };
+                continue;
+            }
+            let letter = options
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, letter)| *letter)
+                .ok_or_else(|| unsupported(name))?;
+            match value {
+                // `geom(8)` passes its boolean options as `int`s.
+                ParamValue::Int(0) => {}
+                ParamValue::Int(1) => argv.push(format!("-{}", letter)),
+                ParamValue::String(v) => argv.extend(vec![format!("-{}", letter), v.clone()]),
+                ParamValue::Number(v) => argv.extend(vec![format!("-{}", letter), v.to_string()]),
+                ParamValue::Int(_) | ParamValue::Binary(_) => return Err(unsupported(name)),
+            }
+        }
+        argv.push("--".to_owned());
+        argv.extend(args.into_values());
+        return Ok(argv);
+    }
+
+    /// Sends the request to the kernel, and returns the class's reply.  A request that the class
+    /// rejects fails with `Error::Ctl`.
+    ///
+    /// Within `CtlPolicy::apply()`, the policy may instead refuse the request, or, in dry-run
+    /// mode, skip sending it and return an empty reply.  Requests that are sent are passed to
+    /// the `CtlHook`, if any.  Under `Backend::Command`, the request is carried out by running
+    /// `geom(8)` instead; see `GctlRequest::command()`.
+    #[cfg(target_os = "freebsd")]
+    pub fn issue(&self) -> Result<GctlResponse, Error> {
+        self.validate()?;
+        if !check_policy(self)? {
+            return Ok(GctlResponse::default());
+        }
+        return audited(self, || match backend() {
+            Backend::Kernel => self.send(),
+            Backend::Command => self.run(),
+        });
+    }
+
+    /// Carries out the request with `geom(8)`.  Its standard output is the reply in each output
+    /// parameter.
+    fn run(&self) -> Result<GctlResponse, Error> {
+        let stdout = run_command(&self.command()?)?;
+        let mut outputs = BTreeMap::new();
+        for (name, param) in &self.params {
+            if let Param::Out(len) = param {
+                let mut value = stdout.clone();
+                value.truncate(*len);
+                outputs.insert(name.clone(), value);
+            }
+        }
+        return Ok(GctlResponse { outputs });
+    }
+
+    /// Sends the (validated) request to the kernel.
+    #[cfg(target_os = "freebsd")]
+    fn send(&self) -> Result<GctlResponse, Error> {
+        use std::ffi::CString;
+        use std::os::unix::io::AsRawFd;
+
+        let mut names = Vec::new();
+        let mut values: Vec<(Vec<u8>, c_int)> = Vec::new();
+        let implicit = [
+            ("class", Param::In(ParamValue::String(self.class.clone()))),
+            ("verb", Param::In(ParamValue::String(self.verb.clone()))),
+        ];
+        let params = implicit
+            .iter()
+            .map(|(n, p)| (*n, p))
+            .chain(self.params.iter().map(|(n, p)| (&n[..], p)));
+        for (name, param) in params {
+            // validate() checked for NULs.
+            names.push(CString::new(name).unwrap());
+            values.push(match param {
+                Param::In(v) => v.encode(),
+                Param::Out(len) => (vec![0u8; *len], GCTL_PARAM_WR | GCTL_PARAM_ASCII),
+            });
+        }
+        let mut args: Vec<GctlReqArg> = names
+            .iter()
+            .zip(values.iter_mut())
+            .map(|(name, (value, flag))| GctlReqArg {
+                nlen: name.as_bytes_with_nul().len() as c_uint,
+                name: name.as_ptr() as *mut c_char,
+                offset: 0,
+                flag: *flag,
+                len: value.len() as c_int,
+                value: value.as_mut_ptr() as *mut c_void,
+                kvalue: std::ptr::null_mut(),
+            })
+            .collect();
+        let mut error = vec![0u8; ERROR_LEN];
+        let mut req = GctlReq {
+            version: GCTL_VERSION,
+            serial: 0,
+            narg: args.len() as c_uint,
+            arg: args.as_mut_ptr(),
+            lerror: error.len() as c_uint,
+            error: error.as_mut_ptr() as *mut c_char,
+            reqt: std::ptr::null_mut(),
+            nerror: 0,
+            serror: std::ptr::null_mut(),
+        };
+
+        let ctl = std::fs::File::open(GEOM_CTL_PATH)?;
+        // The kernel reads the arguments, and writes the error message and output parameters,
+        // through the pointers in `req`, all of which outlive the call.
+        let rc = unsafe { libc::ioctl(ctl.as_raw_fd(), GEOM_CTL, &mut req as *mut GctlReq) };
+        let ioctl_err = std::io::Error::last_os_error();
+        // Don't leave copies of key material behind.
+        for ((_, param), (value, _)) in self.params.iter().zip(values.iter_mut().skip(2)) {
+            if let Param::In(ParamValue::Binary(_)) = param {
+                crate::wipe(value);
+            }
+        }
+        if let Some(msg) = GctlResponse::c_str(&error) {
+            return Err(CtlError::parse(msg).into());
+        }
+        if rc != 0 {
+            return Err(ioctl_err.into());
+        }
+
+        let mut outputs = BTreeMap::new();
+        for ((name, param), (value, _)) in self.params.iter().zip(values.into_iter().skip(2)) {
+            if let Param::Out(_) = param {
+                outputs.insert(name.clone(), value);
+            }
+        }
+        return Ok(GctlResponse { outputs });
+    }
+}
+
+impl std::fmt::Display for GctlRequest {
+    /// E.g., `PART add arg0="da0" nargs=1 type="freebsd-ufs"`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.class, self.verb)?;
+        for (name, value) in self.params() {
+            write!(f, " {}={}", name, value)?;
+        }
+        return Ok(());
+    }
+}
+
+/// The reply to a `GctlRequest`: the contents of its output parameters.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GctlResponse {
+    outputs: BTreeMap<String, Vec<u8>>,
+}
+
+impl GctlResponse {
+    /// The string in a NUL-terminated buffer, or `None` if it is empty.
+    fn c_str(buf: &[u8]) -> Option<&str> {
+        let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+        return std::str::from_utf8(&buf[..end])
+            .ok()
+            .filter(|s| !s.is_empty());
+    }
+
+    /// The full buffer of the output parameter `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        return self.outputs.get(name).map(|v| &v[..]);
+    }
+
+    /// The string written to the output parameter `name`, if any, e.g., `"da0p1 added\n"`.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        return Self::c_str(self.outputs.get(name)?);
+    }
+}
+
+/// The size of the `"output"` buffer `geom(8)` passes with every verb.
+const VERB_OUTPUT_LEN: usize = 4096;
+
+/// Returns the request `geom CLASS VERB ...` would send: `params`, plus an `"output"` buffer for
+/// the class's reply.  Positional arguments go in `"arg0"`, `"arg1"`, ...; unless `params` sets
+/// `"nargs"`, it is set to their count.
+///
+/// `geom(8)` also passes the `"version"` of its class module (e.g., `G_NOP_VERSION`), which most
+/// classes other than PART require; set it in `params` as an `Int`.
+pub fn verb_request(class: &str, verb: &str, params: &BTreeMap<String, ParamValue>) -> GctlRequest {
+    let mut req = GctlRequest::new(class, verb);
+    for (name, value) in params {
+        req = req.param(name, value.clone());
+    }
+    if !params.contains_key("nargs") {
+        let nargs = (0..)
+            .take_while(|i| params.contains_key(&format!("arg{}", i)))
+            .count();
+        req = req.int("nargs", nargs as i32);
+    }
+    return req.output("output", VERB_OUTPUT_LEN);
+}
+
+/// Sends `verb` to `class` with `params`, like `geom CLASS VERB ...`, for classes this library
+/// has no dedicated module for.  See `verb_request()`; the class's reply, if any, is in the
+/// response's `"output"`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+/// use geom::ctl::ParamValue;
+///
+/// // Like `gnop reset md0.nop`, which zeroes its counters.
+/// #[cfg(target_os = "freebsd")]
+/// fn myfoo() -> Result<(), geom::Error> {
+///     let mut params = std::collections::BTreeMap::new();
+///     params.insert("version".to_owned(), ParamValue::Int(4));
+///     params.insert("arg0".to_owned(), ParamValue::String("md0.nop".to_owned()));
+///     let resp = geom::ctl::verb("NOP", "reset", &params)?;
+///     print!("{}", resp.get_str("output").unwrap_or_default());
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn verb(
+    class: &str,
+    verb: &str,
+    params: &BTreeMap<String, ParamValue>,
+) -> Result<GctlResponse, Error> {
+    return verb_request(class, verb, params).issue();
+}
+
+/// Guards on the control requests made by this library, for automation that must not change
+/// the system unless told to.  A policy takes effect within `CtlPolicy::apply()`, where it
+/// covers every request sent by `GctlRequest::issue()` (and so by the wrappers in `part`,
+/// `eli`, `nop`, ...), as well as the operations that write to providers or `/dev/mdctl`
+/// directly (e.g., `eli::init()`, `concat::label()`, `md::destroy()`).
+///
+/// The policy does not log anything itself: `apply()` returns the requests made under it, whose
+/// `Display` is a one-line form to log, e.g., `PART destroy arg0="da0" nargs=1 force=1`.  To log
+/// the requests that are actually carried out, as they are, install a `CtlHook`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn plan() -> Result<(), geom::Error> {
+///     let policy = geom::ctl::CtlPolicy {
+///         dry_run: true,
+///         ..Default::default()
+///     };
+///     let (res, requests) =
+///         policy.apply(|| geom::part::create_table("da0", &geom::PartScheme::GPT, None));
+///     res?;
+///     for req in requests {
+///         println!("would send: {}", req);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CtlPolicy {
+    /// Validate and record requests, but do not send them; `issue()` returns an empty reply,
+    /// and the wrappers report no new provider.
+    pub dry_run: bool,
+    /// Refuse destructive requests (see `GctlRequest::is_destructive()`) unless their
+    /// `confirmation_token()` was passed to `confirm()`.
+    pub require_confirmation: bool,
+    confirmed: Vec<String>,
+}
+
+/// The policy in effect on this thread, and the requests made under it.
+type PolicyScope = Option<(CtlPolicy, Vec<GctlRequest>)>;
+
+thread_local! {
+    static POLICY: std::cell::RefCell<PolicyScope> = const { std::cell::RefCell::new(None) };
+}
+
+impl CtlPolicy {
+    /// Confirms the destructive request with `token` (e.g., `"PART destroy da0"`); see
+    /// `GctlRequest::confirmation_token()`.
+    pub fn confirm(mut self, token: &str) -> Self {
+        self.confirmed.push(token.to_owned());
+        return self;
+    }
+
+    /// Checks `req` against the policy: fails if it needs confirmation, and otherwise returns
+    /// whether to actually send it.
+    pub fn check(&self, req: &GctlRequest) -> Result<bool, Error> {
+        if self.require_confirmation && req.is_destructive() {
+            let token = req.confirmation_token();
+            if !self.confirmed.contains(&token) {
+                let msg = format!("{} requires confirmation", token);
+                return Err(CtlError::new(EPERM, msg).into());
+            }
+        }
+        return Ok(!self.dry_run);
+    }
+
+    /// Runs `f` under this policy, and returns its result and the requests it made (or, in
+    /// dry-run mode, would have made), in order.  Requests refused by the policy are not
+    /// included, and the `Binary` parameters (e.g., keys) of those returned are zeroed.
+    /// Policies apply to the calling thread only, and an inner `apply()` replaces the outer
+    /// policy until it returns.
+    pub fn apply<R, F: FnOnce() -> R>(&self, f: F) -> (R, Vec<GctlRequest>) {
+        /// Restores the outer policy, even if `f` panics.
+        struct Restore(PolicyScope);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let outer = self.0.take();
+                POLICY.with(|p| *p.borrow_mut() = outer);
+            }
+        }
+
+        let outer = POLICY.with(|p| p.borrow_mut().replace((self.clone(), Vec::new())));
+        let restore = Restore(outer);
+        let res = f();
+        let (_, requests) = POLICY.with(|p| p.borrow_mut().take()).unwrap();
+        drop(restore);
+        return (res, requests);
+    }
+}
+
+/// Checks `req` against the policy in effect, if any, recording it if allowed.  Returns whether
+/// to actually carry it out.
+pub(crate) fn check_policy(req: &GctlRequest) -> Result<bool, Error> {
+    return POLICY.with(|p| match &mut *p.borrow_mut() {
+        Some((policy, requests)) => {
+            let send = policy.check(req)?;
+            requests.push(req.redacted());
+            Ok(send)
+        }
+        None => Ok(true),
+    });
+}
+
+/// Whether the policy in effect, if any, is a dry run, so that wrappers must not expect a reply.
+pub(crate) fn dry_run() -> bool {
+    return POLICY.with(|p| matches!(&*p.borrow(), Some((policy, _)) if policy.dry_run));
+}
+
+/// Observes the control operations this library carries out, e.g., to keep an audit log, or to
+/// ask a user to confirm each one; see `set_hook()`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+/// use geom::ctl::{CtlHook, GctlRequest, GctlResponse};
+///
+/// struct Log;
+///
+/// impl CtlHook for Log {
+///     fn after(&self, req: &GctlRequest, result: Result<&GctlResponse, &geom::Error>) {
+///         match result {
+///             Ok(_) => eprintln!("{}", req),
+///             Err(e) => eprintln!("{}: {}", req, e),
+///         }
+///     }
+/// }
+///
+/// geom::ctl::set_hook(Some(std::sync::Arc::new(Log)));
+/// ```
+pub trait CtlHook: Send + Sync {
+    /// Called before `req` is sent (or, for operations that write to providers or `/dev/mdctl`
+    /// directly, carried out).  Returning an error aborts the operation with that error.
+    fn before(&self, _req: &GctlRequest) -> Result<(), Error> {
+        return Ok(());
+    }
+
+    /// Called once `req` has been carried out, with its outcome.  Operations other than control
+    /// requests report an empty reply.
+    fn after(&self, _req: &GctlRequest, _result: Result<&GctlResponse, &Error>) {}
+}
+
+/// The hook installed with `set_hook()`.
+static HOOK: RwLock<Option<Arc<dyn CtlHook>>> = RwLock::new(None);
+
+/// Installs `hook` for every thread, and returns the one it replaces, if any.  The hook sees
+/// each operation that is actually carried out, not those skipped in dry-run mode or refused by
+/// a `CtlPolicy`.
+pub fn set_hook(hook: Option<Arc<dyn CtlHook>>) -> Option<Arc<dyn CtlHook>> {
+    let mut current = HOOK.write().unwrap_or_else(|e| e.into_inner());
+    return std::mem::replace(&mut *current, hook);
+}
+
+/// Carries out `req` with `op`, between the calls to the `CtlHook`, if any.
+pub(crate) fn audited<F>(req: &GctlRequest, op: F) -> Result<GctlResponse, Error>
+where
+    F: FnOnce() -> Result<GctlResponse, Error>,
+{
+    let hook = match &*HOOK.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(hook) => hook.clone(),
+        None => return op(),
+    };
+    hook.before(req)?;
+    let res = op();
+    hook.after(req, res.as_ref());
+    return res;
+}
+
+/// How this library carries out control operations; see `set_backend()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    /// Send control requests to `/dev/geom.ctl`, and memory disk requests to `/dev/mdctl` (the
+    /// default).
+    Kernel,
+    /// Run the base system's utilities instead, for environments where issuing those ioctls
+    /// directly is undesirable: `geom(8)` (which is what `gpart(8)`, `geli(8)`, ... run) for
+    /// control requests, and `mdconfig(8)` for memory disks.  The parameters are the same;
+    /// errors the utilities print are parsed back into `CtlError`s.
+    ///
+    /// Requests that cannot be expressed on a command line fail (see `GctlRequest::command()`),
+    /// notably `eli::attach()`.  Operations that write metadata to providers directly
+    /// (`eli::init()`, `concat::label()`, `stripe::label()`) are not affected.
+    Command,
+}
+
+/// The backend selected with `set_backend()`.
+static BACKEND: RwLock<Backend> = RwLock::new(Backend::Kernel);
+
+/// Selects the backend for every thread, and returns the one it replaces.
+pub fn set_backend(backend: Backend) -> Backend {
+    let mut current = BACKEND.write().unwrap_or_else(|e| e.into_inner());
+    return std::mem::replace(&mut *current, backend);
+}
+
+/// The backend in effect; see `set_backend()`.
+pub fn backend() -> Backend {
+    return *BACKEND.read().unwrap_or_else(|e| e.into_inner());
+}
+
+/// The generic GEOM utility, which `gpart(8)`, `geli(8)`, ... are links to.
+pub(crate) const GEOM_PATH: &str = "/sbin/geom";
+
+/// The `geom(8)` options for control request parameters, by class: (parameter, option letter),
+/// from the class modules in `lib/geom` (e.g., `geom_part.c`).  `Int` parameters are boolean
+/// options, given without a value.
+const COMMAND_OPTIONS: &[(&str, &[(&str, char)])] = &[
+    ("CONCAT", &[("hardcode", 'h')]),
+    (
+        "ELI",
+        &[
+            ("detach", 'd'),
+            ("dryrun", 'C'),
+            ("force", 'f'),
+            ("last", 'l'),
+            ("readonly", 'r'),
+        ],
+    ),
+    ("MULTIPATH", &[("active_active", 'A'), ("active_read", 'R')]),
+    (
+        "NOP",
+        &[
+            ("count_until_fail", 'c'),
+            ("delaymsec", 'd'),
+            ("error", 'e'),
+            ("force", 'f'),
+            ("gnopname", 'Z'),
+            ("offset", 'o'),
+            ("physpath", 'z'),
+            ("rdelayprob", 'q'),
+            ("rfailprob", 'r'),
+            ("secsize", 'S'),
+            ("size", 's'),
+            ("stripeoffset", 'P'),
+            ("stripesize", 'p'),
+            ("wdelayprob", 'x'),
+            ("wfailprob", 'w'),
+        ],
+    ),
+    (
+        "PART",
+        &[
+            ("alignment", 'a'),
+            ("attrib", 'a'),
+            ("bootcode", 'b'),
+            ("entries", 'n'),
+            ("flags", 'f'),
+            ("force", 'F'),
+            ("index", 'i'),
+            ("label", 'l'),
+            ("partcode", 'p'),
+            ("scheme", 's'),
+            ("size", 's'),
+            ("start", 'b'),
+            ("type", 't'),
+        ],
+    ),
+    ("STRIPE", &[("hardcode", 'h'), ("stripesize", 's')]),
+];
+
+/// Runs a utility for `Backend::Command`, and returns its standard output.  If it fails, the
+/// last line of its standard error becomes the `CtlError`; see `command_error()`.
+pub(crate) fn run_command(argv: &[String]) -> Result<Vec<u8>, Error> {
+    let output = std::process::Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(std::process::Stdio::null())
+        .output()?;
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let program = argv[0].rsplit('/').next().unwrap_or_default();
+    return Err(command_error(program, &String::from_utf8_lossy(&output.stderr)).into());
+}
+
+/// Parses the error a utility printed, e.g., `"geom: index '9': Invalid argument"`: the
+/// program name is dropped, and a trailing `strerror(3)` message becomes the `errno`.
+fn command_error(program: &str, stderr: &str) -> CtlError {
+    let line = stderr
+        .lines()
+        .map(str::trim)
+        .rfind(|l| !l.is_empty())
+        .unwrap_or_default();
+    let msg = line
+        .strip_prefix(program)
+        .and_then(|l| l.strip_prefix(": "))
+        .unwrap_or(line);
+    for (errno, _, desc) in ERRNOS {
+        if msg == *desc {
+            return CtlError::new(*errno, String::new());
+        }
+        if let Some(rest) = msg.strip_suffix(desc).and_then(|m| m.strip_suffix(": ")) {
+            return CtlError::new(*errno, rest.to_owned());
+        }
+    }
+    return CtlError::other(msg.to_owned());
+}
+
+/// What a control operation did.  Returned by the wrappers in `part`, `eli`, `nop`, ...: the
+/// provider the operation created, if any, and the graph it was checked against.  Callers that
+/// want to see the new state (e.g., a new provider's `Edge`) without another `get_graph()` can
+/// `refresh()` it, which also finds what changed.
+#[derive(Clone, Debug)]
+pub struct CtlResult {
+    /// The provider the operation created, as the kernel (or `mdconfig(8)`) reported it, e.g.,
+    /// `"da0p1"` from `part::add()` or `"md3"` from `md::create()`; `None` for other operations,
+    /// and in dry-run mode (see `CtlPolicy`)
+    pub provider: Option<String>,
+    /// The graph re-fetched after the operation, once `refreshed`; until then, the graph from
+    /// before the operation
+    pub graph: Graph,
+    /// Whether `graph` was re-fetched after the operation; see `CtlResult::refresh()`
+    pub refreshed: bool,
+    /// The geoms in `graph` that were not in the graph before the operation (matched as by
+    /// `Graph::diff()`), e.g., a new partition's DEV geom; empty unless `refreshed`
+    pub new_graph_nodes: Vec<NodeId>,
+    /// The providers in `graph` that did not exist before the operation, by name, e.g.,
+    /// `"da0p2"`; empty unless `refreshed`
+    pub new_providers: Vec<String>,
+    /// Every change from the graph before the operation; empty unless `refreshed`
+    pub delta: GraphDelta,
+}
+
+impl CtlResult {
+    /// The result of an operation checked against `graph`, which created `provider`, if any.
+    pub(crate) fn unrefreshed(graph: Graph, provider: Option<String>) -> CtlResult {
+        return CtlResult {
+            provider,
+            graph,
+            refreshed: false,
+            new_graph_nodes: Vec::new(),
+            new_providers: Vec::new(),
+            delta: GraphDelta::default(),
+        };
+    }
+
+    /// Compares the graph `before` an operation with the one `after` it.
+    pub(crate) fn new(before: &Graph, after: Graph) -> CtlResult {
+        let delta = Graph::diff(before, &after);
+        let keys = geom_keys(&after);
+        let added: BTreeSet<&GeomKey> = delta
+            .changes
+            .iter()
+            .filter_map(|c| match c {
+                Change::GeomAdded(key) => Some(key),
+                _ => None,
+            })
+            .collect();
+        let new_graph_nodes = keys
+            .iter()
+            .filter(|(_, key)| added.contains(key))
+            .map(|(id, _)| *id)
+            .collect();
+        let old_providers: BTreeSet<&str> = before
+            .edges
+            .values()
+            .map(|e| e.name.as_str())
+            .chain(before.orphans.values().map(|o| o.name.as_str()))
+            .collect();
+        let mut new_providers: Vec<String> = after
+            .edges
+            .values()
+            .map(|e| e.name.as_str())
+            .chain(after.orphans.values().map(|o| o.name.as_str()))
+            .filter(|name| !old_providers.contains(name))
+            .map(|name| name.to_owned())
+            .collect();
+        new_providers.sort_unstable();
+        new_providers.dedup();
+        return CtlResult {
+            provider: None,
+            graph: after,
+            refreshed: true,
+            new_graph_nodes,
+            new_providers,
+            delta,
+        };
+    }
+
+    /// Re-fetches the graph, and finds what changed since the graph the operation was checked
+    /// against, e.g., the new partition's geoms and `Edge`s.  Changes made by others in the
+    /// meantime are included too.  Returns the result as is if it was already refreshed.
+    #[cfg(target_os = "freebsd")]
+    pub fn refresh(self) -> Result<CtlResult, Error> {
+        if self.refreshed {
+            return Ok(self);
+        }
+        let mut result = CtlResult::new(&self.graph, crate::get_graph()?);
+        result.provider = self.provider;
+        return Ok(result);
+    }
+
+    /// The geoms in `graph` that the operation added or changed, or that gained, lost, or
+    /// changed a provider or consumer; empty unless `refreshed`.
+    pub fn affected_nodes(&self) -> BTreeSet<NodeId> {
+        let ids: BTreeMap<GeomKey, NodeId> = geom_keys(&self.graph)
+            .into_iter()
+            .map(|(id, key)| (key, id))
+            .collect();
+        let provider_geom = |name: &str| {
+            self.graph
+                .edges
+                .values()
+                .map(|e| (&e.name, e.provider_geom))
+                .chain(
+                    self.graph
+                        .orphans
+                        .values()
+                        .map(|o| (&o.name, o.provider_geom)),
+                )
+                .find(|(n, _)| *n == name)
+                .map(|(_, id)| id)
+        };
+        let mut affected = BTreeSet::new();
+        for change in &self.delta.changes {
+            let (geom, provider) = match change {
+                Change::GeomRemoved(_) => (None, None),
+                Change::GeomAdded(key) | Change::GeomMetadata { geom: key, .. } => {
+                    (Some(key), None)
+                }
+                Change::EdgeAdded(edge)
+                | Change::EdgeRemoved(edge)
+                | Change::ConsumerMode { edge, .. } => (Some(&edge.consumer), Some(&edge.provider)),
+                Change::Mediasize { provider, .. }
+                | Change::Mode { provider, .. }
+                | Change::EdgeMetadata { provider, .. }
+                | Change::Withered { provider, .. } => (None, Some(provider)),
+            };
+            affected.extend(geom.and_then(|k| ids.get(k)).copied());
+            affected.extend(provider.and_then(|p| provider_geom(p)));
+        }
+        return affected;
+    }
+
+    /// The trees of `graph` containing the `affected_nodes()`, as a self-contained `Graph`; e.g.,
+    /// after adding a partition, the whole disk.  Empty if nothing in the graph changed.
+    pub fn subtree(&self) -> Graph {
+        let mut members = BTreeSet::new();
+        for id in self.affected_nodes() {
+            if !members.contains(&id) {
+                members.extend(self.graph.component(&id));
+            }
+        }
+        return self.graph.induced(&members);
+    }
+
+    /// The `Edge`s (one per consumer) of the new provider `name`, e.g., a new partition.
+    pub fn new_provider_edges<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = (&'a EdgeId, &'a Edge)> + 'a {
+        return self.graph.edges.iter().filter(move |(_, e)| e.name == name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctl::{
+        audited, check_policy, command_error, dry_run, run_command, set_hook, verb_request,
+        CtlError, CtlErrorKind, CtlHook, CtlPolicy, CtlResult, GctlRequest, GctlResponse, Param,
+        ParamValue, GEOM_CTL,
+    };
+    use crate::{graph, raw, Error, GeomClass};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn build() {
+        let req = GctlRequest::new("PART", "add")
+            .args(&["da0"])
+            .string("type", "freebsd-ufs")
+            .number("index", 2)
+            .output("output", 1024)
+            .string("type", "freebsd-swap");
+        assert_eq!(req.class(), "PART");
+        assert_eq!(req.verb(), "add");
+        assert_eq!(req.get("nargs"), Some(&ParamValue::Int(1)));
+        assert_eq!(req.get("output"), None);
+        let names: Vec<&str> = req.params().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["arg0", "nargs", "type", "index"]);
+        assert_eq!(
+            req.to_string(),
+            "PART add arg0=\"da0\" nargs=1 type=\"freebsd-swap\" index=2"
+        );
+        assert!(req.validate().is_ok());
+
+        let bad = GctlRequest::new("PART", "add").string("type", "a\0b");
+        assert!(matches!(bad.validate(), Err(Error::Ctl(_))));
+
+        // Key material is not printed.
+        let attach = GctlRequest::new("ELI", "attach").binary("key", &[0xa5; 64]);
+        assert!(attach.to_string().ends_with(" key=<64 bytes>"));
+        assert!(format!("{:?}", attach).contains("Binary(<64 bytes>)"));
+        assert!(!format!("{:?}", attach).contains("165"));
+    }
+
+    #[test]
+    fn verb() {
+        let mut params = BTreeMap::new();
+        params.insert("version".to_owned(), ParamValue::Int(4));
+        params.insert("arg0".to_owned(), ParamValue::String("md0.nop".into()));
+        params.insert("arg1".to_owned(), ParamValue::String("md1.nop".into()));
+        params.insert("arg3".to_owned(), ParamValue::String("ignored".into()));
+        let req = verb_request("NOP", "reset", &params);
+        assert_eq!((req.class(), req.verb()), ("NOP", "reset"));
+        assert_eq!(req.get("nargs"), Some(&ParamValue::Int(2)));
+        assert_eq!(req.get("version"), Some(&ParamValue::Int(4)));
+        assert!(req
+            .params
+            .contains(&("output".to_owned(), Param::Out(4096))));
+
+        params.insert("nargs".to_owned(), ParamValue::Int(4));
+        let req = verb_request("NOP", "reset", &params);
+        assert_eq!(req.get("nargs"), Some(&ParamValue::Int(4)));
+        assert_eq!(
+            verb_request("NOP", "list", &BTreeMap::new())
+                .params()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn policy() {
+        let destroy = GctlRequest::new("PART", "destroy")
+            .args(&["da0"])
+            .int("force", 1);
+        let add = GctlRequest::new("PART", "add").args(&["da0"]);
+        assert!(destroy.is_destructive());
+        assert!(!add.is_destructive());
+        assert!(GctlRequest::new("PART", "resize").is_destructive());
+        assert!(GctlRequest::new("ELI", "delkey").is_destructive());
+        assert_eq!(destroy.confirmation_token(), "PART destroy da0");
+        assert_eq!(
+            destroy.to_string(),
+            r#"PART destroy arg0="da0" nargs=1 force=1"#
+        );
+
+        // Without a policy, everything goes.
+        assert!(check_policy(&destroy).unwrap());
+
+        let policy = CtlPolicy {
+            dry_run: true,
+            require_confirmation: true,
+            ..Default::default()
+        };
+        assert!(!dry_run());
+        let (res, requests) = policy.apply(|| {
+            assert!(dry_run());
+            assert!(!check_policy(&add).unwrap());
+            return check_policy(&destroy);
+        });
+        match res {
+            Err(Error::Ctl(e)) => assert_eq!(e.kind(), CtlErrorKind::PermissionDenied),
+            _ => panic!(),
+        }
+        assert_eq!(requests, vec![add.clone()]);
+        assert!(check_policy(&destroy).unwrap());
+
+        let policy = CtlPolicy {
+            require_confirmation: true,
+            ..Default::default()
+        }
+        .confirm("PART destroy da0");
+        let dry = CtlPolicy {
+            dry_run: true,
+            ..Default::default()
+        };
+        let (res, requests) = policy.apply(|| {
+            let (inner, _) = dry.apply(|| check_policy(&destroy).unwrap());
+            return (inner, check_policy(&destroy).unwrap());
+        });
+        assert_eq!(res, (false, true));
+        assert_eq!(requests, vec![destroy]);
+
+        // Recorded requests don't keep keys.
+        let attach = GctlRequest::new("ELI", "attach").binary("key", &[0xa5; 64]);
+        let (_, requests) = dry.apply(|| check_policy(&attach));
+        assert_eq!(
+            requests[0].get("key"),
+            Some(&ParamValue::Binary(vec![0; 64]))
+        );
+    }
+
+    #[test]
+    fn command() {
+        let add = GctlRequest::new("PART", "add")
+            .args(&["da0"])
+            .string("flags", "C")
+            .string("type", "freebsd-ufs")
+            .number("index", 2)
+            .output("output", 4096);
+        assert_eq!(
+            add.command().unwrap(),
+            vec![
+                "/sbin/geom",
+                "part",
+                "add",
+                "-f",
+                "C",
+                "-t",
+                "freebsd-ufs",
+                "-i",
+                "2",
+                "--",
+                "da0"
+            ]
+        );
+        let destroy = GctlRequest::new("NOP", "destroy")
+            .int("version", 4)
+            .args(&["md0.nop", "md1.nop"])
+            .int("force", 1);
+        assert_eq!(
+            destroy.command().unwrap(),
+            vec![
+                "/sbin/geom",
+                "nop",
+                "destroy",
+                "-f",
+                "--",
+                "md0.nop",
+                "md1.nop"
+            ]
+        );
+        let attach = GctlRequest::new("ELI", "attach")
+            .args(&["ada0p4"])
+            .int("readonly", 0)
+            .binary("key", &[0; 64]);
+        assert!(matches!(attach.command(), Err(Error::Ctl(e)) if e.message.contains("key")));
+        let unknown = GctlRequest::new("PART", "add").string("frobnicate", "yes");
+        assert!(unknown.command().is_err());
+
+        let e = command_error("geom", "geom: index '9': Invalid argument\n");
+        assert_eq!(e, CtlError::new(22, "index '9'".to_owned()));
+        let e = command_error("mdconfig", "mdconfig: ioctl(/dev/mdctl): Device busy\n");
+        assert_eq!(e.kind(), CtlErrorKind::Busy);
+        assert_eq!(e.message, "ioctl(/dev/mdctl)");
+        assert_eq!(
+            command_error("geom", "warning\ngeom: Device busy\n\n"),
+            CtlError::new(16, String::new())
+        );
+        assert_eq!(
+            command_error("geom", "Provider da9 is invalid."),
+            CtlError::other("Provider da9 is invalid.".to_owned())
+        );
+
+        let argv: Vec<String> = vec![
+            "/bin/sh".into(),
+            "-c".into(),
+            "echo sh: File exists >&2; exit 1".into(),
+        ];
+        match run_command(&argv) {
+            Err(Error::Ctl(e)) => assert_eq!(e.kind(), CtlErrorKind::AlreadyExists),
+            other => panic!("{:?}", other),
+        }
+        let argv: Vec<String> = vec!["/bin/sh".into(), "-c".into(), "echo da0p1 added".into()];
+        assert_eq!(run_command(&argv).unwrap(), b"da0p1 added\n");
+    }
+
+    #[test]
+    fn hook() {
+        /// Records each operation, and vetoes those of the class `"VETO"`.
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+        impl CtlHook for Recorder {
+            fn before(&self, req: &GctlRequest) -> Result<(), Error> {
+                self.0.lock().unwrap().push(format!("before {}", req));
+                if req.class() == "VETO" {
+                    return Err(CtlError::new(super::EPERM, "vetoed".to_owned()).into());
+                }
+                return Ok(());
+            }
+            fn after(&self, req: &GctlRequest, result: Result<&GctlResponse, &Error>) {
+                let outcome = match result {
+                    Ok(_) => "ok".to_owned(),
+                    Err(e) => e.to_string(),
+                };
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("after {}: {}", req, outcome));
+            }
+        }
+
+        let recorder = std::sync::Arc::new(Recorder::default());
+        assert!(set_hook(Some(recorder.clone())).is_none());
+        let add = GctlRequest::new("PART", "add").args(&["da0"]);
+        assert!(audited(&add, || Ok(GctlResponse::default())).is_ok());
+        let res = audited(&add, || Err(CtlError::parse("28").into()));
+        assert!(matches!(res, Err(Error::Ctl(_))));
+        let veto = GctlRequest::new("VETO", "destroy");
+        let res = audited(&veto, || panic!("vetoed operation was carried out"));
+        assert!(matches!(res, Err(Error::Ctl(e)) if e.message == "vetoed"));
+        assert!(set_hook(None).is_some());
+        assert!(audited(&veto, || Ok(GctlResponse::default())).is_ok());
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "before PART add arg0=\"da0\" nargs=1",
+                "after PART add arg0=\"da0\" nargs=1: ok",
+                "before PART add arg0=\"da0\" nargs=1",
+                "after PART add arg0=\"da0\" nargs=1: Ctl: No space left on device",
+                "before VETO destroy",
+            ]
+        );
+    }
+
+    #[test]
+    fn result() {
+        const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+        // A NOP device over nvd0p1, and its DEV geom.
+        const NOP_XML: &str = r#"<class id="0x10">
+            <name>NOP</name>
+            <geom id="0x11">
+              <class ref="0x10"/>
+              <name>nvd0p1.nop</name>
+              <rank>3</rank>
+              <consumer id="0x12">
+                <geom ref="0x11"/>
+                <provider ref="0xfffff804947b4100"/>
+                <mode>r0w0e0</mode>
+              </consumer>
+              <provider id="0x13">
+                <geom ref="0x11"/>
+                <mode>r0w0e0</mode>
+                <name>nvd0p1.nop</name>
+                <mediasize>209715200</mediasize>
+                <sectorsize>512</sectorsize>
+                <stripesize>0</stripesize>
+                <stripeoffset>0</stripeoffset>
+              </provider>
+            </geom>
+          </class>
+          <class id="0x20">
+            <name>DEV</name>
+            <geom id="0x21">
+              <class ref="0x20"/>
+              <name>nvd0p1.nop</name>
+              <rank>4</rank>
+              <consumer id="0x22">
+                <geom ref="0x21"/>
+                <provider ref="0x13"/>
+                <mode>r0w0e0</mode>
+              </consumer>
+            </geom>
+          </class>
+        </mesh>"#;
+        let decode = |xml: &str| graph::decode_graph(&raw::parse_xml(xml).unwrap()).unwrap();
+        let before = decode(SAMPLE_XML);
+        let after = decode(&SAMPLE_XML.replace("</mesh>", NOP_XML));
+
+        let res = CtlResult::new(&before, after);
+        assert_eq!(res.new_providers, vec!["nvd0p1.nop"]);
+        let mut new: Vec<_> = res
+            .new_graph_nodes
+            .iter()
+            .map(|id| &res.graph.nodes[id].class)
+            .collect();
+        new.sort_by_key(|c| c.as_ref().to_owned());
+        assert_eq!(new, vec![&GeomClass::DEV, &GeomClass::NOP]);
+        assert_eq!(res.new_provider_edges("nvd0p1.nop").count(), 1);
+
+        // The NOP geom, its DEV geom, and the partition table it consumes from.
+        let affected = res.affected_nodes();
+        assert_eq!(affected.len(), 3);
+        let (nvd0, _) = res
+            .graph
+            .find_by_class_name(&GeomClass::DISK, "nvd0")
+            .unwrap();
+        let subtree = res.subtree();
+        assert!(subtree.nodes.contains_key(nvd0));
+        assert!(subtree.nodes.len() < res.graph.nodes.len());
+
+        let res = CtlResult::new(&before, before.clone());
+        assert!(res.delta.is_empty() && res.new_graph_nodes.is_empty());
+        assert!(res.subtree().nodes.is_empty());
+
+        // The wrappers' results, until refreshed.
+        let res = CtlResult::unrefreshed(before, Some("nvd0p1.nop".to_owned()));
+        assert!(!res.refreshed && res.new_providers.is_empty());
+        assert!(res.affected_nodes().is_empty());
+    }
+
+    #[test]
+    fn encode() {
+        let (bytes, flag) = ParamValue::String("da0".to_owned()).encode();
+        assert_eq!((&bytes[..], flag), (&b"da0\0"[..], 5));
+        let (bytes, flag) = ParamValue::Number(1).encode();
+        assert_eq!((bytes.len(), flag), (8, 1));
+        let (bytes, _) = ParamValue::Int(1).encode();
+        assert_eq!(bytes.len(), 4);
+    }
+
+    #[test]
+    fn errors() {
+        let e = CtlError::parse("22 index '9'");
+        assert_eq!((e.errno, &e.message[..]), (Some(22), "index '9'"));
+        assert_eq!(e.kind(), CtlErrorKind::InvalidArgument);
+        assert_eq!(e.to_string(), "index '9': Invalid argument");
+
+        let e = CtlError::parse("16");
+        assert_eq!(e.kind(), CtlErrorKind::Busy);
+        assert_eq!(e.to_string(), "Device busy");
+
+        let e = CtlError::parse("Provider ada9 not found.");
+        assert_eq!(e.errno, None);
+        assert_eq!(e.kind(), CtlErrorKind::Other);
+        assert_eq!(e.to_string(), "Provider ada9 not found.");
+        assert_eq!(
+            CtlError::parse("999 x").to_string(),
+            "x: Unknown error: 999"
+        );
+    }
+
+    #[test]
+    fn response() {
+        let mut resp = GctlResponse::default();
+        let mut buf = b"da0p1 added\n".to_vec();
+        buf.resize(1024, 0);
+        resp.outputs.insert("output".to_owned(), buf);
+        assert_eq!(resp.get_str("output"), Some("da0p1 added\n"));
+        assert_eq!(resp.get("output").unwrap().len(), 1024);
+        assert_eq!(resp.get_str("missing"), None);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn abi() {
+        assert_eq!(std::mem::size_of::<super::GctlReqArg>(), 48);
+        assert_eq!(std::mem::size_of::<super::GctlReq>(), 64);
+        assert_eq!(GEOM_CTL, 0x8040_4702);
+    }
+}
@@ -0,0 +1,252 @@
+//! Parsing of `devctl(4)` notifications.
+//!
+//! The kernel reports device events to `devd(8)` through `/dev/devctl` as one line of text each,
+//! which `devd(8)` relays to its socket clients.  `parse()` decodes such a line, however it was
+//! obtained; `watch::GeomEvent::from_notify()` picks out the GEOM events.
+//!
+//! # Examples
+//!
+//! ```
+//! use freebsd_geom as geom;
+//!
+//! let msg = geom::devctl::parse("!system=GEOM subsystem=DEV type=CREATE cdev=ada0p3").unwrap();
+//! if let geom::devctl::Message::Notify(n) = msg {
+//!     assert_eq!(n.system, "GEOM");
+//!     assert_eq!(n.get("cdev"), Some("ada0p3"));
+//! }
+//! ```
+use std::collections::BTreeMap;
+
+/// A `devctl(4)` notification (`!system=... subsystem=... type=...`).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Notify {
+    /// E.g., `"GEOM"`, `"DEVFS"`, `"IFNET"`
+    pub system: String,
+    /// E.g., `"DEV"` for `system=GEOM`
+    pub subsystem: String,
+    /// The `type=` of the notification, e.g., `"CREATE"`
+    pub kind: String,
+    /// Any other `key=value` pairs, e.g., `cdev`
+    pub attrs: BTreeMap<String, String>,
+}
+
+impl Notify {
+    /// The value of the attribute `key`, if present.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        return self.attrs.get(key).map(|s| &s[..]);
+    }
+}
+
+/// A device attach, detach, or unmatched-device event (`+umass0 at bus=0 ... on uhub0`).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Device {
+    /// The device name, e.g., `"umass0"`.  Empty for `Message::NoMatch`.
+    pub device: String,
+    /// The `key=value` pairs following `at`, e.g., `sernum`
+    pub attrs: BTreeMap<String, String>,
+    /// The parent device (following `on`), e.g., `"uhub0"`
+    pub parent: Option<String>,
+}
+
+/// A `devctl(4)` message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Message {
+    /// `!`: a notification from some subsystem.  GEOM events are of this kind.
+    Notify(Notify),
+    /// `+`: a device attached.
+    Attach(Device),
+    /// `-`: a device detached.
+    Detach(Device),
+    /// `?`: no driver attached to a new device.
+    NoMatch(Device),
+}
+
+/// Splits `key=value` pairs, some of whose values may be double-quoted (the kernel escapes `"` and
+/// `\` within them with a backslash).  Stops at the first word that is not a pair.  Returns the
+/// pairs and the remaining input.
+fn attributes(s: &str) -> (BTreeMap<String, String>, &str) {
+    let mut result = BTreeMap::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let eq = match rest[..word_end].find('=') {
+            Some(eq) => eq,
+            None => break,
+        };
+        let key = &rest[..eq];
+        let after = &rest[eq + 1..];
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => unquote(quoted),
+            None => match after.find(char::is_whitespace) {
+                Some(end) => (after[..end].to_owned(), &after[end..]),
+                None => (after.to_owned(), ""),
+            },
+        };
+        result.insert(key.to_owned(), value);
+        rest = next.trim_start();
+    }
+    return (result, rest);
+}
+
+/// Decodes a quoted value, up to its closing quote (or the end of `s`).  Returns the value and the
+/// input following the quote.
+fn unquote(s: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, &s[i + 1..]),
+            '\\' => match chars.next() {
+                Some((_, escaped)) => value.push(escaped),
+                None => value.push(c),
+            },
+            _ => value.push(c),
+        }
+    }
+    return (value, "");
+}
+
+/// If `s` starts with the word `keyword`, returns what follows it.
+fn strip_keyword<'a>(s: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = s.strip_prefix(keyword)?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        return Some(rest);
+    }
+    return None;
+}
+
+/// Parses `<device> at <attrs> on <parent>`, any part of which may be missing.
+fn parse_device(s: &str) -> Device {
+    let s = s.trim();
+    let (device, mut rest) = match s.find(char::is_whitespace) {
+        Some(end) => (&s[..end], s[end..].trim_start()),
+        None => (s, ""),
+    };
+    // `?` messages have no device name.
+    let device = if device == "at" {
+        rest = s;
+        ""
+    } else {
+        device
+    };
+    let mut attrs = BTreeMap::new();
+    if let Some(after) = strip_keyword(rest, "at") {
+        let (a, r) = attributes(after);
+        attrs = a;
+        rest = r;
+    }
+    let parent = strip_keyword(rest, "on")
+        .map(|p| p.trim().to_owned())
+        .filter(|p| !p.is_empty());
+    return Device {
+        device: device.to_owned(),
+        attrs,
+        parent,
+    };
+}
+
+/// Parses one `devctl(4)` message, with or without its trailing newline.  Returns `None` if it
+/// is not of a known kind, or a notification lacks `system`, `subsystem`, or `type`.
+pub fn parse(msg: &str) -> Option<Message> {
+    let msg = msg.trim_end();
+    let mut chars = msg.chars();
+    let kind = chars.next()?;
+    let body = chars.as_str();
+    let result = match kind {
+        '!' => {
+            let (mut attrs, _) = attributes(body);
+            Message::Notify(Notify {
+                system: attrs.remove("system")?,
+                subsystem: attrs.remove("subsystem")?,
+                kind: attrs.remove("type")?,
+                attrs,
+            })
+        }
+        '+' => Message::Attach(parse_device(body)),
+        '-' => Message::Detach(parse_device(body)),
+        '?' => Message::NoMatch(parse_device(body)),
+        _ => return None,
+    };
+    return Some(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::devctl::{attributes, parse, Device, Message, Notify};
+
+    #[test]
+    fn notify() {
+        let msg = parse("!system=GEOM subsystem=DEV type=CREATE cdev=ada0p3\n").unwrap();
+        let mut expected = Notify {
+            system: "GEOM".to_owned(),
+            subsystem: "DEV".to_owned(),
+            kind: "CREATE".to_owned(),
+            ..Default::default()
+        };
+        expected
+            .attrs
+            .insert("cdev".to_owned(), "ada0p3".to_owned());
+        assert_eq!(msg, Message::Notify(expected));
+
+        assert_eq!(parse("!system=GEOM subsystem=DEV"), None);
+        assert_eq!(parse("hello"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn devices() {
+        let msg = parse("+umass0 at bus=0 sernum=\"AA 123\" intclass=0x08 on uhub0").unwrap();
+        let dev = match msg {
+            Message::Attach(dev) => dev,
+            _ => panic!("{:?}", msg),
+        };
+        assert_eq!(dev.device, "umass0");
+        assert_eq!(dev.attrs["sernum"], "AA 123");
+        assert_eq!(dev.attrs["intclass"], "0x08");
+        assert_eq!(dev.parent.as_deref(), Some("uhub0"));
+
+        assert_eq!(
+            parse("-da0").unwrap(),
+            Message::Detach(Device {
+                device: "da0".to_owned(),
+                ..Default::default()
+            })
+        );
+        let msg = parse("? at vendor=0x8086 on pci0").unwrap();
+        assert!(matches!(
+            msg,
+            Message::NoMatch(Device { ref device, ref parent, .. })
+                if device.is_empty() && parent.as_deref() == Some("pci0")
+        ));
+    }
+
+    #[test]
+    fn quoted_attributes() {
+        let (attrs, rest) = attributes(r#"system=USB type="ATTACH now" vendor=0x0781 on x"#);
+        assert_eq!(attrs["type"], "ATTACH now");
+        assert_eq!(attrs["vendor"], "0x0781");
+        assert_eq!(rest, "on x");
+
+        let (attrs, rest) = attributes(r#"desc="say \"hi\" C:\\" x=1 on y"#);
+        assert_eq!(attrs["desc"], r#"say "hi" C:\"#);
+        assert_eq!(attrs["x"], "1");
+        assert_eq!(rest, "on y");
+    }
+
+    #[test]
+    fn keywords() {
+        // `at` and `on` are whole words; a device named like them is not mistaken for them.
+        let msg = parse("+atp0 attrs=1 on usbus0").unwrap();
+        assert!(matches!(
+            msg,
+            Message::Attach(Device { ref device, ref attrs, ref parent })
+                if device == "atp0" && attrs.is_empty() && parent.is_none()
+        ));
+        let msg = parse("-da0 at bus=0 onward").unwrap();
+        assert!(matches!(
+            msg,
+            Message::Detach(Device { ref attrs, ref parent, .. })
+                if attrs["bus"] == "0" && parent.is_none()
+        ));
+    }
+}
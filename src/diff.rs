@@ -0,0 +1,302 @@
+//! Differences between two snapshots of a graph.
+//!
+//! Kernel pointers (`NodeId`, `EdgeId`) are reused as geoms come and go, and differ across reboots,
+//! so `Graph::diff()` matches geoms by class and name, and providers by name, instead.
+use crate::graph::{Edge, EdgeMetadata, GeomClass, GeomMetadata, Graph, Mode, NodeId};
+use std::collections::BTreeMap;
+
+/// Identifies a `Geom` across snapshots.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct GeomKey {
+    pub class: GeomClass,
+    pub name: String,
+    /// Distinguishes geoms of the same class and name (e.g., SWAP geoms, which are all named
+    /// `"swap"`): their rank when ordered by the names of the providers they consume.  Zero for
+    /// most geoms.
+    pub ordinal: usize,
+}
+
+/// Identifies an `Edge` across snapshots: a provider, by name, and the geom consuming it.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct EdgeKey {
+    pub provider: String,
+    pub consumer: GeomKey,
+}
+
+/// A difference between two graphs; see `Graph::diff()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    GeomAdded(GeomKey),
+    GeomRemoved(GeomKey),
+    /// The `Geom::metadata` of a geom changed, e.g., a partition table was modified, or a
+    /// `geli(8)` provider was suspended.  Changes to DEV metadata are reported as
+    /// `Change::ConsumerMode`.
+    GeomMetadata {
+        geom: GeomKey,
+        old: Option<Box<GeomMetadata>>,
+        new: Option<Box<GeomMetadata>>,
+    },
+    EdgeAdded(EdgeKey),
+    EdgeRemoved(EdgeKey),
+    /// A provider was resized
+    Mediasize {
+        provider: String,
+        old: u64,
+        new: u64,
+    },
+    /// A provider's mode changed, e.g., it was opened for writing
+    Mode {
+        provider: String,
+        old: Mode,
+        new: Mode,
+    },
+    /// The access held by a single consumer changed
+    ConsumerMode {
+        edge: EdgeKey,
+        old: Mode,
+        new: Mode,
+    },
+    /// A provider's `Edge::metadata` changed, e.g., a partition entry was relabeled
+    EdgeMetadata {
+        provider: String,
+        old: Option<Box<EdgeMetadata>>,
+        new: Option<Box<EdgeMetadata>>,
+    },
+    /// A provider started withering (or, unusually, stopped)
+    Withered {
+        provider: String,
+        withered: bool,
+    },
+}
+
+/// The changes from one graph to another, as computed by `Graph::diff()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphDelta {
+    /// Removed geoms come first, then added geoms, changed geoms, removed and added edges, and
+    /// changed providers.
+    pub changes: Vec<Change>,
+}
+
+impl GraphDelta {
+    /// True if the graphs are equivalent.
+    pub fn is_empty(&self) -> bool {
+        return self.changes.is_empty();
+    }
+}
+
+/// Geoms sharing a class and name, with the names of the providers they consume.
+type SameName<'a> = Vec<(Vec<&'a str>, NodeId)>;
+
+/// Assigns every geom in `g` a `GeomKey`.
+pub(crate) fn geom_keys(g: &Graph) -> BTreeMap<NodeId, GeomKey> {
+    let mut groups: BTreeMap<(&GeomClass, &str), SameName> = BTreeMap::new();
+    for (id, geom) in &g.nodes {
+        let mut consumes: Vec<&str> = g.parent_edges_iter(id).map(|(_, e)| &e.name[..]).collect();
+        consumes.sort_unstable();
+        groups
+            .entry((&geom.class, &geom.name))
+            .or_default()
+            .push((consumes, *id));
+    }
+    let mut result = BTreeMap::new();
+    for ((class, name), mut ids) in groups {
+        ids.sort();
+        for (ordinal, (_, id)) in ids.into_iter().enumerate() {
+            result.insert(
+                id,
+                GeomKey {
+                    class: class.clone(),
+                    name: name.to_owned(),
+                    ordinal,
+                },
+            );
+        }
+    }
+    return result;
+}
+
+/// The first `Edge` out of each provider, by provider name.
+fn providers(g: &Graph) -> BTreeMap<&str, &Edge> {
+    let mut result = BTreeMap::new();
+    for edge in g.edges.values() {
+        result.entry(&edge.name[..]).or_insert(edge);
+    }
+    return result;
+}
+
+impl Graph {
+    /// Compares two snapshots of a graph, e.g., taken before and after some event.
+    ///
+    /// Geoms are matched by `GeomKey`, edges by `EdgeKey`, and providers by name, so the
+    /// snapshots need not come from the same boot.  A renamed provider shows up as a removed edge
+    /// and an added one.  Providers without consumers (`Graph::orphan_providers()`) are not
+    /// compared.
+    pub fn diff(old: &Graph, new: &Graph) -> GraphDelta {
+        let mut changes = Vec::new();
+
+        let old_keys = geom_keys(old);
+        let new_keys = geom_keys(new);
+        let old_geoms: BTreeMap<&GeomKey, NodeId> = old_keys.iter().map(|(i, k)| (k, *i)).collect();
+        let new_geoms: BTreeMap<&GeomKey, NodeId> = new_keys.iter().map(|(i, k)| (k, *i)).collect();
+        for key in old_geoms.keys() {
+            if !new_geoms.contains_key(key) {
+                changes.push(Change::GeomRemoved((*key).clone()));
+            }
+        }
+        for key in new_geoms.keys() {
+            if !old_geoms.contains_key(key) {
+                changes.push(Change::GeomAdded((*key).clone()));
+            }
+        }
+        for (key, old_id) in &old_geoms {
+            let (old_geom, new_geom) = match new_geoms.get(key) {
+                Some(new_id) => (&old.nodes[old_id], &new.nodes[new_id]),
+                None => continue,
+            };
+            if key.class != GeomClass::DEV && old_geom.metadata != new_geom.metadata {
+                changes.push(Change::GeomMetadata {
+                    geom: (*key).clone(),
+                    old: old_geom.metadata.clone(),
+                    new: new_geom.metadata.clone(),
+                });
+            }
+        }
+
+        let edge_key = |keys: &BTreeMap<NodeId, GeomKey>, edge: &Edge| EdgeKey {
+            provider: edge.name.to_owned(),
+            consumer: keys[&edge.consumer_geom].clone(),
+        };
+        let old_edges: BTreeMap<EdgeKey, &Edge> = old
+            .edges
+            .values()
+            .map(|e| (edge_key(&old_keys, e), e))
+            .collect();
+        let new_edges: BTreeMap<EdgeKey, &Edge> = new
+            .edges
+            .values()
+            .map(|e| (edge_key(&new_keys, e), e))
+            .collect();
+        for key in old_edges.keys() {
+            if !new_edges.contains_key(key) {
+                changes.push(Change::EdgeRemoved(key.clone()));
+            }
+        }
+        for key in new_edges.keys() {
+            if !old_edges.contains_key(key) {
+                changes.push(Change::EdgeAdded(key.clone()));
+            }
+        }
+
+        let new_providers = providers(new);
+        for (name, old_edge) in providers(old) {
+            let new_edge = match new_providers.get(name) {
+                Some(e) => e,
+                None => continue,
+            };
+            if old_edge.mediasize != new_edge.mediasize {
+                changes.push(Change::Mediasize {
+                    provider: name.to_owned(),
+                    old: old_edge.mediasize,
+                    new: new_edge.mediasize,
+                });
+            }
+            if old_edge.mode != new_edge.mode {
+                changes.push(Change::Mode {
+                    provider: name.to_owned(),
+                    old: old_edge.mode,
+                    new: new_edge.mode,
+                });
+            }
+            if old_edge.metadata != new_edge.metadata {
+                changes.push(Change::EdgeMetadata {
+                    provider: name.to_owned(),
+                    old: old_edge.metadata.clone(),
+                    new: new_edge.metadata.clone(),
+                });
+            }
+            if old_edge.withered != new_edge.withered {
+                changes.push(Change::Withered {
+                    provider: name.to_owned(),
+                    withered: new_edge.withered,
+                });
+            }
+        }
+        for (key, old_edge) in &old_edges {
+            if let Some(new_edge) = new_edges.get(key) {
+                if old_edge.consumer_mode != new_edge.consumer_mode {
+                    changes.push(Change::ConsumerMode {
+                        edge: key.clone(),
+                        old: old_edge.consumer_mode,
+                        new: new_edge.consumer_mode,
+                    });
+                }
+            }
+        }
+
+        return GraphDelta { changes };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{Change, GeomKey};
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn identical() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        assert!(graph::Graph::diff(&g, &g).is_empty());
+
+        // Different kernel pointers, same topology.
+        let moved = SAMPLE_XML.replace("0xfffff8", "0xfffffe");
+        let g2 = graph::decode_graph(&raw::parse_xml(&moved).unwrap()).unwrap();
+        assert!(g2.nodes.keys().all(|id| !g.nodes.contains_key(id)));
+        assert!(graph::Graph::diff(&g, &g2).is_empty());
+    }
+
+    #[test]
+    fn changes() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let old = graph::decode_graph(&rawmesh).unwrap();
+        let mut new = old.clone();
+
+        // Resize and relabel nvd0p2, and drop its DEV geom.
+        let (eid, _) = new.edges.iter().find(|(_, e)| e.name == "nvd0p2").unwrap();
+        let eid = *eid;
+        let edge = new.edges.get_mut(&eid).unwrap();
+        edge.mediasize += 1024;
+        if let Some(graph::EdgeMetadata::PART(p)) = edge.metadata.as_deref_mut() {
+            p.label = Some("newlabel".to_owned());
+        }
+        let (dev, _) = new
+            .find_by_class_name(&graph::GeomClass::DEV, "nvd0p2")
+            .unwrap();
+        let dev = *dev;
+        new.nodes.remove(&dev);
+        new.edges.retain(|_, e| e.consumer_geom != dev);
+
+        let delta = graph::Graph::diff(&old, &new);
+        let dev_key = GeomKey {
+            class: graph::GeomClass::DEV,
+            name: "nvd0p2".to_owned(),
+            ordinal: 0,
+        };
+        assert_eq!(delta.changes[0], Change::GeomRemoved(dev_key.clone()));
+        assert!(delta
+            .changes
+            .iter()
+            .any(|c| matches!(c, Change::EdgeRemoved(k) if k.consumer == dev_key)));
+        assert!(delta.changes.contains(&Change::Mediasize {
+            provider: "nvd0p2".to_owned(),
+            old: old[eid].mediasize,
+            new: old[eid].mediasize + 1024,
+        }));
+        assert!(delta.changes.iter().any(|c| matches!(
+            c,
+            Change::EdgeMetadata { provider, .. } if provider == "nvd0p2"
+        )));
+        assert_eq!(delta.changes.len(), 4);
+    }
+}
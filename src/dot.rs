@@ -0,0 +1,149 @@
+//! Graphviz export.
+//!
+//! `Graph::to_dot()` renders the graph in the DOT language, with providers above their
+//! consumers, e.g., for `dot -Tsvg -o geom.svg`.
+use crate::graph::{GeomClass, Graph};
+use std::fmt::Write;
+
+/// Options for `Graph::to_dot()`.
+#[derive(Clone, Debug)]
+pub struct DotOptions {
+    /// Fill geoms with a color chosen by class
+    pub colors: bool,
+    /// Label edges with the provider name
+    pub edge_names: bool,
+    /// Label edges with the provider size
+    pub sizes: bool,
+    /// Omit DEV geoms, which otherwise double the number of nodes
+    pub hide_dev: bool,
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        Self {
+            colors: true,
+            edge_names: true,
+            sizes: true,
+            hide_dev: false,
+        }
+    }
+}
+
+fn class_color(class: &GeomClass) -> &'static str {
+    return match class {
+        GeomClass::DISK | GeomClass::MD | GeomClass::FD => "lightblue",
+        GeomClass::PART => "lightyellow",
+        GeomClass::LABEL => "palegreen",
+        GeomClass::DEV => "lightgrey",
+        GeomClass::VFS | GeomClass::SWAP => "orange",
+        GeomClass::ELI => "salmon",
+        GeomClass::ZFSVDEV | GeomClass::ZFSZVOL => "plum",
+        GeomClass::MIRROR
+        | GeomClass::RAID
+        | GeomClass::CONCAT
+        | GeomClass::STRIPE
+        | GeomClass::CCD
+        | GeomClass::VINUM => "lightcyan",
+        _ => "white",
+    };
+}
+
+/// Escapes a string for a double-quoted DOT ID.
+fn quote(s: &str) -> String {
+    return s.replace('\\', "\\\\").replace('"', "\\\"");
+}
+
+impl Graph {
+    /// Renders the graph as a Graphviz `digraph`.  Each geom is a node labeled with its class and
+    /// name; each `Edge` points from the provider's geom to the consumer's.
+    pub fn to_dot(&self, opts: &DotOptions) -> String {
+        let mut out = String::new();
+        // Writing to a String cannot fail.
+        let _ = self.write_dot(&mut out, opts);
+        return out;
+    }
+
+    fn write_dot(&self, out: &mut String, opts: &DotOptions) -> std::fmt::Result {
+        writeln!(out, "digraph geom {{")?;
+        writeln!(out, "\tnode [shape=box, fontname=\"monospace\"];")?;
+        for (id, geom) in &self.nodes {
+            if opts.hide_dev && geom.class == GeomClass::DEV {
+                continue;
+            }
+            write!(
+                out,
+                "\tg{:x} [label=\"{}\\n{}\"",
+                id,
+                quote(geom.class.name()),
+                quote(&geom.name)
+            )?;
+            if opts.colors {
+                write!(
+                    out,
+                    ", style=filled, fillcolor=\"{}\"",
+                    class_color(&geom.class)
+                )?;
+            }
+            writeln!(out, "];")?;
+        }
+        for edge in self.edges.values() {
+            if opts.hide_dev && self.nodes[&edge.consumer_geom].class == GeomClass::DEV {
+                continue;
+            }
+            let mut label = Vec::new();
+            if opts.edge_names {
+                label.push(quote(&edge.name));
+            }
+            if opts.sizes {
+                label.push(edge.size().to_string());
+            }
+            write!(
+                out,
+                "\tg{:x} -> g{:x}",
+                edge.provider_geom, edge.consumer_geom
+            )?;
+            if !label.is_empty() {
+                write!(out, " [label=\"{}\"]", label.join("\\n"))?;
+            }
+            writeln!(out, ";")?;
+        }
+        writeln!(out, "}}")?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dot::DotOptions, graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn sample() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let dot = g.to_dot(&DotOptions::default());
+        assert!(dot.starts_with("digraph geom {\n"));
+        assert!(dot.ends_with("}\n"));
+        let nodes = dot
+            .lines()
+            .filter(|l| l.contains("[label=\"") && !l.contains("->"));
+        assert_eq!(nodes.count(), g.nodes.len());
+        assert_eq!(dot.matches(" -> ").count(), g.edges.len());
+        assert!(dot.contains("label=\"DISK\\nada0\", style=filled, fillcolor=\"lightblue\""));
+        assert!(dot.contains("[label=\"ada0\\n932G\"]"));
+
+        let opts = DotOptions {
+            colors: false,
+            edge_names: false,
+            sizes: false,
+            hide_dev: true,
+        };
+        let dot = g.to_dot(&opts);
+        assert!(!dot.contains("fillcolor") && !dot.contains("DEV\\n"));
+        assert!(dot
+            .lines()
+            .filter(|l| l.contains(" -> "))
+            .all(|l| !l.contains("label")));
+    }
+}
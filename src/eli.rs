@@ -0,0 +1,948 @@
+//! `geli(8)` encrypted providers: initializing them, inspecting their on-disk metadata, and
+//! attaching and detaching them.
+//!
+//! As with `geli attach`, the user key is derived here, from key files and/or a passphrase and
+//! the salt in the provider's on-disk metadata, and passed to the kernel as the `key` parameter
+//! of an ELI class control request; the kernel uses it to decrypt the provider's Master Key.
+//! Like `geli init`, `eli::init()` writes the metadata itself, without the kernel's help.
+//!
+//! Key derivation and metadata checksums need the system's `libcrypto`, so `eli::attach()`,
+//! `eli::init()`, `EliMetadata::parse()`, and the like are only built with the `eli` feature.
+//! Detaching, and attaching with an already derived key (`eli::attach_request()`), are always
+//! available.
+#[cfg(feature = "eli")]
+use crate::crypto::{aes_cbc_encrypt, md5, pbkdf2_sha512, HmacSha512};
+#[cfg(target_os = "freebsd")]
+use crate::ctl::CtlResult;
+#[cfg(feature = "eli")]
+use crate::ctl::EINVAL;
+use crate::ctl::{CtlError, GctlRequest, EBUSY, EEXIST, ENOENT};
+use crate::graph::{GeomClass, Graph};
+#[cfg(feature = "eli")]
+use crate::wipe;
+use crate::Error;
+#[cfg(feature = "eli")]
+use std::convert::TryFrom;
+
+/// The magic string at the start of the GELI metadata sector (NUL-padded to 16 bytes).
+const G_ELI_MAGIC: &[u8] = b"GEOM::ELI";
+
+/// The newest metadata version, written by `eli::init()`.
+pub const G_ELI_VERSION: u32 = 7;
+
+/// The length of the user key, in bytes.
+pub const G_ELI_USERKEYLEN: usize = 64;
+
+/// The length of the metadata's salt, in bytes.
+const G_ELI_SALTLEN: usize = 64;
+
+/// The length of the Data and IV keys in a Master Key, in bytes.
+const G_ELI_DATAIVKEYLEN: usize = 128;
+
+/// The length of a Master Key (its Data and IV keys, and their HMAC), in bytes.
+const G_ELI_MKEYLEN: usize = G_ELI_DATAIVKEYLEN + 64;
+
+/// The number of Master Key slots.
+const G_ELI_MAXMKEYS: usize = 2;
+
+/// The length of the encoded metadata, in bytes.
+const G_ELI_METADATA_LEN: usize = 511;
+
+/// The provider is attached with a random, one-time key (never on disk).
+pub const G_ELI_FLAG_ONETIME: u32 = 0x1;
+/// Attach the provider during boot.
+pub const G_ELI_FLAG_BOOT: u32 = 0x2;
+/// Detach the provider on last close, after it was opened for writing.
+pub const G_ELI_FLAG_WO_DETACH: u32 = 0x4;
+/// Detach the provider on last close.
+pub const G_ELI_FLAG_RW_DETACH: u32 = 0x8;
+/// Data is authenticated (`aalgo`).
+pub const G_ELI_FLAG_AUTH: u32 = 0x10;
+/// The provider is read-only.
+pub const G_ELI_FLAG_RO: u32 = 0x20;
+/// Do not pass `BIO_DELETE` (TRIM) requests through.
+pub const G_ELI_FLAG_NODELETE: u32 = 0x40;
+/// The loader (`gptboot`, `loader(8)`) attaches the provider to boot from it.
+pub const G_ELI_FLAG_GELIBOOT: u32 = 0x80;
+/// Show the passphrase as it is typed at boot.
+pub const G_ELI_FLAG_GELIDISPLAYPASS: u32 = 0x100;
+/// Update the metadata when the underlying provider grows.
+pub const G_ELI_FLAG_AUTORESIZE: u32 = 0x200;
+
+/// Flag names, as in the kernel's `GeomMetadata::ELI` `flags`.
+const FLAG_NAMES: &[(u32, &str)] = &[
+    (G_ELI_FLAG_ONETIME, "ONETIME"),
+    (G_ELI_FLAG_BOOT, "BOOT"),
+    (G_ELI_FLAG_WO_DETACH, "W-DETACH"),
+    (G_ELI_FLAG_RW_DETACH, "RW-DETACH"),
+    (G_ELI_FLAG_AUTH, "AUTH"),
+    (G_ELI_FLAG_RO, "READ-ONLY"),
+    (G_ELI_FLAG_NODELETE, "NODELETE"),
+    (G_ELI_FLAG_GELIBOOT, "GELIBOOT"),
+    (G_ELI_FLAG_GELIDISPLAYPASS, "GELIDISPLAYPASS"),
+    (G_ELI_FLAG_AUTORESIZE, "AUTORESIZE"),
+];
+
+/// Encryption algorithms (`opencrypto` `CRYPTO_*` values), as in `GeomMetadata::ELI`
+/// `encryption_algorithm`.
+const EALGOS: &[(u16, &str)] = &[
+    (11, "AES-CBC"),
+    (16, "NULL"),
+    (21, "CAMELLIA-CBC"),
+    (22, "AES-XTS"),
+];
+
+/// Authentication algorithms, as in `GeomMetadata::ELI` `authentication_algorithm`.
+const AALGOS: &[(u16, &str)] = &[
+    (1, "HMAC/MD5"),
+    (2, "HMAC/SHA1"),
+    (8, "HMAC/RIPEMD160"),
+    (18, "HMAC/SHA256"),
+    (19, "HMAC/SHA384"),
+    (20, "HMAC/SHA512"),
+];
+
+/// The GELI metadata of a provider, kept in its last sector; see `struct g_eli_metadata` in
+/// `sys/geom/eli/g_eli.h`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EliMetadata {
+    /// The metadata version; see `G_ELI_VERSION`
+    pub version: u32,
+    /// `G_ELI_FLAG_*` flags; see `EliMetadata::flag_names()`
+    pub flags: u32,
+    /// The data encryption algorithm; see `EliMetadata::encryption_algorithm()`
+    pub ealgo: u16,
+    /// The data encryption key length, in bits
+    pub keylen: u16,
+    /// The data authentication algorithm, if the `G_ELI_FLAG_AUTH` flag is set
+    pub aalgo: u16,
+    /// The size of the underlying provider when the metadata was written, in bytes
+    pub provsize: u64,
+    /// The sector size of the `.eli` provider
+    pub sectorsize: u32,
+    /// Which Master Key slots are in use, as a bitmask
+    pub keys: u8,
+    /// The PKCS #5 v2 iteration count for the passphrase: `0` if it is used as is, or `-1` if
+    /// the provider has no passphrase
+    pub iterations: i32,
+    /// The salt for the passphrase
+    pub salt: [u8; G_ELI_SALTLEN],
+    /// The encrypted Master Keys
+    pub mkeys: [u8; G_ELI_MAXMKEYS * G_ELI_MKEYLEN],
+}
+
+impl EliMetadata {
+    /// The offset of `md_provsize` in the encoded metadata; version 0 lacks `md_aalgo`.
+    fn provsize_offset(version: u32) -> usize {
+        return if version == 0 { 28 } else { 30 };
+    }
+
+    /// Decodes the metadata at the start of a provider's last sector, checking its checksum.
+    #[cfg(feature = "eli")]
+    pub fn parse(sector: &[u8]) -> Result<EliMetadata, Error> {
+        let invalid = |what: &str| CtlError::new(EINVAL, what.to_owned());
+        if sector.len() < G_ELI_METADATA_LEN || !sector[..16].starts_with(G_ELI_MAGIC) {
+            return Err(invalid("no GELI metadata").into());
+        }
+        let u16_at = |off: usize| u16::from_le_bytes([sector[off], sector[off + 1]]);
+        let u32_at = |off: usize| {
+            u32::from_le_bytes([
+                sector[off],
+                sector[off + 1],
+                sector[off + 2],
+                sector[off + 3],
+            ])
+        };
+        let version = u32_at(16);
+        if version > G_ELI_VERSION {
+            return Err(invalid(&format!("unsupported GELI metadata version {}", version)).into());
+        }
+        let off = Self::provsize_offset(version);
+        let end = off + 17 + G_ELI_SALTLEN + G_ELI_MAXMKEYS * G_ELI_MKEYLEN;
+        if md5(&sector[..end])[..] != sector[end..end + 16] {
+            return Err(invalid("GELI metadata checksum mismatch").into());
+        }
+        let mut provsize = [0u8; 8];
+        provsize.copy_from_slice(&sector[off..off + 8]);
+        let mut salt = [0u8; G_ELI_SALTLEN];
+        salt.copy_from_slice(&sector[off + 17..off + 17 + G_ELI_SALTLEN]);
+        let mut mkeys = [0u8; G_ELI_MAXMKEYS * G_ELI_MKEYLEN];
+        mkeys.copy_from_slice(&sector[off + 17 + G_ELI_SALTLEN..end]);
+        return Ok(EliMetadata {
+            version,
+            flags: u32_at(20),
+            ealgo: u16_at(24),
+            keylen: u16_at(26),
+            aalgo: if version == 0 { 0 } else { u16_at(28) },
+            provsize: u64::from_le_bytes(provsize),
+            sectorsize: u32_at(off + 8),
+            keys: sector[off + 12],
+            iterations: u32_at(off + 13) as i32,
+            salt,
+            mkeys,
+        });
+    }
+
+    /// Encodes the metadata (in the layout of its `version`), with its checksum.
+    #[cfg(feature = "eli")]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(G_ELI_METADATA_LEN);
+        buf.extend_from_slice(G_ELI_MAGIC);
+        buf.resize(16, 0);
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+        buf.extend_from_slice(&self.ealgo.to_le_bytes());
+        buf.extend_from_slice(&self.keylen.to_le_bytes());
+        if self.version > 0 {
+            buf.extend_from_slice(&self.aalgo.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.provsize.to_le_bytes());
+        buf.extend_from_slice(&self.sectorsize.to_le_bytes());
+        buf.push(self.keys);
+        buf.extend_from_slice(&self.iterations.to_le_bytes());
+        buf.extend_from_slice(&self.salt);
+        buf.extend_from_slice(&self.mkeys);
+        let hash = md5(&buf);
+        buf.extend_from_slice(&hash);
+        return buf;
+    }
+
+    /// The names of the set `flags`, e.g., `["BOOT", "AUTORESIZE"]`.
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        return FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| self.flags & flag != 0)
+            .map(|(_, name)| *name)
+            .collect();
+    }
+
+    /// The data encryption algorithm, e.g., `"AES-XTS"`, if known.
+    pub fn encryption_algorithm(&self) -> Option<&'static str> {
+        return EALGOS
+            .iter()
+            .find(|(id, _)| *id == self.ealgo)
+            .map(|(_, name)| *name);
+    }
+
+    /// The data authentication algorithm, e.g., `"HMAC/SHA256"`, if the provider has one.
+    pub fn authentication_algorithm(&self) -> Option<&'static str> {
+        if self.flags & G_ELI_FLAG_AUTH == 0 {
+            return None;
+        }
+        return AALGOS
+            .iter()
+            .find(|(id, _)| *id == self.aalgo)
+            .map(|(_, name)| *name);
+    }
+
+    /// Whether the provider has a passphrase (as opposed to only key files).
+    pub fn has_passphrase(&self) -> bool {
+        return self.iterations != -1;
+    }
+}
+
+/// Derives the user key, like `geli attach`: an HMAC-SHA-512 (with no key) of the contents of
+/// each key file, in order, followed by the passphrase component.  The passphrase is strengthened
+/// with PKCS #5 v2 unless `iterations` is zero; it is not used at all if `iterations` is `-1`.
+#[cfg(feature = "eli")]
+fn user_key(
+    salt: &[u8],
+    iterations: i32,
+    keyfiles: &[&[u8]],
+    passphrase: Option<&str>,
+) -> [u8; G_ELI_USERKEYLEN] {
+    let mut ctx = HmacSha512::new(&[]);
+    for keyfile in keyfiles {
+        ctx.update(keyfile);
+    }
+    match (passphrase, iterations) {
+        (None, _) | (_, -1) => {}
+        (Some(pass), 0) => {
+            ctx.update(salt);
+            ctx.update(pass.as_bytes());
+        }
+        (Some(pass), n) => {
+            let mut dkey = [0u8; G_ELI_USERKEYLEN];
+            // `n` is a positive `i32`.
+            pbkdf2_sha512(&mut dkey, salt, pass.as_bytes(), n as u32).expect("PBKDF2 failed");
+            ctx.update(&dkey);
+            wipe(&mut dkey);
+        }
+    }
+    return ctx.finalize();
+}
+
+/// Derives the user key for a provider with metadata `md` from the contents of its key files
+/// and/or its passphrase, like `geli attach -k keyfile -j passfile`.  Overwrite the key once done
+/// with it.
+#[cfg(feature = "eli")]
+pub fn derive_key(
+    md: &EliMetadata,
+    keyfiles: &[&[u8]],
+    passphrase: Option<&str>,
+) -> Result<[u8; G_ELI_USERKEYLEN], Error> {
+    if keyfiles.is_empty() && (passphrase.is_none() || !md.has_passphrase()) {
+        return Err(CtlError::new(EINVAL, "No key components given".to_owned()).into());
+    }
+    return Ok(user_key(&md.salt, md.iterations, keyfiles, passphrase));
+}
+
+/// Protects a Master Key with a user `key`, like `g_eli_mkey_encrypt()`: appends an HMAC of its
+/// Data and IV keys, and encrypts the whole with AES-CBC (for both AES-XTS and AES-CBC
+/// providers) under a key derived from the user key.
+#[cfg(feature = "eli")]
+fn encrypt_mkey(mkey: &mut [u8], key: &[u8; G_ELI_USERKEYLEN], keylen: u16) {
+    let hmac = |k: &[u8], data: &[u8]| {
+        let mut ctx = HmacSha512::new(k);
+        ctx.update(data);
+        return ctx.finalize();
+    };
+    let mut hmkey = hmac(key, b"\x00");
+    let odhmac = hmac(&hmkey, &mkey[..G_ELI_DATAIVKEYLEN]);
+    wipe(&mut hmkey);
+    mkey[G_ELI_DATAIVKEYLEN..G_ELI_MKEYLEN].copy_from_slice(&odhmac);
+    let mut enckey = hmac(key, b"\x01");
+    aes_cbc_encrypt(&mut mkey[..G_ELI_MKEYLEN], &enckey[..keylen as usize / 8])
+        .expect("key length checked");
+    wipe(&mut enckey);
+}
+
+/// A data encryption algorithm `eli::init()` supports.
+#[cfg(feature = "eli")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EliAlgorithm {
+    /// AES-XTS (the default), with 128- or 256-bit keys
+    AesXts,
+    /// AES-CBC, with 128-, 192-, or 256-bit keys
+    AesCbc,
+}
+
+#[cfg(feature = "eli")]
+impl Default for EliAlgorithm {
+    fn default() -> EliAlgorithm {
+        return EliAlgorithm::AesXts;
+    }
+}
+
+/// How `eli::init()` configures a provider, as the options of `geli init` do.
+#[cfg(feature = "eli")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EliParams {
+    /// The data encryption algorithm (`geli init -e`)
+    pub ealgo: EliAlgorithm,
+    /// The data encryption key length, in bits (`-l`); by default, 128
+    pub keylen: Option<u16>,
+    /// The sector size of the `.eli` provider (`-s`), a power of two multiple of the underlying
+    /// provider's; by default, the same as the underlying provider's
+    pub sectorsize: Option<u32>,
+    /// `G_ELI_FLAG_BOOT`, `GELIBOOT`, `GELIDISPLAYPASS`, `NODELETE`, and/or `AUTORESIZE` (set by
+    /// default, like `geli init` without `-R`)
+    pub flags: u32,
+    /// The PKCS #5 v2 iteration count for the passphrase (`-i`); by default, as many as take
+    /// about two seconds on this machine
+    pub iterations: Option<u32>,
+}
+
+#[cfg(feature = "eli")]
+impl Default for EliParams {
+    fn default() -> EliParams {
+        return EliParams {
+            ealgo: EliAlgorithm::AesXts,
+            keylen: None,
+            sectorsize: None,
+            flags: G_ELI_FLAG_AUTORESIZE,
+            iterations: None,
+        };
+    }
+}
+
+/// Picks a PKCS #5 v2 iteration count that takes about two seconds, like `geli init`.
+#[cfg(feature = "eli")]
+fn calibrate_iterations() -> u32 {
+    const PROBE: u32 = 10_000;
+    let start = std::time::Instant::now();
+    let mut key = [0u8; G_ELI_USERKEYLEN];
+    pbkdf2_sha512(&mut key, &[0; G_ELI_SALTLEN], b"passphrase", PROBE).expect("PBKDF2 failed");
+    let usecs = start.elapsed().as_micros().max(1);
+    return (PROBE as u128 * 2_000_000 / usecs).clamp(PROBE as u128, i32::MAX as u128) as u32;
+}
+
+/// Builds the metadata for a new provider of `provsize` bytes and `secsize`-byte sectors, like
+/// `geli init`, from `random` bytes for the salt and Master Keys.
+#[cfg(feature = "eli")]
+fn new_metadata(
+    params: &EliParams,
+    provsize: u64,
+    secsize: u32,
+    random: &[u8; G_ELI_SALTLEN + G_ELI_MAXMKEYS * G_ELI_MKEYLEN],
+    keyfiles: &[&[u8]],
+    passphrase: Option<&str>,
+) -> Result<EliMetadata, Error> {
+    let invalid = |what: String| -> Error { CtlError::new(EINVAL, what).into() };
+    let (ealgo, keylens): (u16, &[u16]) = match params.ealgo {
+        EliAlgorithm::AesXts => (22, &[128, 256]),
+        EliAlgorithm::AesCbc => (11, &[128, 192, 256]),
+    };
+    let keylen = params.keylen.unwrap_or(128);
+    if !keylens.contains(&keylen) {
+        return Err(invalid(format!("Invalid key length: {}", keylen)));
+    }
+    let sectorsize = params.sectorsize.unwrap_or(secsize);
+    if secsize == 0 || !sectorsize.is_power_of_two() || !sectorsize.is_multiple_of(secsize) {
+        return Err(invalid(format!("Invalid sector size: {}", sectorsize)));
+    }
+    let settable = G_ELI_FLAG_BOOT
+        | G_ELI_FLAG_GELIBOOT
+        | G_ELI_FLAG_GELIDISPLAYPASS
+        | G_ELI_FLAG_NODELETE
+        | G_ELI_FLAG_AUTORESIZE;
+    if params.flags & !settable != 0 {
+        return Err(invalid(format!("Invalid flags: {:#x}", params.flags)));
+    }
+    if keyfiles.is_empty() && passphrase.is_none() {
+        return Err(invalid("No key components given".to_owned()));
+    }
+    let iterations = match passphrase {
+        Some(_) => {
+            let n = params.iterations.unwrap_or_else(calibrate_iterations);
+            i32::try_from(n).map_err(|_| invalid(format!("Invalid number of iterations: {}", n)))?
+        }
+        None => -1,
+    };
+
+    let mut md = EliMetadata {
+        version: G_ELI_VERSION,
+        flags: params.flags,
+        ealgo,
+        keylen,
+        aalgo: 0,
+        provsize,
+        sectorsize,
+        keys: 0x1,
+        iterations,
+        salt: [0; G_ELI_SALTLEN],
+        mkeys: [0; G_ELI_MAXMKEYS * G_ELI_MKEYLEN],
+    };
+    md.salt.copy_from_slice(&random[..G_ELI_SALTLEN]);
+    md.mkeys.copy_from_slice(&random[G_ELI_SALTLEN..]);
+    let mut key = user_key(&md.salt, iterations, keyfiles, passphrase);
+    encrypt_mkey(&mut md.mkeys[..G_ELI_MKEYLEN], &key, keylen);
+    wipe(&mut key);
+    return Ok(md);
+}
+
+/// Returns the size and sector size of `provider`.
+#[cfg(feature = "eli")]
+fn provider_size(graph: &Graph, provider: &str) -> Result<(u64, u64), Error> {
+    return graph
+        .provider_size(provider)
+        .ok_or_else(|| CtlError::new(ENOENT, format!("No such provider: {}", provider)).into());
+}
+
+/// Checks that a kernel whose `geom_eli` supports metadata up to version `kernel` (the
+/// `kern.geom.eli.version` sysctl, if the module is loaded) can attach what `eli::init()` writes.
+#[cfg(feature = "eli")]
+fn check_kernel_version(kernel: Option<u32>) -> Result<(), Error> {
+    match kernel {
+        Some(v) if v < G_ELI_VERSION => {
+            let msg = format!(
+                "Kernel supports GELI metadata version {}, not {}",
+                v, G_ELI_VERSION
+            );
+            return Err(CtlError::new(EINVAL, msg).into());
+        }
+        _ => return Ok(()),
+    }
+}
+
+/// Returns the newest GELI metadata version the kernel supports, or `None` if `geom_eli` is not
+/// loaded.
+#[cfg(all(feature = "eli", target_os = "freebsd"))]
+fn kernel_version() -> Option<u32> {
+    use sysctl::{CtlValue, Sysctl};
+
+    match sysctl::Ctl::new("kern.geom.eli.version").and_then(|ctl| ctl.value()) {
+        Ok(CtlValue::Uint(v)) => return Some(v),
+        Ok(CtlValue::Int(v)) => return u32::try_from(v).ok(),
+        _ => return None,
+    }
+}
+
+/// Opens the device of `provider` at its last sector, where GELI keeps its metadata.
+#[cfg(all(feature = "eli", target_os = "freebsd"))]
+fn open_metadata_sector(
+    graph: &Graph,
+    provider: &str,
+    write: bool,
+) -> Result<(std::fs::File, u64), Error> {
+    use std::io::{Seek, SeekFrom};
+
+    let (mediasize, sectorsize) = provider_size(graph, provider)?;
+    let mut dev = std::fs::OpenOptions::new()
+        .read(true)
+        .write(write)
+        .open(format!("/dev/{}", provider))?;
+    let offset = mediasize.checked_sub(sectorsize).ok_or_else(|| {
+        let msg = format!("{} is smaller than its sector size", provider);
+        return Error::from(CtlError::new(EINVAL, msg));
+    })?;
+    dev.seek(SeekFrom::Start(offset))?;
+    return Ok((dev, sectorsize));
+}
+
+/// Reads the GELI metadata of `provider` (e.g., `"ada0p4"`), like `geli dump`.
+#[cfg(all(feature = "eli", target_os = "freebsd"))]
+pub fn read_metadata(provider: &str) -> Result<EliMetadata, Error> {
+    return read_metadata_from(&crate::get_graph()?, provider);
+}
+
+#[cfg(all(feature = "eli", target_os = "freebsd"))]
+fn read_metadata_from(graph: &Graph, provider: &str) -> Result<EliMetadata, Error> {
+    use std::io::Read;
+
+    let provider = provider.trim_start_matches("/dev/");
+    let (mut dev, sectorsize) = open_metadata_sector(graph, provider, false)?;
+    let mut sector = vec![0u8; sectorsize as usize];
+    dev.read_exact(&mut sector)?;
+    return EliMetadata::parse(&sector);
+}
+
+/// Initializes `provider` for encryption with the given key files and/or passphrase, like `geli
+/// init`, writing new metadata (and a new, random Master Key) to its last sector.  Any existing
+/// metadata is overwritten, and the provider's contents become inaccessible once attached.
+///
+/// Fails if the provider is open, or if the running kernel's `geom_eli` is too old to attach
+/// version `G_ELI_VERSION` metadata.  Unlike `geli init`, it does not back up the metadata in
+/// `/var/backups`; save `EliMetadata::encode()` of the result to be able to restore it.
+#[cfg(all(feature = "eli", target_os = "freebsd"))]
+pub fn init(
+    provider: &str,
+    params: &EliParams,
+    keyfiles: &[&[u8]],
+    passphrase: Option<&str>,
+) -> Result<EliMetadata, Error> {
+    use std::io::Write;
+
+    let provider = provider.trim_start_matches("/dev/");
+    check_kernel_version(kernel_version())?;
+    let graph = crate::get_graph()?;
+    let (mediasize, secsize) = provider_size(&graph, provider)?;
+    if let Some(e) = graph
+        .edges
+        .values()
+        .find(|e| e.name == provider && e.mode.is_open())
+    {
+        return Err(CtlError::new(EBUSY, format!("{} is open ({})", provider, e.mode)).into());
+    }
+
+    let mut random = [0u8; G_ELI_SALTLEN + G_ELI_MAXMKEYS * G_ELI_MKEYLEN];
+    // SAFETY: `arc4random_buf(3)` fills exactly `random.len()` bytes of the buffer.
+    unsafe { libc::arc4random_buf(random.as_mut_ptr() as *mut libc::c_void, random.len()) };
+    let md = new_metadata(
+        params,
+        mediasize,
+        secsize as u32,
+        &random,
+        keyfiles,
+        passphrase,
+    )?;
+
+    let req = GctlRequest::new("ELI", "init").args(&[provider]);
+    if !crate::ctl::check_policy(&req)? {
+        return Ok(md);
+    }
+    crate::ctl::audited(&req, || {
+        let (mut dev, sectorsize) = open_metadata_sector(&graph, provider, true)?;
+        let mut sector = md.encode();
+        sector.resize(sectorsize as usize, 0);
+        dev.write_all(&sector)?;
+        dev.sync_all()?;
+        return Ok(crate::ctl::GctlResponse::default());
+    })?;
+    return Ok(md);
+}
+
+/// Returns the request to attach `provider` (e.g., `"ada0p4"`) with a user `key` (see
+/// `eli::derive_key()`), like `geli attach`, creating the `<provider>.eli` provider.
+///
+/// Fails if the provider does not exist, or is already attached.
+pub fn attach_request(
+    graph: &Graph,
+    provider: &str,
+    key: &[u8; G_ELI_USERKEYLEN],
+    readonly: bool,
+) -> Result<GctlRequest, Error> {
+    let provider = provider.trim_start_matches("/dev/");
+    if !graph.has_provider(provider) {
+        return Err(CtlError::new(ENOENT, format!("No such provider: {}", provider)).into());
+    }
+    let eli = format!("{}.eli", provider);
+    if graph.has_provider(&eli) {
+        return Err(CtlError::new(EEXIST, format!("Provider {} already exists", eli)).into());
+    }
+    return Ok(GctlRequest::new("ELI", "attach")
+        .int("version", G_ELI_VERSION as i32)
+        .args(&[provider])
+        .int("detach", 0)
+        .int("readonly", readonly as i32)
+        .int("dryrun", 0)
+        .binary("key", key));
+}
+
+/// Attaches `provider` with the contents of its key files and/or its passphrase, like `geli
+/// attach`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(all(feature = "eli", target_os = "freebsd"))]
+/// fn unlock(passphrase: &str) -> Result<(), geom::Error> {
+///     let keyfile = std::fs::read("/boot/keys/ada0p4.key")?;
+///     let res = geom::eli::attach("ada0p4", &[&keyfile], Some(passphrase))?.refresh()?;
+///     assert_eq!(res.new_providers, vec!["ada0p4.eli"]);
+///     Ok(())
+/// }
+/// ```
+#[cfg(all(feature = "eli", target_os = "freebsd"))]
+pub fn attach(
+    provider: &str,
+    keyfiles: &[&[u8]],
+    passphrase: Option<&str>,
+) -> Result<CtlResult, Error> {
+    let provider = provider.trim_start_matches("/dev/");
+    let graph = crate::get_graph()?;
+    let md = read_metadata_from(&graph, provider)?;
+    let mut key = derive_key(&md, keyfiles, passphrase)?;
+    let res = attach_request(&graph, provider, &key, false).and_then(|req| req.issue());
+    wipe(&mut key);
+    res?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Returns the request to detach an attached `provider` (e.g., `"ada0p4"` or `"ada0p4.eli"`),
+/// like `geli detach`.  Unless `force` is set, fails if the `.eli` provider is open.
+pub fn detach_request(graph: &Graph, provider: &str, force: bool) -> Result<GctlRequest, Error> {
+    let provider = provider.trim_start_matches("/dev/");
+    let name = if provider.ends_with(".eli") {
+        provider.to_owned()
+    } else {
+        format!("{}.eli", provider)
+    };
+    let (id, _) = graph
+        .find_by_class_name(&GeomClass::ELI, &name)
+        .ok_or_else(|| CtlError::new(ENOENT, format!("Provider {} is invalid", name)))?;
+    if !force {
+        let open = graph
+            .child_edges_iter(id)
+            .find(|(_, e)| e.mode.is_open())
+            .map(|(_, e)| e.mode);
+        if let Some(mode) = open {
+            return Err(CtlError::new(EBUSY, format!("{} is open ({})", name, mode)).into());
+        }
+    }
+    return Ok(GctlRequest::new("ELI", "detach")
+        .int("version", G_ELI_VERSION as i32)
+        .args(&[provider])
+        .int("force", force as i32)
+        .int("last", 0));
+}
+
+/// Detaches an attached `provider`, like `geli detach`.
+#[cfg(target_os = "freebsd")]
+pub fn detach(provider: &str, force: bool) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    detach_request(&graph, provider, force)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctl::{CtlErrorKind, ParamValue};
+    use crate::eli::{attach_request, detach_request};
+    #[cfg(feature = "eli")]
+    use crate::eli::{
+        check_kernel_version, derive_key, new_metadata, user_key, EliAlgorithm, EliMetadata,
+        EliParams, G_ELI_FLAG_ONETIME, G_ELI_VERSION,
+    };
+    use crate::{graph, raw, Error};
+
+    const ELI_XML: &str = r#"<mesh>
+      <class id="0x10">
+        <name>DISK</name>
+        <geom id="0x11">
+          <class ref="0x10"/>
+          <name>ada0</name>
+          <rank>1</rank>
+          <provider id="0x12">
+            <geom ref="0x11"/>
+            <mode>r1w1e1</mode>
+            <name>ada0</name>
+            <mediasize>1048576</mediasize>
+            <sectorsize>512</sectorsize>
+            <stripesize>0</stripesize>
+            <stripeoffset>0</stripeoffset>
+            <config>
+              <fwheads>0</fwheads>
+              <fwsectors>0</fwsectors>
+              <rotationrate>0</rotationrate>
+              <ident>X</ident>
+              <lunid>Y</lunid>
+              <descr>Z</descr>
+            </config>
+          </provider>
+        </geom>
+        <geom id="0x13">
+          <class ref="0x10"/>
+          <name>ada1</name>
+          <rank>1</rank>
+          <provider id="0x14">
+            <geom ref="0x13"/>
+            <mode>r0w0e0</mode>
+            <name>ada1</name>
+            <mediasize>1048576</mediasize>
+            <sectorsize>512</sectorsize>
+            <stripesize>0</stripesize>
+            <stripeoffset>0</stripeoffset>
+            <config>
+              <fwheads>0</fwheads>
+              <fwsectors>0</fwsectors>
+              <rotationrate>0</rotationrate>
+              <ident>X</ident>
+              <lunid>Y</lunid>
+              <descr>Z</descr>
+            </config>
+          </provider>
+        </geom>
+      </class>
+      <class id="0x20">
+        <name>ELI</name>
+        <geom id="0x21">
+          <class ref="0x20"/>
+          <name>ada0.eli</name>
+          <rank>2</rank>
+          <consumer id="0x22">
+            <geom ref="0x21"/>
+            <provider ref="0x12"/>
+            <mode>r1w1e1</mode>
+          </consumer>
+          <provider id="0x23">
+            <geom ref="0x21"/>
+            <mode>r1w1e0</mode>
+            <name>ada0.eli</name>
+            <mediasize>1044480</mediasize>
+            <sectorsize>4096</sectorsize>
+            <stripesize>0</stripesize>
+            <stripeoffset>0</stripeoffset>
+          </provider>
+        </geom>
+      </class>
+      <class id="0x30">
+        <name>DEV</name>
+        <geom id="0x31">
+          <class ref="0x30"/>
+          <name>ada0.eli</name>
+          <rank>3</rank>
+          <consumer id="0x32">
+            <geom ref="0x31"/>
+            <provider ref="0x23"/>
+            <mode>r1w1e0</mode>
+          </consumer>
+        </geom>
+      </class>
+    </mesh>"#;
+
+    fn hex(bytes: &[u8]) -> String {
+        return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    }
+
+    /// Random bytes for `new_metadata()`: a salt of `0, 1, 2, ...`, and so on.
+    fn random() -> [u8; 448] {
+        let mut random = [0u8; 448];
+        for (i, b) in random.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        return random;
+    }
+
+    const KEYFILE: &[u8] = b"0123456789abcdef";
+
+    #[test]
+    #[cfg(feature = "eli")]
+    fn keys() {
+        let salt: Vec<u8> = (0..64).collect();
+        let params = EliParams {
+            iterations: Some(1000),
+            ..Default::default()
+        };
+        let md = new_metadata(
+            &params,
+            1 << 20,
+            512,
+            &random(),
+            &[KEYFILE],
+            Some("hunter2"),
+        )
+        .unwrap();
+
+        // Checked against Python's `hmac` and `hashlib.pbkdf2_hmac`.
+        let key = derive_key(&md, &[KEYFILE], Some("hunter2")).unwrap();
+        assert_eq!(
+            hex(&key),
+            "d5d8ff295679c535f660b730afcf3d2691a97b7da7b55eaf0b42ea6b9401fd9b\
+             3a91619446c1a12010eeb7c8e91bb675a553026b2e867b95a96b4b054c9b9da4"
+        );
+        assert_eq!(
+            hex(&user_key(&salt, 0, &[], Some("hunter2"))),
+            "46f120722453fea8db160b84e1b298479e4e07253d4213e65bba09ee44ca51ac\
+             35ea58bb64671b210b0d585b244f259e50c0605b00fb353ff64516cf3e55393b"
+        );
+        // Providers without a passphrase ignore one.
+        let md = new_metadata(&params, 1 << 20, 512, &random(), &[KEYFILE], None).unwrap();
+        assert_eq!(md.iterations, -1);
+        assert_eq!(
+            derive_key(&md, &[KEYFILE], Some("hunter2")).unwrap(),
+            user_key(&salt, 0, &[KEYFILE], None)
+        );
+        assert!(derive_key(&md, &[], Some("hunter2")).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "eli")]
+    fn metadata() {
+        let params = EliParams {
+            sectorsize: Some(4096),
+            iterations: Some(1000),
+            ..Default::default()
+        };
+        let md = new_metadata(
+            &params,
+            1 << 20,
+            512,
+            &random(),
+            &[KEYFILE],
+            Some("hunter2"),
+        )
+        .unwrap();
+        assert_eq!(md.encryption_algorithm(), Some("AES-XTS"));
+        assert_eq!(md.authentication_algorithm(), None);
+        assert_eq!(md.flag_names(), vec!["AUTORESIZE"]);
+        assert!(md.has_passphrase());
+
+        // The encrypted Master Key and checksum were checked against a Python model of `geli
+        // init`, using `cryptography`'s AES-CBC.
+        assert_eq!(
+            hex(&md.mkeys[..32]),
+            "42d7cb775dfae1387f8e7b17003ca6856555c01863bb432708a02dfd755e150b"
+        );
+        assert_eq!(
+            hex(&md.mkeys[160..192]),
+            "d69ea76fd6a6c55ce9966205fadc019dd166d3fbbea77a1eec941bbb5dc0d82c"
+        );
+        // The second slot is unused, and left random.
+        assert_eq!(md.mkeys[192..], random()[256..]);
+        let mut sector = md.encode();
+        assert_eq!(sector.len(), 511);
+        assert_eq!(hex(&sector[495..]), "06c5f79f4a595c88d4565acbe651946a");
+
+        sector.resize(4096, 0);
+        assert_eq!(EliMetadata::parse(&sector).unwrap(), md);
+        sector[100] ^= 1;
+        assert!(EliMetadata::parse(&sector).is_err());
+        assert!(EliMetadata::parse(&[0u8; 512]).is_err());
+
+        let bad = |params: EliParams| {
+            new_metadata(&params, 1 << 20, 512, &random(), &[KEYFILE], None).is_err()
+        };
+        assert!(bad(EliParams {
+            keylen: Some(192),
+            ..Default::default()
+        }));
+        assert!(!bad(EliParams {
+            ealgo: EliAlgorithm::AesCbc,
+            keylen: Some(192),
+            ..Default::default()
+        }));
+        assert!(bad(EliParams {
+            sectorsize: Some(1536),
+            ..Default::default()
+        }));
+        assert!(bad(EliParams {
+            flags: G_ELI_FLAG_ONETIME,
+            ..Default::default()
+        }));
+        assert!(new_metadata(&params, 1 << 20, 512, &random(), &[], None).is_err());
+        let params = EliParams {
+            iterations: Some(1 << 31),
+            ..Default::default()
+        };
+        match new_metadata(&params, 1 << 20, 512, &random(), &[], Some("hunter2")) {
+            Err(Error::Ctl(e)) => assert_eq!(e.kind(), CtlErrorKind::InvalidArgument),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "eli")]
+    fn kernel_version() {
+        assert!(check_kernel_version(None).is_ok());
+        assert!(check_kernel_version(Some(G_ELI_VERSION)).is_ok());
+        assert!(check_kernel_version(Some(G_ELI_VERSION + 1)).is_ok());
+        assert!(check_kernel_version(Some(G_ELI_VERSION - 1)).is_err());
+    }
+
+    #[test]
+    fn attach_detach() {
+        let g = graph::decode_graph(&raw::parse_xml(ELI_XML).unwrap()).unwrap();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+        let key = [0x5au8; 64];
+
+        let req = attach_request(&g, "/dev/ada1", &key, true).unwrap();
+        assert_eq!(req.class(), "ELI");
+        assert_eq!(req.verb(), "attach");
+        assert_eq!(
+            req.get("arg0"),
+            Some(&ParamValue::String("ada1".to_owned()))
+        );
+        assert_eq!(req.get("readonly"), Some(&ParamValue::Int(1)));
+        assert_eq!(req.get("dryrun"), Some(&ParamValue::Int(0)));
+        assert_eq!(req.get("key"), Some(&ParamValue::Binary(key.to_vec())));
+        assert_eq!(
+            kind(attach_request(&g, "ada0", &key, false)),
+            CtlErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            kind(attach_request(&g, "ada9", &key, false)),
+            CtlErrorKind::NotFound
+        );
+
+        // ada0.eli is open.
+        assert_eq!(kind(detach_request(&g, "ada0", false)), CtlErrorKind::Busy);
+        let req = detach_request(&g, "ada0.eli", true).unwrap();
+        assert_eq!(req.get("force"), Some(&ParamValue::Int(1)));
+        assert_eq!(
+            kind(detach_request(&g, "ada1", false)),
+            CtlErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "eli", target_os = "freebsd"))]
+    fn dry_run() {
+        // `attach()` is left out: it reads the provider's metadata even in dry-run mode.
+        let g = graph::decode_graph(&raw::parse_xml(ELI_XML).unwrap()).unwrap();
+        let (res, requests) = crate::dry_run_on(&g, || -> Result<(), Error> {
+            let md = super::init("ada1", &EliParams::default(), &[KEYFILE], None)?;
+            assert_eq!(md.iterations, -1);
+            assert!(super::detach("ada0.eli", true)?.delta.changes.is_empty());
+            return Ok(());
+        });
+        res.unwrap();
+        let verbs: Vec<&str> = requests.iter().map(|r| r.verb()).collect();
+        assert_eq!(verbs, vec!["init", "detach"]);
+    }
+}
@@ -8,6 +8,14 @@ pub enum Error {
     Decode(quick_xml::DeError),
     Parse(strum::ParseError),
     Scan(scan_fmt::parse::ScanError),
+    /// A failed system call, e.g., `getmntinfo(3)`.
+    Io(std::io::Error),
+    /// A `Graph` snapshot could not be written, or read back (e.g., it is truncated, or from an
+    /// unsupported version).
+    Snapshot(String),
+    /// A GEOM control request (`ctl::GctlRequest`) was rejected, by the kernel or by this
+    /// library's checks before issuing it.
+    Ctl(crate::ctl::CtlError),
     /// Some internal graph invariant was violated.
     GraphError,
 }
@@ -36,6 +44,18 @@ impl std::convert::From<scan_fmt::parse::ScanError> for Error {
     }
 }
 
+impl std::convert::From<crate::ctl::CtlError> for Error {
+    fn from(err: crate::ctl::CtlError) -> Error {
+        Self::Ctl(err)
+    }
+}
+
+impl std::convert::From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Self::Io(err)
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_ref())?;
@@ -44,6 +64,9 @@ impl std::fmt::Display for Error {
             Self::Decode(e) => write!(f, ": {}", e),
             Self::Parse(e) => write!(f, ": {}", e),
             Self::Scan(e) => write!(f, ": {}", e),
+            Self::Io(e) => write!(f, ": {}", e),
+            Self::Snapshot(e) => write!(f, ": {}", e),
+            Self::Ctl(e) => write!(f, ": {}", e),
             Self::GraphError => Ok(()),
         };
     }
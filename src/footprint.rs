@@ -0,0 +1,321 @@
+//! Reduced-footprint graphs for constrained userlands.
+//!
+//! Small appliances (e.g., routers booting from `geom_map` or `geom_uzip` images) may not want to
+//! pay for every string the kernel dumps.  `Graph::compact()` (or `DecodeOptions::compact`) drops
+//! metadata that is not needed to navigate the graph, and `Graph::heap_size()` estimates what is
+//! left.
+use crate::graph::{
+    DiskInfo, Edge, EdgeMetadata, Geom, GeomClass, GeomMetadata, Graph, OrphanProvider, PartInfo,
+};
+use crate::mounts::Mount;
+use crate::stats::DeviceStats;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+/// Approximate heap usage, in bytes, not counting `size_of::<Self>()` itself.
+trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        return self.capacity();
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        return self.as_ref().map_or(0, |v| v.heap_size());
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        return self.capacity() * size_of::<T>()
+            + self.iter().map(|v| v.heap_size()).sum::<usize>();
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        return size_of::<T>() + (**self).heap_size();
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for BTreeMap<K, V> {
+    fn heap_size(&self) -> usize {
+        // B-tree nodes are not always full; this undercounts slack, but is close enough to compare
+        // configurations.
+        return self
+            .iter()
+            .map(|(k, v)| size_of::<K>() + size_of::<V>() + k.heap_size() + v.heap_size())
+            .sum();
+    }
+}
+
+macro_rules! no_heap {
+    ($($t:ty),*) => {
+        $(impl HeapSize for $t {
+            fn heap_size(&self) -> usize {
+                return 0;
+            }
+        })*
+    };
+}
+
+no_heap!(
+    u64,
+    (u64, u64),
+    crate::graph::PartAttrib,
+    crate::graph::LabelKind
+);
+
+impl HeapSize for GeomClass {
+    fn heap_size(&self) -> usize {
+        match self {
+            Self::Other(name) => name.heap_size(),
+            _ => 0,
+        }
+    }
+}
+
+impl HeapSize for GeomMetadata {
+    fn heap_size(&self) -> usize {
+        match self {
+            Self::PART(_) => 0,
+            Self::VFS { fstype, provider } => fstype.heap_size() + provider.heap_size(),
+            Self::ELI {
+                flags,
+                crypto,
+                encryption_algorithm,
+                authentication_algorithm,
+                state,
+                ..
+            } => {
+                flags.heap_size()
+                    + crypto.heap_size()
+                    + encryption_algorithm.heap_size()
+                    + authentication_algorithm.heap_size()
+                    + state.heap_size()
+            }
+            Self::GATE {
+                access,
+                info,
+                read_provider,
+                ..
+            } => access.heap_size() + info.heap_size() + read_provider.heap_size(),
+            Self::CCD { .. } => 0,
+            Self::SCHED { algorithm } => algorithm.heap_size(),
+            Self::DEV { .. } => 0,
+        }
+    }
+}
+
+impl HeapSize for EdgeMetadata {
+    fn heap_size(&self) -> usize {
+        match self {
+            Self::DISK(DiskInfo {
+                ident,
+                lunid,
+                descr,
+                ..
+            }) => ident.heap_size() + lunid.heap_size() + descr.heap_size(),
+            Self::PART(PartInfo {
+                type_,
+                attrib,
+                label,
+                rawtype,
+                rawuuid,
+                efimedia,
+                ..
+            }) => {
+                type_.heap_size()
+                    + attrib.heap_size()
+                    + label.heap_size()
+                    + rawtype.heap_size()
+                    + rawuuid.heap_size()
+                    + efimedia.heap_size()
+            }
+            Self::LABEL(_) => 0,
+        }
+    }
+}
+
+impl HeapSize for Geom {
+    fn heap_size(&self) -> usize {
+        return self.class.heap_size()
+            + self.name.heap_size()
+            + self.metadata.heap_size()
+            + self.unknown_config.heap_size()
+            + self.mounts.heap_size();
+    }
+}
+
+impl HeapSize for OrphanProvider {
+    fn heap_size(&self) -> usize {
+        return self.name.heap_size();
+    }
+}
+
+impl HeapSize for Mount {
+    fn heap_size(&self) -> usize {
+        return self.from.heap_size() + self.mountpoint.heap_size() + self.fstype.heap_size();
+    }
+}
+
+impl HeapSize for DeviceStats {
+    fn heap_size(&self) -> usize {
+        return self.name.heap_size();
+    }
+}
+
+impl HeapSize for Edge {
+    fn heap_size(&self) -> usize {
+        return self.name.heap_size()
+            + self.metadata.heap_size()
+            + self.unknown_config.heap_size()
+            + self.devstat.heap_size();
+    }
+}
+
+/// `Graph::compact()` for a single `Geom`.  Decoding with `DecodeOptions::compact` applies this
+/// to each geom as it is created, so the full graph is never held in memory.
+pub(crate) fn compact_geom(geom: &mut Geom) {
+    if !matches!(
+        geom.metadata.as_deref(),
+        Some(GeomMetadata::PART(_))
+            | Some(GeomMetadata::DEV { .. })
+            | Some(GeomMetadata::CCD { .. })
+            | Some(GeomMetadata::SCHED { .. })
+    ) {
+        geom.metadata = None;
+    }
+    geom.unknown_config.clear();
+    geom.name.shrink_to_fit();
+}
+
+/// `Graph::compact()` for a single `Edge`; see `compact_geom()`.
+pub(crate) fn compact_edge(edge: &mut Edge) {
+    match edge.metadata.as_deref_mut() {
+        Some(EdgeMetadata::DISK(DiskInfo { descr, .. })) => *descr = String::new(),
+        Some(EdgeMetadata::PART(PartInfo {
+            rawtype, efimedia, ..
+        })) => {
+            *rawtype = None;
+            *efimedia = None;
+        }
+        _ => {}
+    }
+    edge.unknown_config.clear();
+    edge.name.shrink_to_fit();
+}
+
+impl Graph {
+    /// Estimates the heap memory used by this `Graph`, in bytes.
+    ///
+    /// The estimate counts every container and string, but not allocator overhead or B-tree slack,
+    /// so actual usage is somewhat higher.
+    pub fn heap_size(&self) -> usize {
+        return self.nodes.heap_size()
+            + self.edges.heap_size()
+            + self.outedges.heap_size()
+            + self.inedges.heap_size()
+            + self.names.heap_size()
+            + self.class_names.heap_size()
+            + self.orphans.heap_size()
+            + self.part_uuids.heap_size();
+    }
+
+    /// Discards data not needed to navigate the graph, to reduce its memory footprint:
+    ///
+    /// * `GeomMetadata` of classes other than `PART`, `DEV`, `CCD`, and `SCHED` (VFS metadata can
+    ///   be recovered from the geom name, and `Geom::typed()` does so),
+    /// * `DiskInfo::descr` and `PartInfo::{rawtype, efimedia}`, which are
+    ///   redundant with other fields,
+    /// * all `unknown_config`, and
+    /// * excess capacity of strings and vectors.
+    ///
+    /// Offsets, sizes, partition types, labels, and identifiers are kept.
+    pub fn compact(&mut self) {
+        for geom in self.nodes.values_mut() {
+            compact_geom(geom);
+        }
+        for edge in self.edges.values_mut() {
+            compact_edge(edge);
+        }
+        for v in self.outedges.values_mut().chain(self.inedges.values_mut()) {
+            v.shrink_to_fit();
+        }
+        for v in self
+            .names
+            .values_mut()
+            .chain(self.class_names.values_mut().flat_map(|m| m.values_mut()))
+        {
+            v.shrink_to_fit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::typed::TypedGeom;
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn compact_is_smaller() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let full = graph::decode_graph(&rawmesh).unwrap();
+        let opts = graph::DecodeOptions {
+            compact: true,
+            ..Default::default()
+        };
+        let compact = graph::decode_graph_with_options(&rawmesh, &opts).unwrap();
+
+        assert!(compact.heap_size() < full.heap_size());
+        assert_eq!(compact.nodes.len(), full.nodes.len());
+        assert_eq!(compact.edges.len(), full.edges.len());
+        assert!(compact.verify().is_empty());
+        assert!(compact.nodes.values().all(|n| n.unknown_config.is_empty()
+            && match n.class {
+                graph::GeomClass::PART | graph::GeomClass::DEV => n.metadata.is_some(),
+                _ => n.metadata.is_none(),
+            }));
+
+        // Compacting a decoded graph gives the same result.
+        let mut compacted = full.clone();
+        compacted.compact();
+        assert_eq!(format!("{:?}", compacted), format!("{:?}", compact));
+
+        // Class views survive compaction; VFS geoms are recovered from their names.
+        for (id, geom) in &full.nodes {
+            assert_eq!(
+                format!("{:?}", geom.typed()).split('(').next(),
+                format!("{:?}", compact.nodes[id].typed()).split('(').next()
+            );
+        }
+        let (_, vfs) = compact
+            .nodes
+            .iter()
+            .find(|(_, n)| n.name == "ffs.gpt-partition-2")
+            .unwrap();
+        match vfs.typed() {
+            TypedGeom::Vfs(v) => {
+                assert_eq!(v.fstype, "ffs");
+                assert_eq!(v.provider, "gpt-partition-2");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // Topology queries still work.
+        let (id, _) = compact
+            .edges
+            .iter()
+            .find(|(_, e)| e.name == "nvd1p2")
+            .unwrap();
+        assert_eq!(
+            compact.absolute_disk_offset(id),
+            full.absolute_disk_offset(id)
+        );
+    }
+}
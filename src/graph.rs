@@ -1,13 +1,15 @@
 //! The GEOM subsystem of FreeBSD is an abstraction of storage topology inside the kernel.
 //!
 //! In math jargon, it is a "forest" of disconnected trees.  The root(s) of these trees are
-//! individual `Geom` objects of class `GeomClass::DISK` or similar (e.g., `MD` — Memory Disk).
+//! individual `Geom` objects of class `GeomClass::DISK` or similar (e.g., `MD` — Memory Disk,
+//! `ZFSZVOL`, or `GATE`).  Any geom which consumes nothing has `rank` 1 and is a root.
 //!
 //! The leaves of the trees are `Geom` objects of type `GeomClass::DEV`, which are responsible for
 //! constructing the virtual files present in `/dev`.
-use crate::{raw, Error};
+use crate::{footprint, mounts::Mount, raw, stats::DeviceStats, Error};
 use std::{
     collections::{BTreeMap, BTreeSet},
+    iter::FusedIterator,
     str::FromStr,
 };
 use strum_macros::{AsRefStr, EnumIter, EnumString};
@@ -18,12 +20,13 @@ use strum_macros::{AsRefStr, EnumIter, EnumString};
 /// represent a disk (`GeomClass::DISK`), or partition (`GeomClass::PART`), or `/dev` device node
 /// (`GeomClass::DEV`), as well as several other classes.
 ///
-/// A geom *may* have some associated `metadata` (e.g., `PART` geoms).
+/// A geom *may* have some associated `metadata` (e.g., `PART`, `VFS`, and `ELI` geoms).
 ///
 /// A geom is related to other geoms in a tree.  In this library, we call edges from child to
 /// parent geoms "outedges" and edges from parent geoms to child geoms "inedges".  In other GEOM
 /// documentation they are called "consumers" and "providers," respectively.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Geom {
     pub class: GeomClass,
     /// The `Geom`'s name, such as "ada0".  Caveat: geom names are not unique.
@@ -31,19 +34,73 @@ pub struct Geom {
     /// The height of this `Geom` in its tree.  For example, a `Geom` at the root of a tree will
     /// have `rank` equal to `1`.
     pub rank: u64,
-    /// If this `Geom` is `GeomClass::PART`, some additional metadata.
-    pub metadata: Option<Box<PartMetadata>>,
+    /// For some classes (e.g., `GeomClass::PART`, `VFS`, `ELI`), some additional metadata.
+    pub metadata: Option<Box<GeomMetadata>>,
+    /// Any config keys of this `Geom` that this library does not recognize, by name.
+    pub unknown_config: BTreeMap<String, String>,
+    /// Where this `Geom` came from in the `raw::Mesh`, if decoded with
+    /// `DecodeOptions::raw_links`; see `Geom::raw_geom()`.
+    pub raw_index: Option<RawGeomIndex>,
+    /// Filesystems mounted from this `Geom` (a DEV geom, or the LABEL geom providing it), once
+    /// `Graph::attach_mounts()` has been called.
+    pub mounts: Vec<Mount>,
+    /// The geom is being torn down.  The kernel omits the config of withering geoms (and of their
+    /// providers), so `metadata` is `None`.
+    pub withered: bool,
+}
+
+/// The position of a `raw::Geom` in its `raw::Mesh`: `mesh.classes[class].geoms[geom]`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct RawGeomIndex {
+    pub class: usize,
+    pub geom: usize,
+}
+
+/// The positions of the `raw::Provider` and `raw::Consumer` an `Edge` was decoded from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct RawEdgeIndex {
+    /// The geom owning the provider
+    pub provider_geom: RawGeomIndex,
+    /// Index into that geom's `providers`
+    pub provider: usize,
+    /// The geom owning the consumer
+    pub consumer_geom: RawGeomIndex,
+    /// Index into that geom's `consumers`
+    pub consumer: usize,
+}
+
+impl Geom {
+    /// The `raw::Geom` this `Geom` was decoded from, for fields this library does not model.
+    ///
+    /// Returns `None` unless the `Graph` was decoded from `mesh` with
+    /// `DecodeOptions::raw_links`.
+    pub fn raw_geom<'m>(&self, mesh: &'m raw::Mesh) -> Option<&'m raw::Geom> {
+        let idx = self.raw_index?;
+        return mesh.classes.get(idx.class)?.geoms.get(idx.geom);
+    }
 }
 
 /// The class of a `Geom`.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, AsRefStr, EnumIter, EnumString)]
+///
+/// Classes this library does not know about (e.g., third-party GEOM modules) are represented by
+/// `GeomClass::Other`, which carries the kernel's class name.  Because of it, `GeomClass` is not
+/// `Copy`; clone or borrow it instead.  More classes may be added, so matches on it need a
+/// wildcard arm.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, AsRefStr, EnumIter, EnumString)]
+#[non_exhaustive]
 pub enum GeomClass {
-    /// Floppy Disk
+    /// Floppy Disk.  Note that `fdc(4)` does not implement a `dumpconf` method, so FD providers
+    /// have an empty config and their `Edge`s carry no `EdgeMetadata`.  (USB floppy drives
+    /// attach via `umass(4)` and show up as `GeomClass::DISK` instead.)
     FD,
     RAID,
     /// Typical PC storage devices: SATA, NVMe, IDE
     DISK,
-    /// Virtual "character device" in `/dev`
+    /// Virtual "character device" in `/dev`.  Each DEV geom consumes one provider, and its
+    /// consumer holds whatever access userland processes hold through the device node (e.g.,
+    /// `r1w0e0` while `dd if=/dev/ada0` runs); see `GeomMetadata::DEV`.
     DEV,
     /// Represents a partition table, such as GPT or MBR.
     PART,
@@ -55,15 +112,54 @@ pub enum GeomClass {
     Flashmap,
     /// A Memory Disk (virtual device)
     MD,
+    /// A userspace-backed device, e.g., `ggated(8)` network disks (`ggatec(8)`) or `ggatel(8)`.
+    /// `hastd(8)` also exposes its resources as GATE providers.
+    GATE,
+    /// Encrypted provider (`geli(8)`)
     ELI,
+    /// RAID1 mirror (`gmirror(8)`)
+    MIRROR,
+    /// Concatenated (JBOD) volume (`gconcat(8)`)
+    CONCAT,
+    /// RAID0 striped volume (`gstripe(8)`)
+    STRIPE,
+    /// One device reachable through several providers ("paths"), e.g., a dual-ported SAS disk
+    /// (`gmultipath(8)`).  Its `Mode` (e.g., `Active/Passive`) and `State` (`OPTIMAL` or
+    /// `DEGRADED`) are available in `Geom::unknown_config`.
+    MULTIPATH,
+    /// Legacy Vinum volume manager (`gvinum(8)`).  Its config is not modeled; any it dumps is
+    /// available in `Geom::unknown_config` and `Edge::unknown_config`.
+    VINUM,
+    /// Legacy concatenated/interleaved disk (`ccdconfig(8)`)
+    CCD,
+    /// Pluggable I/O scheduler interposed on another provider (`gsched(8)`)
+    SCHED,
+    /// Transparent, fault-injecting layer over another provider (`gnop(8)`).  Its counters and
+    /// failure settings are available in `Geom::unknown_config`.
+    NOP,
     #[strum(serialize = "ZFS::ZVOL")]
     ZFSZVOL,
     #[strum(serialize = "ZFS::VDEV")]
     ZFSVDEV,
+    /// Any class not listed above, by name.
+    #[strum(default)]
+    Other(String),
+}
+
+impl GeomClass {
+    /// The kernel's name for the class, e.g., `"PART"` or `"ZFS::VDEV"`.  Unlike `as_ref()`, this
+    /// is the class name for `GeomClass::Other`, too.
+    pub fn name(&self) -> &str {
+        return match self {
+            Self::Other(name) => name,
+            _ => self.as_ref(),
+        };
+    }
 }
 
 /// Specific partition schemes for `GeomClass::PART` geom `PartMetadata`.
-#[derive(AsRefStr, Debug, EnumIter, EnumString)]
+#[derive(AsRefStr, Clone, Debug, EnumIter, EnumString, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum PartScheme {
     /// Apple Partition Map (historical)
     APM,
@@ -94,41 +190,534 @@ pub enum PartScheme {
 ///   other can be recovered.
 /// * EBR scheme: An internal inconsistency exists in EBR's metadata.
 /// * Any scheme: There is some internal inconsistency, such as overlapping partitions.
-#[derive(AsRefStr, Debug, EnumIter, EnumString)]
+#[derive(AsRefStr, Clone, Debug, EnumIter, EnumString, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum PartState {
     CORRUPT,
     OK,
 }
 
 /// Metadata associated with `GeomClass::PART` `Geom`s.
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct PartMetadata {
     /// The partitioning scheme
-    scheme: PartScheme,
+    pub scheme: PartScheme,
     /// The number of partitions in this table
-    entries: u64,
+    pub entries: u64,
     /// First allocatable LBA
-    first: u64,
+    pub first: u64,
     /// Last alloctable LBA
-    last: u64,
+    pub last: u64,
     /// Historical: "S" in "CHS geometry"
-    fwsectors: u64,
+    pub fwsectors: u64,
     /// Historical: "H" in "CHS geometry"
-    fwheads: u64,
+    pub fwheads: u64,
     /// Internal consistency of the partition table
-    state: PartState,
+    pub state: PartState,
     /// If the partition table has been modified and not yet written
-    modified: bool,
+    pub modified: bool,
+}
+
+/// Metadata associated with a `Geom`.
+///
+/// The enum variant depends on the `GeomClass` of the `Geom`.
+#[derive(AsRefStr, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum GeomMetadata {
+    /// `GeomMetadata::PART` is the partition table metadata of a `GeomClass::PART` `Geom`.
+    PART(PartMetadata),
+    /// `GeomMetadata::VFS` describes a `GeomClass::VFS` `Geom`, which represents a mounted
+    /// filesystem's consumer of some provider.
+    ///
+    /// VFS geoms have no config section; the kernel names them `"<fstype>.<provider name>"` (see
+    /// `g_vfs_open()` in `sys/geom/geom_vfs.c`), and that is what we decode here.
+    VFS {
+        /// The filesystem type, e.g., `"ffs"`, `"msdosfs"`, or `"cd9660"`.
+        fstype: String,
+        /// The name of the provider the filesystem was mounted from, e.g., `"ada0p2"` or
+        /// `"gpt/rootfs"`.
+        provider: String,
+    },
+    /// `GeomMetadata::ELI` is the configuration of an attached `geli(8)` encrypted provider.
+    ELI {
+        /// The version of the on-disk GELI metadata.  Providers initialized by older releases
+        /// keep their original version until upgraded with `geli version`.
+        version: u64,
+        /// Flags such as `"BOOT"`, `"GELIBOOT"`, `"ONETIME"`, or `"AUTH"`.  Empty if none are set.
+        flags: Vec<String>,
+        /// How the cryptography is performed: `"hardware"`, `"software"`, or `"accelerated
+        /// software"`.
+        crypto: String,
+        /// E.g., `"AES-XTS"`
+        encryption_algorithm: String,
+        /// The data encryption key length, in bits
+        key_length: u64,
+        /// If the `AUTH` flag is set, the data authentication algorithm, e.g., `"HMAC/SHA256"`
+        authentication_algorithm: Option<String>,
+        /// The total number of data encryption keys
+        keys_total: u64,
+        /// The number of data encryption keys currently allocated in memory
+        keys_allocated: u64,
+        /// Which of the two Master Key slots was used to attach the provider (absent for
+        /// `ONETIME` providers)
+        used_key: Option<u64>,
+        /// `"ACTIVE"` or `"SUSPENDED"` (absent on older kernels)
+        state: Option<String>,
+    },
+    /// `GeomMetadata::GATE` is the configuration of a `geom_gate` device, whose I/O is serviced
+    /// by a userspace daemon (`ggatec(8)`, `ggatel(8)`, or `hastd(8)`).
+    GATE {
+        /// `"read-only"`, `"write-only"`, or `"read-write"`
+        access: String,
+        /// I/O request timeout, in seconds
+        timeout: u64,
+        /// A free-form description set by the daemon, e.g., `"host:/dev/ada1"` for `ggatec(8)`
+        info: String,
+        /// The unit number, as in `/dev/ggate<unit>` (HAST providers are named differently)
+        unit: u64,
+        /// The number of requests queued for the daemon (absent on older kernels)
+        queue_count: Option<u64>,
+        /// The maximum number of queued requests (absent on older kernels)
+        queue_size: Option<u64>,
+        /// If reads are serviced directly by some other provider, its name
+        read_provider: Option<String>,
+        /// The offset of `read_provider` reads, in bytes
+        read_offset: Option<u64>,
+    },
+    /// `GeomMetadata::CCD` describes a `ccdconfig(8)` concatenated or interleaved disk.
+    CCD {
+        /// The number of component providers
+        components: u64,
+        /// The interleave factor, in sectors; 0 for a plain concatenation.  Absent if the kernel
+        /// does not dump it.
+        interleave: Option<u64>,
+    },
+    /// `GeomMetadata::SCHED` describes a `gsched(8)` I/O scheduler geom.
+    SCHED {
+        /// The scheduling algorithm, e.g., `"rr"` (round-robin)
+        algorithm: String,
+    },
+    /// `GeomMetadata::DEV` describes a `GeomClass::DEV` geom, i.e., a `/dev` node.  DEV geoms have
+    /// no config section; this is derived from the mode of their consumer.
+    DEV {
+        /// The access held through the device node by userland `open(2)`s.  `r0w0e0` if the node
+        /// is not open.  This is counted in the provider's mode in addition to the access of
+        /// the provider's other consumers, such as a mounted filesystem.
+        mode: Mode,
+    },
+}
+
+impl GeomMetadata {
+    fn gate_from_raw(g: &raw::Geom) -> Result<Option<Box<GeomMetadata>>, Error> {
+        let raw = match &g.config {
+            Some(c) if c.access.is_some() => c,
+            _ => return Ok(None),
+        };
+        Ok(Some(Box::new(Self::GATE {
+            access: raw.access.as_ref().ok_or(Error::GraphError)?.to_owned(),
+            timeout: raw.timeout.ok_or(Error::GraphError)?,
+            info: raw.info.as_ref().ok_or(Error::GraphError)?.to_owned(),
+            unit: raw.unit.ok_or(Error::GraphError)?,
+            queue_count: raw.queue_count,
+            queue_size: raw.queue_size,
+            read_provider: raw.read_provider.as_ref().map(|v| v.to_owned()),
+            read_offset: raw.read_offset,
+        })))
+    }
+
+    fn ccd_from_raw(g: &raw::Geom) -> Box<GeomMetadata> {
+        Box::new(Self::CCD {
+            components: g.consumers.len() as u64,
+            interleave: g.config.as_ref().and_then(|c| c.interleave),
+        })
+    }
+
+    fn sched_from_raw(g: &raw::Geom) -> Option<Box<GeomMetadata>> {
+        let algorithm = g.config.as_ref()?.algo.as_ref()?;
+        Some(Box::new(Self::SCHED {
+            algorithm: algorithm.to_owned(),
+        }))
+    }
+
+    /// If this geom embeds a versioned on-disk metadata format (e.g., `ELI`), its version.
+    pub fn version(&self) -> Option<u64> {
+        match self {
+            Self::ELI { version, .. } => Some(*version),
+            _ => None,
+        }
+    }
+
+    fn eli_from_raw(g: &raw::Geom) -> Result<Option<Box<GeomMetadata>>, Error> {
+        // ELI geoms that are still being set up (or torn down) dump no config at all.
+        let raw = match &g.config {
+            Some(c) if c.version.is_some() => c,
+            _ => return Ok(None),
+        };
+        let flags = raw.flags.as_ref().ok_or(Error::GraphError)?;
+        Ok(Some(Box::new(Self::ELI {
+            version: raw.version.ok_or(Error::GraphError)?,
+            flags: flags
+                .split(',')
+                .map(|f| f.trim())
+                .filter(|f| !f.is_empty() && *f != "NONE")
+                .map(|f| f.to_owned())
+                .collect(),
+            crypto: raw.crypto.as_ref().ok_or(Error::GraphError)?.to_owned(),
+            encryption_algorithm: raw
+                .encryption_algorithm
+                .as_ref()
+                .ok_or(Error::GraphError)?
+                .to_owned(),
+            key_length: raw.key_length.ok_or(Error::GraphError)?,
+            authentication_algorithm: raw.authentication_algorithm.as_ref().map(|v| v.to_owned()),
+            keys_total: raw.keys_total.ok_or(Error::GraphError)?,
+            keys_allocated: raw.keys_allocated.ok_or(Error::GraphError)?,
+            used_key: raw.used_key,
+            state: raw.status.as_ref().map(|v| v.to_owned()),
+        })))
+    }
+
+    fn vfs_from_raw(g: &raw::Geom) -> Result<Box<GeomMetadata>, Error> {
+        let mut parts = g.name.splitn(2, '.');
+        let fstype = parts.next().ok_or(Error::GraphError)?;
+        let provider = parts.next().ok_or(Error::GraphError)?;
+        Ok(Box::new(Self::VFS {
+            fstype: fstype.to_owned(),
+            provider: provider.to_owned(),
+        }))
+    }
+}
+
+/// Attributes of a `GeomClass::PART` partition entry, as reported by `gpart show -p`.
+///
+/// Which attributes are valid depends on the `PartScheme`.
+#[derive(AsRefStr, Clone, Debug, EnumString, Eq, PartialEq)]
+pub enum PartAttrib {
+    /// MBR, EBR, and VTOC8: the "active" (bootable) partition
+    #[strum(serialize = "active")]
+    Active,
+    /// GPT: attempt to boot from this partition
+    #[strum(serialize = "bootme")]
+    BootMe,
+    /// GPT: attempt to boot from this partition once, then clear `BootMe` and `BootOnce`
+    #[strum(serialize = "bootonce")]
+    BootOnce,
+    /// GPT: a `BootOnce` boot from this partition failed
+    #[strum(serialize = "bootfailed")]
+    BootFailed,
+    /// GPT: set the PMBR partition active, to work around some Lenovo firmware
+    #[strum(serialize = "lenovofix")]
+    LenovoFix,
+    /// Any other attribute, by name.
+    #[strum(default)]
+    Other(String),
+}
+
+/// The canonical FreeBSD GEOM aliases for partition types, which `gpart(8)` accepts and reports
+/// (`PartInfo::type_`) in place of scheme-specific values; see `g_part_alias_list` in
+/// `sys/geom/part/g_part.c`.
+///
+/// Not every alias is valid in every `PartScheme`.
+#[derive(AsRefStr, Clone, Copy, Debug, EnumIter, EnumString, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum PartAlias {
+    #[strum(serialize = "apple-apfs")]
+    AppleAPFS,
+    #[strum(serialize = "apple-boot")]
+    AppleBoot,
+    #[strum(serialize = "apple-core-storage")]
+    AppleCoreStorage,
+    #[strum(serialize = "apple-hfs")]
+    AppleHFS,
+    #[strum(serialize = "apple-label")]
+    AppleLabel,
+    #[strum(serialize = "apple-raid")]
+    AppleRAID,
+    #[strum(serialize = "apple-raid-offline")]
+    AppleRAIDOffline,
+    #[strum(serialize = "apple-tv-recovery")]
+    AppleTVRecovery,
+    #[strum(serialize = "apple-ufs")]
+    AppleUFS,
+    #[strum(serialize = "apple-zfs")]
+    AppleZFS,
+    #[strum(serialize = "bios-boot")]
+    BIOSBoot,
+    #[strum(serialize = "chromeos-firmware")]
+    ChromeOSFirmware,
+    #[strum(serialize = "chromeos-kernel")]
+    ChromeOSKernel,
+    #[strum(serialize = "chromeos-reserved")]
+    ChromeOSReserved,
+    #[strum(serialize = "chromeos-root")]
+    ChromeOSRoot,
+    #[strum(serialize = "dragonfly-ccd")]
+    DragonFlyCCD,
+    #[strum(serialize = "dragonfly-hammer")]
+    DragonFlyHammer,
+    #[strum(serialize = "dragonfly-hammer2")]
+    DragonFlyHammer2,
+    #[strum(serialize = "dragonfly-label32")]
+    DragonFlyLabel32,
+    #[strum(serialize = "dragonfly-label64")]
+    DragonFlyLabel64,
+    #[strum(serialize = "dragonfly-legacy")]
+    DragonFlyLegacy,
+    #[strum(serialize = "dragonfly-swap")]
+    DragonFlySwap,
+    #[strum(serialize = "dragonfly-ufs")]
+    DragonFlyUFS,
+    #[strum(serialize = "dragonfly-vinum")]
+    DragonFlyVinum,
+    #[strum(serialize = "ebr")]
+    Ebr,
+    #[strum(serialize = "efi")]
+    Efi,
+    #[strum(serialize = "fat16")]
+    Fat16,
+    #[strum(serialize = "fat32")]
+    Fat32,
+    #[strum(serialize = "fat32lba")]
+    Fat32Lba,
+    #[strum(serialize = "freebsd")]
+    FreeBSD,
+    #[strum(serialize = "freebsd-boot")]
+    FreeBSDBoot,
+    #[strum(serialize = "freebsd-nandfs")]
+    FreeBSDNandFS,
+    #[strum(serialize = "freebsd-swap")]
+    FreeBSDSwap,
+    #[strum(serialize = "freebsd-ufs")]
+    FreeBSDUFS,
+    #[strum(serialize = "freebsd-vinum")]
+    FreeBSDVinum,
+    #[strum(serialize = "freebsd-zfs")]
+    FreeBSDZFS,
+    #[strum(serialize = "hifive-bbl")]
+    HiFiveBBL,
+    #[strum(serialize = "hifive-fsbl")]
+    HiFiveFSBL,
+    #[strum(serialize = "linux-data")]
+    LinuxData,
+    #[strum(serialize = "linux-lvm")]
+    LinuxLVM,
+    #[strum(serialize = "linux-raid")]
+    LinuxRAID,
+    #[strum(serialize = "linux-swap")]
+    LinuxSwap,
+    #[strum(serialize = "mbr")]
+    Mbr,
+    #[strum(serialize = "ms-basic-data")]
+    MSBasicData,
+    #[strum(serialize = "ms-ldm-data")]
+    MSLDMData,
+    #[strum(serialize = "ms-ldm-metadata")]
+    MSLDMMetadata,
+    #[strum(serialize = "ms-recovery")]
+    MSRecovery,
+    #[strum(serialize = "ms-reserved")]
+    MSReserved,
+    #[strum(serialize = "ms-spaces")]
+    MSSpaces,
+    #[strum(serialize = "netbsd-ccd")]
+    NetBSDCCD,
+    #[strum(serialize = "netbsd-cgd")]
+    NetBSDCGD,
+    #[strum(serialize = "netbsd-ffs")]
+    NetBSDFFS,
+    #[strum(serialize = "netbsd-lfs")]
+    NetBSDLFS,
+    #[strum(serialize = "netbsd-raid")]
+    NetBSDRAID,
+    #[strum(serialize = "netbsd-swap")]
+    NetBSDSwap,
+    #[strum(serialize = "ntfs")]
+    Ntfs,
+    #[strum(serialize = "openbsd-data")]
+    OpenBSDData,
+    #[strum(serialize = "prep-boot")]
+    PRePBoot,
+    #[strum(serialize = "solaris-altsec")]
+    SolarisAltsec,
+    #[strum(serialize = "solaris-backup")]
+    SolarisBackup,
+    #[strum(serialize = "solaris-boot")]
+    SolarisBoot,
+    #[strum(serialize = "solaris-home")]
+    SolarisHome,
+    #[strum(serialize = "solaris-reserved")]
+    SolarisReserved,
+    #[strum(serialize = "solaris-root")]
+    SolarisRoot,
+    #[strum(serialize = "solaris-swap")]
+    SolarisSwap,
+    #[strum(serialize = "solaris-var")]
+    SolarisVar,
+    #[strum(serialize = "u-boot-env")]
+    UBootEnv,
+    #[strum(serialize = "vmware-reserved")]
+    VMwareReserved,
+    #[strum(serialize = "vmware-vmfs")]
+    VMwareVMFS,
+    #[strum(serialize = "vmware-vmkdiag")]
+    VMwareVmkDiag,
+    #[strum(serialize = "vmware-vsanhdr")]
+    VMwareVsanHdr,
+}
+
+/// The namespace of a `GeomClass::LABEL` provider, derived from its name prefix (e.g., `gpt/` in
+/// `gpt/rootfs`).
+///
+/// The LABEL class aggregates several unrelated label sources; this distinguishes, say, a GPT
+/// partition label from a UFS volume label.
+#[derive(AsRefStr, Clone, Debug, EnumString, Eq, PartialEq)]
+pub enum LabelKind {
+    /// `gpt/`: GPT partition label
+    #[strum(serialize = "gpt")]
+    GPT,
+    /// `gptid/`: GPT partition UUID
+    #[strum(serialize = "gptid")]
+    GPTID,
+    /// `diskid/`: disk identifier (serial number)
+    #[strum(serialize = "diskid")]
+    DISKID,
+    /// `label/`: generic label written by `glabel(8)`
+    #[strum(serialize = "label")]
+    LABEL,
+    /// `ufs/`: UFS volume label
+    #[strum(serialize = "ufs")]
+    UFS,
+    /// `ufsid/`: UFS filesystem id
+    #[strum(serialize = "ufsid")]
+    UFSID,
+    /// `msdosfs/`: FAT volume label
+    #[strum(serialize = "msdosfs")]
+    MSDOSFS,
+    /// `ntfs/`: NTFS volume label
+    #[strum(serialize = "ntfs")]
+    NTFS,
+    /// `iso9660/`: CD-ROM volume label
+    #[strum(serialize = "iso9660")]
+    ISO9660,
+    /// `ext2fs/`: ext2/3/4 volume label
+    #[strum(serialize = "ext2fs")]
+    EXT2FS,
+    /// Any other prefix (or the empty string, if the name has no `/`).
+    #[strum(default)]
+    Other(String),
+}
+
+impl Default for LabelKind {
+    fn default() -> Self {
+        Self::Other(String::new())
+    }
+}
+
+impl LabelKind {
+    /// Classifies a LABEL provider name, such as `"gpt/rootfs"`.
+    pub fn from_name(name: &str) -> LabelKind {
+        let prefix = match name.find('/') {
+            Some(idx) => &name[..idx],
+            None => "",
+        };
+        // Infallible, thanks to the `Other` default.
+        return LabelKind::from_str(prefix).unwrap_or_else(|_| LabelKind::Other(prefix.to_owned()));
+    }
 }
 
 /// GEOM internal access reference counts
-#[derive(Debug)]
+///
+/// A provider's mode is the sum of the modes of all of its consumers.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Mode {
     read: u16,
     write: u16,
     exclusive: u16,
 }
 
+impl Mode {
+    /// The number of read references
+    pub fn read(&self) -> u16 {
+        return self.read;
+    }
+
+    /// The number of write references
+    pub fn write(&self) -> u16 {
+        return self.write;
+    }
+
+    /// The number of exclusive references
+    pub fn exclusive(&self) -> u16 {
+        return self.exclusive;
+    }
+
+    /// Whether any access is held at all (i.e., the mode is not `r0w0e0`)
+    pub fn is_open(&self) -> bool {
+        return *self != Mode::default();
+    }
+
+    /// Whether every count in `self` is at most the corresponding count in `other`
+    pub fn is_within(&self, other: &Mode) -> bool {
+        return self.read <= other.read
+            && self.write <= other.write
+            && self.exclusive <= other.exclusive;
+    }
+}
+
+impl std::ops::Add for Mode {
+    type Output = Mode;
+
+    fn add(self, other: Mode) -> Mode {
+        return Mode {
+            read: self.read.saturating_add(other.read),
+            write: self.write.saturating_add(other.write),
+            exclusive: self.exclusive.saturating_add(other.exclusive),
+        };
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "r{}w{}e{}", self.read, self.write, self.exclusive)
+    }
+}
+
+/// A one-line summary: the class and name, e.g., `PART ada0 (GPT)`.
+impl std::fmt::Display for Geom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.class.name(), self.name)?;
+        match self.metadata.as_deref() {
+            Some(GeomMetadata::PART(m)) => write!(f, " ({})", m.scheme.as_ref())?,
+            Some(GeomMetadata::ELI {
+                encryption_algorithm,
+                ..
+            }) => write!(f, " ({})", encryption_algorithm)?,
+            _ => {}
+        }
+        if self.withered {
+            write!(f, " [withered]")?;
+        }
+        return Ok(());
+    }
+}
+
+/// A one-line summary: the provider name, size, and mode, e.g., `ada0p2 100G r1w1e1`, followed
+/// by the partition type of partitions.
+impl std::fmt::Display for Edge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.name, self.size(), self.mode)?;
+        if let Some(EdgeMetadata::PART(p)) = self.metadata.as_deref() {
+            write!(f, " {}", p.type_)?;
+        }
+        if self.withered {
+            write!(f, " [withered]")?;
+        }
+        return Ok(());
+    }
+}
+
 impl std::str::FromStr for Mode {
     type Err = Error;
 
@@ -142,91 +731,144 @@ impl std::str::FromStr for Mode {
     }
 }
 
+/// Metadata of the `Edge` from a `GeomClass::DISK` `Geom`; see `EdgeMetadata::DISK`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DiskInfo {
+    /// Historical: "H" in "CHS geometry"
+    pub fwheads: u64,
+    /// Historical: "S" in "CHS geometry"
+    pub fwsectors: u64,
+    /// Zero indicates solid-state drives; non-zero represents spinning drives.
+    pub rotationrate: u64,
+    /// Serial number, or some other identifier
+    pub ident: String,
+    /// LUN identifier.  Logical Unit Numbers come from SCSI, but are synthesized for other
+    /// non-SCSI devices in FreeBSD's CAM.
+    pub lunid: String,
+    /// A description.  For example, disk make and model.
+    pub descr: String,
+}
+
+/// Metadata of a partition entry's `Edge` from a `GeomClass::PART` `Geom`; see
+/// `EdgeMetadata::PART`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PartInfo {
+    /// First LBA of partition entry
+    pub start: u64,
+    /// Last LBA of partition entry
+    pub end: u64,
+    /// Index of partition entry in partition table
+    pub index: u64,
+    /// A canonical FreeBSD GEOM alias for the filesystem type metadata associated with this
+    /// partition entry.  E.g., both MBR `0xef` and GPT "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+    /// are mapped to the same alias: `G_PART_ALIAS_EFI`, or `"efi"`.
+    ///
+    /// The complete list may be found in `sys/geom/part/g_part.c` in the `g_part_alias_list`
+    /// table; see `PartInfo::alias()`.  Types without an alias are reported as their raw
+    /// value, as for `::rawtype`.
+    pub type_: String,
+    /// The byte offset of the start of the partition entry
+    pub offset: u64,
+    /// The length of the partition entry, in bytes
+    pub length: u64,
+    /// Attributes of the partition entry, such as `PartAttrib::BootMe` or `Active`.  Often
+    /// empty.
+    pub attrib: Vec<PartAttrib>,
+    // These ones are optional / vary by partition scheme.  These are the GPT ones:
+    /// If provided by scheme (e.g., GPT): a label associated with this partition entry
+    pub label: Option<String>,
+    /// If provided by scheme (e.g., GPT, MBR): the raw value that was decoded to the `::type_`
+    /// alias.  String representation varies by the specific scheme implementation.
+    pub rawtype: Option<String>,
+    /// If provided by scheme (e.g., GPT): a unique identifier (UUID, GUID) for this partition.
+    /// These are generated randomly when partitions are created, and are unique unless cloned
+    /// or intentionally duplicated.
+    pub rawuuid: Option<String>,
+    /// If provided by scheme (e.g., GPT, MBR): The EFI path to this partition.  E.g.,
+    /// `HD(1,GPT,12345678-9abc-...,0x80,0xc8)` (GPT) or `HD(2,MBR,0x12345678,0x100,0x100)`
+    /// (MBR).
+    pub efimedia: Option<String>,
+}
+
+impl PartInfo {
+    /// The `type_` of the partition entry as a `PartAlias`, if it has one.
+    pub fn alias(&self) -> Option<PartAlias> {
+        return PartAlias::from_str(&self.type_).ok();
+    }
+}
+
+/// Metadata of the `Edge` from a `GeomClass::LABEL` `Geom`; see `EdgeMetadata::LABEL`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct LabelInfo {
+    /// The namespace of the label, derived from the provider name
+    pub kind: LabelKind,
+    /// Always zero
+    pub index: u64,
+    /// Always zero
+    pub offset: u64,
+    /// The `length` of the volume represented by this label, in bytes
+    pub length: u64,
+    /// `length` divided by 512
+    pub seclength: u64,
+    /// Always zero
+    pub secoffset: u64,
+}
+
 // Keyed off the type of the geom associated with the provider.
 /// Metadata associated with an `Edge`.
 ///
 /// The enum variant depends on the `GeomClass` of the `Geom` associated with the "provider"
 /// represented by this `Edge`.
-#[derive(AsRefStr, Debug, EnumIter, EnumString)]
+#[derive(AsRefStr, Clone, Debug, EnumIter, EnumString, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum EdgeMetadata {
     /// `EdgeMetadata::DISK` is metadata associated with the `Edge` between a `GeomClass::DISK`
     /// `Geom` and some lower `Geom` in the tree.
-    DISK {
-        /// Historical: "H" in "CHS geometry"
-        fwheads: u64,
-        /// Historical: "S" in "CHS geometry"
-        fwsectors: u64,
-        /// Zero indicates solid-state drives; non-zero represents spinning drives.
-        rotationrate: u64,
-        /// Serial number, or some other identifier
-        ident: String,
-        /// LUN identifier.  Logical Unit Numbers come from SCSI, but are synthesized for other
-        /// non-SCSI devices in FreeBSD's CAM.
-        lunid: String,
-        /// A description.  For example, disk make and model.
-        descr: String,
-    },
+    DISK(DiskInfo),
     /// `EdgeMetadata::PART` is metadata associated with the `Edge` between a `GeomClass::PART` and
     /// some lower `Geom` in the tree.
     ///
     /// These edges exist for each partition *entry*, whereas there is only one `PART` `Geom` for
     /// the entire partition *table*.
-    PART {
-        /// First LBA of partition entry
-        start: u64,
-        /// Last LBA of partition entry
-        end: u64,
-        /// Index of partition entry in partition table
-        index: u64,
-        /// A canonical FreeBSD GEOM alias for the filesystem type metadata associated with this
-        /// partition entry.  E.g., both MBR `0xef` and GPT "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
-        /// are mapped to the same alias: `G_PART_ALIAS_EFI`, or `"efi"`.
-        ///
-        /// The complete list may be found in `sys/geom/part/g_part.c` in the `g_part_alias_list`
-        /// table.
-        type_: String, // theoretically, a big enum, but we'd have to extract it from g_part.c
-        /// The byte offset of the start of the partition entry
-        offset: u64,
-        /// The length of the partition entry, in bytes
-        length: u64,
-        // XXX Missing 'attrib's entirely
-        // These ones are optional / vary by partition scheme.  These are the GPT ones:
-        /// If provided by scheme (e.g., GPT): a label associated with this partition entry
-        label: Option<String>,
-        /// If provided by scheme (e.g., GPT, MBR): the raw value that was decoded to the `::type_`
-        /// alias.  String representation varies by the specific scheme implementation.
-        rawtype: Option<String>,
-        /// If provided by scheme (e.g., GPT): a unique identifier (UUID, GUID) for this partition.
-        /// These are generated randomly when partitions are created, and are unique unless cloned
-        /// or intentionally duplicated.
-        rawuuid: Option<String>,
-        /// If provided by scheme (e.g., GPT, MBR): The EFI path to this partition.  E.g.,
-        /// `HD(1,GPT,12345678-9abc-...,0x80,0xc8)` (GPT) or `HD(2,MBR,0x12345678,0x100,0x100)`
-        /// (MBR).
-        efimedia: Option<String>,
-    },
+    PART(PartInfo),
     /// `EdgeMetadata::LABEL` is metadata associated with the `Edge` between a `GeomClass::LABEL`
     /// and some lower `Geom` in the tree.
     ///
     /// It is mostly a vestigial implementation detail of FreeBSD's LABEL GEOM class.
-    LABEL {
-        /// Always zero
-        index: u64,
-        /// Always zero
-        offset: u64,
-        /// The `length` of the volume represented by this label, in bytes
-        length: u64,
-        /// `length` divided by 512
-        seclength: u64,
-        /// Always zero
-        secoffset: u64,
-    },
+    LABEL(LabelInfo),
 }
 
 impl EdgeMetadata {
+    /// The DISK metadata, if this is the `Edge` from a `GeomClass::DISK` `Geom`.
+    pub fn as_disk(&self) -> Option<&DiskInfo> {
+        match self {
+            Self::DISK(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// The partition entry metadata, if this is an `Edge` from a `GeomClass::PART` `Geom`.
+    pub fn as_part(&self) -> Option<&PartInfo> {
+        match self {
+            Self::PART(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// The label metadata, if this is an `Edge` from a `GeomClass::LABEL` `Geom`.
+    pub fn as_label(&self) -> Option<&LabelInfo> {
+        match self {
+            Self::LABEL(l) => Some(l),
+            _ => None,
+        }
+    }
+
     fn disk_from_raw(p: &raw::Provider) -> Result<Box<EdgeMetadata>, Error> {
         let raw = &p.config;
-        Ok(Box::new(Self::DISK {
+        Ok(Box::new(Self::DISK(DiskInfo {
             fwheads: raw.fwheads.ok_or(Error::GraphError)?,
             fwsectors: raw.fwsectors.ok_or(Error::GraphError)?,
             rotationrate: raw
@@ -238,42 +880,49 @@ impl EdgeMetadata {
             ident: raw.ident.as_ref().ok_or(Error::GraphError)?.to_owned(),
             lunid: raw.lunid.as_ref().ok_or(Error::GraphError)?.to_owned(),
             descr: raw.descr.as_ref().ok_or(Error::GraphError)?.to_owned(),
-        }))
+        })))
     }
 
     fn part_from_raw(p: &raw::Provider) -> Result<Box<EdgeMetadata>, Error> {
         let raw = &p.config;
-        Ok(Box::new(Self::PART {
+        Ok(Box::new(Self::PART(PartInfo {
             start: raw.start.ok_or(Error::GraphError)?,
             end: raw.end.ok_or(Error::GraphError)?,
             index: raw.index.ok_or(Error::GraphError)?,
             type_: raw.type_.as_ref().ok_or(Error::GraphError)?.to_owned(),
             offset: raw.offset.ok_or(Error::GraphError)?,
             length: raw.length.ok_or(Error::GraphError)?,
+            attrib: raw
+                .attrib
+                .iter()
+                .map(|a| PartAttrib::from_str(a))
+                .collect::<Result<_, _>>()?,
 
             label: raw.label.as_ref().map(|v| v.to_owned()),
             rawtype: raw.rawtype.as_ref().map(|v| v.to_owned()),
             rawuuid: raw.rawuuid.as_ref().map(|v| v.to_owned()),
             efimedia: raw.efimedia.as_ref().map(|v| v.to_owned()),
-        }))
+        })))
     }
 
     fn label_from_raw(p: &raw::Provider) -> Result<Box<EdgeMetadata>, Error> {
         let raw = &p.config;
-        Ok(Box::new(Self::LABEL {
+        Ok(Box::new(Self::LABEL(LabelInfo {
+            kind: LabelKind::from_name(&p.name),
             index: raw.index.ok_or(Error::GraphError)?,
             offset: raw.offset.ok_or(Error::GraphError)?,
             length: raw.length.ok_or(Error::GraphError)?,
             seclength: raw.seclength.ok_or(Error::GraphError)?,
             secoffset: raw.secoffset.ok_or(Error::GraphError)?,
-        }))
+        })))
     }
 }
 
 /// An `Edge` connects two `Geom`s in a tree.
 ///
 /// In GEOM terminology, it represents a Consumer-Provider pair.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Edge {
     /// The name of the `Edge`, established by the "provider" (associated with the parent `Geom`).
     ///
@@ -281,8 +930,13 @@ pub struct Edge {
     /// names the represent the entire partition table, but individual `Edge`s from them will have
     /// names specific to a single partition entry.
     pub name: String,
-    /// GEOM internal access reference counts
+    /// The provider's GEOM access reference counts: the total over all of its consumers, i.e.,
+    /// every `Edge` out of the same provider.
     pub mode: Mode,
+    /// The access held by this `Edge`'s consumer alone.  Compare with `mode` to see whether the
+    /// consumer is the (only) writer or exclusive holder of a shared provider; see also
+    /// `Graph::access_holders_iter()`.
+    pub consumer_mode: Mode,
     /// The size of the logical volume represented, in bytes
     pub mediasize: u64,
     /// The native sector size of the underlying volume, in bytes
@@ -292,11 +946,98 @@ pub struct Edge {
     pub stripeoffset: u64,
     /// Metadata for `Edge`s descending from `DISK`, `PART`, or `LABEL` `Geom`s.
     pub metadata: Option<Box<EdgeMetadata>>,
+    /// Any config keys of the provider that this library does not recognize, by name.
+    pub unknown_config: BTreeMap<String, String>,
 
     /// Child, or consumer `Geom`.
     pub consumer_geom: NodeId,
     /// Parent, or provider `Geom`.
     pub provider_geom: NodeId,
+    /// Where this `Edge` came from in the `raw::Mesh`, if decoded with
+    /// `DecodeOptions::raw_links`; see `Edge::raw_provider()` and `Edge::raw_consumer()`.
+    pub raw_index: Option<RawEdgeIndex>,
+    /// The provider is being torn down (e.g., its disk was detached) and will go away once its
+    /// consumers close it.  Withering providers (and providers of withering geoms) have no config,
+    /// so `metadata` is `None`.
+    pub withered: bool,
+    /// The provider's I/O statistics, if it is a device counted by `devstat(9)` (e.g., a disk),
+    /// once `Graph::attach_devstat()` has been called, or GEOM's own counters for it, once
+    /// `Graph::attach_geom_devstat()` has been called.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub devstat: Option<Box<DeviceStats>>,
+}
+
+/// A provider without any consumers, which therefore has no `Edge`; see
+/// `Graph::orphan_providers()`.
+///
+/// GEOM classes normally attach a DEV consumer to every new provider, so orphans are transient
+/// (e.g., a provider created a moment before the snapshot) or the result of an unusual class.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct OrphanProvider {
+    /// The provider name, e.g., `"ada0p1"`
+    pub name: String,
+    pub mode: Mode,
+    pub mediasize: u64,
+    pub sectorsize: u64,
+    /// The `Geom` owning the provider
+    pub provider_geom: NodeId,
+    /// The provider is being torn down; see `Edge::withered`.
+    pub withered: bool,
+}
+
+impl Edge {
+    /// The `raw::Provider` this `Edge` was decoded from.  Returns `None` unless the `Graph` was
+    /// decoded from `mesh` with `DecodeOptions::raw_links`.
+    pub fn raw_provider<'m>(&self, mesh: &'m raw::Mesh) -> Option<&'m raw::Provider> {
+        let idx = self.raw_index?;
+        let geom = mesh
+            .classes
+            .get(idx.provider_geom.class)?
+            .geoms
+            .get(idx.provider_geom.geom)?;
+        return geom.providers.get(idx.provider);
+    }
+
+    /// The `raw::Consumer` this `Edge` was decoded from.  Returns `None` unless the `Graph` was
+    /// decoded from `mesh` with `DecodeOptions::raw_links`.
+    pub fn raw_consumer<'m>(&self, mesh: &'m raw::Mesh) -> Option<&'m raw::Consumer> {
+        let idx = self.raw_index?;
+        let geom = mesh
+            .classes
+            .get(idx.consumer_geom.class)?
+            .geoms
+            .get(idx.consumer_geom.geom)?;
+        return geom.consumers.get(idx.consumer);
+    }
+}
+
+/// A geom or provider with config keys this library does not model; see
+/// `Graph::unmodeled_config_report()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnmodeledConfig {
+    /// The class of the geom
+    pub class: GeomClass,
+    /// The geom name, or the provider name if `provider` is set
+    pub name: String,
+    /// The geom (or the geom owning the provider)
+    pub geom: NodeId,
+    /// The provider id, if the keys are from a provider's config rather than the geom's
+    pub provider: Option<u64>,
+    /// The unmodeled keys, sorted
+    pub keys: Vec<String>,
+}
+
+/// A labeled provider, as found by `Graph::find_by_label()`.
+#[derive(Clone, Copy, Debug)]
+pub struct LabelMatch<'a> {
+    /// An `Edge` out of the label's provider, e.g., `gpt/zroot0`.  If the label was found in a
+    /// partition entry rather than as a LABEL provider, this is the partition's `Edge`.
+    pub label_id: &'a EdgeId,
+    pub label: &'a Edge,
+    /// The `Edge` to the labeled provider underneath, e.g., `ada0p3`.
+    pub provider_id: &'a EdgeId,
+    pub provider: &'a Edge,
 }
 
 /// A unique identifier for a `Geom` in a `Graph`.
@@ -308,50 +1049,442 @@ pub type EdgeId = (u64, u64);
 ///
 /// (Math jargon: It is actually a "forest" of disconnected components, rather than a "graph," and
 /// those components form "trees.")
-#[derive(Debug)]
+///
+/// With the `serde` feature, a `Graph` can be serialized (e.g., to JSON) and loaded back on any
+/// OS.  The name and UUID indexes are not serialized; they are rebuilt on deserialization, which
+/// fails if the result does not pass `Graph::verify()`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(try_from = "GraphSnapshot")
+)]
 pub struct Graph {
     /// Contains all of the `Geom`s in the forest
     pub nodes: BTreeMap<NodeId, Geom>,
     /// Contains all of the `Edge`s in the forest
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_edges"))]
     pub edges: BTreeMap<EdgeId, Edge>,
     /// Represents the out-edges of each `Geom`, by id
     pub outedges: BTreeMap<NodeId, Vec<EdgeId>>,
     /// Represents the in-edges of each `Geom`, by id
     pub inedges: BTreeMap<NodeId, Vec<EdgeId>>,
+    /// Indexes each `Geom` by name (see `Graph::find_by_name()`).  Names are not unique; e.g., a
+    /// disk's DISK, PART, and DEV geoms all share the disk's name.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) names: BTreeMap<String, Vec<NodeId>>,
+    /// Indexes each `Geom` by class, then name (see `Graph::find_by_class_name()`).
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) class_names: BTreeMap<GeomClass, BTreeMap<String, Vec<NodeId>>>,
+    /// Providers without consumers, by provider id (see `Graph::orphan_providers()`).
+    pub orphans: BTreeMap<u64, OrphanProvider>,
+    /// Indexes partition `Edge`s by lowercase `PartInfo::rawuuid` (see
+    /// `Graph::find_by_part_uuid()`).  Only the first `Edge` out of each provider is indexed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) part_uuids: BTreeMap<String, EdgeId>,
+}
+
+// Serializers such as JSON's only support string (or number) map keys, so `edges` is serialized as
+// a sequence of `[EdgeId, Edge]` pairs.
+#[cfg(feature = "serde")]
+fn serialize_edges<S: serde::Serializer>(
+    edges: &BTreeMap<EdgeId, Edge>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    return serializer.collect_seq(edges.iter());
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_edges<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<EdgeId, Edge>, D::Error> {
+    let pairs: Vec<(EdgeId, Edge)> = serde::Deserialize::deserialize(deserializer)?;
+    return Ok(pairs.into_iter().collect());
+}
+
+/// The serialized fields of a `Graph`, without the indexes.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct GraphSnapshot {
+    nodes: BTreeMap<NodeId, Geom>,
+    #[serde(deserialize_with = "deserialize_edges")]
+    edges: BTreeMap<EdgeId, Edge>,
+    outedges: BTreeMap<NodeId, Vec<EdgeId>>,
+    inedges: BTreeMap<NodeId, Vec<EdgeId>>,
+    #[serde(default)]
+    orphans: BTreeMap<u64, OrphanProvider>,
+}
+
+// Serialized graphs may come from anywhere, so they are checked before the traversal APIs are let
+// loose on them.
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<GraphSnapshot> for Graph {
+    type Error = String;
+
+    fn try_from(snapshot: GraphSnapshot) -> Result<Self, String> {
+        let mut result = Graph {
+            nodes: snapshot.nodes,
+            edges: snapshot.edges,
+            outedges: snapshot.outedges,
+            inedges: snapshot.inedges,
+            orphans: snapshot.orphans,
+            ..Graph::new()
+        };
+        result.reindex();
+        let findings = result.verify();
+        if !findings.is_empty() {
+            return Err(format!("inconsistent graph: {:?}", findings));
+        }
+        return Ok(result);
+    }
+}
+
+// Enums with an `Other(String)` catch-all are serialized as the kernel's names for them, e.g.,
+// `"ZFS::VDEV"` or `"bootme"`.
+#[cfg(feature = "serde")]
+macro_rules! serialize_as_str {
+    ($($t:ty),*) => {$(
+        impl serde::Serialize for $t {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                return match self {
+                    Self::Other(name) => serializer.serialize_str(name),
+                    _ => serializer.serialize_str(self.as_ref()),
+                };
+            }
+        }
+    )*};
+}
+
+#[cfg(feature = "serde")]
+serialize_as_str!(GeomClass, PartAttrib, LabelKind);
+
+// The inverse of `serialize_as_str!`; unknown names map to `Other`.
+#[cfg(feature = "serde")]
+macro_rules! deserialize_from_str {
+    ($($t:ident),*) => {$(
+        impl<'de> serde::Deserialize<'de> for $t {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let name = String::deserialize(deserializer)?;
+                return Ok($t::from_str(&name).unwrap_or($t::Other(name)));
+            }
+        }
+    )*};
 }
 
+#[cfg(feature = "serde")]
+deserialize_from_str!(GeomClass, PartAttrib, LabelKind);
+
 impl Graph {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             nodes: BTreeMap::new(),
             edges: BTreeMap::new(),
             outedges: BTreeMap::new(),
             inedges: BTreeMap::new(),
+            names: BTreeMap::new(),
+            class_names: BTreeMap::new(),
+            orphans: BTreeMap::new(),
+            part_uuids: BTreeMap::new(),
+        }
+    }
+
+    /// Rebuilds the name and UUID indexes behind `find_by_name()`, `find_by_class_name()`, and
+    /// `find_by_part_uuid()`.  Call it after modifying `nodes` or `edges` directly; until then,
+    /// those lookups may miss added geoms or return nothing for removed ones.
+    pub fn reindex(&mut self) {
+        self.names.clear();
+        self.class_names.clear();
+        for (id, geom) in &self.nodes {
+            self.names
+                .entry(geom.name.to_owned())
+                .or_default()
+                .push(*id);
+            self.class_names
+                .entry(geom.class.clone())
+                .or_default()
+                .entry(geom.name.to_owned())
+                .or_default()
+                .push(*id);
+        }
+        self.part_uuids.clear();
+        for (id, edge) in &self.edges {
+            if let Some(EdgeMetadata::PART(PartInfo {
+                rawuuid: Some(uuid),
+                ..
+            })) = edge.metadata.as_deref()
+            {
+                self.part_uuids.entry(uuid.to_lowercase()).or_insert(*id);
+            }
+        }
+    }
+
+    /// Returns the `Geom` with the given id, if any.  `graph[id]` is the panicking equivalent.
+    pub fn get_node(&self, id: &NodeId) -> Option<&Geom> {
+        return self.nodes.get(id);
+    }
+
+    /// Returns the `Edge` with the given id, if any.  `graph[id]` is the panicking equivalent.
+    pub fn get_edge(&self, id: &EdgeId) -> Option<&Edge> {
+        return self.edges.get(id);
+    }
+
+    /// Finds the partition with the given GPT UUID (`PartInfo::rawuuid`), returning an
+    /// `Edge` out of the partition's provider and the `GeomClass::PART` geom owning it.
+    ///
+    /// The match is case-insensitive, and a `gptid/` prefix is ignored, so names from `fstab(5)`
+    /// can be passed as-is (after stripping `/dev/`).
+    pub fn find_by_part_uuid(&self, uuid: &str) -> Option<(&EdgeId, &Edge, &Geom)> {
+        let uuid = uuid.strip_prefix("gptid/").unwrap_or(uuid).to_lowercase();
+        let (edgeid, edge) = self.edges.get_key_value(self.part_uuids.get(&uuid)?)?;
+        return Some((edgeid, edge, self.nodes.get(&edge.provider_geom)?));
+    }
+
+    /// Resolves a device path, such as `"/dev/gpt/rootfs"` or `"ada0p2"`, to the `GeomClass::DEV`
+    /// geom(s) backing it.
+    ///
+    /// GEOM "withers" the other label aliases of a provider while it is open for writing, so a
+    /// `gpt/<label>` or `gptid/<uuid>` path with no DEV geom of its own is also matched against
+    /// partition entries, and resolves to the DEV geom of the partition.
+    pub fn resolve_dev_path(&self, path: &str) -> Vec<(&NodeId, &Geom)> {
+        let name = path.strip_prefix("/dev/").unwrap_or(path);
+        let result: Vec<_> = self
+            .find_by_name(name)
+            .filter(|(_, n)| n.class == GeomClass::DEV)
+            .collect();
+        if !result.is_empty() {
+            return result;
+        }
+
+        let part = if name.starts_with("gptid/") {
+            self.find_by_part_uuid(name).map(|(id, e, _)| (id, e))
+        } else if name.starts_with("gpt/") {
+            self.find_by_label(name)
+                .map(|m| (m.provider_id, m.provider))
+        } else {
+            None
+        };
+        let (part_id, part_edge) = match part {
+            Some(p) => p,
+            None => return result,
+        };
+        return self
+            .child_geoms_iter(&part_edge.provider_geom)
+            .filter(|(eid, _, n)| eid.1 == part_id.1 && n.class == GeomClass::DEV)
+            .map(|(_, e, _)| self.nodes.get_key_value(&e.consumer_geom).unwrap())
+            .collect();
+    }
+
+    /// Finds a labeled provider by label, such as `"gpt/zroot0"`, `"/dev/ufs/rootfs"`, or just
+    /// `"zroot0"` (a GPT partition label).
+    ///
+    /// LABEL providers are matched by name first; failing that (e.g., because the label withered
+    /// while the partition is open), GPT partition entries are matched by their label.
+    pub fn find_by_label(&self, label: &str) -> Option<LabelMatch<'_>> {
+        let label = label.strip_prefix("/dev/").unwrap_or(label);
+        let labeled = self.edges.iter().find(|(_, e)| {
+            e.name == label && self.nodes[&e.provider_geom].class == GeomClass::LABEL
+        });
+        if let Some((id, edge)) = labeled {
+            let (under_id, under) = self.parent_edges_iter(&edge.provider_geom).next()?;
+            return Some(LabelMatch {
+                label_id: id,
+                label: edge,
+                provider_id: under_id,
+                provider: under,
+            });
+        }
+
+        let part_label = label.strip_prefix("gpt/").unwrap_or(label);
+        let (id, edge) = self.edges.iter().find(|(_, e)| {
+            let part = e.metadata.as_deref().and_then(EdgeMetadata::as_part);
+            part.and_then(|p| p.label.as_deref()) == Some(part_label)
+        })?;
+        return Some(LabelMatch {
+            label_id: id,
+            label: edge,
+            provider_id: id,
+            provider: edge,
+        });
+    }
+
+    /// Finds the disk with the given serial number (`DiskInfo::ident`), returning an
+    /// `Edge` out of the disk's provider and the `GeomClass::DISK` geom.  Partitions and other
+    /// consumers can be enumerated from there with `child_geoms_iter()` or
+    /// `descendants_with_depth_iter()`.
+    ///
+    /// Leading and trailing whitespace is ignored.  An empty `ident` matches nothing.
+    pub fn find_disk_by_ident(&self, ident: &str) -> Option<(&EdgeId, &Edge, &Geom)> {
+        return self.find_disk(
+            |m| m.as_disk().is_some_and(|d| d.ident.trim() == ident.trim()),
+            ident,
+        );
+    }
+
+    /// Like `find_disk_by_ident()`, but matches the LUN identifier (`DiskInfo::lunid`),
+    /// e.g., a WWN.
+    pub fn find_disk_by_lunid(&self, lunid: &str) -> Option<(&EdgeId, &Edge, &Geom)> {
+        return self.find_disk(
+            |m| m.as_disk().is_some_and(|d| d.lunid.trim() == lunid.trim()),
+            lunid,
+        );
+    }
+
+    fn find_disk<F>(&self, pred: F, key: &str) -> Option<(&EdgeId, &Edge, &Geom)>
+    where
+        F: Fn(&EdgeMetadata) -> bool,
+    {
+        if key.trim().is_empty() {
+            return None;
         }
+        let (id, edge) = self
+            .edges
+            .iter()
+            .find(|(_, e)| e.metadata.as_deref().is_some_and(&pred))?;
+        return Some((id, edge, self.nodes.get(&edge.provider_geom)?));
+    }
+
+    /// Returns an `Iterator` which yields each `(&NodeId, &Geom)` for which `pred` returns true.
+    ///
+    /// For example, `graph.find_nodes(|g| g.class == GeomClass::ELI)` yields every encrypted
+    /// provider's geom.
+    pub fn find_nodes<'a, F>(
+        &'a self,
+        pred: F,
+    ) -> Box<dyn Iterator<Item = (&'a NodeId, &'a Geom)> + 'a>
+    where
+        F: Fn(&Geom) -> bool + 'a,
+    {
+        Box::new(self.nodes.iter().filter(move |(_, n)| pred(n)))
+    }
+
+    /// Returns an `Iterator` which yields each `(&EdgeId, &Edge)` for which `pred` returns true.
+    ///
+    /// For example, `graph.find_edges(|e| e.sectorsize != 512)` yields every `Edge` out of a
+    /// provider with non-512-byte sectors.
+    pub fn find_edges<'a, F>(
+        &'a self,
+        pred: F,
+    ) -> Box<dyn Iterator<Item = (&'a EdgeId, &'a Edge)> + 'a>
+    where
+        F: Fn(&Edge) -> bool + 'a,
+    {
+        Box::new(self.edges.iter().filter(move |(_, e)| pred(e)))
+    }
+
+    /// Returns an `Iterator` which yields each `(&NodeId, &Geom)` named `name`, in `NodeId` order.
+    pub fn find_by_name<'a>(
+        &'a self,
+        name: &str,
+    ) -> Box<dyn Iterator<Item = (&'a NodeId, &'a Geom)> + 'a> {
+        let ids = self.names.get(name).map(|v| v.as_slice()).unwrap_or(&[]);
+        Box::new(
+            ids.iter()
+                .filter_map(move |id| self.nodes.get_key_value(id)),
+        )
+    }
+
+    /// Finds the `Geom` of the given class and name, e.g., the `GeomClass::DISK` named `"ada0"`
+    /// rather than the PART or DEV geoms of the same name.
+    ///
+    /// Names are unique within most classes; where they are not (e.g., every SWAP geom is named
+    /// `"swap"`), the lowest `NodeId` is returned.  Use `find_by_name()` to see them all.
+    pub fn find_by_class_name(&self, class: &GeomClass, name: &str) -> Option<(&NodeId, &Geom)> {
+        let id = self.class_names.get(class)?.get(name)?.first()?;
+        return self.nodes.get_key_value(id);
     }
 
     /// Returns an `Iterator` which yields each `(&NodeId, &Geom)` for roots (i.e., `rank` 1).
-    pub fn roots_iter(&self) -> RootsIter {
+    ///
+    /// Roots are not necessarily `GeomClass::DISK`; memory disks (`MD`), ZFS volumes (`ZFSZVOL`),
+    /// `GATE` devices, and others are roots too.
+    pub fn roots_iter(&self) -> RootsIter<'_> {
         RootsIter {
             iter: self.nodes.iter(),
         }
     }
 
+    /// Returns an `Iterator` which yields each `(&NodeId, &Geom)` for roots of the given `class`.
+    ///
+    /// For example, `graph.roots_by_class(&GeomClass::DISK)` yields physical disks but not memory
+    /// disks.
+    pub fn roots_by_class<'a>(
+        &'a self,
+        class: &'a GeomClass,
+    ) -> Box<dyn Iterator<Item = (&'a NodeId, &'a Geom)> + 'a> {
+        Box::new(self.roots_iter().filter(move |(_, n)| n.class == *class))
+    }
+
+    /// Runs `f` on every root (see `Graph::roots_iter()`) and returns the results in root order.
+    ///
+    /// Analyses of independent roots (e.g., disks) do not depend on each other; with the `rayon`
+    /// feature enabled, they are run in parallel.
+    pub fn map_roots<R, F>(&self, f: F) -> Vec<R>
+    where
+        R: Send,
+        F: Fn(&NodeId, &Geom) -> R + Send + Sync,
+    {
+        let roots: Vec<_> = self.roots_iter().collect();
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            return roots.into_par_iter().map(|(id, g)| f(id, g)).collect();
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            return roots.into_iter().map(|(id, g)| f(id, g)).collect();
+        }
+    }
+
+    /// Lists every geom and provider whose config contained keys this library does not model (see
+    /// `Geom::unknown_config` and `Edge::unknown_config`), sorted by class and name.
+    ///
+    /// Useful for discovering kernel data worth adding typed support for.
+    pub fn unmodeled_config_report(&self) -> Vec<UnmodeledConfig> {
+        let mut result = Vec::new();
+        for (id, geom) in &self.nodes {
+            if !geom.unknown_config.is_empty() {
+                result.push(UnmodeledConfig {
+                    class: geom.class.clone(),
+                    name: geom.name.to_owned(),
+                    geom: *id,
+                    provider: None,
+                    keys: geom.unknown_config.keys().cloned().collect(),
+                });
+            }
+        }
+        // Several consumers may share a provider; report it once.
+        let mut seen = BTreeSet::new();
+        for ((_, prov_id), edge) in &self.edges {
+            if edge.unknown_config.is_empty() || !seen.insert(*prov_id) {
+                continue;
+            }
+            result.push(UnmodeledConfig {
+                class: self.nodes[&edge.provider_geom].class.clone(),
+                name: edge.name.to_owned(),
+                geom: edge.provider_geom,
+                provider: Some(*prov_id),
+                keys: edge.unknown_config.keys().cloned().collect(),
+            });
+        }
+        result.sort_by(|a, b| {
+            (a.class.name(), &a.name, a.provider).cmp(&(b.class.name(), &b.name, b.provider))
+        });
+        return result;
+    }
+
     /// Given the `NodeId` of a `Geom`, returns an `Iterator` which yields each `EdgeId` descending
     /// from the node.
-    pub fn child_edgeids_iter(&self, id: &NodeId) -> ChildEdgeIdsIter {
-        let v = self.inedges.get(&id);
+    pub fn child_edgeids_iter(&self, id: &NodeId) -> ChildEdgeIdsIter<'_> {
+        let v = self.inedges.get(id);
         ChildEdgeIdsIter {
-            iter: match v {
-                Some(edges) => Some(edges.iter()),
-                None => None,
-            },
+            iter: v.map(|edges| edges.iter()),
         }
     }
 
     /// Given the `NodeId` of a `Geom`, returns an `Iterator` which yields each `(&EdgeId, &Edge)`
     /// descending from the node.
-    pub fn child_edges_iter(&self, id: &NodeId) -> ChildEdgesIter {
+    pub fn child_edges_iter(&self, id: &NodeId) -> ChildEdgesIter<'_> {
         ChildEdgesIter {
             edges: &self.edges,
             iter: self.child_edgeids_iter(id),
@@ -360,7 +1493,7 @@ impl Graph {
 
     /// Given the `NodeId` of a `Geom`, returns an `Iterator` which yields each `(&EdgeId, &Edge,
     /// &Geom)` descending from the node.
-    pub fn child_geoms_iter(&self, id: &NodeId) -> ChildGeomsIter {
+    pub fn child_geoms_iter(&self, id: &NodeId) -> ChildGeomsIter<'_> {
         ChildGeomsIter {
             nodes: &self.nodes,
             iter: self.child_edges_iter(id),
@@ -368,6 +1501,10 @@ impl Graph {
     }
 
     /// Given the `NodeId` of a `Geom`, iterate every Geom descending from it.
+    ///
+    /// Direct children are yielded first, then their descendents; see
+    /// `descendants_with_depth_iter()` for a depth-first walk, with depths, suitable for rendering
+    /// trees.
     pub fn descendents_iter<'a>(
         &'a self,
         id: &NodeId,
@@ -375,24 +1512,380 @@ impl Graph {
         Box::new(
             self.child_geoms_iter(id).chain(
                 self.child_geoms_iter(id)
-                    .map(move |(_, e, _)| self.descendents_iter(&e.consumer_geom))
-                    .flatten(),
+                    .flat_map(move |(_, e, _)| self.descendents_iter(&e.consumer_geom)),
             ),
         )
     }
-}
 
-#[derive(Debug)]
-pub struct RootsIter<'a> {
-    iter: std::collections::btree_map::Iter<'a, NodeId, Geom>,
-}
+    /// Given the `NodeId` of a `Geom`, walks its whole subtree depth-first (pre-order), yielding
+    /// `(depth, &EdgeId, &Edge, &Geom)` for each descendent.  Direct children have depth 1.
+    ///
+    /// Each child is immediately followed by its own subtree, so the output can be rendered as an
+    /// indented tree as-is.
+    pub fn descendants_with_depth_iter(&self, id: &NodeId) -> DescendantsWithDepthIter<'_> {
+        DescendantsWithDepthIter {
+            graph: self,
+            stack: vec![self.child_geoms_iter(id)],
+        }
+    }
 
-impl<'a> Iterator for RootsIter<'a> {
-    type Item = (&'a NodeId, &'a Geom);
+    /// Given the `NodeId` of a `Geom`, returns an `Iterator` which yields each `(&EdgeId, &Edge)`
+    /// the node consumes, i.e., the edges to its parents.
+    pub fn parent_edges_iter(&self, id: &NodeId) -> ParentEdgesIter<'_> {
+        ParentEdgesIter {
+            edges: &self.edges,
+            iter: self.outedges.get(id).map(|edges| edges.iter()),
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let next = self.iter.next();
+    /// Given the `NodeId` of a `Geom`, returns an `Iterator` which yields each `(&EdgeId, &Edge,
+    /// &Geom)` the node consumes, where the `Geom` is the parent (provider) geom.
+    pub fn parent_geoms_iter(&self, id: &NodeId) -> ParentGeomsIter<'_> {
+        ParentGeomsIter {
+            nodes: &self.nodes,
+            iter: self.parent_edges_iter(id),
+        }
+    }
+
+    /// Given the `NodeId` of a `Geom`, iterate every Geom it transitively consumes, up to and
+    /// including the roots.  For example, walking up from a DEV node yields the LABEL, PART, and
+    /// DISK geoms backing it.
+    ///
+    /// Like `descendents_iter()`, a geom reachable by several paths (e.g., the disk under both
+    /// halves of a mirror built from two of its partitions) is yielded once per path.
+    pub fn ancestors_iter<'a>(
+        &'a self,
+        id: &NodeId,
+    ) -> Box<dyn Iterator<Item = (&'a EdgeId, &'a Edge, &'a Geom)> + 'a> {
+        Box::new(
+            self.parent_geoms_iter(id).chain(
+                self.parent_geoms_iter(id)
+                    .flat_map(move |(_, e, _)| self.ancestors_iter(&e.provider_geom)),
+            ),
+        )
+    }
+
+    /// Given the `NodeId` of a `Geom`, returns every path from it down to a root (e.g., a DISK),
+    /// as lists of `NodeId`s starting with `id` itself and ending with the root.
+    ///
+    /// There is one path per way of reaching a root: a filesystem on a two-way mirror has two
+    /// paths, one per disk.  A root has the single path `[id]`; an unknown `id` has none.
+    pub fn lineage(&self, id: &NodeId) -> Vec<Vec<NodeId>> {
+        if !self.nodes.contains_key(id) {
+            return Vec::new();
+        }
+        let mut parents = self.parent_geoms_iter(id).peekable();
+        if parents.peek().is_none() {
+            return vec![vec![*id]];
+        }
+        let mut result = Vec::new();
+        for (_, edge, _) in parents {
+            for mut path in self.lineage(&edge.provider_geom) {
+                path.insert(0, *id);
+                result.push(path);
+            }
+        }
+        return result;
+    }
+
+    /// Given the `NodeId` of a `GeomClass::VFS` `Geom`, returns the `(&EdgeId, &Edge)` connecting
+    /// the mounted filesystem to the provider it was mounted from.
+    ///
+    /// Returns `None` if the node does not exist or is not a VFS geom.
+    pub fn vfs_provider_edge(&self, id: &NodeId) -> Option<(&EdgeId, &Edge)> {
+        if self.nodes.get(id)?.class != GeomClass::VFS {
+            return None;
+        }
+        let edgeid = self.outedges.get(id)?.first()?;
+        return Some((edgeid, self.edges.get(edgeid)?));
+    }
+
+    /// Given the `EdgeId` of any `Edge` out of some provider, returns an `Iterator` which yields
+    /// each `(&EdgeId, &Edge, &Geom)` of a `GeomClass::VFS` consumer of that same provider.
+    ///
+    /// I.e., this answers "which mounted filesystem is holding this partition open?"
+    pub fn vfs_consumers_iter<'a>(
+        &'a self,
+        id: &EdgeId,
+    ) -> Box<dyn Iterator<Item = (&'a EdgeId, &'a Edge, &'a Geom)> + 'a> {
+        let prov_id = id.1;
+        match self.edges.get(id) {
+            None => Box::new(std::iter::empty()),
+            Some(edge) => Box::new(
+                self.child_geoms_iter(&edge.provider_geom)
+                    .filter(move |(eid, _, g)| eid.1 == prov_id && g.class == GeomClass::VFS),
+            ),
+        }
+    }
+
+    /// Given the `EdgeId` of any `Edge` out of some provider, returns an `Iterator` which yields
+    /// each `(&EdgeId, &Edge, &Geom)` consuming that same provider (including the given one), such
+    /// as its DEV node, a mounted filesystem, and any LABEL geoms that have tasted it.
+    pub fn provider_consumers_iter<'a>(
+        &'a self,
+        id: &EdgeId,
+    ) -> Box<dyn Iterator<Item = (&'a EdgeId, &'a Edge, &'a Geom)> + 'a> {
+        let prov_id = id.1;
+        match self.edges.get(id) {
+            None => Box::new(std::iter::empty()),
+            Some(edge) => Box::new(
+                self.child_geoms_iter(&edge.provider_geom)
+                    .filter(move |(eid, _, _)| eid.1 == prov_id),
+            ),
+        }
+    }
+
+    /// Like `provider_consumers_iter()`, but yields only the consumers actually holding access
+    /// to the provider (`Edge::consumer_mode`), e.g., to find out who has a disk open for writing
+    /// before detaching it.
+    pub fn access_holders_iter<'a>(
+        &'a self,
+        id: &EdgeId,
+    ) -> Box<dyn Iterator<Item = (&'a EdgeId, &'a Edge, &'a Geom)> + 'a> {
+        Box::new(
+            self.provider_consumers_iter(id)
+                .filter(|(_, e, _)| e.consumer_mode.is_open()),
+        )
+    }
+
+    /// Returns an `Iterator` which yields each `(&EdgeId, &Edge)` currently in use as swap space.
+    ///
+    /// Each active swap device is represented by a `GeomClass::SWAP` `Geom` (named `"swap"`)
+    /// consuming the provider.  SWAP geoms have no config section, so the provider `Edge` is the
+    /// only interesting data.
+    pub fn swap_devices<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a EdgeId, &'a Edge)> + 'a> {
+        Box::new(
+            self.nodes
+                .iter()
+                .filter(|(_, n)| n.class == GeomClass::SWAP)
+                .filter_map(move |(id, _)| self.outedges.get(id))
+                .flatten()
+                .filter_map(move |eid| self.edges.get_key_value(eid)),
+        )
+    }
+
+    /// Returns an `Iterator` which yields each `(&provider id, &OrphanProvider)` for providers
+    /// without consumers.  Such providers have no `Edge`, so they are invisible to the rest of
+    /// the `Graph` API.
+    pub fn orphan_providers(&self) -> std::collections::btree_map::Iter<'_, u64, OrphanProvider> {
+        return self.orphans.iter();
+    }
+
+    /// Returns an `Iterator` which yields each `/dev` node (`GeomClass::DEV` `Geom`) as
+    /// `(&NodeId, &Geom, &EdgeId, &Edge)`, with the `Edge` to the provider it sits on.
+    ///
+    /// Each DEV geom consumes exactly one provider, whose name matches the node's path under
+    /// `/dev`.
+    pub fn dev_nodes<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (&'a NodeId, &'a Geom, &'a EdgeId, &'a Edge)> + 'a> {
+        Box::new(
+            self.nodes
+                .iter()
+                .filter(|(_, n)| n.class == GeomClass::DEV)
+                .filter_map(move |(id, geom)| {
+                    let (eid, edge) = self.edges.get_key_value(self.outedges.get(id)?.first()?)?;
+                    Some((id, geom, eid, edge))
+                }),
+        )
+    }
+
+    /// Returns each mounted filesystem (`GeomClass::VFS` geom) stored at least partly on spinning
+    /// media, with the `Edge` of each rotating DISK beneath it.
+    ///
+    /// Disks whose rotation rate the kernel reports as unknown are decoded as 0 and treated as
+    /// non-rotating.
+    pub fn rotational_filesystems(&self) -> Vec<(&NodeId, &Geom, Vec<&Edge>)> {
+        let mut result = Vec::new();
+        for (id, geom) in self.nodes.iter().filter(|(_, n)| n.class == GeomClass::VFS) {
+            let mut disks: Vec<&Edge> = Vec::new();
+            for path in self.lineage(id) {
+                let root = path.last().unwrap();
+                let edge = match self.child_edges_iter(root).next() {
+                    Some((_, e)) => e,
+                    None => continue,
+                };
+                let spinning = edge
+                    .metadata
+                    .as_deref()
+                    .and_then(EdgeMetadata::as_disk)
+                    .is_some_and(|d| d.rotationrate > 0);
+                if spinning && !disks.iter().any(|d| d.provider_geom == edge.provider_geom) {
+                    disks.push(edge);
+                }
+            }
+            if !disks.is_empty() {
+                result.push((id, geom, disks));
+            }
+        }
+        return result;
+    }
+
+    /// Given the `EdgeId` of an `Edge`, follows its provider down through any `PART` and `LABEL`
+    /// layers to the root, and returns the `(&EdgeId, &Edge)` of the root's provider (e.g., the
+    /// DISK provider `"ada0"` for `"gpt/rootfs"`).
+    ///
+    /// Returns `None` under the same conditions as `Graph::absolute_disk_offset()`.
+    pub fn disk_edge(&self, id: &EdgeId) -> Option<(&EdgeId, &Edge)> {
+        let (mut edgeid, mut edge) = self.edges.get_key_value(id)?;
+        loop {
+            match self.nodes.get(&edge.provider_geom)?.class {
+                GeomClass::DISK | GeomClass::MD => return Some((edgeid, edge)),
+                GeomClass::PART | GeomClass::LABEL => {}
+                _ => return None,
+            }
+            edgeid = self.outedges.get(&edge.provider_geom)?.first()?;
+            edge = self.edges.get(edgeid)?;
+        }
+    }
+
+    /// Given the `NodeId` of a root `Geom` (e.g., a DISK) and a byte offset on it, returns each
+    /// `(&EdgeId, &Edge, &Geom)` whose provider covers that byte, in depth-first order.
+    ///
+    /// For example, for a byte inside `ada0p2` this returns the `ada0` edges into the `PART` and
+    /// `DEV` geoms, then the `ada0p2` edges into its consumers (`DEV`, `LABEL`, `VFS`, ...), then
+    /// any edges out of those `LABEL`s, and so on.  This is handy when the kernel reports an I/O
+    /// error at an absolute disk offset.
+    ///
+    /// Offsets are translated through `PART` and `LABEL` geoms only.  Consumers of other classes
+    /// (e.g., `ELI` or `MIRROR`) are included, but not descended into.
+    pub fn who_owns(&self, disk: &NodeId, offset: u64) -> Vec<(&EdgeId, &Edge, &Geom)> {
+        let mut result = Vec::new();
+        self.who_owns_rec(disk, offset, &mut result);
+        return result;
+    }
+
+    fn who_owns_rec<'a>(
+        &'a self,
+        id: &NodeId,
+        offset: u64,
+        result: &mut Vec<(&'a EdgeId, &'a Edge, &'a Geom)>,
+    ) {
+        for (edgeid, edge, consumer) in self.child_geoms_iter(id) {
+            let start = match edge.metadata.as_deref() {
+                Some(EdgeMetadata::PART(PartInfo { offset, .. })) => *offset,
+                Some(EdgeMetadata::LABEL(LabelInfo { offset, .. })) => *offset,
+                _ => 0,
+            };
+            if offset < start || offset - start >= edge.mediasize {
+                continue;
+            }
+            result.push((edgeid, edge, consumer));
+            if consumer.class == GeomClass::PART || consumer.class == GeomClass::LABEL {
+                self.who_owns_rec(&edge.consumer_geom, offset - start, result);
+            }
+        }
+    }
+
+    /// Returns each `(&EdgeId, &Edge, resource name)` exposing a `hastd(8)` resource.
+    ///
+    /// HAST has no GEOM class of its own.  On the node where a resource has the primary role,
+    /// `hastd` exposes it as a `GeomClass::GATE` provider named `hast/<resource>`; on secondary
+    /// nodes (and for resources in the init role), nothing is exposed.  So every resource returned
+    /// here is in the primary role on this host.
+    ///
+    /// If a provider has several consumers (e.g., `DEV` and `VFS`), only one of its `Edge`s is
+    /// returned.
+    pub fn hast_resources(&self) -> Vec<(&EdgeId, &Edge, &str)> {
+        let mut seen = BTreeSet::new();
+        return self
+            .nodes
+            .iter()
+            .filter(|(_, n)| n.class == GeomClass::GATE)
+            .flat_map(|(id, _)| self.child_edges_iter(id))
+            .filter(|(eid, _)| seen.insert(eid.1))
+            .filter_map(|(eid, e)| Some((eid, e, e.name.strip_prefix("hast/")?)))
+            .collect();
+    }
+
+    /// Returns an `Iterator` which yields each `(&NodeId, &Geom, version)` of class `class` whose
+    /// on-disk metadata version is older than `current`.
+    ///
+    /// For example, `graph.outdated_metadata_iter(&GeomClass::ELI, 7)` finds `geli(8)` providers
+    /// that need `geli version` to be upgraded to version 7 metadata.
+    pub fn outdated_metadata_iter<'a>(
+        &'a self,
+        class: &'a GeomClass,
+        current: u64,
+    ) -> Box<dyn Iterator<Item = (&'a NodeId, &'a Geom, u64)> + 'a> {
+        Box::new(
+            self.nodes
+                .iter()
+                .filter(move |(_, n)| n.class == *class)
+                .filter_map(|(id, n)| Some((id, n, n.metadata.as_ref()?.version()?)))
+                .filter(move |(_, _, v)| *v < current),
+        )
+    }
+
+    /// Given the `EdgeId` of an `Edge`, returns the byte offset of the start of its provider on
+    /// the underlying physical disk (or other root, like an `MD`).
+    ///
+    /// Partition and label offsets are relative to their immediate parent provider; this composes
+    /// them through any number of nested `PART` (e.g., a BSD disklabel inside an MBR slice) and
+    /// `LABEL` layers.
+    ///
+    /// Returns `None` if the edge does not exist, or if some layer between it and the root does
+    /// not map its provider linearly onto its parent (e.g., `ELI`, `RAID`, or `ZFS::VDEV`).
+    pub fn absolute_disk_offset(&self, id: &EdgeId) -> Option<u64> {
+        let mut total: u64 = 0;
+        let mut edge = self.edges.get(id)?;
+        loop {
+            let provgeom = self.nodes.get(&edge.provider_geom)?;
+            let offset = match (&provgeom.class, edge.metadata.as_deref()) {
+                (GeomClass::DISK, _) | (GeomClass::MD, _) => return Some(total),
+                (GeomClass::PART, Some(EdgeMetadata::PART(PartInfo { offset, .. }))) => *offset,
+                (GeomClass::LABEL, Some(EdgeMetadata::LABEL(LabelInfo { offset, .. }))) => *offset,
+                _ => return None,
+            };
+            total = total.checked_add(offset)?;
+
+            let parent_edgeid = self.outedges.get(&edge.provider_geom)?.first()?;
+            edge = self.edges.get(parent_edgeid)?;
+        }
+    }
+}
+
+/// Looks up a `Geom` by id.
+///
+/// # Panics
+///
+/// Panics if the `Graph` has no such node; see `Graph::get_node()`.
+impl std::ops::Index<NodeId> for Graph {
+    type Output = Geom;
+
+    fn index(&self, id: NodeId) -> &Geom {
+        return self
+            .get_node(&id)
+            .unwrap_or_else(|| panic!("no node {:#x} in graph", id));
+    }
+}
+
+/// Looks up an `Edge` by id.
+///
+/// # Panics
+///
+/// Panics if the `Graph` has no such edge; see `Graph::get_edge()`.
+impl std::ops::Index<EdgeId> for Graph {
+    type Output = Edge;
+
+    fn index(&self, id: EdgeId) -> &Edge {
+        return self
+            .get_edge(&id)
+            .unwrap_or_else(|| panic!("no edge ({:#x}, {:#x}) in graph", id.0, id.1));
+    }
+}
+
+#[derive(Debug)]
+pub struct RootsIter<'a> {
+    iter: std::collections::btree_map::Iter<'a, NodeId, Geom>,
+}
+
+impl<'a> Iterator for RootsIter<'a> {
+    type Item = (&'a NodeId, &'a Geom);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next = self.iter.next();
             if next.is_none() {
                 return next;
             }
@@ -403,8 +1896,20 @@ impl<'a> Iterator for RootsIter<'a> {
             }
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return (0, self.iter.size_hint().1);
+    }
+}
+
+impl<'a> DoubleEndedIterator for RootsIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        return self.iter.by_ref().rev().find(|(_, n)| n.rank == 1);
+    }
 }
 
+impl<'a> FusedIterator for RootsIter<'a> {}
+
 #[derive(Debug)]
 pub struct ChildEdgeIdsIter<'a> {
     iter: Option<std::slice::Iter<'a, EdgeId>>,
@@ -419,186 +1924,1230 @@ impl<'a> Iterator for ChildEdgeIdsIter<'a> {
             Some(iter) => iter.next(),
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len();
+        return (len, Some(len));
+    }
+}
+
+impl<'a> DoubleEndedIterator for ChildEdgeIdsIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match &mut self.iter {
+            None => None,
+            Some(iter) => iter.next_back(),
+        }
+    }
+}
+
+impl<'a> ExactSizeIterator for ChildEdgeIdsIter<'a> {
+    fn len(&self) -> usize {
+        return self.iter.as_ref().map_or(0, |iter| iter.len());
+    }
 }
 
+impl<'a> FusedIterator for ChildEdgeIdsIter<'a> {}
+
 #[derive(Debug)]
 pub struct ChildEdgesIter<'a> {
     edges: &'a BTreeMap<EdgeId, Edge>,
     iter: ChildEdgeIdsIter<'a>,
 }
 
+impl<'a> ChildEdgesIter<'a> {
+    fn lookup(&self, edgeid: &'a EdgeId) -> (&'a EdgeId, &'a Edge) {
+        return (edgeid, self.edges.get(edgeid).unwrap());
+    }
+}
+
 impl<'a> Iterator for ChildEdgesIter<'a> {
     type Item = (&'a EdgeId, &'a Edge);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match &self.iter.next() {
-            None => None,
-            Some(edgeid) => Some((edgeid, self.edges.get(edgeid).unwrap())),
-        }
+        let edgeid = self.iter.next()?;
+        return Some(self.lookup(edgeid));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return self.iter.size_hint();
+    }
+}
+
+impl<'a> DoubleEndedIterator for ChildEdgesIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let edgeid = self.iter.next_back()?;
+        return Some(self.lookup(edgeid));
     }
 }
 
+impl<'a> ExactSizeIterator for ChildEdgesIter<'a> {}
+
+impl<'a> FusedIterator for ChildEdgesIter<'a> {}
+
 #[derive(Debug)]
 pub struct ChildGeomsIter<'a> {
     nodes: &'a BTreeMap<NodeId, Geom>,
     iter: ChildEdgesIter<'a>,
 }
 
+impl<'a> ChildGeomsIter<'a> {
+    fn lookup(&self, (edgeid, edge): (&'a EdgeId, &'a Edge)) -> (&'a EdgeId, &'a Edge, &'a Geom) {
+        return (edgeid, edge, self.nodes.get(&edge.consumer_geom).unwrap());
+    }
+}
+
 impl<'a> Iterator for ChildGeomsIter<'a> {
     type Item = (&'a EdgeId, &'a Edge, &'a Geom);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match &self.iter.next() {
-            None => None,
-            Some((edgeid, edge)) => {
-                Some((edgeid, edge, self.nodes.get(&edge.consumer_geom).unwrap()))
-            }
-        }
+        let kv = self.iter.next()?;
+        return Some(self.lookup(kv));
     }
-}
 
-fn scan_ptr(s: &str) -> Result<u64, Error> {
-    let p = scan_fmt!(s, "{x}", [hex u64])?;
-    return Ok(p);
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return self.iter.size_hint();
+    }
 }
 
-// XXX double check that all providers are attached to a consumer, but I think they are via DEV.
-/// Converts a logical GEOM forest from the unprocessed, `geom::raw::Mesh` format to the more
-/// convenient and strongly-typed `geom::Graph` format.
-pub fn decode_graph(mesh: &raw::Mesh) -> Result<Graph, Error> {
-    let mut result = Graph::new();
-
-    // First pass: create nodes; temp: collect consumers, producers, and their pairs.
-    let mut cons: BTreeMap<u64, &raw::Consumer> = BTreeMap::new();
-    let mut provs: BTreeMap<u64, &raw::Provider> = BTreeMap::new();
-    let mut conprods: BTreeSet<EdgeId> = BTreeSet::new();
+impl<'a> DoubleEndedIterator for ChildGeomsIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let kv = self.iter.next_back()?;
+        return Some(self.lookup(kv));
+    }
+}
 
-    for class in &mesh.classes {
-        let classkind = GeomClass::from_str(&class.name)?;
+impl<'a> ExactSizeIterator for ChildGeomsIter<'a> {}
 
-        for geom in &class.geoms {
-            let geom_id = scan_ptr(&geom.id)?;
-            let mut config = None;
-            if classkind == GeomClass::PART {
-                let rawconfig = &geom.config.as_ref().ok_or(Error::GraphError)?;
-                let partscheme =
-                    PartScheme::from_str(&rawconfig.scheme.as_ref().ok_or(Error::GraphError)?)?;
-                let partstate =
-                    PartState::from_str(&rawconfig.state.as_ref().ok_or(Error::GraphError)?)?;
+impl<'a> FusedIterator for ChildGeomsIter<'a> {}
 
-                config = Some(Box::new(PartMetadata {
-                    scheme: partscheme,
-                    state: partstate,
-                    entries: rawconfig.entries.ok_or(Error::GraphError)?,
-                    first: rawconfig.first.ok_or(Error::GraphError)?,
-                    last: rawconfig.last.ok_or(Error::GraphError)?,
-                    fwsectors: rawconfig.fwsectors.ok_or(Error::GraphError)?,
-                    fwheads: rawconfig.fwheads.ok_or(Error::GraphError)?,
-                    modified: rawconfig.modified.ok_or(Error::GraphError)?,
-                }));
-            }
-            result.nodes.insert(
-                geom_id,
-                Geom {
-                    class: classkind,
-                    name: geom.name.to_owned(),
-                    rank: geom.rank,
-                    metadata: config,
-                },
-            );
+#[derive(Debug)]
+pub struct DescendantsWithDepthIter<'a> {
+    graph: &'a Graph,
+    // One iterator per level of the walk; the last is the deepest.
+    stack: Vec<ChildGeomsIter<'a>>,
+}
 
-            for c in &geom.consumers {
-                let cons_id = scan_ptr(&c.id)?;
-                let prov_id = scan_ptr(&c.provider_ref.ref_)?;
+impl<'a> Iterator for DescendantsWithDepthIter<'a> {
+    type Item = (usize, &'a EdgeId, &'a Edge, &'a Geom);
 
-                cons.insert(cons_id, &c);
-                conprods.insert((cons_id, prov_id));
-            }
-            for p in &geom.providers {
-                let prov_id = scan_ptr(&p.id)?;
-                provs.insert(prov_id, &p);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let depth = self.stack.len();
+            match self.stack.last_mut()?.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some((edgeid, edge, geom)) => {
+                    self.stack
+                        .push(self.graph.child_geoms_iter(&edge.consumer_geom));
+                    return Some((depth, edgeid, edge, geom));
+                }
             }
         }
     }
+}
 
-    // Second pass: create Con-Prov Edges; fill inedges, outedges.
-    for (cid, pid) in &conprods {
-        let rawcons = cons.get(&cid).ok_or(Error::GraphError)?;
-        let rawprov = provs.get(&pid).ok_or(Error::GraphError)?;
-        // DEV geoms consume providers with access r0w0e0; allow it.
-        // I guess it is technically possible for non-zero consumers to share providers, in which
-        // case their access would sum to the provider's?  Maybe we should just track both values.
-        if &rawcons.mode != &rawprov.mode && &rawcons.mode != "r0w0e0" {
-            return Err(Error::GraphError);
-        }
-
-        // Geom associated with the provider in this pair.
-        let provgeom_id = scan_ptr(&rawprov.geom_ref.ref_)?;
-        let provgeom = result.nodes.get(&provgeom_id).ok_or(Error::GraphError)?;
-        // And consumer.
-        let consgeom_id = scan_ptr(&rawcons.geom_ref.ref_)?;
+impl<'a> FusedIterator for DescendantsWithDepthIter<'a> {}
 
-        let edge = Edge {
-            name: rawprov.name.to_owned(),
-            mode: Mode::from_str(&rawprov.mode)?,
-            mediasize: rawprov.mediasize,
-            sectorsize: rawprov.sectorsize,
-            stripesize: rawprov.stripesize,
-            stripeoffset: rawprov.stripeoffset,
-            metadata: match provgeom.class {
-                GeomClass::DISK => Some(EdgeMetadata::disk_from_raw(rawprov)?),
-                GeomClass::PART => Some(EdgeMetadata::part_from_raw(rawprov)?),
-                GeomClass::LABEL => Some(EdgeMetadata::label_from_raw(rawprov)?),
-                _ => None,
-            },
-            consumer_geom: consgeom_id,
-            provider_geom: provgeom_id,
-        };
+#[derive(Debug)]
+pub struct ParentEdgesIter<'a> {
+    edges: &'a BTreeMap<EdgeId, Edge>,
+    iter: Option<std::slice::Iter<'a, EdgeId>>,
+}
 
-        let edge_id = (*cid, *pid);
-        result.edges.insert(edge_id, edge);
+impl<'a> ParentEdgesIter<'a> {
+    fn lookup(&self, edgeid: &'a EdgeId) -> (&'a EdgeId, &'a Edge) {
+        return (edgeid, self.edges.get(edgeid).unwrap());
+    }
+}
 
-        let invec = result.inedges.entry(provgeom_id).or_insert(Vec::new());
-        (*invec).push(edge_id);
+impl<'a> Iterator for ParentEdgesIter<'a> {
+    type Item = (&'a EdgeId, &'a Edge);
 
-        let outvec = result.outedges.entry(consgeom_id).or_insert(Vec::new());
-        (*outvec).push(edge_id);
+    fn next(&mut self) -> Option<Self::Item> {
+        let edgeid = self.iter.as_mut()?.next()?;
+        return Some(self.lookup(edgeid));
     }
 
-    return Ok(result);
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len();
+        return (len, Some(len));
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::{graph, raw};
-    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+impl<'a> DoubleEndedIterator for ParentEdgesIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let edgeid = self.iter.as_mut()?.next_back()?;
+        return Some(self.lookup(edgeid));
+    }
+}
 
-    #[test]
-    fn large_sample_decode() {
-        let rawmesh = raw::parse_xml(&SAMPLE_XML).unwrap();
-        graph::decode_graph(&rawmesh).unwrap();
+impl<'a> ExactSizeIterator for ParentEdgesIter<'a> {
+    fn len(&self) -> usize {
+        return self.iter.as_ref().map_or(0, |iter| iter.len());
     }
+}
 
-    #[test]
-    fn roots_iterator() {
-        let rawmesh = raw::parse_xml(&SAMPLE_XML).unwrap();
-        let g = graph::decode_graph(&rawmesh).unwrap();
+impl<'a> FusedIterator for ParentEdgesIter<'a> {}
 
-        for (_, root) in g.roots_iter() {
-            assert_eq!(root.class, graph::GeomClass::DISK);
-        }
+#[derive(Debug)]
+pub struct ParentGeomsIter<'a> {
+    nodes: &'a BTreeMap<NodeId, Geom>,
+    iter: ParentEdgesIter<'a>,
+}
+
+impl<'a> ParentGeomsIter<'a> {
+    fn lookup(&self, (edgeid, edge): (&'a EdgeId, &'a Edge)) -> (&'a EdgeId, &'a Edge, &'a Geom) {
+        return (edgeid, edge, self.nodes.get(&edge.provider_geom).unwrap());
+    }
+}
+
+impl<'a> Iterator for ParentGeomsIter<'a> {
+    type Item = (&'a EdgeId, &'a Edge, &'a Geom);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let kv = self.iter.next()?;
+        return Some(self.lookup(kv));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return self.iter.size_hint();
+    }
+}
+
+impl<'a> DoubleEndedIterator for ParentGeomsIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let kv = self.iter.next_back()?;
+        return Some(self.lookup(kv));
+    }
+}
+
+impl<'a> ExactSizeIterator for ParentGeomsIter<'a> {}
+
+impl<'a> FusedIterator for ParentGeomsIter<'a> {}
+
+fn scan_ptr(s: &str) -> Result<u64, Error> {
+    let p = scan_fmt!(s, "{x}", [hex u64])?;
+    return Ok(p);
+}
+
+/// How `decode_graph_with_options()` treats identifying values, such as disk serial numbers.
+///
+/// The identifying values are disk `ident` (serial number), `lunid`, and `descr`; partition
+/// `label`, `rawuuid`, and `efimedia`; the GATE `info` (which names the remote host and path);
+/// the names of LABEL providers (`gpt/...`, `gptid/...`, `diskid/...`, `ufsid/...`, ...), and of
+/// any geoms and providers built on them (e.g., `gpt/rootfs.eli`); and unrecognized config values.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Redaction {
+    /// Keep all values as reported by the kernel.
+    #[default]
+    Keep,
+    /// Remove identifying values.  Optional fields become `None` and required ones become empty.
+    /// Label names keep their prefix, e.g., `gpt/redacted`.
+    Drop,
+    /// Replace identifying values with a pseudonym derived from a hash of the value, so that
+    /// equal values remain equal within (and across) snapshots, hosts, and releases of this
+    /// library.  Label names keep their prefix, e.g., `gpt/redacted-0123456789abcdef`.
+    ///
+    /// The hash is SipHash-2-4 with a fixed key.  It keeps serial numbers out of memory and
+    /// logs, but it is not secret: anyone can compute the pseudonym of a value they guess.
+    Hash,
+}
+
+impl Redaction {
+    /// Prefixes of LABEL provider names, after which comes a label, serial number, or UUID.
+    const NAME_PREFIXES: [&'static str; 10] = [
+        "diskid/", "gptid/", "ufsid/", "gpt/", "label/", "ufs/", "msdosfs/", "ntfs/", "iso9660/",
+        "ext2fs/",
+    ];
+
+    /// The SipHash key of `Redaction::Hash`.  Changing it changes every pseudonym.
+    const KEY: (u64, u64) = (0x6765_6f6d_2d72_6564, 0x6163_7469_6f6e_2d31);
+
+    fn pseudonym(s: &str) -> String {
+        use std::hash::Hasher;
+        #[allow(deprecated)]
+        let mut h = std::hash::SipHasher::new_with_keys(Self::KEY.0, Self::KEY.1);
+        h.write(s.as_bytes());
+        return format!("redacted-{:016x}", h.finish());
+    }
+
+    fn value(&self, s: &str) -> Option<String> {
+        match self {
+            Self::Keep => Some(s.to_owned()),
+            Self::Drop => None,
+            Self::Hash => Some(Self::pseudonym(s)),
+        }
+    }
+
+    fn opt_value(&self, s: &mut Option<String>) {
+        *s = s.as_deref().and_then(|v| self.value(v));
+    }
+
+    /// Like `opt_value()`, for values the decoder requires: dropped values become empty.
+    fn req_value(&self, s: &mut Option<String>) {
+        if let Some(v) = s {
+            *v = self.value(v).unwrap_or_default();
+        }
+    }
+
+    /// Names containing a LABEL provider name keep everything up to the end of its prefix, but
+    /// the remainder is redacted.
+    fn name(&self, s: &mut String) {
+        let found = Self::NAME_PREFIXES
+            .iter()
+            .filter_map(|prefix| Some(s.find(prefix)? + prefix.len()))
+            .min();
+        if let Some(split) = found {
+            let rest = self
+                .value(&s[split..])
+                .unwrap_or_else(|| "redacted".to_owned());
+            s.replace_range(split.., &rest);
+        }
+    }
+
+    /// Redacts the identifying values in `mesh`, in place.  `decode_graph_with_options()` does
+    /// this to a copy of its `mesh`, and `get_graph_with_options()` to the mesh it parses,
+    /// before decoding it.
+    pub fn apply(&self, mesh: &mut raw::Mesh) {
+        if *self == Self::Keep {
+            return;
+        }
+        for geom in mesh.classes.iter_mut().flat_map(|c| c.geoms.iter_mut()) {
+            self.name(&mut geom.name);
+            if let Some(config) = &mut geom.config {
+                self.req_value(&mut config.info);
+                if let Some(name) = &mut config.read_provider {
+                    self.name(name);
+                }
+                self.unknown(&mut config.unknown);
+            }
+            for prov in &mut geom.providers {
+                self.name(&mut prov.name);
+                let config = &mut prov.config;
+                self.req_value(&mut config.ident);
+                self.req_value(&mut config.lunid);
+                self.req_value(&mut config.descr);
+                self.opt_value(&mut config.label);
+                self.opt_value(&mut config.rawuuid);
+                self.opt_value(&mut config.efimedia);
+                self.unknown(&mut config.unknown);
+            }
+        }
+    }
+
+    /// Redacts a mount table entry (see `mounts::get_mounts()`) to match a `Graph` decoded with
+    /// this redaction: the device it was mounted from is redacted like provider names, so
+    /// `Graph::attach_mounts()` still finds it (with `Redaction::Hash`), and the mountpoint is
+    /// treated as identifying.
+    pub fn apply_to_mount(&self, mount: &mut crate::mounts::Mount) {
+        if *self == Self::Keep {
+            return;
+        }
+        self.name(&mut mount.from);
+        mount.mountpoint = self.value(&mount.mountpoint).unwrap_or_default();
+    }
+
+    /// We cannot know what unrecognized config values contain, so treat them all as identifying.
+    fn unknown(&self, map: &mut BTreeMap<String, String>) {
+        let old = std::mem::take(map);
+        *map = old
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, self.value(&v)?)))
+            .collect();
+    }
+}
+
+/// Options for `decode_graph_with_options()`.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    /// How to treat identifying values; see `Redaction`.
+    ///
+    /// Values are redacted in the raw `Mesh`, before it is decoded, so the resulting `Graph`
+    /// never contains the original values.  `get_graph_with_options()` redacts the mesh as soon
+    /// as it is parsed, and overwrites the XML it was parsed from; `decode_graph_with_options()`
+    /// redacts a copy of the caller's mesh, and leaves the caller's mesh alone.  Mounts are not
+    /// part of the mesh; see `Redaction::apply_to_mount()`.
+    pub redaction: Redaction,
+    /// Whether to `Graph::compact()` the result, for constrained systems.  Each geom and edge is
+    /// compacted as it is decoded, so the uncompacted graph is never held in memory.
+    pub compact: bool,
+    /// Whether to record in each `Geom` and `Edge` the position of the raw object(s) it was
+    /// decoded from (`Geom::raw_index`, `Edge::raw_index`), so that callers can drill down to
+    /// fields this library does not model.
+    ///
+    /// Ignored unless `redaction` is `Redaction::Keep`, since the links would lead back to the
+    /// original values.
+    pub raw_links: bool,
+}
+
+/// Converts a logical GEOM forest from the unprocessed, `geom::raw::Mesh` format to the more
+/// convenient and strongly-typed `geom::Graph` format.
+pub fn decode_graph(mesh: &raw::Mesh) -> Result<Graph, Error> {
+    return decode_graph_with_options(mesh, &DecodeOptions::default());
+}
+
+/// Like `decode_graph()`, but records links back into `mesh`; see `DecodeOptions::raw_links`.
+pub fn decode_graph_with_raw(mesh: &raw::Mesh) -> Result<Graph, Error> {
+    let opts = DecodeOptions {
+        raw_links: true,
+        ..Default::default()
+    };
+    return decode_graph_with_options(mesh, &opts);
+}
+
+/// Like `decode_graph()`, but with non-default `DecodeOptions`.
+pub fn decode_graph_with_options(mesh: &raw::Mesh, opts: &DecodeOptions) -> Result<Graph, Error> {
+    if opts.redaction != Redaction::Keep {
+        let mut mesh = mesh.clone();
+        opts.redaction.apply(&mut mesh);
+        return decode_redacted(&mesh, opts);
+    }
+    return decode_redacted(mesh, opts);
+}
+
+/// Decodes `mesh`, whose values have already been redacted as `opts.redaction` requires.
+pub(crate) fn decode_redacted(mesh: &raw::Mesh, opts: &DecodeOptions) -> Result<Graph, Error> {
+    let raw_links = opts.raw_links && opts.redaction == Redaction::Keep;
+    let mut result = Graph::new();
+
+    // First pass: create nodes; temp: collect consumers, producers, and their pairs.
+    let mut cons: BTreeMap<u64, &raw::Consumer> = BTreeMap::new();
+    let mut provs: BTreeMap<u64, &raw::Provider> = BTreeMap::new();
+    let mut conprods: BTreeSet<EdgeId> = BTreeSet::new();
+    // Positions of the raw consumers and providers, by id, if `raw_links`.
+    let mut raw_positions: BTreeMap<u64, (RawGeomIndex, usize)> = BTreeMap::new();
+
+    for (class_idx, class) in mesh.classes.iter().enumerate() {
+        let classkind = GeomClass::from_str(&class.name)?;
+
+        for (geom_idx, geom) in class.geoms.iter().enumerate() {
+            let geom_id = scan_ptr(&geom.id)?;
+            let raw_index = RawGeomIndex {
+                class: class_idx,
+                geom: geom_idx,
+            };
+            let mut config = None;
+            if geom.wither.is_some() {
+                // The kernel omits the config of withering geoms.
+            } else if classkind == GeomClass::PART {
+                let rawconfig = &geom.config.as_ref().ok_or(Error::GraphError)?;
+                let partscheme =
+                    PartScheme::from_str(rawconfig.scheme.as_ref().ok_or(Error::GraphError)?)?;
+                let partstate =
+                    PartState::from_str(rawconfig.state.as_ref().ok_or(Error::GraphError)?)?;
+
+                config = Some(Box::new(GeomMetadata::PART(PartMetadata {
+                    scheme: partscheme,
+                    state: partstate,
+                    entries: rawconfig.entries.ok_or(Error::GraphError)?,
+                    first: rawconfig.first.ok_or(Error::GraphError)?,
+                    last: rawconfig.last.ok_or(Error::GraphError)?,
+                    fwsectors: rawconfig.fwsectors.ok_or(Error::GraphError)?,
+                    fwheads: rawconfig.fwheads.ok_or(Error::GraphError)?,
+                    modified: rawconfig.modified.ok_or(Error::GraphError)?,
+                })));
+            } else if classkind == GeomClass::VFS {
+                config = Some(GeomMetadata::vfs_from_raw(geom)?);
+            } else if classkind == GeomClass::ELI {
+                config = GeomMetadata::eli_from_raw(geom)?;
+            } else if classkind == GeomClass::GATE {
+                config = GeomMetadata::gate_from_raw(geom)?;
+            } else if classkind == GeomClass::CCD {
+                config = Some(GeomMetadata::ccd_from_raw(geom));
+            } else if classkind == GeomClass::SCHED {
+                config = GeomMetadata::sched_from_raw(geom);
+            }
+            let mut unknown_config = geom
+                .config
+                .as_ref()
+                .map(|c| c.unknown.clone())
+                .unwrap_or_default();
+            // `State` is only modeled for ELI; other classes (e.g., MIRROR) report it too.
+            if classkind != GeomClass::ELI {
+                if let Some(state) = geom.config.as_ref().and_then(|c| c.status.as_ref()) {
+                    unknown_config.insert("State".to_owned(), state.to_owned());
+                }
+            }
+            let mut node = Geom {
+                class: classkind.clone(),
+                name: geom.name.to_owned(),
+                rank: geom.rank,
+                metadata: config,
+                unknown_config,
+                raw_index: if raw_links { Some(raw_index) } else { None },
+                mounts: Vec::new(),
+                withered: geom.wither.is_some(),
+            };
+            if opts.compact {
+                footprint::compact_geom(&mut node);
+            }
+            result.nodes.insert(geom_id, node);
+
+            for (i, c) in geom.consumers.iter().enumerate() {
+                let cons_id = scan_ptr(&c.id)?;
+                let prov_id = scan_ptr(&c.provider_ref.ref_)?;
+
+                cons.insert(cons_id, c);
+                conprods.insert((cons_id, prov_id));
+                if raw_links {
+                    raw_positions.insert(cons_id, (raw_index, i));
+                }
+            }
+            for (i, p) in geom.providers.iter().enumerate() {
+                let prov_id = scan_ptr(&p.id)?;
+                provs.insert(prov_id, p);
+                if raw_links {
+                    raw_positions.insert(prov_id, (raw_index, i));
+                }
+            }
+        }
+    }
+
+    // Second pass: create Con-Prov Edges; fill inedges, outedges.
+    for (cid, pid) in &conprods {
+        let rawcons = cons.get(cid).ok_or(Error::GraphError)?;
+        let rawprov = provs.get(pid).ok_or(Error::GraphError)?;
+        // Geom associated with the consumer in this pair.
+        let consgeom_id = scan_ptr(&rawcons.geom_ref.ref_)?;
+        let consgeom = result
+            .nodes
+            .get_mut(&consgeom_id)
+            .ok_or(Error::GraphError)?;
+
+        // A provider may have any number of consumers (DEV, VFS, SWAP, tasting LABEL geoms, ...),
+        // each holding its own access.  The provider's mode is their sum; `Graph::verify()`
+        // checks that, rather than rejecting the graph here.
+        let prov_mode = Mode::from_str(&rawprov.mode)?;
+        let cons_mode = Mode::from_str(&rawcons.mode)?;
+        if consgeom.class == GeomClass::DEV {
+            consgeom.metadata = Some(Box::new(GeomMetadata::DEV { mode: cons_mode }));
+        }
+
+        // And provider.
+        let provgeom_id = scan_ptr(&rawprov.geom_ref.ref_)?;
+        let provgeom = result.nodes.get(&provgeom_id).ok_or(Error::GraphError)?;
+        let withered = rawprov.wither.is_some() || provgeom.withered;
+
+        let mut edge = Edge {
+            name: rawprov.name.to_owned(),
+            mode: prov_mode,
+            consumer_mode: cons_mode,
+            mediasize: rawprov.mediasize,
+            sectorsize: rawprov.sectorsize,
+            stripesize: rawprov.stripesize,
+            stripeoffset: rawprov.stripeoffset,
+            metadata: match provgeom.class {
+                _ if withered => None,
+                GeomClass::DISK => Some(EdgeMetadata::disk_from_raw(rawprov)?),
+                GeomClass::PART => Some(EdgeMetadata::part_from_raw(rawprov)?),
+                GeomClass::LABEL => Some(EdgeMetadata::label_from_raw(rawprov)?),
+                _ => None,
+            },
+            unknown_config: rawprov.config.unknown.clone(),
+            consumer_geom: consgeom_id,
+            provider_geom: provgeom_id,
+            raw_index: match (raw_positions.get(pid), raw_positions.get(cid)) {
+                (Some(p), Some(c)) => Some(RawEdgeIndex {
+                    provider_geom: p.0,
+                    provider: p.1,
+                    consumer_geom: c.0,
+                    consumer: c.1,
+                }),
+                _ => None,
+            },
+            withered,
+            devstat: None,
+        };
+        if opts.compact {
+            footprint::compact_edge(&mut edge);
+        }
+
+        let edge_id = (*cid, *pid);
+        result.edges.insert(edge_id, edge);
+
+        let invec = result.inedges.entry(provgeom_id).or_default();
+        (*invec).push(edge_id);
+
+        let outvec = result.outedges.entry(consgeom_id).or_default();
+        (*outvec).push(edge_id);
+    }
+
+    // Providers without consumers have no Edge; keep them aside.
+    let consumed: BTreeSet<u64> = conprods.iter().map(|(_, pid)| *pid).collect();
+    for (pid, rawprov) in &provs {
+        if consumed.contains(pid) {
+            continue;
+        }
+        let provgeom_id = scan_ptr(&rawprov.geom_ref.ref_)?;
+        let provgeom = result.nodes.get(&provgeom_id).ok_or(Error::GraphError)?;
+        result.orphans.insert(
+            *pid,
+            OrphanProvider {
+                name: rawprov.name.to_owned(),
+                mode: Mode::from_str(&rawprov.mode)?,
+                mediasize: rawprov.mediasize,
+                sectorsize: rawprov.sectorsize,
+                provider_geom: provgeom_id,
+                withered: rawprov.wither.is_some() || provgeom.withered,
+            },
+        );
+    }
+
+    result.reindex();
+    if opts.compact {
+        // The geoms and edges were compacted as they were created; trim the indexes.
+        result.compact();
+    }
+    return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn large_sample_decode() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        graph::decode_graph(&rawmesh).unwrap();
+    }
+
+    #[test]
+    fn roots_iterator() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        // The sample only has DISK roots.
+        assert_eq!(g.roots_iter().count(), 3);
+        for (_, root) in g.roots_iter() {
+            assert_eq!(root.rank, 1);
+            assert_eq!(root.class, graph::GeomClass::DISK);
+        }
+        assert_eq!(g.roots_by_class(&graph::GeomClass::DISK).count(), 3);
+        assert_eq!(g.roots_by_class(&graph::GeomClass::MD).count(), 0);
+    }
+
+    #[test]
+    fn iterator_traits() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let fwd: Vec<_> = g.roots_iter().map(|(id, _)| *id).collect();
+        let mut rev: Vec<_> = g.roots_iter().rev().map(|(id, _)| *id).collect();
+        rev.reverse();
+        assert_eq!(fwd, rev);
+
+        let (disk, _) = g.roots_iter().next().unwrap();
+        let children = g.child_geoms_iter(disk);
+        let n = children.len();
+        assert!(n > 0);
+        assert_eq!(n, g.child_edges_iter(disk).count());
+        assert_eq!(g.child_edgeids_iter(disk).size_hint(), (n, Some(n)));
+
+        let fwd: Vec<_> = g.child_geoms_iter(disk).map(|(id, _, _)| *id).collect();
+        let mut rev: Vec<_> = g
+            .child_geoms_iter(disk)
+            .rev()
+            .map(|(id, _, _)| *id)
+            .collect();
+        rev.reverse();
+        assert_eq!(fwd, rev);
+
+        // Leaves have no children at all.
+        let mut leaf = g.child_edgeids_iter(&0);
+        assert_eq!(leaf.len(), 0);
+        assert!(leaf.next().is_none());
+        assert!(leaf.next_back().is_none());
+        assert!(leaf.next().is_none());
+    }
+
+    #[test]
+    fn find_by_name() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let classes: Vec<_> = g
+            .find_by_name("nvd0")
+            .map(|(_, n)| n.class.clone())
+            .collect();
+        assert!(classes.len() > 1);
+        assert!(classes.contains(&graph::GeomClass::DISK));
+        assert!(classes.contains(&graph::GeomClass::PART));
+        assert!(g.find_by_name("nvd0").all(|(_, n)| n.name == "nvd0"));
+        assert_eq!(g.find_by_name("no-such-geom").count(), 0);
+
+        // A stale index skips removed geoms rather than panicking, until rebuilt.
+        let mut g = g;
+        let (disk, _) = g
+            .find_by_class_name(&graph::GeomClass::DISK, "nvd0")
+            .unwrap();
+        let disk = *disk;
+        g.nodes.remove(&disk);
+        assert_eq!(g.find_by_name("nvd0").count(), classes.len() - 1);
+        assert!(g
+            .find_by_class_name(&graph::GeomClass::DISK, "nvd0")
+            .is_none());
+        let (&other, _) = g.find_by_name("nvd0").next().unwrap();
+        g.nodes.get_mut(&other).unwrap().name = "x".into();
+        g.reindex();
+        assert_eq!(g.find_by_name("nvd0").count(), classes.len() - 2);
+        assert_eq!(g.find_by_name("x").count(), 1);
+    }
+
+    #[test]
+    fn map_roots() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let names =
+            g.map_roots(|id, root| (*id, root.name.clone(), g.descendents_iter(id).count()));
+        assert_eq!(names.len(), g.roots_iter().count());
+        for ((id, name, n), (rid, root)) in names.iter().zip(g.roots_iter()) {
+            assert_eq!(id, rid);
+            assert_eq!(name, &root.name);
+            assert_eq!(*n, g.descendents_iter(rid).count());
+        }
+    }
+
+    #[test]
+    fn depth_first_descendants() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        for (root, _) in g.roots_iter() {
+            let walk: Vec<_> = g.descendants_with_depth_iter(root).collect();
+            assert_eq!(walk.len(), g.descendents_iter(root).count());
+
+            let mut last_depth = 0;
+            for (depth, _, edge, _) in &walk {
+                assert!(*depth >= 1 && *depth <= last_depth + 1);
+                last_depth = *depth;
+                if *depth == 1 {
+                    assert_eq!(edge.provider_geom, *root);
+                }
+            }
+            // Pre-order: every node at depth > 1 is preceded by its parent.
+            for (i, (depth, _, edge, _)) in walk.iter().enumerate().filter(|(_, w)| w.0 > 1) {
+                let parent = walk[..i].iter().rev().find(|w| w.0 == depth - 1).unwrap();
+                assert_eq!(parent.2.consumer_geom, edge.provider_geom);
+            }
+        }
+
+        let nvd1 = g
+            .find_by_name("nvd1")
+            .find(|(_, n)| n.class == graph::GeomClass::DISK);
+        let (nvd1, _) = nvd1.unwrap();
+        let max = g
+            .descendants_with_depth_iter(nvd1)
+            .map(|w| w.0)
+            .max()
+            .unwrap();
+        // DISK -> PART -> LABEL -> VFS
+        assert_eq!(max, 3);
+    }
+
+    #[test]
+    fn lineage() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (_, edge) = g
+            .edges
+            .iter()
+            .find(|(_, e)| {
+                e.name == "gpt-partition-2"
+                    && g.nodes[&e.consumer_geom].class == graph::GeomClass::DEV
+            })
+            .unwrap();
+        let dev = edge.consumer_geom;
+        let paths = g.lineage(&dev);
+        assert_eq!(paths.len(), 1);
+        let classes: Vec<_> = paths[0]
+            .iter()
+            .map(|id| g.nodes[id].class.clone())
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                graph::GeomClass::DEV,
+                graph::GeomClass::LABEL,
+                graph::GeomClass::PART,
+                graph::GeomClass::DISK
+            ]
+        );
+
+        let (root, _) = g.roots_iter().next().unwrap();
+        assert_eq!(g.lineage(root), vec![vec![*root]]);
+        assert!(g.lineage(&0).is_empty());
+
+        // A mirror of two disks has a path through each.
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>MD</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>md0</name>
+                <rank>1</rank>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r1w1e1</mode>
+                  <name>md0</name>
+                  <mediasize>1048576</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+              <geom id="0x21">
+                <class ref="0x10"/>
+                <name>md1</name>
+                <rank>1</rank>
+                <provider id="0x31">
+                  <geom ref="0x21"/>
+                  <mode>r1w1e1</mode>
+                  <name>md1</name>
+                  <mediasize>1048576</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>MIRROR</name>
+              <geom id="0x22">
+                <class ref="0x11"/>
+                <name>gm0</name>
+                <rank>2</rank>
+                <consumer id="0x40">
+                  <geom ref="0x22"/>
+                  <provider ref="0x30"/>
+                  <mode>r1w1e1</mode>
+                </consumer>
+                <consumer id="0x41">
+                  <geom ref="0x22"/>
+                  <provider ref="0x31"/>
+                  <mode>r1w1e1</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        assert_eq!(g.lineage(&0x22), vec![vec![0x22, 0x20], vec![0x22, 0x21]]);
+    }
+
+    #[test]
+    fn resolve_dev_path() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        for path in &["/dev/gpt/freebsd-efi", "gpt/freebsd-efi", "/dev/nvd0p1"] {
+            let name = path.trim_start_matches("/dev/");
+            let found = g.resolve_dev_path(path);
+            assert_eq!(found.len(), 1, "{}", path);
+            assert_eq!(found[0].1.class, graph::GeomClass::DEV);
+            assert_eq!(found[0].1.name, name);
+        }
+        assert!(g.resolve_dev_path("/dev/nonexistent").is_empty());
+        assert!(g.resolve_dev_path("/dev/gpt/nonexistent").is_empty());
+
+        // A withered alias resolves through the partition entry.
+        let (part_id, uuid) = g
+            .edges
+            .iter()
+            .find_map(|(id, e)| match e.metadata.as_deref() {
+                Some(graph::EdgeMetadata::PART(graph::PartInfo {
+                    rawuuid: Some(u), ..
+                })) if e.name == "nvd1p2" => Some((*id, u.clone())),
+                _ => None,
+            })
+            .unwrap();
+        let path = format!("/dev/gptid/{}", uuid);
+        assert!(g.find_by_name(&path[5..]).next().is_none());
+        let found = g.resolve_dev_path(&path);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.name, "nvd1p2");
+        assert!(g
+            .edges
+            .contains_key(&(g.outedges[found[0].0][0].0, part_id.1)));
+    }
+
+    #[test]
+    fn find_by_part_uuid() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        const UUID: &str = "aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa";
+        let (_, edge, part) = g.find_by_part_uuid(UUID).unwrap();
+        assert_eq!(part.class, graph::GeomClass::PART);
+        match edge.metadata.as_deref() {
+            Some(graph::EdgeMetadata::PART(graph::PartInfo { rawuuid, .. })) => {
+                assert_eq!(rawuuid.as_deref(), Some(UUID))
+            }
+            _ => panic!("expected PART metadata"),
+        }
+
+        let upper = format!("gptid/{}", UUID.to_uppercase());
+        assert_eq!(
+            g.find_by_part_uuid(&upper).unwrap().0,
+            g.find_by_part_uuid(UUID).unwrap().0
+        );
+        assert!(g
+            .find_by_part_uuid("00000000-0000-0000-0000-000000000000")
+            .is_none());
+    }
+
+    #[test]
+    fn find_by_label() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let m = g.find_by_label("/dev/gpt/freebsd-efi").unwrap();
+        assert_eq!(m.label.name, "gpt/freebsd-efi");
+        assert_eq!(m.provider.name, "nvd0p1");
+
+        let m = g.find_by_label("msdosfs/EFI").unwrap();
+        assert_eq!(m.label.name, "msdosfs/EFI");
+        assert_eq!(m.provider.name, "nvd0p1");
+
+        // By partition entry label alone.
+        let m = g.find_by_label("freebsd-efi").unwrap();
+        assert_eq!(m.label_id, m.provider_id);
+        assert_eq!(m.provider.name, "nvd0p1");
+
+        assert!(g.find_by_label("gpt/nonexistent").is_none());
+    }
+
+    #[test]
+    fn find_disk_by_ident() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (ident, lunid) = g
+            .edges
+            .values()
+            .find_map(|e| match e.metadata.as_deref() {
+                Some(graph::EdgeMetadata::DISK(graph::DiskInfo { ident, lunid, .. }))
+                    if e.name == "ada0" =>
+                {
+                    Some((ident.clone(), lunid.clone()))
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        let (_, edge, disk) = g.find_disk_by_ident(&ident).unwrap();
+        assert_eq!(edge.name, "ada0");
+        assert_eq!(disk.class, graph::GeomClass::DISK);
+        assert_eq!(disk.name, "ada0");
+        let parts: Vec<_> = g
+            .child_geoms_iter(&edge.provider_geom)
+            .filter(|(_, _, n)| n.class == graph::GeomClass::PART)
+            .collect();
+        assert_eq!(parts.len(), 1);
+
+        let padded = format!(" {} ", ident);
+        assert_eq!(
+            g.find_disk_by_ident(&padded).unwrap().0,
+            g.find_disk_by_ident(&ident).unwrap().0
+        );
+        assert_eq!(g.find_disk_by_lunid(&lunid).unwrap().1.name, "ada0");
+        assert!(g.find_disk_by_ident("NO-SUCH-SERIAL").is_none());
+        assert!(g.find_disk_by_ident("").is_none());
+    }
+
+    #[test]
+    fn rotational_filesystems() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        // All solid state.
+        assert!(g.rotational_filesystems().is_empty());
+
+        // Spin up ada0, which holds ffs.gpt-partition-1.
+        let xml = SAMPLE_XML.replacen(
+            "<rotationrate>0</rotationrate>",
+            "<rotationrate>7200</rotationrate>",
+            1,
+        );
+        let rawmesh = raw::parse_xml(&xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let found = g.rotational_filesystems();
+        assert_eq!(found.len(), 1);
+        let (_, vfs, disks) = &found[0];
+        assert_eq!(vfs.name, "ffs.gpt-partition-1");
+        assert_eq!(disks.len(), 1);
+        assert_eq!(disks[0].name, "ada0");
+    }
+
+    #[test]
+    fn predicate_search() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        assert_eq!(
+            g.find_nodes(|n| n.class == graph::GeomClass::DISK).count(),
+            3
+        );
+        assert_eq!(
+            g.find_nodes(|n| n.class == graph::GeomClass::ELI).count(),
+            0
+        );
+
+        let big = 500 * 1024 * 1024 * 1024;
+        let found: Vec<_> = g.find_edges(|e| e.mediasize > big).collect();
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|(_, e)| e.mediasize > big));
+        assert_eq!(g.find_edges(|e| e.sectorsize != 512).count(), 0);
+    }
+
+    #[test]
+    fn dev_consumer_modes() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        for n in g
+            .nodes
+            .values()
+            .filter(|n| n.class == graph::GeomClass::DEV)
+        {
+            match n.metadata.as_deref() {
+                Some(graph::GeomMetadata::DEV { .. }) => {}
+                _ => panic!("expected DEV metadata"),
+            }
+        }
+
+        // ada0p1 is mounted and also open for reading through /dev/ada0p1 (e.g., by dump(8));
+        // ada0p2 is only open through /dev.
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>PART</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>ada0</name>
+                <rank>2</rank>
+                <config>
+                  <scheme>GPT</scheme>
+                  <entries>128</entries>
+                  <first>40</first>
+                  <last>10000</last>
+                  <fwsectors>63</fwsectors>
+                  <fwheads>16</fwheads>
+                  <state>OK</state>
+                  <modified>false</modified>
+                </config>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r2w1e1</mode>
+                  <name>ada0p1</name>
+                  <mediasize>524288</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>20480</stripeoffset>
+                  <config>
+                    <start>40</start>
+                    <end>1063</end>
+                    <index>1</index>
+                    <type>freebsd-ufs</type>
+                    <offset>20480</offset>
+                    <length>524288</length>
+                  </config>
+                </provider>
+                <provider id="0x31">
+                  <geom ref="0x20"/>
+                  <mode>r1w0e0</mode>
+                  <name>ada0p2</name>
+                  <mediasize>524288</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>544768</stripeoffset>
+                  <config>
+                    <start>1064</start>
+                    <end>2087</end>
+                    <index>2</index>
+                    <type>freebsd-ufs</type>
+                    <offset>544768</offset>
+                    <length>524288</length>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>DEV</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>ada0p1</name>
+                <rank>3</rank>
+                <consumer id="0x40">
+                  <geom ref="0x21"/>
+                  <provider ref="0x30"/>
+                  <mode>DEVMODE</mode>
+                </consumer>
+              </geom>
+              <geom id="0x22">
+                <class ref="0x11"/>
+                <name>ada0p2</name>
+                <rank>3</rank>
+                <consumer id="0x41">
+                  <geom ref="0x22"/>
+                  <provider ref="0x31"/>
+                  <mode>r1w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+            <class id="0x12">
+              <name>VFS</name>
+              <geom id="0x23">
+                <class ref="0x12"/>
+                <name>ffs.ada0p1</name>
+                <rank>3</rank>
+                <consumer id="0x42">
+                  <geom ref="0x23"/>
+                  <provider ref="0x30"/>
+                  <mode>r1w1e1</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(&xml.replace("DEVMODE", "r1w0e0")).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let dev_mode = |id| match g.nodes[&id].metadata.as_deref() {
+            Some(graph::GeomMetadata::DEV { mode }) => *mode,
+            _ => panic!("expected DEV metadata"),
+        };
+        let m = dev_mode(0x21);
+        assert!(m.is_open());
+        assert_eq!((m.read(), m.write(), m.exclusive()), (1, 0, 0));
+        assert_eq!(format!("{}", m), "r1w0e0");
+        assert_eq!(dev_mode(0x22), dev_mode(0x21));
+        assert_eq!(format!("{}", g.edges[&(0x42, 0x30)].mode), "r2w1e1");
+
+        assert_eq!(
+            format!("{}", g.edges[&(0x42, 0x30)].consumer_mode),
+            "r1w1e1"
+        );
+        assert_eq!(
+            format!("{}", g.edges[&(0x40, 0x30)].consumer_mode),
+            "r1w0e0"
+        );
+        let consumers: Vec<_> = g
+            .provider_consumers_iter(&(0x42, 0x30))
+            .map(|(eid, _, _)| *eid)
+            .collect();
+        assert_eq!(consumers, vec![(0x40, 0x30), (0x42, 0x30)]);
+        let writers: Vec<_> = g
+            .access_holders_iter(&(0x40, 0x30))
+            .filter(|(_, e, _)| e.consumer_mode.write() > 0)
+            .map(|(_, _, n)| n.class.clone())
+            .collect();
+        assert_eq!(writers, vec![graph::GeomClass::VFS]);
+        assert_eq!(g.access_holders_iter(&(0x41, 0x31)).count(), 1);
+        assert!(g.verify().is_empty());
+
+        // Modes that do not add up are decoded as-is, and reported by verify().
+        for devmode in &["r0w0e0", "r1w1e0"] {
+            let rawmesh = raw::parse_xml(&xml.replace("DEVMODE", devmode)).unwrap();
+            let g = graph::decode_graph(&rawmesh).unwrap();
+            assert_eq!(g.verify().len(), 1);
+        }
+    }
+
+    #[test]
+    fn ancestors_iterator() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        // The DEV node for gpt-partition-2, a LABEL on nvd1p2.
+        let (_, edge) = g
+            .edges
+            .iter()
+            .find(|(_, e)| {
+                e.name == "gpt-partition-2"
+                    && g.nodes[&e.consumer_geom].class == graph::GeomClass::DEV
+            })
+            .unwrap();
+        let dev = edge.consumer_geom;
+
+        let parents: Vec<_> = g.parent_geoms_iter(&dev).collect();
+        assert_eq!(parents.len(), 1);
+        assert_eq!(parents[0].2.class, graph::GeomClass::LABEL);
+        assert_eq!(g.parent_edges_iter(&dev).len(), 1);
+
+        let classes: Vec<_> = g
+            .ancestors_iter(&dev)
+            .map(|(_, _, n)| n.class.clone())
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                graph::GeomClass::LABEL,
+                graph::GeomClass::PART,
+                graph::GeomClass::DISK
+            ]
+        );
+        let (_, _, disk) = g.ancestors_iter(&dev).last().unwrap();
+        assert_eq!(disk.name, "nvd1");
+
+        // Roots have no parents.
+        let (root, _) = g.roots_iter().next().unwrap();
+        assert_eq!(g.parent_geoms_iter(root).len(), 0);
+        assert_eq!(g.ancestors_iter(root).count(), 0);
+    }
+
+    #[test]
+    fn non_disk_roots() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>MD</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>md0</name>
+                <rank>1</rank>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>ZFS::ZVOL</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>zroot/vol0</name>
+                <rank>1</rank>
+              </geom>
+            </class>
+            <class id="0x12">
+              <name>DISK</name>
+              <geom id="0x22">
+                <class ref="0x12"/>
+                <name>ada0</name>
+                <rank>1</rank>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let roots = g.roots_iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(roots, vec![0x20, 0x21, 0x22]);
+        let mds = g
+            .roots_by_class(&graph::GeomClass::MD)
+            .map(|(_, n)| n.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(mds, vec!["md0"]);
+        assert_eq!(g.roots_by_class(&graph::GeomClass::ZFSZVOL).count(), 1);
     }
 
     #[test]
     fn recursive_iterator() {
-        let rawmesh = raw::parse_xml(&SAMPLE_XML).unwrap();
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
         let g = graph::decode_graph(&rawmesh).unwrap();
 
         // Look for SWAP geoms; there should be exactly one.
         let swap_geoms = g
             .roots_iter()
-            .map(|(id, _)| g.descendents_iter(id))
-            .flatten()
+            .flat_map(|(id, _)| g.descendents_iter(id))
             .filter_map(|(_, _, n)| {
                 if n.class == graph::GeomClass::SWAP {
                     Some(n)
@@ -615,8 +3164,7 @@ mod tests {
         // Check that the iterator did not consume `g`.
         let ada0_geoms = g
             .roots_iter()
-            .map(|(id, _)| g.descendents_iter(id))
-            .flatten()
+            .flat_map(|(id, _)| g.descendents_iter(id))
             .filter(|(_, _, n)| n.name == "ada0")
             .map(|(_, _, n)| n)
             .collect::<Vec<_>>();
@@ -626,15 +3174,1291 @@ mod tests {
         assert_eq!(ada0_geoms.len(), 2);
         let ada0_part = ada0_geoms
             .iter()
-            .filter(|g| g.class == graph::GeomClass::PART)
-            .next()
+            .find(|g| g.class == graph::GeomClass::PART)
             .unwrap();
         let ada0_dev = ada0_geoms
             .iter()
-            .filter(|g| g.class == graph::GeomClass::DEV)
-            .next()
+            .find(|g| g.class == graph::GeomClass::DEV)
             .unwrap();
         assert_eq!(ada0_part.rank, 2);
         assert_eq!(ada0_dev.rank, 2);
     }
+
+    #[test]
+    fn fd_decode() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>FD</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>fd0</name>
+                <rank>1</rank>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r0w0e0</mode>
+                  <name>fd0</name>
+                  <mediasize>1474560</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>DEV</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>fd0</name>
+                <rank>2</rank>
+                <consumer id="0x40">
+                  <geom ref="0x21"/>
+                  <provider ref="0x30"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (_, root) = g.roots_iter().next().unwrap();
+        assert_eq!(root.class, graph::GeomClass::FD);
+        let edge = &g.edges[&(0x40, 0x30)];
+        assert_eq!(edge.mediasize, 1474560);
+        assert!(edge.metadata.is_none());
+    }
+
+    #[test]
+    fn vinum_decode() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>VINUM</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>VINUM</name>
+                <rank>2</rank>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r0w0e0</mode>
+                  <name>gvinum/vol0</name>
+                  <mediasize>1073741824</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>DEV</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>gvinum/vol0</name>
+                <rank>3</rank>
+                <consumer id="0x40">
+                  <geom ref="0x21"/>
+                  <provider ref="0x30"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        assert_eq!(g.nodes[&0x20].class, graph::GeomClass::VINUM);
+        assert!(g.nodes[&0x20].metadata.is_none());
+        assert!(g.edges[&(0x40, 0x30)].metadata.is_none());
+    }
+
+    #[test]
+    fn ccd_decode() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>MD</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>md0</name>
+                <rank>1</rank>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r1w1e1</mode>
+                  <name>md0</name>
+                  <mediasize>1073741824</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+              <geom id="0x21">
+                <class ref="0x10"/>
+                <name>md1</name>
+                <rank>1</rank>
+                <provider id="0x31">
+                  <geom ref="0x21"/>
+                  <mode>r1w1e1</mode>
+                  <name>md1</name>
+                  <mediasize>1073741824</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>CCD</name>
+              <geom id="0x22">
+                <class ref="0x11"/>
+                <name>ccd0</name>
+                <rank>2</rank>
+                <config>
+                  <interleave>128</interleave>
+                </config>
+                <consumer id="0x40">
+                  <geom ref="0x22"/>
+                  <provider ref="0x30"/>
+                  <mode>r1w1e1</mode>
+                </consumer>
+                <consumer id="0x41">
+                  <geom ref="0x22"/>
+                  <provider ref="0x31"/>
+                  <mode>r1w1e1</mode>
+                </consumer>
+                <provider id="0x32">
+                  <geom ref="0x22"/>
+                  <mode>r0w0e0</mode>
+                  <name>ccd0</name>
+                  <mediasize>2147483648</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let ccd = &g.nodes[&0x22];
+        assert_eq!(ccd.class, graph::GeomClass::CCD);
+        assert!(ccd.unknown_config.is_empty());
+        match ccd.metadata.as_deref() {
+            Some(graph::GeomMetadata::CCD {
+                components,
+                interleave,
+            }) => {
+                assert_eq!(*components, 2);
+                assert_eq!(*interleave, Some(128));
+            }
+            _ => panic!("expected CCD metadata"),
+        }
+        assert_eq!(g.child_geoms_iter(&0x20).count(), 1);
+    }
+
+    #[test]
+    fn sched_decode() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>SCHED</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>ada0.sched.</name>
+                <rank>2</rank>
+                <config>
+                  <algo>rr</algo>
+                </config>
+              </geom>
+              <geom id="0x21">
+                <class ref="0x10"/>
+                <name>ada1.sched.</name>
+                <rank>2</rank>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        assert_eq!(g.nodes[&0x20].class, graph::GeomClass::SCHED);
+        match g.nodes[&0x20].metadata.as_deref() {
+            Some(graph::GeomMetadata::SCHED { algorithm }) => assert_eq!(algorithm, "rr"),
+            _ => panic!("expected SCHED metadata"),
+        }
+        assert!(g.nodes[&0x21].metadata.is_none());
+    }
+
+    /// Generates a mesh of `disks` MD disks, each with a GPT table of `parts` partitions, and a DEV
+    /// geom consuming every provider.
+    fn generate_mesh(disks: u64, parts: u64) -> String {
+        use std::fmt::Write;
+
+        const MD: u64 = 0x1_0000_0000;
+        const PART: u64 = 0x2_0000_0000;
+        const DEV: u64 = 0x3_0000_0000;
+        // Each geom, provider, and consumer gets a unique id within its class's range.
+        let id = |class: u64, disk: u64, part: u64, kind: u64| -> u64 {
+            class + (disk << 16) + (part << 2) + kind
+        };
+        let provider = |s: &mut String, pid: u64, gid: u64, name: &str, config: &str| {
+            write!(
+                s,
+                "<provider id=\"{:#x}\"><geom ref=\"{:#x}\"/><mode>r1w1e1</mode>\
+                 <name>{}</name><mediasize>1048576</mediasize><sectorsize>512</sectorsize>\
+                 <stripesize>0</stripesize><stripeoffset>0</stripeoffset>\
+                 <config>{}</config></provider>",
+                pid, gid, name, config
+            )
+            .unwrap();
+        };
+        let consumer = |s: &mut String, cid: u64, gid: u64, pid: u64, mode: &str| {
+            write!(
+                s,
+                "<consumer id=\"{:#x}\"><geom ref=\"{:#x}\"/><provider ref=\"{:#x}\"/>\
+                 <mode>{}</mode></consumer>",
+                cid, gid, pid, mode
+            )
+            .unwrap();
+        };
+        let dev_geom = |s: &mut String, gid: u64, pid: u64| {
+            write!(
+                s,
+                "<geom id=\"{:#x}\"><class ref=\"{:#x}\"/><name>dev</name><rank>3</rank>",
+                gid, DEV
+            )
+            .unwrap();
+            consumer(s, gid + 1, gid, pid, "r0w0e0");
+            s.push_str("</geom>");
+        };
+
+        let mut md = String::new();
+        let mut part = String::new();
+        let mut dev = String::new();
+        for d in 0..disks {
+            let name = format!("md{}", d);
+            let (md_gid, md_pid) = (id(MD, d, 0, 0), id(MD, d, 0, 1));
+            write!(
+                md,
+                "<geom id=\"{:#x}\"><class ref=\"{:#x}\"/><name>{}</name>",
+                md_gid, MD, name
+            )
+            .unwrap();
+            md.push_str("<rank>1</rank>");
+            provider(&mut md, md_pid, md_gid, &name, "");
+            md.push_str("</geom>");
+
+            let part_gid = id(PART, d, 0, 0);
+            write!(
+                part,
+                "<geom id=\"{:#x}\"><class ref=\"{:#x}\"/><name>{}</name><rank>2</rank>\
+                 <config><scheme>GPT</scheme><entries>128</entries><first>40</first>\
+                 <last>2008</last><fwsectors>0</fwsectors><fwheads>0</fwheads>\
+                 <state>OK</state><modified>false</modified></config>",
+                part_gid, PART, name
+            )
+            .unwrap();
+            consumer(&mut part, id(PART, d, 0, 1), part_gid, md_pid, "r1w1e1");
+            dev_geom(&mut dev, id(DEV, d, 0, 0), md_pid);
+            for p in 1..=parts {
+                let pid = id(PART, d, p, 2);
+                let config = format!(
+                    "<start>{}</start><end>{}</end><index>{}</index><type>freebsd-ufs</type>\
+                     <offset>{}</offset><length>512</length>",
+                    40 + p,
+                    40 + p,
+                    p,
+                    (40 + p) * 512
+                );
+                provider(
+                    &mut part,
+                    pid,
+                    part_gid,
+                    &format!("{}p{}", name, p),
+                    &config,
+                );
+                dev_geom(&mut dev, id(DEV, d, p, 0), pid);
+            }
+            part.push_str("</geom>");
+        }
+
+        return format!(
+            "<mesh><class id=\"{:#x}\"><name>MD</name>{}</class>\
+             <class id=\"{:#x}\"><name>PART</name>{}</class>\
+             <class id=\"{:#x}\"><name>DEV</name>{}</class></mesh>",
+            MD, md, PART, part, DEV, dev
+        );
+    }
+
+    /// Guards against accidentally quadratic (or worse) decoding.  The budget is deliberately
+    /// generous for unoptimized builds on slow machines; override it with `GEOM_DECODE_BUDGET_MS`.
+    #[test]
+    fn decode_time_budget() {
+        let budget_ms = std::env::var("GEOM_DECODE_BUDGET_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(10_000);
+
+        let (disks, parts) = (500, 16);
+        let xml = generate_mesh(disks, parts);
+        let start = std::time::Instant::now();
+        let rawmesh = raw::parse_xml(&xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let elapsed = start.elapsed();
+
+        // MD + PART + one DEV per provider
+        assert_eq!(g.nodes.len() as u64, disks * (2 + 1 + parts));
+        assert_eq!(g.edges.len() as u64, disks * (2 + parts));
+        assert!(
+            elapsed.as_millis() < u128::from(budget_ms),
+            "decoding {} geoms took {:?}, over the {} ms budget",
+            g.nodes.len(),
+            elapsed,
+            budget_ms
+        );
+    }
+
+    #[test]
+    fn unknown_class() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>ACME</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>acme0</name>
+                <rank>1</rank>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        assert_eq!(g.nodes[&0x20].class, graph::GeomClass::Other("ACME".into()));
+    }
+
+    #[test]
+    fn eli_metadata() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>ELI</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>ada0p4.eli</name>
+                <rank>3</rank>
+                <config>
+                  <KeysTotal>1</KeysTotal>
+                  <KeysAllocated>1</KeysAllocated>
+                  <Flags>BOOT, AUTH</Flags>
+                  <UsedKey>0</UsedKey>
+                  <Version>6</Version>
+                  <Crypto>accelerated software</Crypto>
+                  <AuthenticationAlgorithm>HMAC/SHA256</AuthenticationAlgorithm>
+                  <KeyLength>128</KeyLength>
+                  <EncryptionAlgorithm>AES-XTS</EncryptionAlgorithm>
+                  <State>ACTIVE</State>
+                </config>
+              </geom>
+              <geom id="0x21">
+                <class ref="0x10"/>
+                <name>ada1p4.eli</name>
+                <rank>3</rank>
+                <config>
+                  <KeysTotal>1</KeysTotal>
+                  <KeysAllocated>1</KeysAllocated>
+                  <Flags>NONE</Flags>
+                  <UsedKey>0</UsedKey>
+                  <Version>7</Version>
+                  <Crypto>software</Crypto>
+                  <KeyLength>256</KeyLength>
+                  <EncryptionAlgorithm>AES-XTS</EncryptionAlgorithm>
+                </config>
+              </geom>
+              <geom id="0x22">
+                <class ref="0x10"/>
+                <name>ada2p4.eli</name>
+                <rank>3</rank>
+                <config>
+                </config>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        match g.nodes[&0x20].metadata.as_deref().unwrap() {
+            graph::GeomMetadata::ELI {
+                version,
+                flags,
+                authentication_algorithm,
+                key_length,
+                state,
+                ..
+            } => {
+                assert_eq!(*version, 6);
+                assert_eq!(flags, &vec!["BOOT".to_owned(), "AUTH".to_owned()]);
+                assert_eq!(authentication_algorithm.as_deref(), Some("HMAC/SHA256"));
+                assert_eq!(*key_length, 128);
+                assert_eq!(state.as_deref(), Some("ACTIVE"));
+            }
+            _ => panic!("expected ELI metadata"),
+        }
+        match g.nodes[&0x21].metadata.as_deref().unwrap() {
+            graph::GeomMetadata::ELI { flags, state, .. } => {
+                assert!(flags.is_empty());
+                assert!(state.is_none());
+            }
+            _ => panic!("expected ELI metadata"),
+        }
+        assert!(g.nodes[&0x22].metadata.is_none());
+
+        let outdated = g
+            .outdated_metadata_iter(&graph::GeomClass::ELI, 7)
+            .collect::<Vec<_>>();
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0].1.name, "ada0p4.eli");
+        assert_eq!(outdated[0].2, 6);
+    }
+
+    #[test]
+    fn unknown_config() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        // Everything in the sample is modeled.
+        assert!(g.nodes.values().all(|n| n.unknown_config.is_empty()));
+        assert!(g.edges.values().all(|e| e.unknown_config.is_empty()));
+
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>ACME</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>acme0</name>
+                <rank>1</rank>
+                <config>
+                  <Widgets>3</Widgets>
+                </config>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        assert_eq!(g.nodes[&0x20].unknown_config["Widgets"], "3");
+    }
+
+    #[test]
+    fn unmodeled_config_report() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        assert!(g.unmodeled_config_report().is_empty());
+
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>ACME</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>acme0</name>
+                <rank>1</rank>
+                <config>
+                  <Widgets>3</Widgets>
+                  <Gadgets>4</Gadgets>
+                </config>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r0w0e0</mode>
+                  <name>acme0</name>
+                  <mediasize>1048576</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                    <sprockets>5</sprockets>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>DEV</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>acme0</name>
+                <rank>2</rank>
+                <consumer id="0x40">
+                  <geom ref="0x21"/>
+                  <provider ref="0x30"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+              <geom id="0x22">
+                <class ref="0x11"/>
+                <name>acme0</name>
+                <rank>2</rank>
+                <consumer id="0x41">
+                  <geom ref="0x22"/>
+                  <provider ref="0x30"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+            <class id="0x12">
+              <name>ZETA</name>
+              <geom id="0x23">
+                <class ref="0x12"/>
+                <name>aaa0</name>
+                <rank>1</rank>
+                <config>
+                  <Doodads>6</Doodads>
+                </config>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let report = g.unmodeled_config_report();
+        assert_eq!(
+            report,
+            vec![
+                graph::UnmodeledConfig {
+                    class: graph::GeomClass::Other("ACME".into()),
+                    name: "acme0".into(),
+                    geom: 0x20,
+                    provider: None,
+                    keys: vec!["Gadgets".into(), "Widgets".into()],
+                },
+                graph::UnmodeledConfig {
+                    class: graph::GeomClass::Other("ACME".into()),
+                    name: "acme0".into(),
+                    geom: 0x20,
+                    provider: Some(0x30),
+                    keys: vec!["sprockets".into()],
+                },
+                // Sorted by class name, although "aaa0" < "acme0".
+                graph::UnmodeledConfig {
+                    class: graph::GeomClass::Other("ZETA".into()),
+                    name: "aaa0".into(),
+                    geom: 0x23,
+                    provider: None,
+                    keys: vec!["Doodads".into()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn redaction() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let disk_ident =
+            |g: &graph::Graph| {
+                g.edges
+                    .values()
+                    .find_map(|e| match e.metadata.as_deref() {
+                        Some(graph::EdgeMetadata::DISK(graph::DiskInfo {
+                            ident, lunid, ..
+                        })) if e.name == "ada0" => Some((ident.clone(), lunid.clone())),
+                        _ => None,
+                    })
+                    .unwrap()
+            };
+        let has_name = |g: &graph::Graph, name: &str| g.edges.values().any(|e| e.name == name);
+        const GPTID: &str = "gptid/aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa";
+
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let (ident, _) = disk_ident(&g);
+        assert_ne!(ident, "");
+        assert!(has_name(&g, GPTID));
+
+        let opts = graph::DecodeOptions {
+            redaction: graph::Redaction::Drop,
+            ..Default::default()
+        };
+        let g = graph::decode_graph_with_options(&rawmesh, &opts).unwrap();
+        assert_eq!(disk_ident(&g), ("".to_owned(), "".to_owned()));
+        assert!(!has_name(&g, GPTID));
+        assert!(has_name(&g, "gptid/redacted"));
+        assert!(g.edges.values().all(|e| match e.metadata.as_deref() {
+            Some(graph::EdgeMetadata::PART(graph::PartInfo { rawuuid, .. })) => rawuuid.is_none(),
+            _ => true,
+        }));
+
+        let opts = graph::DecodeOptions {
+            redaction: graph::Redaction::Hash,
+            ..Default::default()
+        };
+        let g1 = graph::decode_graph_with_options(&rawmesh, &opts).unwrap();
+        let g2 = graph::decode_graph_with_options(&rawmesh, &opts).unwrap();
+        let (ident1, lunid1) = disk_ident(&g1);
+        assert!(ident1.starts_with("redacted-"));
+        assert_ne!(ident1, ident);
+        assert_ne!(ident1, lunid1);
+        assert_eq!(disk_ident(&g1), disk_ident(&g2));
+        assert!(!has_name(&g1, GPTID));
+
+        // Labels, descriptions, and everything built on labels are redacted too, consistently
+        // across the graph.
+        for g in &[&g, &g1] {
+            assert!(!has_name(g, "gpt/freebsd-efi"));
+            assert!(g.nodes.values().all(|n| !n.name.contains("freebsd-efi")));
+            for e in g.edges.values() {
+                match e.metadata.as_deref() {
+                    Some(graph::EdgeMetadata::DISK(d)) => assert!(!d.descr.contains("ZZZ")),
+                    Some(graph::EdgeMetadata::PART(p)) => {
+                        assert!(p.label.as_deref().is_none_or(|l| !l.contains("partition")))
+                    }
+                    _ => {}
+                }
+            }
+            assert!(g.verify().is_empty());
+        }
+        let label = "gpt/".to_owned() + &graph::Redaction::pseudonym("freebsd-efi");
+        assert!(has_name(&g1, &label));
+        assert!(g1.nodes.values().any(|n| n.name == label));
+        // The pseudonyms are stable across hosts and releases.
+        assert_eq!(label, "gpt/redacted-2e76a1b90177fcc9");
+
+        // Raw links would lead back to the original values.
+        let opts = graph::DecodeOptions {
+            redaction: graph::Redaction::Hash,
+            raw_links: true,
+            ..Default::default()
+        };
+        let g = graph::decode_graph_with_options(&rawmesh, &opts).unwrap();
+        assert!(g.nodes.values().all(|n| n.raw_index.is_none()));
+        assert!(g.edges.values().all(|e| e.raw_index.is_none()));
+
+        // Redacting the raw mesh ahead of time is equivalent.
+        let mut mesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        graph::Redaction::Hash.apply(&mut mesh);
+        assert_ne!(mesh, rawmesh);
+        let g = graph::decode_graph(&mesh).unwrap();
+        assert_eq!(format!("{:?}", g), format!("{:?}", g1));
+
+        let mut mount = crate::mounts::Mount {
+            from: "/dev/gpt/freebsd-efi".to_owned(),
+            mountpoint: "/boot/efi".to_owned(),
+            fstype: "msdosfs".to_owned(),
+        };
+        graph::Redaction::Hash.apply_to_mount(&mut mount);
+        assert_eq!(mount.from, format!("/dev/{}", label));
+        assert!(mount.mountpoint.starts_with("redacted-"));
+    }
+
+    #[test]
+    fn part_attribs() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>PART</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>ada0</name>
+                <rank>2</rank>
+                <config>
+                  <scheme>GPT</scheme>
+                  <entries>128</entries>
+                  <first>40</first>
+                  <last>1000</last>
+                  <fwsectors>63</fwsectors>
+                  <fwheads>16</fwheads>
+                  <state>OK</state>
+                  <modified>false</modified>
+                </config>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r0w0e0</mode>
+                  <name>ada0p1</name>
+                  <mediasize>524288</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>20480</stripeoffset>
+                  <config>
+                    <start>40</start>
+                    <end>1063</end>
+                    <index>1</index>
+                    <type>freebsd-boot</type>
+                    <offset>20480</offset>
+                    <length>524288</length>
+                    <attrib>bootme</attrib>
+                    <attrib>frobbed</attrib>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>DEV</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>ada0p1</name>
+                <rank>3</rank>
+                <consumer id="0x40">
+                  <geom ref="0x21"/>
+                  <provider ref="0x30"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        match g.edges[&(0x40, 0x30)].metadata.as_deref().unwrap() {
+            graph::EdgeMetadata::PART(graph::PartInfo { attrib, .. }) => assert_eq!(
+                attrib,
+                &vec![
+                    graph::PartAttrib::BootMe,
+                    graph::PartAttrib::Other("frobbed".into())
+                ]
+            ),
+            _ => panic!("expected PART metadata"),
+        }
+    }
+
+    #[test]
+    fn label_kinds() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let kind_of = |name: &str| {
+            g.edges
+                .values()
+                .filter(|e| e.name == name)
+                .find_map(|e| match e.metadata.as_deref() {
+                    Some(graph::EdgeMetadata::LABEL(graph::LabelInfo { kind, .. })) => {
+                        Some(kind.clone())
+                    }
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert_eq!(kind_of("gpt/freebsd-efi"), graph::LabelKind::GPT);
+        assert_eq!(
+            kind_of("gptid/cccccccc-cccc-cccc-cccc-cccccccccccc"),
+            graph::LabelKind::GPTID
+        );
+        assert_eq!(kind_of("msdosfs/EFI"), graph::LabelKind::MSDOSFS);
+        assert_eq!(
+            kind_of("gpt-partition-1"),
+            graph::LabelKind::Other("".into())
+        );
+
+        assert_eq!(
+            graph::LabelKind::from_name("diskid/DISK-S3Z1NB0K123456"),
+            graph::LabelKind::DISKID
+        );
+        assert_eq!(
+            graph::LabelKind::from_name("ufs/rootfs"),
+            graph::LabelKind::UFS
+        );
+        assert_eq!(
+            graph::LabelKind::from_name("foo/bar/baz"),
+            graph::LabelKind::Other("foo".into())
+        );
+    }
+
+    #[test]
+    fn gate_and_hast() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>GATE</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>hast/shared</name>
+                <rank>1</rank>
+                <config>
+                  <access>read-write</access>
+                  <timeout>20</timeout>
+                  <info>hast shared</info>
+                  <queue_count>0</queue_count>
+                  <queue_size>1024</queue_size>
+                  <ref>1</ref>
+                  <unit>0</unit>
+                </config>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r1w1e1</mode>
+                  <name>hast/shared</name>
+                  <mediasize>1073741824</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+              <geom id="0x21">
+                <class ref="0x10"/>
+                <name>ggate1</name>
+                <rank>1</rank>
+                <config>
+                  <access>read-only</access>
+                  <timeout>0</timeout>
+                  <info>10.0.0.1:/dev/ada1</info>
+                  <unit>1</unit>
+                </config>
+                <provider id="0x31">
+                  <geom ref="0x21"/>
+                  <mode>r0w0e0</mode>
+                  <name>ggate1</name>
+                  <mediasize>1073741824</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>DEV</name>
+              <geom id="0x22">
+                <class ref="0x11"/>
+                <name>hast/shared</name>
+                <rank>2</rank>
+                <consumer id="0x40">
+                  <geom ref="0x22"/>
+                  <provider ref="0x30"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+              <geom id="0x23">
+                <class ref="0x11"/>
+                <name>ggate1</name>
+                <rank>2</rank>
+                <consumer id="0x41">
+                  <geom ref="0x23"/>
+                  <provider ref="0x31"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+            <class id="0x12">
+              <name>VFS</name>
+              <geom id="0x24">
+                <class ref="0x12"/>
+                <name>ffs.hast/shared</name>
+                <rank>2</rank>
+                <consumer id="0x42">
+                  <geom ref="0x24"/>
+                  <provider ref="0x30"/>
+                  <mode>r1w1e1</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        match g.nodes[&0x21].metadata.as_deref().unwrap() {
+            graph::GeomMetadata::GATE {
+                access,
+                info,
+                unit,
+                queue_size,
+                ..
+            } => {
+                assert_eq!(access, "read-only");
+                assert_eq!(info, "10.0.0.1:/dev/ada1");
+                assert_eq!(*unit, 1);
+                assert_eq!(*queue_size, None);
+            }
+            _ => panic!("expected GATE metadata"),
+        }
+
+        let hast = g.hast_resources();
+        assert_eq!(hast.len(), 1);
+        assert_eq!(hast[0].2, "shared");
+        assert_eq!(hast[0].1.mediasize, 1073741824);
+    }
+
+    #[test]
+    fn byte_ownership() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (nvd1, _) = g.roots_iter().find(|(_, n)| n.name == "nvd1").unwrap();
+        // nvd1p2 starts at 34359758848.
+        let owners = g
+            .who_owns(nvd1, 34359758848 + 4096)
+            .into_iter()
+            .map(|(_, e, n)| (e.name.as_str(), n.class.clone()))
+            .collect::<Vec<_>>();
+        assert!(owners.contains(&("nvd1", graph::GeomClass::PART)));
+        assert!(owners.contains(&("nvd1", graph::GeomClass::DEV)));
+        assert!(owners.contains(&("nvd1p2", graph::GeomClass::LABEL)));
+        assert!(owners.contains(&("nvd1p2", graph::GeomClass::DEV)));
+        assert!(owners.contains(&("gpt-partition-2", graph::GeomClass::VFS)));
+        assert!(!owners.iter().any(|(name, _)| *name == "nvd1p1"));
+
+        // Between partitions, only the whole disk consumers own the byte.
+        let owners = g.who_owns(nvd1, 100);
+        assert!(owners.iter().all(|(_, e, _)| e.name == "nvd1"));
+
+        // Past the end of the disk, nothing does.
+        assert!(g.who_owns(nvd1, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn vfs_linkage() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (vfs_id, vfs) = g
+            .nodes
+            .iter()
+            .find(|(_, n)| n.name == "ffs.gpt-partition-2")
+            .unwrap();
+        assert_eq!(vfs.class, graph::GeomClass::VFS);
+        match vfs.metadata.as_ref().unwrap().as_ref() {
+            graph::GeomMetadata::VFS { fstype, provider } => {
+                assert_eq!(fstype, "ffs");
+                assert_eq!(provider, "gpt-partition-2");
+            }
+            _ => panic!("expected VFS metadata"),
+        }
+
+        let (edgeid, edge) = g.vfs_provider_edge(vfs_id).unwrap();
+        assert_eq!(edge.name, "gpt-partition-2");
+        assert_eq!(edge.consumer_geom, *vfs_id);
+
+        // The DEV consumer of the same provider should lead us back to the VFS geom.
+        let (dev_edgeid, _, _) = g
+            .child_geoms_iter(&edge.provider_geom)
+            .find(|(eid, _, n)| eid.1 == edgeid.1 && n.class == graph::GeomClass::DEV)
+            .unwrap();
+        let vfs_consumers = g.vfs_consumers_iter(dev_edgeid).collect::<Vec<_>>();
+        assert_eq!(vfs_consumers.len(), 1);
+        assert_eq!(vfs_consumers[0].2.name, "ffs.gpt-partition-2");
+
+        // Not a VFS geom.
+        assert!(g.vfs_provider_edge(&edge.provider_geom).is_none());
+    }
+
+    #[test]
+    fn swap_devices() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let swaps = g.swap_devices().collect::<Vec<_>>();
+        assert_eq!(swaps.len(), 1);
+        assert_eq!(swaps[0].1.name, "gpt-partition-5");
+        assert_eq!(swaps[0].1.mediasize, 34359738368);
+        assert_eq!(
+            g.nodes[&swaps[0].1.consumer_geom].class,
+            graph::GeomClass::SWAP
+        );
+    }
+
+    #[test]
+    fn absolute_offsets() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let edge_named = |name: &str| {
+            g.edges
+                .iter()
+                .find(|(_, e)| e.name == name)
+                .map(|(id, _)| id)
+                .unwrap()
+        };
+
+        // DISK: always zero.
+        assert_eq!(g.absolute_disk_offset(edge_named("nvd1")), Some(0));
+        // PART directly on a DISK.
+        assert_eq!(
+            g.absolute_disk_offset(edge_named("nvd1p2")),
+            Some(34359758848)
+        );
+        // LABEL on a PART on a DISK.
+        assert_eq!(
+            g.absolute_disk_offset(edge_named("gpt-partition-2")),
+            Some(34359758848)
+        );
+        assert_eq!(
+            g.absolute_disk_offset(edge_named("msdosfs/EFI")),
+            Some(20480)
+        );
+        assert_eq!(g.absolute_disk_offset(&(0, 0)), None);
+    }
+
+    #[test]
+    fn index_sugar() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (nid, _) = g.roots_iter().next().unwrap();
+        assert_eq!(g[*nid].name, g.get_node(nid).unwrap().name);
+        let eid = *g.edges.keys().next().unwrap();
+        assert_eq!(g[eid].name, g.get_edge(&eid).unwrap().name);
+
+        assert!(g.get_node(&0).is_none());
+        assert!(g.get_edge(&(0, 0)).is_none());
+        let missing = std::panic::catch_unwind(|| g[0].name.clone());
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn edge_metadata_accessors() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let mut seen = (0, 0, 0);
+        for e in g.edges.values() {
+            let m = match e.metadata.as_deref() {
+                Some(m) => m,
+                None => continue,
+            };
+            match (m.as_disk(), m.as_part(), m.as_label()) {
+                (Some(d), None, None) => {
+                    assert!(!d.ident.is_empty());
+                    seen.0 += 1;
+                }
+                (None, Some(p), None) => {
+                    assert!(p.end >= p.start);
+                    seen.1 += 1;
+                }
+                (None, None, Some(l)) => {
+                    assert_eq!(l.length, e.mediasize);
+                    seen.2 += 1;
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        assert!(seen.0 > 0 && seen.1 > 0 && seen.2 > 0);
+    }
+
+    #[test]
+    fn dev_nodes() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let devs: Vec<_> = g.dev_nodes().collect();
+        let ndev = g
+            .nodes
+            .values()
+            .filter(|n| n.class == graph::GeomClass::DEV)
+            .count();
+        assert_eq!(devs.len(), ndev);
+        for (id, geom, eid, edge) in devs {
+            assert_eq!(g[*eid].name, edge.name);
+            assert_eq!(edge.consumer_geom, *id);
+            assert_eq!(geom.name, edge.name);
+        }
+    }
+
+    #[test]
+    fn find_by_class_name() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (disk, geom) = g
+            .find_by_class_name(&graph::GeomClass::DISK, "ada0")
+            .unwrap();
+        assert_eq!(geom.class, graph::GeomClass::DISK);
+        assert_eq!(geom.name, "ada0");
+        let (dev, geom) = g
+            .find_by_class_name(&graph::GeomClass::DEV, "ada0")
+            .unwrap();
+        assert_eq!(geom.class, graph::GeomClass::DEV);
+        assert_ne!(disk, dev);
+
+        assert!(g
+            .find_by_class_name(&graph::GeomClass::MIRROR, "ada0")
+            .is_none());
+        assert!(g
+            .find_by_class_name(&graph::GeomClass::DISK, "nonexistent")
+            .is_none());
+    }
+
+    #[test]
+    fn raw_links() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        assert!(g.nodes.values().all(|n| n.raw_geom(&rawmesh).is_none()));
+        assert!(g.edges.values().all(|e| e.raw_provider(&rawmesh).is_none()));
+
+        let g = graph::decode_graph_with_raw(&rawmesh).unwrap();
+        for (id, geom) in &g.nodes {
+            let rawgeom = geom.raw_geom(&rawmesh).unwrap();
+            assert_eq!(graph::scan_ptr(&rawgeom.id).unwrap(), *id);
+            assert_eq!(rawgeom.name, geom.name);
+        }
+        for ((cid, pid), edge) in &g.edges {
+            let prov = edge.raw_provider(&rawmesh).unwrap();
+            assert_eq!(graph::scan_ptr(&prov.id).unwrap(), *pid);
+            assert_eq!(prov.name, edge.name);
+            let cons = edge.raw_consumer(&rawmesh).unwrap();
+            assert_eq!(graph::scan_ptr(&cons.id).unwrap(), *cid);
+        }
+    }
+
+    #[test]
+    fn withered_and_orphans() {
+        let xml = r#"<mesh>
+            <class id="0x10">
+              <name>DISK</name>
+              <geom id="0x20">
+                <class ref="0x10"/>
+                <name>da0</name>
+                <rank>1</rank>
+                <wither/>
+                <provider id="0x30">
+                  <geom ref="0x20"/>
+                  <mode>r0w0e0</mode>
+                  <name>da0</name>
+                  <mediasize>1048576</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <wither/>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x11">
+              <name>MD</name>
+              <geom id="0x21">
+                <class ref="0x11"/>
+                <name>md0</name>
+                <rank>1</rank>
+                <config>
+                </config>
+                <provider id="0x31">
+                  <geom ref="0x21"/>
+                  <mode>r0w0e0</mode>
+                  <name>md0</name>
+                  <mediasize>2097152</mediasize>
+                  <sectorsize>512</sectorsize>
+                  <stripesize>0</stripesize>
+                  <stripeoffset>0</stripeoffset>
+                  <config>
+                  </config>
+                </provider>
+              </geom>
+            </class>
+            <class id="0x12">
+              <name>DEV</name>
+              <geom id="0x22">
+                <class ref="0x12"/>
+                <name>da0</name>
+                <rank>2</rank>
+                <consumer id="0x40">
+                  <geom ref="0x22"/>
+                  <provider ref="0x30"/>
+                  <mode>r0w0e0</mode>
+                </consumer>
+              </geom>
+            </class>
+          </mesh>"#;
+        let rawmesh = raw::parse_xml(xml).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        assert!(g.nodes[&0x20].withered);
+        assert!(!g.nodes[&0x21].withered);
+        let edge = &g[(0x40, 0x30)];
+        assert!(edge.withered);
+        assert!(edge.metadata.is_none());
+
+        let orphans: Vec<_> = g.orphan_providers().collect();
+        assert_eq!(orphans.len(), 1);
+        let (id, orphan) = orphans[0];
+        assert_eq!(*id, 0x31);
+        assert_eq!(orphan.name, "md0");
+        assert_eq!(orphan.provider_geom, 0x21);
+        assert!(!orphan.withered);
+
+        assert_eq!(g.subgraph(&0x21).orphans.len(), 1);
+        assert!(g.subgraph(&0x20).orphans.is_empty());
+        assert!(g.verify().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_json() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let json = serde_json::to_value(&g).unwrap();
+        assert_eq!(json["nodes"].as_object().unwrap().len(), g.nodes.len());
+        assert_eq!(json["edges"].as_array().unwrap().len(), g.edges.len());
+        assert!(json.get("names").is_none());
+
+        let (id, _) = g
+            .find_by_class_name(&graph::GeomClass::PART, "nvd0")
+            .unwrap();
+        let part = &json["nodes"][id.to_string()];
+        assert_eq!(part["class"], "PART");
+        assert_eq!(part["metadata"]["PART"]["scheme"], "GPT");
+
+        let (eid, _) = g.edges.iter().find(|(_, e)| e.name == "nvd0p1").unwrap();
+        let edge = json["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|pair| pair[0] == serde_json::json!([eid.0, eid.1]))
+            .unwrap();
+        assert_eq!(edge[1]["metadata"]["PART"]["type_"], "efi");
+        assert_eq!(
+            edge[1]["mode"]["read"],
+            serde_json::json!(g[*eid].mode.read())
+        );
+
+        let names = serde_json::to_value((
+            graph::GeomClass::ZFSVDEV,
+            graph::GeomClass::Other("FOO".into()),
+            graph::PartAttrib::BootMe,
+        ))
+        .unwrap();
+        assert_eq!(names, serde_json::json!(["ZFS::VDEV", "FOO", "bootme"]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_json() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let json = serde_json::to_string(&g).unwrap();
+        let g2: graph::Graph = serde_json::from_str(&json).unwrap();
+        assert_eq!(g2.nodes.len(), g.nodes.len());
+        assert_eq!(g2.edges.len(), g.edges.len());
+        assert_eq!(g2.names, g.names);
+        assert_eq!(g2.class_names, g.class_names);
+        assert_eq!(g2.part_uuids, g.part_uuids);
+        assert!(g2.verify().is_empty());
+        assert_eq!(serde_json::to_string(&g2).unwrap(), json);
+
+        let (id, _) = g2
+            .find_by_class_name(&graph::GeomClass::PART, "nvd0")
+            .unwrap();
+        assert!(matches!(
+            g2[*id].metadata.as_deref(),
+            Some(graph::GeomMetadata::PART(m)) if matches!(m.scheme, graph::PartScheme::GPT)
+        ));
+
+        let names: (graph::GeomClass, graph::PartAttrib, graph::LabelKind) =
+            serde_json::from_str(r#"["FOO", "bootme", "gpt"]"#).unwrap();
+        assert_eq!(
+            names,
+            (
+                graph::GeomClass::Other("FOO".into()),
+                graph::PartAttrib::BootMe,
+                graph::LabelKind::GPT
+            )
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_inconsistent() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let mut g = graph::decode_graph(&rawmesh).unwrap();
+
+        // Drop a geom that still has edges.
+        let id = *g.outedges.keys().next().unwrap();
+        g.nodes.remove(&id);
+        let json = serde_json::to_string(&g).unwrap();
+        let err = serde_json::from_str::<graph::Graph>(&json).unwrap_err();
+        assert!(err.to_string().contains("inconsistent graph"));
+    }
 }
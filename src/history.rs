@@ -0,0 +1,479 @@
+//! Recent changes to a graph.
+//!
+//! A `GraphHistory` is fed successive snapshots of a graph (e.g., by a daemon each time
+//! `watch::Watcher` reports an event) and keeps the most recent changes between them, so that it
+//! can answer "what changed in the past hour?" without keeping every snapshot.
+//!
+//! It also follows each provider across snapshots by a `StableId`, such as a disk's serial
+//! number, and keeps a timeline of its lifecycle (see `GraphHistory::lifecycles()`): when it
+//! appeared, was resized, degraded, disappeared, or came back under another name.
+use crate::diff::GraphDelta;
+use crate::graph::{EdgeMetadata, GeomMetadata, PartInfo};
+use crate::report::HEALTHY_STATES;
+use crate::Graph;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// How `GraphHistory` tells a provider apart from others across snapshots, even if it is renamed
+/// (e.g., a disk reattached as `da1` instead of `da0`).
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum StableId {
+    /// A disk, by serial number (`DiskInfo::ident`).  The paths of a multipath disk share it;
+    /// only the first path (by provider id) is followed.
+    Serial(String),
+    /// A partition, by GPT UUID (`PartInfo::rawuuid`)
+    PartUuid(String),
+    /// Any other provider, or a disk or partition lacking the above, by name
+    Name(String),
+}
+
+/// Something that happened to a provider; see `LifecycleEntry`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LifecycleEvent {
+    /// The provider was seen for the first time, or again after it disappeared.  If it came back
+    /// under another name, `previous_name` is the name it had.
+    Appeared {
+        name: String,
+        mediasize: u64,
+        previous_name: Option<String>,
+    },
+    /// The provider was renamed between two snapshots
+    Renamed { old: String, new: String },
+    /// The provider's size changed, e.g., a partition was resized
+    Resized { old: u64, new: u64 },
+    /// The provider's geom reported an unhealthy `State`, e.g., a MIRROR going `DEGRADED`
+    Degraded { state: String },
+    /// The provider's geom recovered from an unhealthy `State`
+    Recovered { state: Option<String> },
+    /// The provider is no longer in the graph
+    Disappeared,
+}
+
+/// An event in the lifecycle of a provider, and when the snapshot showing it was recorded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LifecycleEntry {
+    pub time: SystemTime,
+    pub event: LifecycleEvent,
+}
+
+/// The lifecycle of one provider; see `GraphHistory::lifecycles()`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Lifecycle {
+    /// The provider's current name, or its last name if it has disappeared
+    pub name: String,
+    /// Whether the provider is in the latest snapshot
+    pub present: bool,
+    /// Its most recent events, oldest first; at most the history's capacity are kept.
+    pub events: Vec<LifecycleEntry>,
+}
+
+/// What `GraphHistory` follows of a provider in each snapshot.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ProviderState {
+    name: String,
+    mediasize: u64,
+    /// The `State` of the provider's geom, if unhealthy
+    unhealthy: Option<String>,
+    /// The `State` of the provider's geom, if any
+    state: Option<String>,
+}
+
+/// The providers of `graph`, by `StableId`.
+fn provider_states(graph: &Graph) -> BTreeMap<StableId, ProviderState> {
+    let mut result = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    for ((_, pid), edge) in &graph.edges {
+        if !seen.insert(*pid) {
+            continue;
+        }
+        let id = match edge.metadata.as_deref() {
+            Some(EdgeMetadata::DISK(d)) if !d.ident.is_empty() => StableId::Serial(d.ident.clone()),
+            Some(EdgeMetadata::PART(PartInfo {
+                rawuuid: Some(uuid),
+                ..
+            })) => StableId::PartUuid(uuid.to_lowercase()),
+            _ => StableId::Name(edge.name.clone()),
+        };
+        let state =
+            graph
+                .nodes
+                .get(&edge.provider_geom)
+                .and_then(|g| match g.metadata.as_deref() {
+                    Some(GeomMetadata::ELI { state, .. }) => state.clone(),
+                    _ => g.unknown_config.get("State").cloned(),
+                });
+        result.entry(id).or_insert_with(|| ProviderState {
+            name: edge.name.clone(),
+            mediasize: edge.mediasize,
+            unhealthy: state
+                .clone()
+                .filter(|s| !HEALTHY_STATES.contains(&s.as_str())),
+            state,
+        });
+    }
+    for orphan in graph.orphans.values() {
+        result
+            .entry(StableId::Name(orphan.name.clone()))
+            .or_insert_with(|| ProviderState {
+                name: orphan.name.clone(),
+                mediasize: orphan.mediasize,
+                unhealthy: None,
+                state: None,
+            });
+    }
+    return result;
+}
+
+/// A change recorded by `GraphHistory`: the differences from the previous snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// When the snapshot showing the changes was recorded
+    pub time: SystemTime,
+    pub delta: GraphDelta,
+}
+
+/// The latest snapshot of a graph, the last few changes leading up to it, and the lifecycle of
+/// each provider seen.
+///
+/// Only snapshots that differ from the previous one are recorded as entries; the oldest entries
+/// (and the oldest events of each lifecycle) are discarded beyond `capacity`.
+#[derive(Clone, Debug)]
+pub struct GraphHistory {
+    capacity: usize,
+    latest: Option<(SystemTime, Graph)>,
+    entries: VecDeque<HistoryEntry>,
+    lifecycles: BTreeMap<StableId, Lifecycle>,
+}
+
+impl GraphHistory {
+    /// An empty history keeping up to `capacity` changes.  Memory for them is allocated as they
+    /// are recorded, so a generous `capacity` costs nothing up front.
+    pub fn new(capacity: usize) -> GraphHistory {
+        return GraphHistory {
+            capacity,
+            latest: None,
+            entries: VecDeque::new(),
+            lifecycles: BTreeMap::new(),
+        };
+    }
+
+    /// Records a snapshot taken now.  See `GraphHistory::record_at()`.
+    pub fn record(&mut self, graph: Graph) -> Option<&HistoryEntry> {
+        return self.record_at(SystemTime::now(), graph);
+    }
+
+    /// Records a snapshot taken at `time`, and returns the changes from the previous one, if any.
+    /// The first snapshot recorded is the baseline for later ones, and is not itself a change.
+    pub fn record_at(&mut self, time: SystemTime, graph: Graph) -> Option<&HistoryEntry> {
+        let delta = self
+            .latest
+            .as_ref()
+            .map(|(_, old)| Graph::diff(old, &graph));
+        let old = self
+            .latest
+            .as_ref()
+            .map(|(_, old)| provider_states(old))
+            .unwrap_or_default();
+        self.follow(time, &old, &provider_states(&graph));
+        self.latest = Some((time, graph));
+        let delta = delta.filter(|d| !d.is_empty())?;
+        if self.capacity == 0 {
+            return None;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry { time, delta });
+        return self.entries.back();
+    }
+
+    /// Updates the lifecycles for the change from providers `old` to `new`.
+    fn follow(
+        &mut self,
+        time: SystemTime,
+        old: &BTreeMap<StableId, ProviderState>,
+        new: &BTreeMap<StableId, ProviderState>,
+    ) {
+        let capacity = self.capacity;
+        let push = |lifecycle: &mut Lifecycle, event: LifecycleEvent| {
+            if capacity == 0 {
+                return;
+            }
+            if lifecycle.events.len() == capacity {
+                lifecycle.events.remove(0);
+            }
+            lifecycle.events.push(LifecycleEntry { time, event });
+        };
+        for (id, state) in new {
+            let lifecycle = self.lifecycles.entry(id.clone()).or_default();
+            match old.get(id) {
+                None => {
+                    let previous_name = Some(&lifecycle.name)
+                        .filter(|n| !n.is_empty() && **n != state.name)
+                        .cloned();
+                    let event = LifecycleEvent::Appeared {
+                        name: state.name.clone(),
+                        mediasize: state.mediasize,
+                        previous_name,
+                    };
+                    push(lifecycle, event);
+                    if let Some(s) = &state.unhealthy {
+                        push(lifecycle, LifecycleEvent::Degraded { state: s.clone() });
+                    }
+                }
+                Some(prev) => {
+                    if prev.name != state.name {
+                        let event = LifecycleEvent::Renamed {
+                            old: prev.name.clone(),
+                            new: state.name.clone(),
+                        };
+                        push(lifecycle, event);
+                    }
+                    if prev.mediasize != state.mediasize {
+                        let event = LifecycleEvent::Resized {
+                            old: prev.mediasize,
+                            new: state.mediasize,
+                        };
+                        push(lifecycle, event);
+                    }
+                    match (&prev.unhealthy, &state.unhealthy) {
+                        (_, Some(s)) if prev.unhealthy.as_ref() != Some(s) => {
+                            push(lifecycle, LifecycleEvent::Degraded { state: s.clone() });
+                        }
+                        (Some(_), None) => {
+                            let event = LifecycleEvent::Recovered {
+                                state: state.state.clone(),
+                            };
+                            push(lifecycle, event);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            lifecycle.name = state.name.clone();
+            lifecycle.present = true;
+        }
+        for id in old.keys().filter(|id| !new.contains_key(id)) {
+            if let Some(lifecycle) = self.lifecycles.get_mut(id) {
+                push(lifecycle, LifecycleEvent::Disappeared);
+                lifecycle.present = false;
+            }
+        }
+    }
+
+    /// The lifecycle of every provider seen, by `StableId`.
+    pub fn lifecycles(&self) -> impl Iterator<Item = (&StableId, &Lifecycle)> + '_ {
+        return self.lifecycles.iter();
+    }
+
+    /// The lifecycle of the provider `id`, if it was seen.
+    pub fn lifecycle(&self, id: &StableId) -> Option<&Lifecycle> {
+        return self.lifecycles.get(id);
+    }
+
+    /// Every lifecycle event, with the provider it happened to, in order of time, e.g., for an
+    /// audit log.
+    pub fn timeline(&self) -> Vec<(&StableId, &LifecycleEntry)> {
+        let mut result: Vec<_> = self
+            .lifecycles
+            .iter()
+            .flat_map(|(id, l)| l.events.iter().map(move |e| (id, e)))
+            .collect();
+        result.sort_by_key(|(_, e)| e.time);
+        return result;
+    }
+
+    /// The most recently recorded snapshot, and when it was taken.
+    pub fn latest(&self) -> Option<(SystemTime, &Graph)> {
+        return self.latest.as_ref().map(|(t, g)| (*t, g));
+    }
+
+    /// The recorded changes, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &HistoryEntry> + '_ {
+        return self.entries.iter();
+    }
+
+    /// The recorded changes from after `time`, oldest first.
+    pub fn since(&self, time: SystemTime) -> impl Iterator<Item = &HistoryEntry> + '_ {
+        return self.entries.iter().filter(move |e| e.time > time);
+    }
+
+    /// The recorded changes from the past `period`, e.g., the past hour, oldest first.
+    pub fn within(&self, period: Duration) -> impl Iterator<Item = &HistoryEntry> + '_ {
+        let since = SystemTime::now()
+            .checked_sub(period)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        return self.since(since);
+    }
+
+    /// The number of recorded changes.
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    /// Whether no changes have been recorded (or kept).
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::{GraphHistory, LifecycleEvent, StableId};
+    use crate::{graph, raw};
+    use std::time::{Duration, SystemTime};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn record() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let at = |mins: u64| t0 + Duration::from_secs(60 * mins);
+
+        let mut h = GraphHistory::new(2);
+        assert!(h.record_at(at(0), g.clone()).is_none());
+        assert!(h.record_at(at(1), g.clone()).is_none());
+        assert!(h.is_empty());
+        assert_eq!(h.latest().unwrap().0, at(1));
+
+        // Resize nvd0p2 three times; only the last two changes are kept.
+        for i in 1..=3 {
+            let mut grown = g.clone();
+            for edge in grown.edges.values_mut() {
+                if edge.name == "nvd0p2" {
+                    edge.mediasize += i * 1024;
+                }
+            }
+            let entry = h.record_at(at(10 * i), grown).unwrap();
+            assert_eq!(entry.delta.changes.len(), 1);
+        }
+        assert_eq!(h.len(), 2);
+        let times: Vec<_> = h.entries().map(|e| e.time).collect();
+        assert_eq!(times, vec![at(20), at(30)]);
+        assert_eq!(h.since(at(20)).count(), 1);
+        assert_eq!(h.within(Duration::from_secs(60)).count(), 0);
+
+        let mut h = GraphHistory::new(0);
+        h.record_at(at(0), g.clone());
+        assert!(h.record_at(at(1), graph::Graph::new()).is_none());
+        assert!(h.is_empty());
+        assert!(h.lifecycles().all(|(_, l)| l.events.is_empty()));
+
+        // A huge capacity is not allocated up front.
+        let h = GraphHistory::new(usize::MAX);
+        assert_eq!(h.entries.capacity(), 0);
+    }
+
+    #[test]
+    fn lifecycles() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let at = |mins: u64| t0 + Duration::from_secs(60 * mins);
+        let disk = StableId::Serial("AAAAAAAAAAAAAAAA".to_owned());
+        let part = StableId::PartUuid("uuid5".to_owned());
+        let events = |h: &GraphHistory, id: &StableId| -> Vec<LifecycleEvent> {
+            return h
+                .lifecycle(id)
+                .unwrap()
+                .events
+                .iter()
+                .map(|e| e.event.clone())
+                .collect();
+        };
+
+        let mut h = GraphHistory::new(10);
+        h.record_at(at(0), g.clone());
+        assert_eq!(
+            events(&h, &disk),
+            vec![LifecycleEvent::Appeared {
+                name: "nvd1".to_owned(),
+                mediasize: 1024209543168,
+                previous_name: None,
+            }]
+        );
+        let nvd0p2 = h.lifecycle(&part).unwrap();
+        assert_eq!((nvd0p2.name.as_str(), nvd0p2.present), ("nvd0p2", true));
+
+        // nvd1 is detached...
+        let (nvd1, _) = g
+            .find_by_class_name(&graph::GeomClass::DISK, "nvd1")
+            .unwrap();
+        let nvd1 = g.component(nvd1);
+        let rest = g
+            .nodes
+            .keys()
+            .filter(|id| !nvd1.contains(id))
+            .copied()
+            .collect();
+        h.record_at(at(1), g.induced(&rest));
+        assert_eq!(events(&h, &disk)[1], LifecycleEvent::Disappeared);
+        assert!(!h.lifecycle(&disk).unwrap().present);
+
+        // ... and comes back as nvd7, while nvd0p2 grows and its geom degrades.
+        let mut back = g.clone();
+        for edge in back.edges.values_mut() {
+            if edge.name == "nvd1" {
+                edge.name = "nvd7".to_owned();
+            }
+            if edge.name == "nvd0p2" {
+                edge.mediasize *= 2;
+            }
+        }
+        let (id, _) = back
+            .find_by_class_name(&graph::GeomClass::PART, "nvd0")
+            .unwrap();
+        let id = *id;
+        let table = back.nodes.get_mut(&id).unwrap();
+        table
+            .unknown_config
+            .insert("State".to_owned(), "DEGRADED".to_owned());
+        h.record_at(at(2), back);
+        assert_eq!(
+            events(&h, &disk)[2],
+            LifecycleEvent::Appeared {
+                name: "nvd7".to_owned(),
+                mediasize: 1024209543168,
+                previous_name: Some("nvd1".to_owned()),
+            }
+        );
+        assert_eq!(h.lifecycle(&disk).unwrap().name, "nvd7");
+        let mediasize = g
+            .edges
+            .values()
+            .find(|e| e.name == "nvd0p2")
+            .unwrap()
+            .mediasize;
+        assert_eq!(
+            events(&h, &part)[1..],
+            [
+                LifecycleEvent::Resized {
+                    old: mediasize,
+                    new: mediasize * 2,
+                },
+                LifecycleEvent::Degraded {
+                    state: "DEGRADED".to_owned()
+                },
+            ]
+        );
+
+        h.record_at(at(3), g.clone());
+        assert_eq!(
+            events(&h, &disk)[3],
+            LifecycleEvent::Renamed {
+                old: "nvd7".to_owned(),
+                new: "nvd1".to_owned(),
+            }
+        );
+        assert_eq!(
+            events(&h, &part)[4],
+            LifecycleEvent::Recovered { state: None }
+        );
+
+        let timeline = h.timeline();
+        assert!(timeline.windows(2).all(|w| w[0].1.time <= w[1].1.time));
+        // The rename, the resize, and the recovery of the four partitions of nvd0.
+        assert_eq!(timeline.iter().filter(|(_, e)| e.time == at(3)).count(), 6);
+    }
+}
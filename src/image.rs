@@ -0,0 +1,151 @@
+//! Read-only imaging plans for providers in a `geom::Graph`.
+//!
+//! An `ImagePlan` describes how to copy the bytes backing a single provider (e.g., a partition)
+//! directly off of the physical disk it lives on, without going through any of the intermediate
+//! GEOM layers.  This is useful for forensics and backup tooling.
+use crate::graph::{EdgeId, Graph};
+
+/// The largest block size an `ImagePlan` will suggest, in bytes.
+const MAX_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// A plan for reading the contents of a provider off of its underlying disk.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ImagePlan {
+    /// The name of the provider being imaged, e.g., `"ada0p2"`.
+    pub provider: String,
+    /// The path to the device to read from, e.g., `"/dev/ada0"`.
+    pub device: String,
+    /// The byte offset of the provider within `device`.
+    pub offset: u64,
+    /// The length of the provider, in bytes.  A checksum of the resulting image should cover
+    /// exactly this many bytes.
+    pub length: u64,
+    /// A suggested I/O size, in bytes.  It is the largest power of two (up to 1 MiB) that evenly
+    /// divides both `offset` and `length`.
+    pub block_size: u64,
+}
+
+impl ImagePlan {
+    /// The number of `block_size` blocks to skip at the start of `device`.
+    pub fn skip_blocks(&self) -> u64 {
+        return self.offset / self.block_size;
+    }
+
+    /// The number of `block_size` blocks to read.
+    pub fn block_count(&self) -> u64 {
+        return self.length / self.block_size;
+    }
+
+    /// Returns a `dd(1)` command line that copies the provider to `output` without writing to
+    /// `device`.  The paths are quoted for `sh(1)` if they contain anything but letters, digits,
+    /// and a few safe punctuation characters.
+    pub fn dd_command(&self, output: &str) -> String {
+        return format!(
+            "dd if={} of={} bs={} skip={} count={}",
+            shell_quote(&self.device),
+            shell_quote(output),
+            self.block_size,
+            self.skip_blocks(),
+            self.block_count()
+        );
+    }
+}
+
+/// Quotes `word` for `sh(1)`, unless it consists only of characters that are never special.
+fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        return word.to_owned();
+    }
+    return format!("'{}'", word.replace('\'', "'\\''"));
+}
+
+fn suggest_block_size(offset: u64, length: u64) -> u64 {
+    let mut bs = MAX_BLOCK_SIZE;
+    while bs > 1 && !(offset.is_multiple_of(bs) && length.is_multiple_of(bs)) {
+        bs /= 2;
+    }
+    return bs;
+}
+
+impl Graph {
+    /// Given the `EdgeId` of an `Edge`, returns an `ImagePlan` for reading its provider directly
+    /// off of the underlying disk.
+    ///
+    /// Returns `None` under the same conditions as `Graph::absolute_disk_offset()`.
+    pub fn image_plan(&self, id: &EdgeId) -> Option<ImagePlan> {
+        let edge = self.edges.get(id)?;
+        let offset = self.absolute_disk_offset(id)?;
+        let (_, root) = self.disk_edge(id)?;
+
+        return Some(ImagePlan {
+            provider: edge.name.to_owned(),
+            device: format!("/dev/{}", root.name),
+            offset,
+            length: edge.mediasize,
+            block_size: suggest_block_size(offset, edge.mediasize),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, image, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn block_sizes() {
+        assert_eq!(image::suggest_block_size(0, 4096), 4096);
+        assert_eq!(image::suggest_block_size(20480, 1 << 30), 4096);
+        assert_eq!(image::suggest_block_size(1 << 20, 1 << 30), 1 << 20);
+        assert_eq!(image::suggest_block_size(1, 512), 1);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(image::shell_quote("/dev/gpt/rootfs"), "/dev/gpt/rootfs");
+        assert_eq!(image::shell_quote("my disk.img"), "'my disk.img'");
+        assert_eq!(image::shell_quote("$(rm -rf ~)"), "'$(rm -rf ~)'");
+        assert_eq!(image::shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(image::shell_quote(""), "''");
+
+        let plan = image::ImagePlan {
+            provider: "label/my disk".into(),
+            device: "/dev/label/my disk".into(),
+            offset: 0,
+            length: 512,
+            block_size: 512,
+        };
+        assert_eq!(
+            plan.dd_command("out;rm x"),
+            "dd if='/dev/label/my disk' of='out;rm x' bs=512 skip=0 count=1"
+        );
+    }
+
+    #[test]
+    fn label_plan() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (id, _) = g
+            .edges
+            .iter()
+            .find(|(_, e)| e.name == "msdosfs/EFI")
+            .unwrap();
+        let plan = g.image_plan(id).unwrap();
+        assert_eq!(
+            plan,
+            image::ImagePlan {
+                provider: "msdosfs/EFI".into(),
+                device: "/dev/nvd0".into(),
+                offset: 20480,
+                length: 209715200,
+                block_size: 4096,
+            }
+        );
+        assert_eq!(
+            plan.dd_command("efi.img"),
+            "dd if=/dev/nvd0 of=efi.img bs=4096 skip=5 count=51200"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Correlation of kernel I/O error reports with the `geom::Graph`.
+//!
+//! The kernel reports failed I/O against a provider name and an offset relative to that provider
+//! (or a disk LBA).  This module parses such reports into `IoErrorEvent`s, and
+//! `Graph::correlate_io_error()` works out which partitions, filesystems, swap devices, and ZFS
+//! vdevs live at the affected byte.
+//!
+//! Only `g_vfs_done()` console messages are parsed.  Errors reported by CAM or devd (e.g., a
+//! failed `READ(10)` with its LBA) are not; callers which extract a device and LBA from those
+//! can construct an event with `IoErrorEvent::from_lba()`.
+use crate::graph::{Edge, EdgeId, EdgeMetadata, Geom, GeomClass, Graph, NodeId};
+use std::fmt;
+
+/// A failed I/O request against some provider.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IoErrorEvent {
+    /// The provider name, without any `/dev/` prefix, e.g., `"ada0p2"` or `"gpt/rootfs"`.
+    pub device: String,
+    /// The operation, e.g., `"READ"`, `"WRITE"`, `"DELETE"`, or `"FLUSH"`, if known.
+    pub operation: Option<String>,
+    /// The byte offset of the request, relative to the start of `device`.
+    pub offset: u64,
+    /// The length of the request in bytes, if known.
+    pub length: Option<u64>,
+    /// The `errno` the request failed with, if known.
+    pub error: Option<i32>,
+}
+
+impl IoErrorEvent {
+    /// Parses a `g_vfs_done()` console message, as logged when filesystem I/O fails:
+    ///
+    /// ```text
+    /// g_vfs_done():ada0p2[READ(offset=65536, length=4096)]error = 5
+    /// ```
+    ///
+    /// Anything before `g_vfs_done():` (e.g., a syslog timestamp and hostname) is ignored.  Returns
+    /// `None` if the line is not such a message.
+    pub fn parse_console(line: &str) -> Option<IoErrorEvent> {
+        const PREFIX: &str = "g_vfs_done():";
+
+        let rest = &line[line.find(PREFIX)? + PREFIX.len()..];
+        let (device, rest) = rest.split_at(rest.find('[')?);
+        let rest = &rest[1..];
+        let (operation, rest) = rest.split_at(rest.find('(')?);
+        let (args, rest) = rest[1..].split_at(rest.find(")]")? - 1);
+        let rest = &rest[2..];
+
+        let mut offset = None;
+        let mut length = None;
+        for arg in args.split(',') {
+            let mut kv = arg.trim().splitn(2, '=');
+            match (kv.next()?, kv.next()?) {
+                ("offset", v) => offset = Some(v.parse::<u64>().ok()?),
+                ("length", v) => length = Some(v.parse::<u64>().ok()?),
+                _ => {}
+            }
+        }
+        let error = rest
+            .trim()
+            .strip_prefix("error")
+            .and_then(|e| e.trim().strip_prefix('='))
+            .and_then(|e| e.trim().parse::<i32>().ok());
+
+        return Some(IoErrorEvent {
+            device: device.to_owned(),
+            operation: Some(operation.to_owned()),
+            offset: offset?,
+            length,
+            error,
+        });
+    }
+
+    /// Constructs an event from a logical block address on `device`, as reported by disk drivers
+    /// and CAM.  `sectorsize` is the provider's sector size (see `Edge::sectorsize`).
+    ///
+    /// Returns `None` if the byte offset overflows, e.g., for a garbled LBA; such a report should
+    /// be skipped.
+    pub fn from_lba(device: &str, lba: u64, sectorsize: u64) -> Option<IoErrorEvent> {
+        return Some(IoErrorEvent {
+            device: device.trim_start_matches("/dev/").to_owned(),
+            operation: None,
+            offset: lba.checked_mul(sectorsize)?,
+            length: None,
+            error: None,
+        });
+    }
+}
+
+/// What lives at the location of an `IoErrorEvent`, as computed by `Graph::correlate_io_error()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IoErrorAlert {
+    /// The event this alert is about.
+    pub event: IoErrorEvent,
+    /// The root `Geom` (e.g., DISK) the failed I/O landed on.
+    pub disk: NodeId,
+    /// The name of the root provider, e.g., `"ada0"`.
+    pub disk_name: String,
+    /// The absolute byte offset of the failed I/O on `disk`.
+    pub disk_offset: u64,
+    /// Every `Edge` whose provider covers `disk_offset` (see `Graph::who_owns()`).
+    pub owners: Vec<EdgeId>,
+    /// Names of partitions covering `disk_offset`, e.g., `"ada0p2"`.
+    pub partitions: Vec<String>,
+    /// Names of `GeomClass::VFS` geoms (mounted filesystems) covering `disk_offset`, e.g.,
+    /// `"ffs.ada0p2"`.
+    pub filesystems: Vec<String>,
+    /// Names of providers in use as swap covering `disk_offset`.
+    pub swap: Vec<String>,
+    /// Names of providers in use as ZFS vdevs covering `disk_offset`.
+    pub zfs_vdevs: Vec<String>,
+}
+
+impl fmt::Display for IoErrorAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "I/O error on {} at byte {}",
+            self.disk_name, self.disk_offset
+        )?;
+        for (what, names) in &[
+            ("partitions", &self.partitions),
+            ("filesystems", &self.filesystems),
+            ("swap", &self.swap),
+            ("ZFS vdevs", &self.zfs_vdevs),
+        ] {
+            if !names.is_empty() {
+                write!(f, "; {}: {}", what, names.join(", "))?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+fn push_unique(v: &mut Vec<String>, s: &str) {
+    if !v.iter().any(|x| x == s) {
+        v.push(s.to_owned());
+    }
+}
+
+impl Graph {
+    /// Maps an `IoErrorEvent` onto the graph: finds the provider named by the event, translates
+    /// the offset to the underlying disk, and collects everything that lives there.
+    ///
+    /// Returns `None` if no provider has the event's name, or if the offset cannot be translated
+    /// (see `Graph::absolute_disk_offset()`).
+    pub fn correlate_io_error(&self, event: &IoErrorEvent) -> Option<IoErrorAlert> {
+        let (edgeid, _) = self.edges.iter().find(|(_, e)| e.name == event.device)?;
+        let disk_offset = self
+            .absolute_disk_offset(edgeid)?
+            .checked_add(event.offset)?;
+        let (_, disk_edge) = self.disk_edge(edgeid)?;
+
+        let owners: Vec<(&EdgeId, &Edge, &Geom)> =
+            self.who_owns(&disk_edge.provider_geom, disk_offset);
+
+        let mut alert = IoErrorAlert {
+            event: event.clone(),
+            disk: disk_edge.provider_geom,
+            disk_name: disk_edge.name.to_owned(),
+            disk_offset,
+            owners: owners.iter().map(|(id, _, _)| **id).collect(),
+            partitions: Vec::new(),
+            filesystems: Vec::new(),
+            swap: Vec::new(),
+            zfs_vdevs: Vec::new(),
+        };
+        for (_, edge, consumer) in owners {
+            if let Some(EdgeMetadata::PART(_)) = edge.metadata.as_deref() {
+                push_unique(&mut alert.partitions, &edge.name);
+            }
+            match consumer.class {
+                GeomClass::VFS => push_unique(&mut alert.filesystems, &consumer.name),
+                GeomClass::SWAP => push_unique(&mut alert.swap, &edge.name),
+                GeomClass::ZFSVDEV => push_unique(&mut alert.zfs_vdevs, &edge.name),
+                _ => {}
+            }
+        }
+        return Some(alert);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, ioerror::IoErrorEvent, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn parse_g_vfs_done() {
+        let ev = IoErrorEvent::parse_console(
+            "Oct 15 12:00:00 host kernel: g_vfs_done():gpt/rootfs[WRITE(offset=65536, length=4096)]error = 6",
+        )
+        .unwrap();
+        assert_eq!(
+            ev,
+            IoErrorEvent {
+                device: "gpt/rootfs".into(),
+                operation: Some("WRITE".into()),
+                offset: 65536,
+                length: Some(4096),
+                error: Some(6),
+            }
+        );
+
+        assert!(
+            IoErrorEvent::parse_console("ada0: <Samsung SSD> ACS-4 ATA SATA 3.x device").is_none()
+        );
+        assert!(IoErrorEvent::parse_console("g_vfs_done():ada0p2[READ(length=4096)]").is_none());
+    }
+
+    #[test]
+    fn from_lba() {
+        let ev = IoErrorEvent::from_lba("/dev/ada0", 10, 512).unwrap();
+        assert_eq!(ev.device, "ada0");
+        assert_eq!(ev.offset, 5120);
+        assert!(IoErrorEvent::from_lba("ada0", u64::MAX / 2, 512).is_none());
+    }
+
+    #[test]
+    fn correlate() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        // Relative to a LABEL on nvd1p2.
+        let ev = IoErrorEvent::parse_console(
+            "g_vfs_done():gpt-partition-2[READ(offset=8192, length=4096)]error = 5",
+        )
+        .unwrap();
+        let alert = g.correlate_io_error(&ev).unwrap();
+        assert_eq!(alert.disk_name, "nvd1");
+        assert_eq!(alert.disk_offset, 34359758848 + 8192);
+        assert_eq!(alert.partitions, vec!["nvd1p2".to_owned()]);
+        assert_eq!(alert.filesystems, vec!["ffs.gpt-partition-2".to_owned()]);
+        assert!(alert.swap.is_empty());
+        assert_eq!(
+            format!("{}", alert),
+            "I/O error on nvd1 at byte 34359767040; partitions: nvd1p2; filesystems: ffs.gpt-partition-2"
+        );
+
+        // An LBA on the whole disk, landing in swap.
+        let ev = IoErrorEvent::from_lba("nvd1", 40 + 8, 512).unwrap();
+        let alert = g.correlate_io_error(&ev).unwrap();
+        assert_eq!(alert.partitions, vec!["nvd1p1".to_owned()]);
+        assert_eq!(alert.swap, vec!["gpt-partition-5".to_owned()]);
+
+        let ev = IoErrorEvent::from_lba("da9", 0, 512).unwrap();
+        assert!(g.correlate_io_error(&ev).is_none());
+    }
+}
@@ -1,9 +1,11 @@
 #![allow(dead_code)]
+#![allow(clippy::needless_return)]
 
 #[macro_use]
 extern crate scan_fmt;
 extern crate sysctl;
 
+#[cfg(target_os = "freebsd")]
 use sysctl::Sysctl;
 
 #[cfg(target_os = "freebsd")]
@@ -28,10 +30,88 @@ fn get_confxml() -> Result<String, Error> {
 /// ```
 #[cfg(target_os = "freebsd")]
 pub fn get_graph() -> Result<Graph, Error> {
+    #[cfg(test)]
+    if let Some(graph) = TEST_GRAPH.with(|g| g.borrow().clone()) {
+        return Ok(graph);
+    }
     let raw_mesh = raw::get_mesh()?;
     return graph::decode_graph(&raw_mesh);
 }
 
+#[cfg(all(test, target_os = "freebsd"))]
+thread_local! {
+    /// The graph `get_graph()` returns on this thread instead of the running system's; see
+    /// `dry_run_on()`.
+    static TEST_GRAPH: std::cell::RefCell<Option<Graph>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Runs `f`, which calls control wrappers, under a dry-run `CtlPolicy` and with `get_graph()`
+/// returning `graph`, and returns its result and the requests it would have made.  This lets the
+/// wrappers be tested against a sample graph without touching the system.
+#[cfg(all(test, target_os = "freebsd"))]
+pub(crate) fn dry_run_on<R, F: FnOnce() -> R>(graph: &Graph, f: F) -> (R, Vec<ctl::GctlRequest>) {
+    let mut policy = ctl::CtlPolicy::default();
+    policy.dry_run = true;
+    TEST_GRAPH.with(|g| g.replace(Some(graph.clone())));
+    let result = policy.apply(f);
+    TEST_GRAPH.with(|g| g.replace(None));
+    return result;
+}
+
+/// Like `get_graph()`, but with non-default `DecodeOptions` (e.g., to redact serial numbers).
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// fn myfoo() -> Result<(), geom::Error> {
+///     let opts = geom::DecodeOptions {
+///         redaction: geom::Redaction::Hash,
+///         ..Default::default()
+///     };
+///     let graph = geom::get_graph_with_options(&opts)?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn get_graph_with_options(opts: &DecodeOptions) -> Result<Graph, Error> {
+    if opts.redaction == Redaction::Keep {
+        return graph::decode_graph_with_options(&raw::get_mesh()?, opts);
+    }
+    // Redact in place, rather than copying the original values once more.
+    let xml = get_confxml()?;
+    let mesh = raw::parse_xml(&xml);
+    crate::wipe(&mut xml.into_bytes());
+    let mut mesh = mesh?;
+    opts.redaction.apply(&mut mesh);
+    return graph::decode_redacted(&mesh, opts);
+}
+
+/// Like `get_graph()`, but runs the sysctl and decoding on `tokio`'s blocking thread pool, so as
+/// not to stall other tasks.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(feature = "tokio")]
+/// async fn myfoo() -> Result<(), geom::Error> {
+///     let graph = geom::get_graph_async().await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(all(target_os = "freebsd", feature = "tokio"))]
+pub async fn get_graph_async() -> Result<Graph, Error> {
+    return match tokio::task::spawn_blocking(get_graph).await {
+        Ok(res) => res,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        // The runtime is shutting down.
+        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, e).into()),
+    };
+}
+
 #[cfg(all(test, target_os = "freebsd"))]
 mod tests_freebsd {
     use crate::*;
@@ -44,14 +124,64 @@ mod tests_freebsd {
     }
 }
 
+/// Overwrites `buf` with zeroes, in a way the compiler will not optimize out even if `buf` is
+/// about to be freed.
+pub(crate) fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        // `b` is a valid, aligned `&mut u8`.
+        unsafe { std::ptr::write_volatile(b, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
 // reexport
+pub mod advisor;
+mod capabilities;
+pub mod concat;
+pub mod conftxt;
+#[cfg(feature = "eli")]
+mod crypto;
+pub mod ctl;
+pub mod devctl;
+pub mod diff;
+pub mod dot;
+pub mod eli;
 pub mod error;
+mod footprint;
 mod graph;
+pub mod history;
+pub mod image;
+pub mod ioerror;
+pub mod list;
+pub mod md;
+pub mod mounts;
+pub mod multipath;
+pub mod nop;
+pub mod part;
+pub mod partitions;
+pub mod prometheus;
+pub mod render;
+pub mod report;
+pub mod size;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+pub mod stats;
+pub mod stripe;
 pub mod structs;
+pub mod subgraph;
+pub mod typed;
+pub mod update;
+pub mod verify;
+pub mod watch;
 
+pub use capabilities::{capabilities, Capabilities};
 pub use error::Error;
 pub use graph::{
-    Edge, EdgeId, EdgeMetadata, Geom, GeomClass, Graph, Mode, NodeId, PartMetadata, PartScheme,
-    PartState,
+    decode_graph, decode_graph_with_options, decode_graph_with_raw, DecodeOptions, DiskInfo, Edge,
+    EdgeId, EdgeMetadata, Geom, GeomClass, GeomMetadata, Graph, LabelInfo, LabelKind, LabelMatch,
+    Mode, NodeId, OrphanProvider, PartAlias, PartAttrib, PartInfo, PartMetadata, PartScheme,
+    PartState, RawEdgeIndex, RawGeomIndex, Redaction, UnmodeledConfig,
 };
 pub use structs as raw;
+pub use typed::TypedGeom;
+pub use verify::IntegrityFinding;
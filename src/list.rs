@@ -0,0 +1,331 @@
+//! `geom(8)`-style `list` output.
+//!
+//! `geom disk list`, `gpart list`, `geli list`, etc. print each geom's config, providers, and
+//! consumers.  `Graph::list_geom()`, `Graph::list_class()`, and `Graph::list()` render the same
+//! text from a decoded `Graph`, so tools need not shell out to `geom(8)`.
+//!
+//! Config keys are printed in the reverse of the order the kernel dumps them, as `geom(8)` does.
+//! Keys this library does not model (`unknown_config`) follow, sorted by name.
+use crate::graph::{EdgeMetadata, Geom, GeomClass, GeomMetadata, Graph, Mode, NodeId, PartAttrib};
+use crate::size::ByteSize;
+use std::fmt::Write;
+
+/// A provider or consumer, as printed by `geom(8)`.
+struct Listed<'a> {
+    name: &'a str,
+    mediasize: u64,
+    sectorsize: u64,
+    stripesize: u64,
+    stripeoffset: u64,
+    mode: Mode,
+    config: Vec<(&'a str, String)>,
+}
+
+fn geom_config(geom: &Geom) -> Vec<(&str, String)> {
+    let mut result: Vec<(&str, String)> = Vec::new();
+    match geom.metadata.as_deref() {
+        Some(GeomMetadata::PART(m)) => {
+            result.push(("modified", m.modified.to_string()));
+            result.push(("state", m.state.as_ref().to_owned()));
+            result.push(("fwheads", m.fwheads.to_string()));
+            result.push(("fwsectors", m.fwsectors.to_string()));
+            result.push(("last", m.last.to_string()));
+            result.push(("first", m.first.to_string()));
+            result.push(("entries", m.entries.to_string()));
+            result.push(("scheme", m.scheme.as_ref().to_owned()));
+        }
+        Some(GeomMetadata::ELI {
+            version,
+            flags,
+            crypto,
+            encryption_algorithm,
+            key_length,
+            authentication_algorithm,
+            keys_total,
+            keys_allocated,
+            used_key,
+            state,
+        }) => {
+            if let Some(state) = state {
+                result.push(("State", state.to_owned()));
+            }
+            result.push(("EncryptionAlgorithm", encryption_algorithm.to_owned()));
+            result.push(("KeyLength", key_length.to_string()));
+            if let Some(alg) = authentication_algorithm {
+                result.push(("AuthenticationAlgorithm", alg.to_owned()));
+            }
+            result.push(("Crypto", crypto.to_owned()));
+            result.push(("Version", version.to_string()));
+            if let Some(key) = used_key {
+                result.push(("UsedKey", key.to_string()));
+            }
+            let flags = if flags.is_empty() {
+                "NONE".to_owned()
+            } else {
+                flags.join(", ")
+            };
+            result.push(("Flags", flags));
+            result.push(("KeysAllocated", keys_allocated.to_string()));
+            result.push(("KeysTotal", keys_total.to_string()));
+        }
+        Some(GeomMetadata::GATE {
+            access,
+            timeout,
+            info,
+            unit,
+            queue_count,
+            queue_size,
+            read_provider,
+            read_offset,
+        }) => {
+            if let Some(offset) = read_offset {
+                result.push(("read_offset", offset.to_string()));
+            }
+            if let Some(provider) = read_provider {
+                result.push(("read_provider", provider.to_owned()));
+            }
+            result.push(("unit", unit.to_string()));
+            if let Some(size) = queue_size {
+                result.push(("queue_size", size.to_string()));
+            }
+            if let Some(count) = queue_count {
+                result.push(("queue_count", count.to_string()));
+            }
+            result.push(("info", info.to_owned()));
+            result.push(("timeout", timeout.to_string()));
+            result.push(("access", access.to_owned()));
+        }
+        Some(GeomMetadata::CCD {
+            interleave: Some(interleave),
+            ..
+        }) => result.push(("interleave", interleave.to_string())),
+        Some(GeomMetadata::SCHED { algorithm }) => result.push(("algo", algorithm.to_owned())),
+        // VFS and DEV metadata are not derived from config.
+        _ => {}
+    }
+    for (k, v) in &geom.unknown_config {
+        result.push((k, v.to_owned()));
+    }
+    return result;
+}
+
+fn provider_config(metadata: Option<&EdgeMetadata>) -> Vec<(&'static str, String)> {
+    let mut result: Vec<(&str, String)> = Vec::new();
+    match metadata {
+        Some(EdgeMetadata::DISK(d)) => {
+            result.push(("descr", d.descr.to_owned()));
+            result.push(("lunid", d.lunid.to_owned()));
+            result.push(("ident", d.ident.to_owned()));
+            result.push(("rotationrate", d.rotationrate.to_string()));
+            result.push(("fwsectors", d.fwsectors.to_string()));
+            result.push(("fwheads", d.fwheads.to_string()));
+        }
+        Some(EdgeMetadata::PART(p)) => {
+            if let Some(efimedia) = &p.efimedia {
+                result.push(("efimedia", efimedia.to_owned()));
+            }
+            if let Some(rawuuid) = &p.rawuuid {
+                result.push(("rawuuid", rawuuid.to_owned()));
+            }
+            if let Some(rawtype) = &p.rawtype {
+                result.push(("rawtype", rawtype.to_owned()));
+            }
+            if let Some(label) = &p.label {
+                result.push(("label", label.to_owned()));
+            }
+            for attrib in p.attrib.iter().rev() {
+                let name = match attrib {
+                    PartAttrib::Other(name) => name.as_str(),
+                    a => a.as_ref(),
+                };
+                result.push(("attrib", name.to_owned()));
+            }
+            result.push(("length", p.length.to_string()));
+            result.push(("offset", p.offset.to_string()));
+            result.push(("type", p.type_.to_owned()));
+            result.push(("index", p.index.to_string()));
+            result.push(("end", p.end.to_string()));
+            result.push(("start", p.start.to_string()));
+        }
+        Some(EdgeMetadata::LABEL(l)) => {
+            result.push(("secoffset", l.secoffset.to_string()));
+            result.push(("offset", l.offset.to_string()));
+            result.push(("seclength", l.seclength.to_string()));
+            result.push(("length", l.length.to_string()));
+            result.push(("index", l.index.to_string()));
+        }
+        None => {}
+    }
+    return result;
+}
+
+fn write_listed(out: &mut String, n: usize, item: &Listed) -> std::fmt::Result {
+    writeln!(out, "{}. Name: {}", n, item.name)?;
+    writeln!(
+        out,
+        "   Mediasize: {} ({})",
+        item.mediasize,
+        ByteSize(item.mediasize)
+    )?;
+    writeln!(out, "   Sectorsize: {}", item.sectorsize)?;
+    if item.stripesize > 0 || item.stripeoffset > 0 {
+        writeln!(out, "   Stripesize: {}", item.stripesize)?;
+        writeln!(out, "   Stripeoffset: {}", item.stripeoffset)?;
+    }
+    writeln!(out, "   Mode: {}", item.mode)?;
+    for (k, v) in &item.config {
+        writeln!(out, "   {}: {}", k, v)?;
+    }
+    return Ok(());
+}
+
+impl Graph {
+    /// Renders one geom as `geom <class> list <name>` would, or `None` if there is no such geom.
+    pub fn list_geom(&self, id: &NodeId) -> Option<String> {
+        let geom = self.nodes.get(id)?;
+        let mut out = String::new();
+        // Writing to a String cannot fail.
+        let _ = self.write_geom(&mut out, id, geom);
+        return Some(out);
+    }
+
+    /// Renders every geom of a class, as `geom <class> list` would (e.g., `GeomClass::PART` for
+    /// `gpart list`).  Geoms are listed by name.
+    pub fn list_class(&self, class: &GeomClass) -> String {
+        let mut out = String::new();
+        for id in self
+            .class_names
+            .get(class)
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, ids)| ids)
+        {
+            let _ = self.write_geom(&mut out, id, &self.nodes[id]);
+        }
+        return out;
+    }
+
+    /// Renders every geom in the graph, by class, then name.
+    pub fn list(&self) -> String {
+        let mut out = String::new();
+        for class in self.class_names.keys() {
+            out.push_str(&self.list_class(class));
+        }
+        return out;
+    }
+
+    fn write_geom(&self, out: &mut String, id: &NodeId, geom: &Geom) -> std::fmt::Result {
+        writeln!(out, "Geom name: {}", geom.name)?;
+        for (k, v) in geom_config(geom) {
+            writeln!(out, "{}: {}", k, v)?;
+        }
+
+        // Several consumers may share a provider; list each provider once.  Partitions are listed
+        // by index, like gpart(8) does.
+        let mut providers: Vec<(u64, u64, Listed)> = Vec::new();
+        for eid in self.inedges.get(id).into_iter().flatten() {
+            if providers.iter().any(|(_, pid, _)| *pid == eid.1) {
+                continue;
+            }
+            let edge = &self.edges[eid];
+            let mut config: Vec<(&str, String)> = provider_config(edge.metadata.as_deref());
+            for (k, v) in &edge.unknown_config {
+                config.push((k, v.to_owned()));
+            }
+            let order = match edge.metadata.as_deref() {
+                Some(EdgeMetadata::PART(p)) => p.index,
+                _ => 0,
+            };
+            providers.push((
+                order,
+                eid.1,
+                Listed {
+                    name: &edge.name,
+                    mediasize: edge.mediasize,
+                    sectorsize: edge.sectorsize,
+                    stripesize: edge.stripesize,
+                    stripeoffset: edge.stripeoffset,
+                    mode: edge.mode,
+                    config,
+                },
+            ));
+        }
+        for (pid, orphan) in self.orphans.iter().filter(|(_, o)| o.provider_geom == *id) {
+            providers.push((
+                0,
+                *pid,
+                Listed {
+                    name: &orphan.name,
+                    mediasize: orphan.mediasize,
+                    sectorsize: orphan.sectorsize,
+                    stripesize: 0,
+                    stripeoffset: 0,
+                    mode: orphan.mode,
+                    config: Vec::new(),
+                },
+            ));
+        }
+        providers.sort_by_key(|(order, pid, _)| (*order, *pid));
+        if !providers.is_empty() {
+            writeln!(out, "Providers:")?;
+            for (n, (_, _, p)) in providers.iter().enumerate() {
+                write_listed(out, n + 1, p)?;
+            }
+        }
+
+        let consumers = self.outedges.get(id).map_or(&[][..], |v| &v[..]);
+        if !consumers.is_empty() {
+            writeln!(out, "Consumers:")?;
+            for (n, eid) in consumers.iter().enumerate() {
+                let edge = &self.edges[eid];
+                let consumer = Listed {
+                    name: &edge.name,
+                    mediasize: edge.mediasize,
+                    sectorsize: edge.sectorsize,
+                    stripesize: edge.stripesize,
+                    stripeoffset: edge.stripeoffset,
+                    mode: edge.consumer_mode,
+                    config: Vec::new(),
+                };
+                write_listed(out, n + 1, &consumer)?;
+            }
+        }
+        writeln!(out)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn gpart_list() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (id, _) = g
+            .find_by_class_name(&graph::GeomClass::PART, "nvd0")
+            .unwrap();
+        let out = g.list_geom(id).unwrap();
+        assert!(out.starts_with("Geom name: nvd0\nmodified: false\nstate: OK\n"));
+        assert!(out.contains("scheme: GPT\nProviders:\n1. Name: nvd0p1\n"));
+        assert!(out.contains("   type: efi\n   index: 1\n"));
+        assert!(out.contains("Consumers:\n1. Name: nvd0\n"));
+        assert!(out.ends_with("\n\n"));
+        assert_eq!(g.list_geom(&0), None);
+    }
+
+    #[test]
+    fn list_all() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let out = g.list();
+        assert_eq!(out.matches("Geom name: ").count(), g.nodes.len());
+        let disks = g.list_class(&graph::GeomClass::DISK);
+        assert!(disks.contains("   descr: "));
+        assert!(disks.contains("   rotationrate: "));
+    }
+}
@@ -0,0 +1,482 @@
+//! Creating and destroying memory disks, like `mdconfig(8)`.
+//!
+//! An `md(4)` device is backed by kernel memory, swap, or a file (a "vnode"), and appears in the
+//! graph as a root geom of class `GeomClass::MD` named `md<unit>`.  Being cheap and disposable,
+//! they are the usual scratch disks for exercising the other control modules (`part`, `nop`,
+//! `eli`) without touching real hardware.
+//!
+//! Disks are configured through `/dev/mdctl`, or, under `ctl::Backend::Command`, by running
+//! `mdconfig(8)`.
+#[cfg(target_os = "freebsd")]
+use crate::ctl::CtlResult;
+use crate::ctl::GctlRequest;
+use crate::Error;
+use std::os::raw::{c_char, c_int, c_uint, c_ulong};
+use std::path::PathBuf;
+
+/// The memory disk control device.
+pub const MDCTL_PATH: &str = "/dev/mdctl";
+
+/// `MDIOVERSION` from `<sys/mdioctl.h>`
+const MDIOVERSION: c_uint = 0;
+
+/// `enum md_types`
+const MD_MALLOC: c_int = 0;
+const MD_VNODE: c_int = 2;
+const MD_SWAP: c_int = 3;
+
+/// `md_options` flags
+const MD_CLUSTER: c_uint = 0x01;
+const MD_AUTOUNIT: c_uint = 0x04;
+const MD_READONLY: c_uint = 0x08;
+const MD_COMPRESS: c_uint = 0x10;
+const MD_FORCE: c_uint = 0x20;
+
+/// `DEV_BSIZE`, the sector size the kernel uses if none is given.
+const DEV_BSIZE: u32 = 512;
+
+/// `struct md_ioctl`
+#[repr(C)]
+struct MdIoctl {
+    md_version: c_uint,
+    md_unit: c_uint,
+    md_type: c_int,
+    md_file: *mut c_char,
+    md_mediasize: i64,
+    md_sectorsize: c_uint,
+    md_options: c_uint,
+    md_base: u64,
+    md_fwheads: c_int,
+    md_fwsectors: c_int,
+    md_label: *mut c_char,
+    md_pad: [c_int; 96],
+}
+
+impl MdIoctl {
+    fn new() -> MdIoctl {
+        return MdIoctl {
+            md_version: MDIOVERSION,
+            md_unit: 0,
+            md_type: MD_MALLOC,
+            md_file: std::ptr::null_mut(),
+            md_mediasize: 0,
+            md_sectorsize: 0,
+            md_options: 0,
+            md_base: 0,
+            md_fwheads: 0,
+            md_fwsectors: 0,
+            md_label: std::ptr::null_mut(),
+            md_pad: [0; 96],
+        };
+    }
+}
+
+/// `_IOWR('m', num, struct md_ioctl)`
+const fn md_ioc(num: c_ulong) -> c_ulong {
+    const IOC_INOUT: c_ulong = 0xc000_0000;
+    const IOCPARM_MASK: c_ulong = (1 << 13) - 1;
+    return IOC_INOUT
+        | ((std::mem::size_of::<MdIoctl>() as c_ulong & IOCPARM_MASK) << 16)
+        | ((b'm' as c_ulong) << 8)
+        | num;
+}
+
+const MDIOCATTACH: c_ulong = md_ioc(0);
+const MDIOCDETACH: c_ulong = md_ioc(1);
+
+/// What a memory disk stores its contents in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MdBacking {
+    /// Kernel memory, of the given size in bytes (`mdconfig -t malloc -s`)
+    Malloc(u64),
+    /// Swap, of the given size in bytes (`-t swap -s`)
+    Swap(u64),
+    /// An existing file, whose size is the disk's (`-t vnode -f`)
+    Vnode(PathBuf),
+}
+
+/// How to configure a memory disk; see `md::create()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MdSpec {
+    /// The backing store
+    pub backing: MdBacking,
+    /// The sector size (`-S`); by default, 512
+    pub sectorsize: Option<u32>,
+    /// The unit number (`-u`); by default, the lowest free one
+    pub unit: Option<u32>,
+    /// Attach a vnode-backed disk read-only (`-o readonly`)
+    pub readonly: bool,
+}
+
+impl MdSpec {
+    /// A malloc-backed disk of `size` bytes.
+    pub fn malloc(size: u64) -> MdSpec {
+        return MdSpec::new(MdBacking::Malloc(size));
+    }
+
+    /// A swap-backed disk of `size` bytes.
+    pub fn swap(size: u64) -> MdSpec {
+        return MdSpec::new(MdBacking::Swap(size));
+    }
+
+    /// A disk backed by the file at `path`.
+    pub fn vnode<P: Into<PathBuf>>(path: P) -> MdSpec {
+        return MdSpec::new(MdBacking::Vnode(path.into()));
+    }
+
+    fn new(backing: MdBacking) -> MdSpec {
+        return MdSpec {
+            backing,
+            sectorsize: None,
+            unit: None,
+            readonly: false,
+        };
+    }
+}
+
+fn invalid(what: String) -> Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidInput, what).into();
+}
+
+/// Fills in the `MDIOCATTACH` argument for `spec`, like `mdconfig -a`.  For vnode-backed disks,
+/// the caller sets `md_file` to the (absolute) path, which must outlive the ioctl.
+fn attach_ioctl(spec: &MdSpec) -> Result<MdIoctl, Error> {
+    let sectorsize = spec.sectorsize.unwrap_or(DEV_BSIZE);
+    if !sectorsize.is_power_of_two() || sectorsize < DEV_BSIZE {
+        return Err(invalid(format!("Invalid sector size {}", sectorsize)));
+    }
+
+    let mut mdio = MdIoctl::new();
+    mdio.md_sectorsize = spec.sectorsize.unwrap_or(0);
+    mdio.md_options = MD_AUTOUNIT | MD_COMPRESS;
+    match &spec.backing {
+        MdBacking::Malloc(size) | MdBacking::Swap(size) => {
+            if *size == 0 || !size.is_multiple_of(sectorsize as u64) || *size > i64::MAX as u64 {
+                return Err(invalid(format!("Invalid size {}", size)));
+            }
+            mdio.md_mediasize = *size as i64;
+            if let MdBacking::Swap(_) = spec.backing {
+                mdio.md_type = MD_SWAP;
+                mdio.md_options |= MD_CLUSTER;
+            }
+        }
+        MdBacking::Vnode(_) => {
+            mdio.md_type = MD_VNODE;
+            mdio.md_options |= MD_CLUSTER;
+        }
+    }
+    if spec.readonly {
+        mdio.md_options |= MD_READONLY;
+    }
+    if let Some(unit) = spec.unit {
+        mdio.md_unit = unit;
+        mdio.md_options &= !MD_AUTOUNIT;
+    }
+    return Ok(mdio);
+}
+
+/// Fills in the `MDIOCDETACH` argument for `unit`, like `mdconfig -d`.
+fn detach_ioctl(unit: u32, force: bool) -> MdIoctl {
+    let mut mdio = MdIoctl::new();
+    mdio.md_unit = unit;
+    if force {
+        mdio.md_options = MD_FORCE;
+    }
+    return mdio;
+}
+
+/// The memory disk utility, for `Backend::Command`.
+pub(crate) const MDCONFIG_PATH: &str = "/sbin/mdconfig";
+
+/// The `mdconfig -a` command line for `spec`, for `Backend::Command`.  `path` is the absolute
+/// path of a vnode-backed disk's file.
+fn attach_command(spec: &MdSpec, path: Option<&std::path::Path>) -> Result<Vec<String>, Error> {
+    attach_ioctl(spec)?;
+    let mut argv: Vec<String> = vec![MDCONFIG_PATH.into(), "-a".into(), "-t".into()];
+    match (&spec.backing, path) {
+        (MdBacking::Malloc(size), _) => {
+            argv.extend(vec!["malloc".into(), "-s".into(), format!("{}b", size)])
+        }
+        (MdBacking::Swap(size), _) => {
+            argv.extend(vec!["swap".into(), "-s".into(), format!("{}b", size)])
+        }
+        (MdBacking::Vnode(_), Some(path)) => {
+            let path = path
+                .to_str()
+                .ok_or_else(|| invalid(format!("Invalid path {}", path.display())))?;
+            argv.extend(vec!["vnode".into(), "-f".into(), path.into()]);
+        }
+        (MdBacking::Vnode(path), None) => {
+            return Err(invalid(format!("Invalid path {}", path.display())));
+        }
+    }
+    if let Some(sectorsize) = spec.sectorsize {
+        argv.extend(vec!["-S".into(), sectorsize.to_string()]);
+    }
+    if let Some(unit) = spec.unit {
+        argv.extend(vec!["-u".into(), unit.to_string()]);
+    }
+    if spec.readonly {
+        argv.extend(vec!["-o".into(), "readonly".into()]);
+    }
+    return Ok(argv);
+}
+
+/// The `mdconfig -d` command line, for `Backend::Command`.
+fn detach_command(unit: u32, force: bool) -> Vec<String> {
+    let mut argv: Vec<String> = vec![
+        MDCONFIG_PATH.into(),
+        "-d".into(),
+        "-u".into(),
+        unit.to_string(),
+    ];
+    if force {
+        argv.extend(vec!["-o".into(), "force".into()]);
+    }
+    return argv;
+}
+
+/// Describes an `mdconfig` operation on `md<unit>` to a `CtlPolicy`, in the form of a control
+/// request, as there is no GEOM verb for it.
+fn policy_request(verb: &str, unit: Option<u32>) -> GctlRequest {
+    let req = GctlRequest::new("MD", verb);
+    return match unit {
+        Some(unit) => req.args(&[format!("md{}", unit)]),
+        None => req,
+    };
+}
+
+#[cfg(target_os = "freebsd")]
+fn issue(cmd: c_ulong, mdio: &mut MdIoctl) -> Result<(), Error> {
+    use std::os::unix::io::AsRawFd;
+
+    let ctl = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(MDCTL_PATH)?;
+    // The kernel reads the request, including any strings it points to, and writes back the
+    // unit number.
+    let rc = unsafe { libc::ioctl(ctl.as_raw_fd(), cmd, mdio as *mut MdIoctl) };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    return Ok(());
+}
+
+/// Creates a memory disk, like `mdconfig -a`.  The result's `provider` is the new disk's name,
+/// e.g., `"md3"`, except in dry-run mode.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn scratch_disk() -> Result<(), geom::Error> {
+///     let name = geom::md::create(&geom::md::MdSpec::swap(64 << 20))?.provider.unwrap();
+///     println!("created {}", name);
+///     let unit = name.trim_start_matches("md").parse().unwrap();
+///     geom::md::destroy(unit, false)?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn create(spec: &MdSpec) -> Result<CtlResult, Error> {
+    use crate::ctl::Backend;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let graph = crate::get_graph()?;
+    let mut mdio = attach_ioctl(spec)?;
+    if !crate::ctl::check_policy(&policy_request("create", spec.unit))? {
+        return Ok(CtlResult::unrefreshed(graph, None));
+    }
+    let path = match &spec.backing {
+        MdBacking::Vnode(path) => Some(std::fs::canonicalize(path)?),
+        _ => None,
+    };
+    let file = match &path {
+        Some(path) => Some(
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|_| invalid(format!("Invalid path {}", path.display())))?,
+        ),
+        None => None,
+    };
+    if let Some(file) = &file {
+        mdio.md_file = file.as_ptr() as *mut c_char;
+    }
+    crate::ctl::audited(&policy_request("create", spec.unit), || {
+        match crate::ctl::backend() {
+            Backend::Kernel => issue(MDIOCATTACH, &mut mdio)?,
+            Backend::Command => {
+                let argv = attach_command(spec, path.as_deref())?;
+                let stdout = crate::ctl::run_command(&argv)?;
+                // `mdconfig` names the disk only if it picked the unit.
+                if spec.unit.is_none() {
+                    mdio.md_unit = String::from_utf8_lossy(&stdout)
+                        .trim()
+                        .strip_prefix("md")
+                        .and_then(|u| u.parse().ok())
+                        .ok_or_else(|| invalid("Unexpected mdconfig output".to_owned()))?;
+                }
+            }
+        }
+        return Ok(crate::ctl::GctlResponse::default());
+    })?;
+    let provider = format!("md{}", mdio.md_unit);
+    return Ok(CtlResult::unrefreshed(graph, Some(provider)));
+}
+
+/// Destroys memory disk `md<unit>`, like `mdconfig -d`.  Unless `force` is set, fails if it is
+/// open.
+#[cfg(target_os = "freebsd")]
+pub fn destroy(unit: u32, force: bool) -> Result<CtlResult, Error> {
+    use crate::ctl::Backend;
+
+    let graph = crate::get_graph()?;
+    let req = policy_request("destroy", Some(unit)).int("force", force as i32);
+    if crate::ctl::check_policy(&req)? {
+        crate::ctl::audited(&req, || {
+            match crate::ctl::backend() {
+                Backend::Kernel => issue(MDIOCDETACH, &mut detach_ioctl(unit, force))?,
+                Backend::Command => {
+                    crate::ctl::run_command(&detach_command(unit, force))?;
+                }
+            }
+            return Ok(crate::ctl::GctlResponse::default());
+        })?;
+    }
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::md::*;
+    use crate::Error;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn abi() {
+        assert_eq!(std::mem::size_of::<MdIoctl>(), 448);
+        assert_eq!(MDIOCATTACH, 0xc1c0_6d00);
+        assert_eq!(MDIOCDETACH, 0xc1c0_6d01);
+    }
+
+    #[test]
+    fn ioctls() {
+        let mdio = attach_ioctl(&MdSpec::malloc(1 << 20)).unwrap();
+        assert_eq!(mdio.md_type, MD_MALLOC);
+        assert_eq!(mdio.md_mediasize, 1 << 20);
+        assert_eq!(mdio.md_sectorsize, 0);
+        assert_eq!(mdio.md_options, MD_AUTOUNIT | MD_COMPRESS);
+
+        let spec = MdSpec {
+            sectorsize: Some(4096),
+            unit: Some(7),
+            ..MdSpec::swap(1 << 20)
+        };
+        let mdio = attach_ioctl(&spec).unwrap();
+        assert_eq!(mdio.md_type, MD_SWAP);
+        assert_eq!(mdio.md_unit, 7);
+        assert_eq!(mdio.md_sectorsize, 4096);
+        assert_eq!(mdio.md_options, MD_CLUSTER | MD_COMPRESS);
+
+        let spec = MdSpec {
+            readonly: true,
+            ..MdSpec::vnode("/tmp/disk.img")
+        };
+        let mdio = attach_ioctl(&spec).unwrap();
+        assert_eq!(mdio.md_type, MD_VNODE);
+        assert_eq!(mdio.md_mediasize, 0);
+        assert_eq!(mdio.md_options & MD_READONLY, MD_READONLY);
+        assert!(mdio.md_file.is_null());
+
+        let invalid = |spec: MdSpec| match attach_ioctl(&spec) {
+            Err(Error::Io(e)) => e.kind() == std::io::ErrorKind::InvalidInput,
+            _ => false,
+        };
+        assert!(invalid(MdSpec::malloc(0)));
+        assert!(invalid(MdSpec::malloc(1000)));
+        assert!(invalid(MdSpec {
+            sectorsize: Some(1000),
+            ..MdSpec::malloc(1 << 20)
+        }));
+
+        let req = super::policy_request("destroy", Some(3));
+        assert_eq!(req.confirmation_token(), "MD destroy md3");
+        assert!(req.is_destructive());
+
+        let mdio = detach_ioctl(3, true);
+        assert_eq!((mdio.md_unit, mdio.md_options), (3, MD_FORCE));
+        assert_eq!(detach_ioctl(3, false).md_options, 0);
+    }
+
+    #[test]
+    fn commands() {
+        let spec = MdSpec {
+            sectorsize: Some(4096),
+            unit: Some(7),
+            ..MdSpec::swap(1 << 20)
+        };
+        assert_eq!(
+            attach_command(&spec, None).unwrap(),
+            vec![
+                "/sbin/mdconfig",
+                "-a",
+                "-t",
+                "swap",
+                "-s",
+                "1048576b",
+                "-S",
+                "4096",
+                "-u",
+                "7"
+            ]
+        );
+        let spec = MdSpec {
+            readonly: true,
+            ..MdSpec::vnode("disk.img")
+        };
+        let path = std::path::Path::new("/tmp/disk.img");
+        assert_eq!(
+            attach_command(&spec, Some(path)).unwrap(),
+            vec![
+                "/sbin/mdconfig",
+                "-a",
+                "-t",
+                "vnode",
+                "-f",
+                "/tmp/disk.img",
+                "-o",
+                "readonly"
+            ]
+        );
+        assert!(attach_command(&spec, None).is_err());
+        assert!(attach_command(&MdSpec::malloc(1000), None).is_err());
+        assert_eq!(
+            detach_command(3, true),
+            vec!["/sbin/mdconfig", "-d", "-u", "3", "-o", "force"]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn dry_run() {
+        use crate::{graph, raw};
+        const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let (res, requests) = crate::dry_run_on(&g, || -> Result<(), Error> {
+            // No unit was picked, so there is no disk to name.
+            assert_eq!(
+                create(&MdSpec::vnode("/nonexistent/disk.img"))?.provider,
+                None
+            );
+            destroy(7, false)?;
+            return Ok(());
+        });
+        res.unwrap();
+        let verbs: Vec<&str> = requests.iter().map(|r| r.verb()).collect();
+        assert_eq!(verbs, vec!["create", "destroy"]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Correlation of the mount table with the GEOM graph.
+//!
+//! `get_mounts()` reads the kernel's mount table with `getfsstat(2)`, and
+//! `Graph::attach_mounts()` records each mount on the `/dev` node (and label) it was mounted from,
+//! so storage views can show mountpoints without running `mount(8)`.
+use crate::graph::{GeomClass, Graph, NodeId};
+#[cfg(target_os = "freebsd")]
+use crate::Error;
+
+/// A mounted filesystem, as reported by `getfsstat(2)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Mount {
+    /// What was mounted, e.g., `"/dev/gpt/rootfs"`, or `"zroot/ROOT/default"` for ZFS
+    pub from: String,
+    /// Where it is mounted, e.g., `"/"`
+    pub mountpoint: String,
+    /// The filesystem type, e.g., `"ufs"` or `"zfs"`
+    pub fstype: String,
+}
+
+/// Returns the mount table of the running system.
+#[cfg(target_os = "freebsd")]
+pub fn get_mounts() -> Result<Vec<Mount>, Error> {
+    fn cstr(chars: &[libc::c_char]) -> String {
+        // The kernel NUL-terminates these fixed-size buffers; don't read past them regardless.
+        let bytes: Vec<u8> = chars
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        return String::from_utf8_lossy(&bytes).into_owned();
+    }
+
+    // Not getmntinfo(3), whose buffer is shared by every caller in the process.
+    let mut stats: Vec<libc::statfs>;
+    loop {
+        let n = unsafe { libc::getfsstat(std::ptr::null_mut(), 0, libc::MNT_NOWAIT) };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // Leave room for a few mounts made in the meantime.
+        let cap = n as usize + 4;
+        stats = Vec::with_capacity(cap);
+        let bufsize = (cap * std::mem::size_of::<libc::statfs>()) as libc::c_long;
+        let n = unsafe { libc::getfsstat(stats.as_mut_ptr(), bufsize, libc::MNT_NOWAIT) };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // A full buffer may have been truncated; try again.
+        if (n as usize) < cap {
+            unsafe { stats.set_len(n as usize) };
+            break;
+        }
+    }
+    return Ok(stats
+        .iter()
+        .map(|st| Mount {
+            from: cstr(&st.f_mntfromname),
+            mountpoint: cstr(&st.f_mntonname),
+            fstype: cstr(&st.f_fstypename),
+        })
+        .collect());
+}
+
+impl Graph {
+    /// Records each of `mounts` in `Geom::mounts` of the DEV geom it was mounted from, and of the
+    /// LABEL geom providing it, if any (e.g., for `/dev/gpt/rootfs`).  Mounts of things other than
+    /// GEOM providers (ZFS datasets, `tmpfs`, `nullfs`, ...) are ignored.
+    ///
+    /// Any previously attached mounts are replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// #[cfg(target_os = "freebsd")]
+    /// fn myfoo() -> Result<(), geom::Error> {
+    ///     let mut graph = geom::get_graph()?;
+    ///     graph.attach_mounts(&geom::mounts::get_mounts()?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn attach_mounts(&mut self, mounts: &[Mount]) {
+        for geom in self.nodes.values_mut() {
+            geom.mounts.clear();
+        }
+        for mount in mounts {
+            if !mount.from.starts_with("/dev/") {
+                continue;
+            }
+            let mut ids: Vec<NodeId> = Vec::new();
+            for (dev, _) in self.resolve_dev_path(&mount.from) {
+                ids.push(*dev);
+                let labels = self
+                    .parent_geoms_iter(dev)
+                    .filter(|(_, _, g)| g.class == GeomClass::LABEL);
+                ids.extend(labels.map(|(_, e, _)| e.provider_geom));
+            }
+            for id in ids {
+                if let Some(geom) = self.nodes.get_mut(&id) {
+                    geom.mounts.push(mount.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, mounts::Mount, raw, GeomClass};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    fn mount(from: &str, mountpoint: &str, fstype: &str) -> Mount {
+        return Mount {
+            from: from.to_owned(),
+            mountpoint: mountpoint.to_owned(),
+            fstype: fstype.to_owned(),
+        };
+    }
+
+    #[test]
+    fn attach() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let mut g = graph::decode_graph(&rawmesh).unwrap();
+        let (_, efi) = g
+            .find_by_class_name(&GeomClass::DEV, "gpt/freebsd-efi")
+            .unwrap();
+        assert!(efi.mounts.is_empty());
+
+        let efi_mount = mount("/dev/gpt/freebsd-efi", "/boot/efi", "msdosfs");
+        g.attach_mounts(&[
+            efi_mount.clone(),
+            mount("zroot/ROOT/default", "/", "zfs"),
+            mount("tmpfs", "/tmp", "tmpfs"),
+        ]);
+
+        let annotated: Vec<_> = g.nodes.values().filter(|n| !n.mounts.is_empty()).collect();
+        assert_eq!(annotated.len(), 2);
+        for geom in annotated {
+            assert!(geom.class == GeomClass::DEV || geom.class == GeomClass::LABEL);
+            assert_eq!(geom.mounts, vec![efi_mount.clone()]);
+        }
+
+        g.attach_mounts(&[]);
+        assert!(g.nodes.values().all(|n| n.mounts.is_empty()));
+    }
+}
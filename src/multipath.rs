@@ -0,0 +1,364 @@
+//! Managing `gmultipath(8)` devices.
+//!
+//! A MULTIPATH geom presents one provider, `multipath/<name>`, over several providers that reach
+//! the same device (e.g., both ports of a SAS disk), sending I/O down the active path(s) and
+//! failing over when one errors.  A storage daemon can follow path failures with a
+//! `watch::Watcher` (a path's `ProviderDestroyed` event) and use this module to fail, restore,
+//! add, or remove paths, or to rotate I/O onto the next one.
+#[cfg(target_os = "freebsd")]
+use crate::ctl::CtlResult;
+use crate::ctl::{CtlError, GctlRequest, EEXIST, EINVAL, ENOENT};
+use crate::graph::{GeomClass, Graph};
+use crate::Error;
+
+/// `G_MULTIPATH_VERSION`, which the kernel checks against each request's `version`.
+const G_MULTIPATH_VERSION: i32 = 1;
+/// The size of the metadata's `md_name`, including the terminating NUL.
+const NAME_LEN: usize = 16;
+
+/// How a multipath device spreads I/O over its paths.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MultipathMode {
+    /// All I/O goes down one path at a time; the others stand by.
+    ActivePassive,
+    /// I/O is spread over all paths (`gmultipath create -A`).
+    ActiveActive,
+    /// Reads are spread over all paths, writes go down one (`-R`).
+    ActiveRead,
+}
+
+impl Default for MultipathMode {
+    fn default() -> Self {
+        return MultipathMode::ActivePassive;
+    }
+}
+
+fn new_request(verb: &str, args: &[&str]) -> GctlRequest {
+    return GctlRequest::new("MULTIPATH", verb)
+        .int("version", G_MULTIPATH_VERSION)
+        .args(args);
+}
+
+/// Finds the multipath device `name` and returns the names of its paths.
+fn find<'a>(graph: &'a Graph, name: &str) -> Result<Vec<&'a str>, Error> {
+    let (id, _) = graph
+        .find_by_class_name(&GeomClass::MULTIPATH, name)
+        .ok_or_else(|| CtlError::new(ENOENT, format!("Device {} is invalid", name)))?;
+    let paths = graph
+        .parent_edges_iter(id)
+        .map(|(_, e)| e.name.as_str())
+        .collect();
+    return Ok(paths);
+}
+
+/// Returns the request to create the multipath device `multipath/<name>` over `paths` (e.g.,
+/// `["da0", "da4"]`), like `gmultipath create`.  The first path starts out active.
+///
+/// Fails if the device already exists, or if a path does not exist or is given twice.
+pub fn create_request(
+    graph: &Graph,
+    name: &str,
+    paths: &[&str],
+    mode: MultipathMode,
+) -> Result<GctlRequest, Error> {
+    let invalid = |what: String| -> Error { CtlError::new(EINVAL, what).into() };
+    if name.is_empty() || name.len() >= NAME_LEN || name.contains('/') {
+        return Err(invalid(format!("Invalid name {:?}", name)));
+    }
+    if graph
+        .find_by_class_name(&GeomClass::MULTIPATH, name)
+        .is_some()
+    {
+        return Err(CtlError::new(EEXIST, format!("Device {} already exists", name)).into());
+    }
+    if paths.is_empty() {
+        return Err(invalid("Too few arguments.".to_owned()));
+    }
+    let mut args = vec![name];
+    for path in paths {
+        let path = path.trim_start_matches("/dev/");
+        if args[1..].contains(&path) {
+            return Err(invalid(format!("Provider {} given twice", path)));
+        }
+        if !graph.has_provider(path) {
+            return Err(CtlError::new(ENOENT, format!("Provider {} is invalid", path)).into());
+        }
+        args.push(path);
+    }
+    return Ok(new_request("create", &args)
+        .int(
+            "active_active",
+            (mode == MultipathMode::ActiveActive) as i32,
+        )
+        .int("active_read", (mode == MultipathMode::ActiveRead) as i32));
+}
+
+/// Creates the multipath device `multipath/<name>` over `paths`, like `gmultipath create`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn dual_port() -> Result<(), geom::Error> {
+///     let mode = geom::multipath::MultipathMode::default();
+///     let res = geom::multipath::create("disk0", &["da0", "da4"], mode)?.refresh()?;
+///     let (_, mp) = res
+///         .graph
+///         .find_by_class_name(&geom::GeomClass::MULTIPATH, "disk0")
+///         .unwrap();
+///     println!("{:?}", mp.unknown_config.get("State"));
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn create(name: &str, paths: &[&str], mode: MultipathMode) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    create_request(&graph, name, paths, mode)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Returns the request to add `path` to the multipath device `name`, like `gmultipath add`.
+/// Fails if the device or path does not exist, or if `path` is already one of its paths.
+pub fn add_request(graph: &Graph, name: &str, path: &str) -> Result<GctlRequest, Error> {
+    let path = path.trim_start_matches("/dev/");
+    let paths = find(graph, name)?;
+    if paths.contains(&path) {
+        return Err(
+            CtlError::new(EEXIST, format!("{} is already a path of {}", path, name)).into(),
+        );
+    }
+    if !graph.has_provider(path) {
+        return Err(CtlError::new(ENOENT, format!("Provider {} is invalid", path)).into());
+    }
+    return Ok(new_request("add", &[name, path]));
+}
+
+/// Returns the `verb` request for `path` of the multipath device `name`, failing if it is not
+/// one of its paths.
+fn path_request(graph: &Graph, verb: &str, name: &str, path: &str) -> Result<GctlRequest, Error> {
+    let path = path.trim_start_matches("/dev/");
+    let paths = find(graph, name)?;
+    if !paths.contains(&path) {
+        return Err(CtlError::new(ENOENT, format!("{} is not a path of {}", path, name)).into());
+    }
+    return Ok(new_request(verb, &[name, path]));
+}
+
+/// Returns the request to mark `path` of the multipath device `name` as failed, like
+/// `gmultipath fail`, so no I/O is sent down it.
+pub fn fail_request(graph: &Graph, name: &str, path: &str) -> Result<GctlRequest, Error> {
+    return path_request(graph, "fail", name, path);
+}
+
+/// Returns the request to mark failed `path` of the multipath device `name` as usable again, like
+/// `gmultipath restore`.
+pub fn restore_request(graph: &Graph, name: &str, path: &str) -> Result<GctlRequest, Error> {
+    return path_request(graph, "restore", name, path);
+}
+
+/// Returns the request to remove `path` from the multipath device `name`, like `gmultipath
+/// remove`.
+pub fn remove_request(graph: &Graph, name: &str, path: &str) -> Result<GctlRequest, Error> {
+    return path_request(graph, "remove", name, path);
+}
+
+/// Returns the request to make the next path of the multipath device `name` the active one, like
+/// `gmultipath rotate`.
+pub fn rotate_request(graph: &Graph, name: &str) -> Result<GctlRequest, Error> {
+    find(graph, name)?;
+    return Ok(new_request("rotate", &[name]));
+}
+
+/// Adds `path` to the multipath device `name`, like `gmultipath add`.
+#[cfg(target_os = "freebsd")]
+pub fn add(name: &str, path: &str) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    add_request(&graph, name, path)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Marks `path` of the multipath device `name` as failed, like `gmultipath fail`.
+#[cfg(target_os = "freebsd")]
+pub fn fail(name: &str, path: &str) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    fail_request(&graph, name, path)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Marks `path` of the multipath device `name` as usable again, like `gmultipath restore`.
+#[cfg(target_os = "freebsd")]
+pub fn restore(name: &str, path: &str) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    restore_request(&graph, name, path)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Removes `path` from the multipath device `name`, like `gmultipath remove`.
+#[cfg(target_os = "freebsd")]
+pub fn remove(name: &str, path: &str) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    remove_request(&graph, name, path)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Makes the next path of the multipath device `name` the active one, like `gmultipath rotate`.
+#[cfg(target_os = "freebsd")]
+pub fn rotate(name: &str) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    rotate_request(&graph, name)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctl::{CtlErrorKind, ParamValue};
+    use crate::multipath::*;
+    use crate::{graph, raw, Error};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    // A multipath device over nvd0p4 and nvd1p2.
+    const MULTIPATH_XML: &str = r#"<class id="0x10">
+        <name>MULTIPATH</name>
+        <geom id="0x11">
+          <class ref="0x10"/>
+          <name>disk0</name>
+          <rank>3</rank>
+          <config>
+            <Mode>Active/Passive</Mode>
+            <State>OPTIMAL</State>
+          </config>
+          <consumer id="0x12">
+            <geom ref="0x11"/>
+            <provider ref="0xfffff8001b62a900"/>
+            <mode>r0w0e0</mode>
+          </consumer>
+          <consumer id="0x13">
+            <geom ref="0x11"/>
+            <provider ref="0xfffff804947b5a00"/>
+            <mode>r0w0e0</mode>
+          </consumer>
+          <provider id="0x14">
+            <geom ref="0x11"/>
+            <mode>r0w0e0</mode>
+            <name>multipath/disk0</name>
+            <mediasize>1048576</mediasize>
+            <sectorsize>512</sectorsize>
+            <stripesize>0</stripesize>
+            <stripeoffset>0</stripeoffset>
+          </provider>
+        </geom>
+      </class>
+    </mesh>"#;
+
+    #[test]
+    fn create() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+
+        let req = create_request(&g, "disk0", &["/dev/nvd0", "nvd1"], Default::default()).unwrap();
+        assert_eq!(req.get("version"), Some(&ParamValue::Int(1)));
+        assert_eq!(req.get("nargs"), Some(&ParamValue::Int(3)));
+        assert_eq!(req.get("arg1"), Some(&ParamValue::String("nvd0".into())));
+        assert_eq!(req.get("active_active"), Some(&ParamValue::Int(0)));
+        assert_eq!(req.get("active_read"), Some(&ParamValue::Int(0)));
+        let req = create_request(&g, "disk0", &["nvd0"], MultipathMode::ActiveRead).unwrap();
+        assert_eq!(req.get("active_read"), Some(&ParamValue::Int(1)));
+
+        assert_eq!(
+            kind(create_request(&g, "disk0", &[], Default::default())),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            kind(create_request(
+                &g,
+                "disk0",
+                &["nvd0", "nvd0"],
+                Default::default()
+            )),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            kind(create_request(&g, "disk0", &["nvd9"], Default::default())),
+            CtlErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn paths() {
+        let xml = SAMPLE_XML.replace("</mesh>", MULTIPATH_XML);
+        let g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+        let paths = super::find(&g, "disk0").unwrap();
+        assert_eq!(paths.len(), 2);
+
+        assert_eq!(
+            kind(create_request(&g, "disk0", &["nvd0"], Default::default())),
+            CtlErrorKind::AlreadyExists
+        );
+
+        let req = add_request(&g, "disk0", "/dev/nvd0p3").unwrap();
+        assert_eq!(req.verb(), "add");
+        assert_eq!(req.get("arg1"), Some(&ParamValue::String("nvd0p3".into())));
+        assert_eq!(
+            kind(add_request(&g, "disk0", paths[0])),
+            CtlErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            kind(add_request(&g, "disk9", "nvd0p3")),
+            CtlErrorKind::NotFound
+        );
+
+        for (verb, req) in &[
+            ("fail", fail_request(&g, "disk0", paths[0])),
+            ("restore", restore_request(&g, "disk0", paths[0])),
+            ("remove", remove_request(&g, "disk0", paths[1])),
+        ] {
+            let req = req.as_ref().unwrap();
+            assert_eq!(req.verb(), *verb);
+            assert_eq!(req.get("arg0"), Some(&ParamValue::String("disk0".into())));
+            assert_eq!(req.get("nargs"), Some(&ParamValue::Int(2)));
+        }
+        assert_eq!(
+            kind(fail_request(&g, "disk0", "nvd0p3")),
+            CtlErrorKind::NotFound
+        );
+
+        let req = rotate_request(&g, "disk0").unwrap();
+        assert_eq!(req.get("nargs"), Some(&ParamValue::Int(1)));
+        assert_eq!(kind(rotate_request(&g, "disk9")), CtlErrorKind::NotFound);
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn dry_run() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let (res, requests) = crate::dry_run_on(&g, || {
+            super::create("disk0", &["nvd0"], MultipathMode::ActiveRead)
+        });
+        res.unwrap();
+        assert_eq!(requests.len(), 1);
+
+        let xml = SAMPLE_XML.replace("</mesh>", MULTIPATH_XML);
+        let g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let paths = super::find(&g, "disk0").unwrap();
+        let (res, requests) = crate::dry_run_on(&g, || -> Result<(), Error> {
+            super::add("disk0", "nvd0p3")?;
+            super::fail("disk0", paths[0])?;
+            super::restore("disk0", paths[0])?;
+            super::remove("disk0", paths[1])?;
+            super::rotate("disk0")?;
+            return Ok(());
+        });
+        res.unwrap();
+        let verbs: Vec<&str> = requests.iter().map(|r| r.verb()).collect();
+        assert_eq!(verbs, vec!["add", "fail", "restore", "remove", "rotate"]);
+    }
+}
@@ -0,0 +1,335 @@
+//! Creating and destroying `gnop(8)` devices.
+//!
+//! A NOP geom passes I/O through to the provider beneath it, optionally failing or delaying a
+//! share of requests, or exposing only part of the provider with a different sector size.  Test
+//! suites can layer one over a real (or memory) disk to exercise error handling, and observe it
+//! through the graph like any other geom (see `GeomClass::NOP`).
+#[cfg(target_os = "freebsd")]
+use crate::ctl::CtlResult;
+use crate::ctl::{CtlError, GctlRequest, EBUSY, EEXIST, EINVAL, ENOENT};
+use crate::graph::{GeomClass, Graph};
+use crate::Error;
+use std::time::Duration;
+
+/// `G_NOP_VERSION`, which the kernel checks against each request's `version`.
+const G_NOP_VERSION: i32 = 4;
+
+/// How to configure a NOP device; see `nop::create()`.  The defaults pass all I/O through.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NopSpec {
+    /// The percentage (0 to 100) of read and write requests that fail (`gnop create -r -w`)
+    pub error_rate: Option<u32>,
+    /// The `errno` value failed requests return (`-e`); by default, `EIO`
+    pub error: Option<i32>,
+    /// Delay every read and write request by this long (`-d`, with `-q 100 -x 100`); the
+    /// resolution is one millisecond
+    pub delay: Option<Duration>,
+    /// Where the NOP provider starts in the underlying provider, in bytes (`-o`)
+    pub offset: u64,
+    /// The size of the NOP provider, in bytes (`-s`); by default, the rest of the underlying
+    /// provider after `offset`
+    pub size: Option<u64>,
+    /// The sector size of the NOP provider (`-S`), a multiple of the underlying provider's; by
+    /// default, the same
+    pub secsize: Option<u64>,
+    /// The name of the NOP provider (`-n`); by default, `<provider>.nop`
+    pub name: Option<String>,
+}
+
+impl NopSpec {
+    /// The name of the NOP provider created over `provider` with this spec.
+    pub fn provider_name(&self, provider: &str) -> String {
+        return match &self.name {
+            Some(name) => name.to_owned(),
+            None => format!("{}.nop", provider.trim_start_matches("/dev/")),
+        };
+    }
+}
+
+/// Returns the request to create a NOP device over `provider` (e.g., `"md0"`), like `gnop
+/// create`.
+///
+/// Fails if the provider does not exist, if the NOP provider would already exist, or if the
+/// spec does not fit the provider.
+pub fn create_request(graph: &Graph, provider: &str, spec: &NopSpec) -> Result<GctlRequest, Error> {
+    let provider = provider.trim_start_matches("/dev/");
+    let invalid = |what: String| -> Error { CtlError::new(EINVAL, what).into() };
+    let (mediasize, sectorsize) = graph
+        .provider_size(provider)
+        .ok_or_else(|| CtlError::new(ENOENT, format!("Provider {} is invalid", provider)))?;
+    let name = spec.provider_name(provider);
+    if graph.has_provider(&name) {
+        return Err(CtlError::new(EEXIST, format!("Provider {} already exists", name)).into());
+    }
+    if let Some(rate) = spec.error_rate.filter(|r| *r > 100) {
+        return Err(invalid(format!("Invalid error rate: {}%", rate)));
+    }
+    if sectorsize == 0 || !spec.offset.is_multiple_of(sectorsize) || spec.offset >= mediasize {
+        return Err(invalid(format!("Invalid offset for provider {}", provider)));
+    }
+    let size = spec.size.unwrap_or(mediasize - spec.offset);
+    if !size.is_multiple_of(sectorsize)
+        || size == 0
+        || spec
+            .offset
+            .checked_add(size)
+            .is_none_or(|end| end > mediasize)
+    {
+        return Err(invalid(format!("Invalid size for provider {}", provider)));
+    }
+    if let Some(secsize) = spec
+        .secsize
+        .filter(|s| *s == 0 || !s.is_multiple_of(sectorsize))
+    {
+        return Err(invalid(format!("Invalid secsize {}", secsize)));
+    }
+
+    let rate = spec.error_rate.map(|r| r as i64).unwrap_or(-1);
+    let (delay, delayprob) = match spec.delay {
+        Some(d) => (d.as_millis() as i64, 100),
+        None => (-1, -1),
+    };
+    let mut req = GctlRequest::new("NOP", "create")
+        .int("version", G_NOP_VERSION)
+        .args(&[provider])
+        .number("error", spec.error.map(|e| e as i64).unwrap_or(-1))
+        .number("count_until_fail", -1)
+        .number("rfailprob", rate)
+        .number("wfailprob", rate)
+        .number("delaymsec", delay)
+        .number("rdelayprob", delayprob)
+        .number("wdelayprob", delayprob)
+        .number("offset", spec.offset as i64)
+        .number("size", spec.size.map(|s| s as i64).unwrap_or(0))
+        .number("secsize", spec.secsize.map(|s| s as i64).unwrap_or(0))
+        .number("stripesize", 0)
+        .number("stripeoffset", 0);
+    if let Some(name) = &spec.name {
+        req = req.string("gnopname", name);
+    }
+    return Ok(req);
+}
+
+/// Creates a NOP device over `provider`, like `gnop create`.  The new provider is named
+/// `spec.provider_name(provider)`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn flaky_disk() -> Result<(), geom::Error> {
+///     let spec = geom::nop::NopSpec {
+///         error_rate: Some(10),
+///         ..Default::default()
+///     };
+///     let res = geom::nop::create("md0", &spec)?.refresh()?;
+///     let (_, nop) = res
+///         .graph
+///         .find_by_class_name(&geom::GeomClass::NOP, "md0.nop")
+///         .unwrap();
+///     println!("{:?}", nop.unknown_config);
+///     geom::nop::destroy("md0.nop", false)?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn create(provider: &str, spec: &NopSpec) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    create_request(&graph, provider, spec)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Returns the request to destroy the NOP device `name` (e.g., `"md0.nop"`), like `gnop
+/// destroy`.  Unless `force` is set, fails if its provider is open.
+pub fn destroy_request(graph: &Graph, name: &str, force: bool) -> Result<GctlRequest, Error> {
+    let name = name.trim_start_matches("/dev/");
+    let (id, _) = graph
+        .find_by_class_name(&GeomClass::NOP, name)
+        .ok_or_else(|| CtlError::new(ENOENT, format!("Device {} is invalid", name)))?;
+    if !force {
+        let open = graph
+            .child_edges_iter(id)
+            .find(|(_, e)| e.mode.is_open())
+            .map(|(_, e)| e.mode);
+        if let Some(mode) = open {
+            return Err(CtlError::new(EBUSY, format!("{} is open ({})", name, mode)).into());
+        }
+    }
+    return Ok(GctlRequest::new("NOP", "destroy")
+        .int("version", G_NOP_VERSION)
+        .args(&[name])
+        .int("force", force as i32));
+}
+
+/// Destroys the NOP device `name`, like `gnop destroy`.
+#[cfg(target_os = "freebsd")]
+pub fn destroy(name: &str, force: bool) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    destroy_request(&graph, name, force)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctl::{CtlErrorKind, ParamValue};
+    use crate::nop::{create_request, destroy_request, NopSpec};
+    use crate::{graph, raw, Error};
+    use std::time::Duration;
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    /// An open NOP device over nvd0p1.
+    const NOP_XML: &str = r#"<class id="0x10">
+        <name>NOP</name>
+        <geom id="0x11">
+          <class ref="0x10"/>
+          <name>nvd0p1.nop</name>
+          <rank>3</rank>
+          <config>
+            <ReadFailProb>10</ReadFailProb>
+          </config>
+          <consumer id="0x12">
+            <geom ref="0x11"/>
+            <provider ref="0xfffff804947b4100"/>
+            <mode>r1w0e0</mode>
+          </consumer>
+          <provider id="0x13">
+            <geom ref="0x11"/>
+            <mode>r1w0e0</mode>
+            <name>nvd0p1.nop</name>
+            <mediasize>209715200</mediasize>
+            <sectorsize>512</sectorsize>
+            <stripesize>0</stripesize>
+            <stripeoffset>0</stripeoffset>
+          </provider>
+        </geom>
+      </class>
+      <class id="0x20">
+        <name>DEV</name>
+        <geom id="0x21">
+          <class ref="0x20"/>
+          <name>nvd0p1.nop</name>
+          <rank>4</rank>
+          <consumer id="0x22">
+            <geom ref="0x21"/>
+            <provider ref="0x13"/>
+            <mode>r1w0e0</mode>
+          </consumer>
+        </geom>
+      </class>
+    </mesh>"#;
+
+    #[test]
+    fn create_destroy() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+
+        let spec = NopSpec {
+            error_rate: Some(10),
+            delay: Some(Duration::from_millis(50)),
+            offset: 1 << 20,
+            secsize: Some(4096),
+            ..Default::default()
+        };
+        let req = create_request(&g, "/dev/nvd0p1", &spec).unwrap();
+        assert_eq!(req.get("arg0"), Some(&ParamValue::String("nvd0p1".into())));
+        assert_eq!(req.get("rfailprob"), Some(&ParamValue::Number(10)));
+        assert_eq!(req.get("error"), Some(&ParamValue::Number(-1)));
+        assert_eq!(req.get("delaymsec"), Some(&ParamValue::Number(50)));
+        assert_eq!(req.get("wdelayprob"), Some(&ParamValue::Number(100)));
+        assert_eq!(req.get("offset"), Some(&ParamValue::Number(1 << 20)));
+        assert_eq!(req.get("size"), Some(&ParamValue::Number(0)));
+        assert_eq!(req.get("gnopname"), None);
+        assert_eq!(req.get("version"), Some(&ParamValue::Int(4)));
+        assert_eq!(spec.provider_name("nvd0p1"), "nvd0p1.nop");
+
+        let bad = |spec: NopSpec| kind(create_request(&g, "nvd0p1", &spec));
+        assert_eq!(
+            bad(NopSpec {
+                error_rate: Some(101),
+                ..Default::default()
+            }),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            bad(NopSpec {
+                offset: 100,
+                ..Default::default()
+            }),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            bad(NopSpec {
+                size: Some(1 << 50),
+                ..Default::default()
+            }),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            bad(NopSpec {
+                offset: 1 << 20,
+                size: Some(u64::MAX - 511),
+                ..Default::default()
+            }),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            bad(NopSpec {
+                name: Some("nvd0p2".into()),
+                ..Default::default()
+            }),
+            CtlErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            kind(create_request(&g, "nvd9", &NopSpec::default())),
+            CtlErrorKind::NotFound
+        );
+
+        assert_eq!(
+            kind(destroy_request(&g, "nvd0p1.nop", false)),
+            CtlErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn destroy() {
+        let xml = SAMPLE_XML.replace("</mesh>", NOP_XML);
+        let g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let (_, geom) = g
+            .find_by_class_name(&graph::GeomClass::NOP, "nvd0p1.nop")
+            .unwrap();
+        assert_eq!(geom.unknown_config.get("ReadFailProb").unwrap(), "10");
+
+        assert!(matches!(
+            destroy_request(&g, "nvd0p1.nop", false),
+            Err(Error::Ctl(e)) if e.kind() == CtlErrorKind::Busy
+        ));
+        let req = destroy_request(&g, "/dev/nvd0p1.nop", true).unwrap();
+        assert_eq!(
+            req.get("arg0"),
+            Some(&ParamValue::String("nvd0p1.nop".into()))
+        );
+        assert_eq!(req.get("force"), Some(&ParamValue::Int(1)));
+        assert!(create_request(&g, "nvd0p1", &NopSpec::default()).is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn dry_run() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let (res, requests) =
+            crate::dry_run_on(&g, || super::create("nvd0p1", &NopSpec::default()));
+        res.unwrap();
+        assert_eq!(requests.len(), 1);
+
+        let xml = SAMPLE_XML.replace("</mesh>", NOP_XML);
+        let g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let (res, requests) = crate::dry_run_on(&g, || super::destroy("nvd0p1.nop", true));
+        res.unwrap();
+        assert_eq!(requests[0].verb(), "destroy");
+    }
+}
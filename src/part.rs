@@ -0,0 +1,981 @@
+//! Changes to partition tables, like `gpart(8)`.
+//!
+//! Each operation builds a `ctl::GctlRequest` for the PART class from a `Graph` (so it can be
+//! inspected, or checked against the graph, before anything is changed), and, on FreeBSD,
+//! issues it and returns the resulting graph.
+//!
+//! Sizes and offsets are in bytes, and must be multiples of the partitioned provider's sector
+//! size.
+#[cfg(target_os = "freebsd")]
+use crate::ctl::CtlResult;
+use crate::ctl::{CtlError, GctlRequest, EBUSY, EEXIST, EINVAL, ENOENT, ENOSPC};
+use crate::graph::{
+    Edge, GeomClass, GeomMetadata, Graph, NodeId, PartAlias, PartAttrib, PartInfo, PartMetadata,
+    PartScheme, PartState,
+};
+use crate::size::ByteSize;
+use crate::Error;
+
+/// The `flags` `gpart(8)` passes by default: commit the change to disk immediately.
+const COMMIT: &str = "C";
+
+/// The length of the `output` buffer `gpart(8)` passes.
+const OUTPUT_LEN: usize = 1024;
+
+/// A partition type, as `gpart(8)` accepts it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PartType {
+    /// A canonical alias, valid in any scheme that has an equivalent type
+    Alias(PartAlias),
+    /// A scheme-specific raw type, e.g., `"0x83"` for MBR, or a GUID for GPT
+    Raw(String),
+}
+
+impl std::fmt::Display for PartType {
+    /// Formats the type as `gpart(8)` takes it: an alias, or a raw type prefixed with `!`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Self::Alias(a) => write!(f, "{}", a.as_ref()),
+            Self::Raw(r) => write!(f, "!{}", r),
+        };
+    }
+}
+
+impl std::str::FromStr for PartType {
+    type Err = strum::ParseError;
+
+    /// Parses a type as `gpart(8)` takes it (see the `Display` implementation).
+    fn from_str(s: &str) -> Result<PartType, Self::Err> {
+        if let Some(raw) = s.strip_prefix('!') {
+            return Ok(Self::Raw(raw.to_owned()));
+        }
+        return Ok(Self::Alias(PartAlias::from_str(s)?));
+    }
+}
+
+impl From<PartAlias> for PartType {
+    fn from(alias: PartAlias) -> PartType {
+        return Self::Alias(alias);
+    }
+}
+
+/// A partition to add; see `part::add()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartitionSpec {
+    /// The partition type
+    pub type_: PartType,
+    /// The offset of the partition, in bytes.  If `None`, the partition is placed in the first
+    /// free range large enough (after alignment).
+    pub start: Option<u64>,
+    /// The size of the partition, in bytes.  If `None`, the partition extends to the end of the
+    /// free range it starts in.
+    pub size: Option<u64>,
+    /// The partition label, for schemes that support one (e.g., GPT)
+    pub label: Option<String>,
+    /// The index of the partition entry; if `None`, the first free index is used.
+    pub index: Option<u64>,
+    /// If set, the start and size are aligned to multiples of this many bytes (e.g., 1 MiB),
+    /// relative to the provider's stripe offset, as with `gpart add -a`.
+    pub alignment: Option<u64>,
+}
+
+impl PartitionSpec {
+    /// A partition of type `type_`, placed and sized like `gpart add` does by default: at the
+    /// start of the first free range, extending to its end.
+    pub fn new<T: Into<PartType>>(type_: T) -> PartitionSpec {
+        return PartitionSpec {
+            type_: type_.into(),
+            start: None,
+            size: None,
+            label: None,
+            index: None,
+            alignment: None,
+        };
+    }
+}
+
+/// Finds the partition table on `disk` (e.g., `"ada0"`), and the provider it partitions.
+pub(crate) fn table<'a>(
+    graph: &'a Graph,
+    disk: &str,
+) -> Result<(&'a NodeId, &'a PartMetadata, &'a Edge), Error> {
+    let not_found = || CtlError::new(ENOENT, format!("{} has no partition table", disk));
+    let (id, geom) = graph
+        .find_by_class_name(&GeomClass::PART, disk)
+        .ok_or_else(not_found)?;
+    let meta = match geom.metadata.as_deref() {
+        Some(GeomMetadata::PART(m)) => m,
+        _ => return Err(not_found().into()),
+    };
+    let (_, provider) = graph.parent_edges_iter(id).next().ok_or_else(not_found)?;
+    return Ok((id, meta, provider));
+}
+
+/// Finds partition entry `index` of the table `id`, and its provider.
+fn entry<'a>(graph: &'a Graph, id: &NodeId, index: u64) -> Result<(&'a Edge, &'a PartInfo), Error> {
+    return graph
+        .child_edges_iter(id)
+        .find_map(|(_, e)| {
+            let part = e.metadata.as_deref()?.as_part()?;
+            return if part.index == index {
+                Some((e, part))
+            } else {
+                None
+            };
+        })
+        .ok_or_else(|| CtlError::new(ENOENT, format!("index '{}'", index)).into());
+}
+
+/// Converts `bytes` to sectors.
+fn to_sectors(bytes: u64, sectorsize: u64, what: &str) -> Result<u64, Error> {
+    if sectorsize == 0 || !bytes.is_multiple_of(sectorsize) {
+        return Err(CtlError::new(
+            EINVAL,
+            format!(
+                "{} {} is not a multiple of the sector size ({})",
+                what, bytes, sectorsize
+            ),
+        )
+        .into());
+    }
+    return Ok(bytes / sectorsize);
+}
+
+/// Returns an `alignment` in bytes as sectors, and the offset from it of the provider's
+/// stripes, as `gpart(8)` aligns partitions.
+fn alignment(provider: &Edge, alignment: Option<u64>) -> Result<(u64, u64), Error> {
+    let align = match alignment {
+        Some(a) => to_sectors(a, provider.sectorsize, "alignment")?.max(1),
+        None => 1,
+    };
+    let offset = (provider.stripeoffset / provider.sectorsize.max(1)) % align;
+    return Ok((align, offset));
+}
+
+/// Returns the request to add a partition to the table on `disk`, with its start and size
+/// filled in (and aligned) like `gpart add` does, or an error if there is no room for it.
+pub fn add_request(graph: &Graph, disk: &str, spec: &PartitionSpec) -> Result<GctlRequest, Error> {
+    let (id, _, provider) = table(graph, disk)?;
+    let sectorsize = provider.sectorsize;
+    let (align, offset) = alignment(provider, spec.alignment)?;
+    let align_up = |lba: u64| (lba + offset).div_ceil(align) * align - offset;
+    let want_start = spec
+        .start
+        .map(|s| to_sectors(s, sectorsize, "start"))
+        .transpose()?;
+    let want_size = spec
+        .size
+        .map(|s| to_sectors(s, sectorsize, "size"))
+        .transpose()?;
+
+    let free = graph.part_free_ranges(id).unwrap_or_default();
+    let mut largest = 0;
+    let mut placed = None;
+    for range in &free {
+        let start = match want_start {
+            Some(s) if s < range.start || s > range.end => continue,
+            Some(s) => s,
+            None => align_up(range.start),
+        };
+        if start > range.end {
+            continue;
+        }
+        let avail = range.end - start + 1;
+        largest = largest.max(avail);
+        let size = match want_size {
+            Some(size) if size <= avail => size,
+            Some(_) => continue,
+            None => avail / align * align,
+        };
+        if size > 0 {
+            placed = Some((start, size));
+            break;
+        }
+    }
+    let (start, size) = placed.ok_or_else(|| {
+        let what = match want_start {
+            Some(s) => format!("at LBA {}", s),
+            None => format!("in {}", disk),
+        };
+        CtlError::new(
+            ENOSPC,
+            format!(
+                "only {} contiguous free {}",
+                ByteSize(largest * sectorsize),
+                what
+            ),
+        )
+    })?;
+
+    let mut req = GctlRequest::new("PART", "add")
+        .args(&[disk])
+        .string("flags", COMMIT)
+        .string("type", &spec.type_.to_string())
+        .string("start", &start.to_string())
+        .string("size", &size.to_string());
+    if let Some(label) = &spec.label {
+        req = req.string("label", label);
+    }
+    if let Some(index) = spec.index {
+        req = req.number("index", index as i64);
+    }
+    return Ok(req.output("output", OUTPUT_LEN));
+}
+
+/// Extracts the provider name from `gpart add`'s output, e.g., `"da0p1 added\n"`.
+fn added_provider(output: &str) -> Option<String> {
+    let name = output.trim().strip_suffix(" added")?;
+    return Some(name.to_owned());
+}
+
+/// Adds a partition to the table on `disk` (e.g., `"ada0"`), like `gpart add`.  The result's
+/// `provider` is the new partition's, e.g., `"ada0p3"`, except in dry-run mode.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn myfoo() -> Result<(), geom::Error> {
+///     let spec = geom::part::PartitionSpec {
+///         label: Some("zfs0".to_owned()),
+///         alignment: Some(1 << 20),
+///         ..geom::part::PartitionSpec::new(geom::PartAlias::FreeBSDZFS)
+///     };
+///     let res = geom::part::add("da0", &spec)?.refresh()?;
+///     if let Some(provider) = &res.provider {
+///         println!("created {}: {:?}", provider, res.new_provider_edges(provider).next());
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn add(disk: &str, spec: &PartitionSpec) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    let resp = add_request(&graph, disk, spec)?.issue()?;
+    if crate::ctl::dry_run() {
+        return Ok(CtlResult::unrefreshed(graph, None));
+    }
+    let provider = resp
+        .get_str("output")
+        .and_then(added_provider)
+        .ok_or_else(|| CtlError::other("unexpected reply from gpart add".to_owned()))?;
+    return Ok(CtlResult::unrefreshed(graph, Some(provider)));
+}
+
+/// Returns the request to delete partition entry `index` from the table on `disk`, like
+/// `gpart delete -i index disk`.  Fails if there is no such entry, or its provider is open.
+pub fn delete_request(graph: &Graph, disk: &str, index: u64) -> Result<GctlRequest, Error> {
+    let (id, _, _) = table(graph, disk)?;
+    let (edge, _) = entry(graph, id, index)?;
+    if edge.mode.is_open() {
+        return Err(CtlError::new(EBUSY, format!("{} is open ({})", edge.name, edge.mode)).into());
+    }
+    return Ok(GctlRequest::new("PART", "delete")
+        .args(&[disk])
+        .string("flags", COMMIT)
+        .number("index", index as i64));
+}
+
+/// Returns the request to destroy the partition table on `disk`, like `gpart destroy`.  Unless
+/// `force` is set (`gpart destroy -F`), fails if the table has any partition entries.
+pub fn destroy_request(graph: &Graph, disk: &str, force: bool) -> Result<GctlRequest, Error> {
+    let (id, _, _) = table(graph, disk)?;
+    if !force {
+        let n = graph
+            .child_edges_iter(id)
+            .filter(|(_, e)| e.metadata.as_deref().and_then(|m| m.as_part()).is_some())
+            .count();
+        if n > 0 {
+            return Err(CtlError::new(
+                EBUSY,
+                format!(
+                    "{} has partition entries; use force to destroy it anyway",
+                    disk
+                ),
+            )
+            .into());
+        }
+    }
+    return Ok(GctlRequest::new("PART", "destroy")
+        .args(&[disk])
+        .string("flags", COMMIT)
+        .int("force", force as i32));
+}
+
+/// The range of table sizes (numbers of partition entries) each scheme supports, and the default
+/// (the minimum); see `gps_minent` and `gps_maxent` in the kernel's scheme implementations.
+/// `None` if tables of the scheme cannot be created.
+fn entries_range(scheme: &PartScheme) -> Option<(u64, u64)> {
+    return match scheme {
+        PartScheme::APM => Some((16, 4096)),
+        PartScheme::BSD | PartScheme::BSD64 => Some((8, 20)),
+        PartScheme::EBR => Some((1, i32::MAX as u64)),
+        PartScheme::GPT => Some((128, 4096)),
+        PartScheme::MBR => Some((4, 4)),
+        PartScheme::VTOC8 => Some((8, 8)),
+        PartScheme::LDM => None,
+    };
+}
+
+/// Returns the request to create a `scheme` partition table on `disk`, like `gpart create`.
+/// `entries` is the size of the table (`gpart create -n`), e.g., the number of GPT entries; by
+/// default, the smallest the scheme allows.
+///
+/// Fails if `disk` does not exist or is already partitioned, or if the scheme does not support
+/// `entries`.
+pub fn create_table_request(
+    graph: &Graph,
+    disk: &str,
+    scheme: &PartScheme,
+    entries: Option<u64>,
+) -> Result<GctlRequest, Error> {
+    if !graph.has_provider(disk) {
+        return Err(CtlError::new(ENOENT, format!("No such provider: {}", disk)).into());
+    }
+    if let Some((_, geom)) = graph.find_by_class_name(&GeomClass::PART, disk) {
+        let existing = match geom.metadata.as_deref() {
+            Some(GeomMetadata::PART(m)) => m.scheme.as_ref(),
+            _ => "",
+        };
+        return Err(CtlError::new(
+            EEXIST,
+            format!("{} already has a {} partition table", disk, existing),
+        )
+        .into());
+    }
+    let (min, max) = entries_range(scheme).ok_or_else(|| {
+        CtlError::new(
+            EINVAL,
+            format!("{} partition tables cannot be created", scheme.as_ref()),
+        )
+    })?;
+    let mut req = GctlRequest::new("PART", "create")
+        .args(&[disk])
+        .string("flags", COMMIT)
+        .string("scheme", scheme.as_ref());
+    if let Some(n) = entries {
+        if n < min || n > max {
+            return Err(CtlError::new(
+                EINVAL,
+                format!(
+                    "{} tables have {} to {} entries, not {}",
+                    scheme.as_ref(),
+                    min,
+                    max,
+                    n
+                ),
+            )
+            .into());
+        }
+        req = req.number("entries", n as i64);
+    }
+    return Ok(req.output("output", OUTPUT_LEN));
+}
+
+/// Creates a `scheme` partition table on `disk`, like `gpart create`.  See
+/// `part::create_table_request()`.
+#[cfg(target_os = "freebsd")]
+pub fn create_table(
+    disk: &str,
+    scheme: &PartScheme,
+    entries: Option<u64>,
+) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    create_table_request(&graph, disk, scheme, entries)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Returns the request to resize partition entry `index` of the table on `disk` to `new_size`
+/// bytes, like `gpart resize`.  If `new_size` is `None`, the partition grows into all of the free
+/// space following it.  With an `alignment`, the end of the partition is aligned (down) to it.
+///
+/// Fails if there is not enough free space after the partition, or if `new_size` is `None` and
+/// aligning the end would shrink the partition rather than grow it.
+pub fn resize_request(
+    graph: &Graph,
+    disk: &str,
+    index: u64,
+    new_size: Option<u64>,
+    alignment: Option<u64>,
+) -> Result<GctlRequest, Error> {
+    let (id, _, provider) = table(graph, disk)?;
+    let sectorsize = provider.sectorsize;
+    let (align, offset) = self::alignment(provider, alignment)?;
+    let (_, part) = entry(graph, id, index)?;
+
+    let free = graph
+        .part_free_ranges(id)
+        .unwrap_or_default()
+        .iter()
+        .find(|r| r.start == part.end + 1)
+        .map(|r| r.sectors())
+        .unwrap_or(0);
+    let max = part.sectors() + free;
+    let size = match new_size {
+        Some(s) => to_sectors(s, sectorsize, "size")?,
+        None => max,
+    };
+    if size > max {
+        return Err(CtlError::new(
+            ENOSPC,
+            format!(
+                "only {} contiguous free after partition {}",
+                ByteSize(free * sectorsize),
+                index
+            ),
+        )
+        .into());
+    }
+    let end = (part.start + size + offset) / align * align - offset;
+    if end <= part.start {
+        return Err(CtlError::new(
+            EINVAL,
+            format!("size {} is smaller than the alignment", size * sectorsize),
+        )
+        .into());
+    }
+    if new_size.is_none() && end < part.end + 1 {
+        return Err(CtlError::new(
+            EINVAL,
+            format!(
+                "no aligned end beyond partition {}'s current end; give a size to shrink it",
+                index
+            ),
+        )
+        .into());
+    }
+    return Ok(GctlRequest::new("PART", "resize")
+        .args(&[disk])
+        .string("flags", COMMIT)
+        .number("index", index as i64)
+        .string("size", &(end - part.start).to_string()));
+}
+
+/// Resizes partition entry `index` of the table on `disk`, like `gpart resize`.  See
+/// `part::resize_request()`.
+#[cfg(target_os = "freebsd")]
+pub fn resize(
+    disk: &str,
+    index: u64,
+    new_size: Option<u64>,
+    alignment: Option<u64>,
+) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    resize_request(&graph, disk, index, new_size, alignment)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Whether `attrib` can be set on a partition entry (or, with no `index`, on the table itself) of
+/// a `scheme` table, per the scheme's implementation in the kernel.
+fn attrib_valid(scheme: &PartScheme, attrib: &PartAttrib, index: Option<u64>) -> bool {
+    return match (scheme, index) {
+        (PartScheme::GPT, Some(_)) => matches!(
+            attrib,
+            PartAttrib::BootMe | PartAttrib::BootOnce | PartAttrib::BootFailed
+        ),
+        // The protective MBR
+        (PartScheme::GPT, None) => matches!(attrib, PartAttrib::Active | PartAttrib::LenovoFix),
+        (PartScheme::MBR, Some(_)) | (PartScheme::EBR, Some(_)) => {
+            matches!(attrib, PartAttrib::Active)
+        }
+        _ => false,
+    };
+}
+
+fn setunset_request(
+    verb: &str,
+    graph: &Graph,
+    disk: &str,
+    index: Option<u64>,
+    attrib: &PartAttrib,
+) -> Result<GctlRequest, Error> {
+    let (id, meta, _) = table(graph, disk)?;
+    if let Some(index) = index {
+        entry(graph, id, index)?;
+    }
+    if !attrib_valid(&meta.scheme, attrib, index) {
+        let what = match index {
+            Some(index) => format!("partition {}", index),
+            None => "the table".to_owned(),
+        };
+        return Err(CtlError::new(
+            EINVAL,
+            format!(
+                "attribute '{}' is not valid for {} of {} ({})",
+                attrib.as_ref(),
+                what,
+                disk,
+                meta.scheme.as_ref()
+            ),
+        )
+        .into());
+    }
+    let mut req = GctlRequest::new("PART", verb)
+        .args(&[disk])
+        .string("flags", COMMIT)
+        .string("attrib", attrib.as_ref());
+    if let Some(index) = index {
+        req = req.number("index", index as i64);
+    }
+    return Ok(req);
+}
+
+/// Returns the request to set `attrib` on partition entry `index` of the table on `disk`, like
+/// `gpart set -a`.  Without an `index`, the attribute is set on the table itself; for GPT, that
+/// is its protective MBR (`active`, `lenovofix`).
+///
+/// Fails if the table's scheme does not support the attribute: `bootme`, `bootonce`, and
+/// `bootfailed` are GPT's, and `active` is MBR's and EBR's.
+pub fn set_request(
+    graph: &Graph,
+    disk: &str,
+    index: Option<u64>,
+    attrib: &PartAttrib,
+) -> Result<GctlRequest, Error> {
+    return setunset_request("set", graph, disk, index, attrib);
+}
+
+/// Returns the request to clear `attrib` on partition entry `index` of the table on `disk`, like
+/// `gpart unset -a`.  See `part::set_request()`.
+pub fn unset_request(
+    graph: &Graph,
+    disk: &str,
+    index: Option<u64>,
+    attrib: &PartAttrib,
+) -> Result<GctlRequest, Error> {
+    return setunset_request("unset", graph, disk, index, attrib);
+}
+
+/// Sets `attrib` on partition entry `index` of the table on `disk`, like `gpart set`.  See
+/// `part::set_request()`.
+#[cfg(target_os = "freebsd")]
+pub fn set(disk: &str, index: Option<u64>, attrib: &PartAttrib) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    set_request(&graph, disk, index, attrib)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Clears `attrib` on partition entry `index` of the table on `disk`, like `gpart unset`.  See
+/// `part::set_request()`.
+#[cfg(target_os = "freebsd")]
+pub fn unset(disk: &str, index: Option<u64>, attrib: &PartAttrib) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    unset_request(&graph, disk, index, attrib)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Whether partition entries of a `scheme` table have labels.
+fn has_labels(scheme: &PartScheme) -> bool {
+    return matches!(
+        scheme,
+        PartScheme::APM | PartScheme::BSD64 | PartScheme::GPT
+    );
+}
+
+/// Returns the request to change the type and/or label of partition entry `index` of the table
+/// on `disk`, like `gpart modify`.  The partition's contents are untouched.
+///
+/// Fails if neither is given, or if a label is given but the scheme has none.
+pub fn modify_request(
+    graph: &Graph,
+    disk: &str,
+    index: u64,
+    new_type: Option<&PartType>,
+    new_label: Option<&str>,
+) -> Result<GctlRequest, Error> {
+    let (id, meta, _) = table(graph, disk)?;
+    entry(graph, id, index)?;
+    if new_type.is_none() && new_label.is_none() {
+        return Err(CtlError::new(EINVAL, "no type or label to modify".to_owned()).into());
+    }
+    if new_label.is_some() && !has_labels(&meta.scheme) {
+        return Err(CtlError::new(
+            EINVAL,
+            format!(
+                "{} ({}) has no partition labels",
+                disk,
+                meta.scheme.as_ref()
+            ),
+        )
+        .into());
+    }
+    let mut req = GctlRequest::new("PART", "modify")
+        .args(&[disk])
+        .string("flags", COMMIT)
+        .number("index", index as i64);
+    if let Some(type_) = new_type {
+        req = req.string("type", &type_.to_string());
+    }
+    if let Some(label) = new_label {
+        req = req.string("label", label);
+    }
+    return Ok(req);
+}
+
+/// Changes the type and/or label of partition entry `index` of the table on `disk`, like `gpart
+/// modify`.  See `part::modify_request()`.
+#[cfg(target_os = "freebsd")]
+pub fn modify(
+    disk: &str,
+    index: u64,
+    new_type: Option<&PartType>,
+    new_label: Option<&str>,
+) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    modify_request(&graph, disk, index, new_type, new_label)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Returns the request to repair the corrupt partition table on `disk`, like `gpart recover`;
+/// e.g., to rewrite a GPT's damaged primary or backup header from the intact one.
+///
+/// Fails if the table is not `PartState::CORRUPT`; see `Graph::corrupt_part_tables()`.
+pub fn recover_request(graph: &Graph, disk: &str) -> Result<GctlRequest, Error> {
+    let (_, meta, _) = table(graph, disk)?;
+    if meta.state != PartState::CORRUPT {
+        return Err(
+            CtlError::new(EINVAL, format!("{} partition table is not corrupt", disk)).into(),
+        );
+    }
+    return Ok(GctlRequest::new("PART", "recover")
+        .args(&[disk])
+        .string("flags", COMMIT));
+}
+
+/// Repairs the corrupt partition table on `disk`, like `gpart recover`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn repair() -> Result<(), geom::Error> {
+///     let graph = geom::get_graph()?;
+///     for (_, table) in graph.corrupt_part_tables() {
+///         geom::part::recover(&table.name)?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn recover(disk: &str) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    recover_request(&graph, disk)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Deletes partition entry `index` from the table on `disk`, like `gpart delete`.
+#[cfg(target_os = "freebsd")]
+pub fn delete(disk: &str, index: u64) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    delete_request(&graph, disk, index)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Destroys the partition table on `disk`, like `gpart destroy`.
+#[cfg(target_os = "freebsd")]
+pub fn destroy(disk: &str, force: bool) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    destroy_request(&graph, disk, force)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctl::CtlErrorKind;
+    use crate::ctl::ParamValue;
+    use crate::part::{
+        add_request, added_provider, create_table_request, delete_request, destroy_request,
+        modify_request, recover_request, resize_request, set_request, unset_request, PartType,
+        PartitionSpec,
+    };
+    use crate::{graph, raw, Error};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    /// The sample, with 2 GiB of free space at the end of nvd0.
+    fn sample() -> graph::Graph {
+        let xml = SAMPLE_XML.replace("<last>976773127</last>", "<last>980967431</last>");
+        return graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+    }
+
+    /// The sample, with nvd0's table marked corrupt.
+    fn corrupt_sample() -> graph::Graph {
+        let xml = SAMPLE_XML.replace(
+            "<last>976773127</last>\n\t<fwsectors>63</fwsectors>\n\t<fwheads>255</fwheads>\n\t<state>OK</state>",
+            "<last>976773127</last>\n\t<fwsectors>63</fwsectors>\n\t<fwheads>255</fwheads>\n\t<state>CORRUPT</state>",
+        );
+        return graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+    }
+
+    fn string(s: &str) -> Option<ParamValue> {
+        return Some(ParamValue::String(s.to_owned()));
+    }
+
+    #[test]
+    fn add() {
+        let g = sample();
+        let mut spec = PartitionSpec {
+            size: Some(1 << 30),
+            label: Some("data".to_owned()),
+            alignment: Some(1 << 20),
+            ..PartitionSpec::new(graph::PartAlias::FreeBSDUFS)
+        };
+        let req = add_request(&g, "nvd0", &spec).unwrap();
+        assert_eq!(req.get("arg0").cloned(), string("nvd0"));
+        assert_eq!(req.get("flags").cloned(), string("C"));
+        assert_eq!(req.get("type").cloned(), string("freebsd-ufs"));
+        assert_eq!(req.get("start").cloned(), string("976775168"));
+        assert_eq!(req.get("size").cloned(), string("2097152"));
+        assert_eq!(req.get("label").cloned(), string("data"));
+        assert_eq!(req.get("index"), None);
+
+        // The rest of the free range, aligned.
+        spec.size = None;
+        spec.type_ = PartType::Raw("516e7cb6-6ecf-11d6-8ff8-00022d09712b".to_owned());
+        let req = add_request(&g, "nvd0", &spec).unwrap();
+        assert_eq!(req.get("size").cloned(), string("4192256"));
+        assert_eq!(
+            req.get("type").cloned(),
+            string("!516e7cb6-6ecf-11d6-8ff8-00022d09712b")
+        );
+
+        spec.size = Some(3 << 30);
+        let err = add_request(&g, "nvd0", &spec).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Ctl: only 2.0G contiguous free in nvd0: No space left on device"
+        );
+
+        spec.size = Some(1000);
+        assert!(matches!(add_request(&g, "nvd0", &spec), Err(Error::Ctl(_))));
+        spec.size = None;
+        spec.start = Some(512);
+        let err = add_request(&g, "nvd0", &spec).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Ctl: only 0B contiguous free at LBA 1: No space left on device"
+        );
+
+        // ada0 is full; nvd0p1 is not partitioned.
+        spec.start = None;
+        assert!(add_request(&g, "ada0", &spec).is_err());
+        assert!(add_request(&g, "nvd0p1", &spec).is_err());
+    }
+
+    #[test]
+    fn output() {
+        assert_eq!(added_provider("da0p1 added\n"), Some("da0p1".to_owned()));
+        assert_eq!(added_provider("hello"), None);
+    }
+
+    #[test]
+    fn delete_destroy() {
+        let g = sample();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+
+        // nvd0p1 (index 1) is closed; nvd0p2 holds the root filesystem.
+        let req = delete_request(&g, "nvd0", 1).unwrap();
+        assert_eq!(req.get("index"), Some(&ParamValue::Number(1)));
+        assert_eq!(kind(delete_request(&g, "nvd0", 2)), CtlErrorKind::Busy);
+        assert_eq!(kind(delete_request(&g, "nvd0", 9)), CtlErrorKind::NotFound);
+        assert_eq!(kind(delete_request(&g, "nvd9", 1)), CtlErrorKind::NotFound);
+
+        assert_eq!(kind(destroy_request(&g, "nvd0", false)), CtlErrorKind::Busy);
+        let req = destroy_request(&g, "nvd0", true).unwrap();
+        assert_eq!(req.get("force"), Some(&ParamValue::Int(1)));
+    }
+
+    #[test]
+    fn resize() {
+        let g = sample();
+        let (nvd0, _) = g
+            .find_by_class_name(&graph::GeomClass::PART, "nvd0")
+            .unwrap();
+        let last = g
+            .child_edges_iter(nvd0)
+            .filter_map(|(_, e)| e.metadata.as_deref()?.as_part())
+            .max_by_key(|p| p.end)
+            .unwrap()
+            .clone();
+
+        // Grow into all 2 GiB (and one sector) of free space.
+        let req = resize_request(&g, "nvd0", last.index, None, None).unwrap();
+        let size = last.sectors() + 4194305;
+        assert_eq!(req.get("size"), Some(&ParamValue::String(size.to_string())));
+
+        let err = resize_request(&g, "nvd0", last.index, Some(last.length + (4 << 30)), None)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Ctl: only 2.0G contiguous free after partition {}: No space left on device",
+                last.index
+            )
+        );
+
+        // Shrinking needs no free space; the end is aligned down.
+        let req = resize_request(&g, "nvd0", 1, Some(100 << 20), Some(1 << 20)).unwrap();
+        let p1 = g
+            .child_edges_iter(nvd0)
+            .filter_map(|(_, e)| e.metadata.as_deref()?.as_part())
+            .find(|p| p.index == 1)
+            .unwrap();
+        let end = (p1.start + 204800) / 2048 * 2048;
+        assert_eq!(
+            req.get("size"),
+            Some(&ParamValue::String((end - p1.start).to_string()))
+        );
+
+        // Growing p1, which has no free space after it, would shrink it to align its end.
+        assert_ne!((p1.end + 1) % 2048, 0);
+        let err = resize_request(&g, "nvd0", 1, None, Some(1 << 20)).unwrap_err();
+        assert!(matches!(err, Error::Ctl(e) if e.kind() == CtlErrorKind::InvalidArgument));
+    }
+
+    #[test]
+    fn set_unset() {
+        let g = sample();
+        let bootme = graph::PartAttrib::BootMe;
+        let req = set_request(&g, "nvd0", Some(2), &bootme).unwrap();
+        assert_eq!(req.verb(), "set");
+        assert_eq!(req.get("attrib").cloned(), string("bootme"));
+        assert_eq!(req.get("index"), Some(&ParamValue::Number(2)));
+        let req = unset_request(&g, "nvd0", None, &graph::PartAttrib::LenovoFix).unwrap();
+        assert_eq!(req.verb(), "unset");
+        assert_eq!(req.get("index"), None);
+
+        let err = set_request(&g, "nvd0", Some(2), &graph::PartAttrib::Active).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Ctl: attribute 'active' is not valid for partition 2 of nvd0 (GPT): Invalid argument"
+        );
+        assert!(set_request(&g, "nvd0", None, &bootme).is_err());
+        let other = graph::PartAttrib::Other("frobbed".to_owned());
+        assert!(set_request(&g, "nvd0", Some(2), &other).is_err());
+        assert!(set_request(&g, "nvd0", Some(9), &bootme).is_err());
+    }
+
+    #[test]
+    fn modify() {
+        let g = sample();
+        let swap = PartType::Alias(graph::PartAlias::FreeBSDSwap);
+        let req = modify_request(&g, "nvd0", 2, Some(&swap), Some("swap0")).unwrap();
+        assert_eq!(req.get("type").cloned(), string("freebsd-swap"));
+        assert_eq!(req.get("label").cloned(), string("swap0"));
+        let raw: PartType = "!516e7cb5-6ecf-11d6-8ff8-00022d09712b".parse().unwrap();
+        let req = modify_request(&g, "nvd0", 2, Some(&raw), None).unwrap();
+        assert_eq!(
+            req.get("type").cloned(),
+            string("!516e7cb5-6ecf-11d6-8ff8-00022d09712b")
+        );
+        assert_eq!(req.get("label"), None);
+        assert_eq!("efi".parse(), Ok(PartType::Alias(graph::PartAlias::Efi)));
+        assert!("frobbed".parse::<PartType>().is_err());
+
+        assert!(modify_request(&g, "nvd0", 2, None, None).is_err());
+        assert!(modify_request(&g, "nvd0", 9, Some(&swap), None).is_err());
+
+        let part = g
+            .edges
+            .values()
+            .find(|e| e.name == "nvd0p1")
+            .and_then(|e| e.metadata.as_deref()?.as_part())
+            .unwrap();
+        assert_eq!(part.alias(), Some(graph::PartAlias::Efi));
+    }
+
+    #[test]
+    fn create_table() {
+        let g = sample();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+        let gpt = graph::PartScheme::GPT;
+
+        // nvd0p1 holds a filesystem, but is not partitioned.
+        let req = create_table_request(&g, "nvd0p1", &gpt, Some(256)).unwrap();
+        assert_eq!(req.get("scheme").cloned(), string("GPT"));
+        assert_eq!(req.get("entries"), Some(&ParamValue::Number(256)));
+        let req = create_table_request(&g, "nvd0p1", &graph::PartScheme::MBR, None).unwrap();
+        assert_eq!(req.get("entries"), None);
+
+        assert_eq!(
+            kind(create_table_request(&g, "nvd0", &gpt, None)),
+            CtlErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            kind(create_table_request(&g, "nvd9", &gpt, None)),
+            CtlErrorKind::NotFound
+        );
+        assert_eq!(
+            kind(create_table_request(&g, "nvd0p1", &gpt, Some(64))),
+            CtlErrorKind::InvalidArgument
+        );
+        assert_eq!(
+            kind(create_table_request(
+                &g,
+                "nvd0p1",
+                &graph::PartScheme::LDM,
+                None
+            )),
+            CtlErrorKind::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn recover() {
+        let g = sample();
+        assert!(g.corrupt_part_tables().is_empty());
+        assert!(recover_request(&g, "nvd0").is_err());
+
+        let g = corrupt_sample();
+        let corrupt: Vec<_> = g
+            .corrupt_part_tables()
+            .into_iter()
+            .map(|(_, geom)| geom.name.as_str())
+            .collect();
+        assert_eq!(corrupt, vec!["nvd0"]);
+        let req = recover_request(&g, "nvd0").unwrap();
+        assert_eq!(req.verb(), "recover");
+        assert_eq!(req.get("arg0").cloned(), string("nvd0"));
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn dry_run() {
+        let g = sample();
+        let spec = PartitionSpec {
+            size: Some(1 << 30),
+            ..PartitionSpec::new(graph::PartAlias::FreeBSDUFS)
+        };
+        let swap = PartType::Alias(graph::PartAlias::FreeBSDSwap);
+        let (res, requests) = crate::dry_run_on(&g, || -> Result<(), Error> {
+            assert_eq!(super::add("nvd0", &spec)?.provider, None);
+            super::create_table("nvd0p1", &graph::PartScheme::GPT, None)?;
+            super::resize("nvd0", 1, Some(100 << 20), Some(1 << 20))?;
+            super::set("nvd0", Some(2), &graph::PartAttrib::BootMe)?;
+            super::unset("nvd0", None, &graph::PartAttrib::LenovoFix)?;
+            super::modify("nvd0", 2, Some(&swap), None)?;
+            super::delete("nvd0", 1)?;
+            super::destroy("nvd0", true)?;
+            return Ok(());
+        });
+        res.unwrap();
+        let verbs: Vec<&str> = requests.iter().map(|r| r.verb()).collect();
+        assert_eq!(
+            verbs,
+            vec!["add", "create", "resize", "set", "unset", "modify", "delete", "destroy"]
+        );
+
+        let (res, requests) = crate::dry_run_on(&corrupt_sample(), || super::recover("nvd0"));
+        assert!(!res.unwrap().refreshed);
+        assert_eq!(requests.len(), 1);
+    }
+}
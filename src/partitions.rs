@@ -0,0 +1,235 @@
+//! Partition table layout and boot configuration analysis.
+use crate::graph::{EdgeId, Geom, GeomClass, GeomMetadata, Graph, NodeId, PartAttrib, PartState};
+
+/// An unallocated range of a partition table, like a `- free -` line of `gpart show`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FreeRange {
+    /// First free LBA
+    pub start: u64,
+    /// Last free LBA (inclusive, like `PartInfo::end`)
+    pub end: u64,
+    /// The byte offset of `start` within the partitioned provider
+    pub offset: u64,
+    /// The length of the range, in bytes
+    pub length: u64,
+}
+
+impl FreeRange {
+    /// The length of the range, in sectors.
+    pub fn sectors(&self) -> u64 {
+        return self.end - self.start + 1;
+    }
+}
+
+/// Why a partition entry is relevant to booting; see `Graph::boot_partitions()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BootKind {
+    /// An EFI system partition (`type_` alias `"efi"`), from which UEFI firmware loads
+    /// `loader.efi`
+    Efi,
+    /// A `freebsd-boot` partition holding `gptboot` or `gptzfsboot`, for BIOS boot from GPT
+    FreebsdBoot,
+    /// An MBR (or EBR, VTOC8) slice marked active, which the MBR boot code boots from
+    Active,
+}
+
+/// A partition entry that some firmware or boot code can boot from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BootPartition {
+    pub kind: BootKind,
+    /// An `Edge` out of the partition's provider
+    pub partition: EdgeId,
+    /// The partition's provider name, e.g., `"ada0p1"`
+    pub name: String,
+    /// The name of the disk (or MD) provider the partition table is on, e.g., `"ada0"`.  Nested
+    /// tables (e.g., a BSD label inside an MBR slice) report the underlying disk.
+    pub disk: Option<String>,
+    /// Whether the GPT `bootme` (or `bootonce`) attribute is set, which `gptboot` uses to choose
+    /// among several `freebsd-ufs` partitions
+    pub bootme: bool,
+}
+
+impl Graph {
+    /// Finds every EFI system partition, `freebsd-boot` partition, and active MBR slice, in
+    /// `EdgeId` order.  A disk is bootable (by some firmware) if any of these are on it.
+    ///
+    /// The presence of a boot partition does not guarantee it contains working boot code.
+    pub fn boot_partitions(&self) -> Vec<BootPartition> {
+        let mut result: Vec<BootPartition> = Vec::new();
+        for (eid, edge) in &self.edges {
+            match self.nodes.get(&edge.provider_geom) {
+                Some(g) if g.class == GeomClass::PART => {}
+                _ => continue,
+            }
+            let part = match edge.metadata.as_deref().and_then(|m| m.as_part()) {
+                Some(p) => p,
+                None => continue,
+            };
+            // Several consumers of one partition yield several edges.
+            if result.iter().any(|b| b.partition.1 == eid.1) {
+                continue;
+            }
+            let kind = if part.type_ == "efi" {
+                BootKind::Efi
+            } else if part.type_ == "freebsd-boot" {
+                BootKind::FreebsdBoot
+            } else if part.attrib.contains(&PartAttrib::Active) {
+                BootKind::Active
+            } else {
+                continue;
+            };
+            result.push(BootPartition {
+                kind,
+                partition: *eid,
+                name: edge.name.to_owned(),
+                disk: self.disk_edge(eid).map(|(_, d)| d.name.to_owned()),
+                bootme: part
+                    .attrib
+                    .iter()
+                    .any(|a| matches!(a, PartAttrib::BootMe | PartAttrib::BootOnce)),
+            });
+        }
+        return result;
+    }
+
+    /// Finds every `GeomClass::PART` geom whose table is `PartState::CORRUPT` (e.g., a GPT disk
+    /// whose backup header was overwritten), in `NodeId` order.  Such tables can usually be
+    /// repaired with `part::recover()`.
+    pub fn corrupt_part_tables(&self) -> Vec<(&NodeId, &Geom)> {
+        return self
+            .nodes
+            .iter()
+            .filter(|(_, g)| match g.metadata.as_deref() {
+                Some(GeomMetadata::PART(m)) => m.state == PartState::CORRUPT,
+                _ => false,
+            })
+            .collect();
+    }
+
+    /// Given the `NodeId` of a `GeomClass::PART` geom, returns the ranges between the table's
+    /// first and last allocatable LBAs that no partition entry covers, in LBA order.
+    ///
+    /// Returns `None` if the node does not exist or is not a partition table.
+    pub fn part_free_ranges(&self, id: &NodeId) -> Option<Vec<FreeRange>> {
+        let table = match self.nodes.get(id)?.metadata.as_deref()? {
+            GeomMetadata::PART(t) => t,
+            _ => return None,
+        };
+        // LBAs are in units of the partitioned provider's sectors.
+        let sectorsize = self.parent_edges_iter(id).next()?.1.sectorsize;
+
+        let mut entries: Vec<(u64, u64)> = Vec::new();
+        for (eid, edge) in self.child_edges_iter(id) {
+            let part = match edge.metadata.as_deref().and_then(|m| m.as_part()) {
+                Some(p) => p,
+                None => continue,
+            };
+            // Several consumers of one partition yield several edges.
+            if self
+                .child_edgeids_iter(id)
+                .any(|other| other.1 == eid.1 && other < eid)
+            {
+                continue;
+            }
+            entries.push((part.start, part.end));
+        }
+        entries.sort_unstable();
+
+        let mut result = Vec::new();
+        let mut next = table.first;
+        let mut push = |start: u64, end: u64| {
+            if start <= end {
+                result.push(FreeRange {
+                    start,
+                    end,
+                    offset: start * sectorsize,
+                    length: (end - start + 1) * sectorsize,
+                });
+            }
+        };
+        for (start, end) in entries {
+            if start > next {
+                push(next, (start - 1).min(table.last));
+            }
+            next = next.max(end.saturating_add(1));
+        }
+        push(next, table.last);
+        return Some(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graph,
+        partitions::{BootKind, FreeRange},
+        raw, GeomClass,
+    };
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn free_ranges() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (ada0, _) = g.find_by_class_name(&GeomClass::PART, "ada0").unwrap();
+        assert_eq!(g.part_free_ranges(ada0), Some(vec![]));
+
+        let (nvd0, _) = g.find_by_class_name(&GeomClass::PART, "nvd0").unwrap();
+        let free = g.part_free_ranges(nvd0).unwrap();
+        assert_eq!(
+            free,
+            vec![FreeRange {
+                start: 976773127,
+                end: 976773127,
+                offset: 976773127 * 512,
+                length: 512,
+            }]
+        );
+        assert_eq!(free[0].sectors(), 1);
+
+        // Leading gaps are found too.
+        let xml = SAMPLE_XML.replace(
+            "<first>40</first>\n\t<last>976773127</last>",
+            "<first>34</first>\n\t<last>976773127</last>",
+        );
+        let g2 = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let free = g2.part_free_ranges(nvd0).unwrap();
+        assert_eq!(free.len(), 2);
+        assert_eq!((free[0].start, free[0].end, free[0].sectors()), (34, 39, 6));
+
+        let (disk, _) = g.find_by_class_name(&GeomClass::DISK, "nvd0").unwrap();
+        assert_eq!(g.part_free_ranges(disk), None);
+    }
+
+    #[test]
+    fn boot_partitions() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let boot = g.boot_partitions();
+        let summary: Vec<_> = boot
+            .iter()
+            .map(|b| (b.kind, b.name.as_str(), b.disk.as_deref(), b.bootme))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (BootKind::FreebsdBoot, "nvd0p4", Some("nvd0"), false),
+                (BootKind::Efi, "nvd0p1", Some("nvd0"), false),
+            ]
+        );
+
+        // An active slice, with GPT-style boot attributes for good measure.
+        let xml = SAMPLE_XML.replace(
+            "<end>1953525127</end>",
+            "<end>1953525127</end><attrib>active</attrib><attrib>bootme</attrib>",
+        );
+        let g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let boot = g.boot_partitions();
+        assert_eq!(boot.len(), 3);
+        assert_eq!(boot[0].kind, BootKind::Active);
+        assert_eq!(boot[0].name, "ada0p1");
+        assert!(boot[0].bootme);
+    }
+}
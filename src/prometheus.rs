@@ -0,0 +1,353 @@
+//! Prometheus metrics.
+//!
+//! `Graph::to_prometheus()` renders the graph in the Prometheus text exposition format, so that
+//! exporters can serve GEOM metrics without shelling out, e.g.:
+//!
+//! ```text
+//! geom_provider_mediasize_bytes{name="ada0p2",class="PART"} 1000204845056
+//! ```
+//!
+//! Once `Graph::attach_devstat()` has been called, the `devstat(9)` counters of each device are
+//! exported too, e.g., `geom_provider_bytes_total{name="ada0",class="DISK",op="read"}`, as are
+//! GEOM's own counters for other providers once `Graph::attach_geom_devstat()` has.
+use crate::graph::{GeomClass, GeomMetadata, Graph, Mode, PartState};
+use crate::stats::DeviceStats;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A provider, whether or not it has consumers.
+struct Provider<'a> {
+    name: &'a str,
+    class: &'a GeomClass,
+    mediasize: u64,
+    sectorsize: u64,
+    mode: Mode,
+    withered: bool,
+    devstat: Option<&'a DeviceStats>,
+}
+
+/// Escapes a label value: backslash, double-quote, and line feed.
+fn escape(s: &str) -> String {
+    return s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+}
+
+fn header(out: &mut String, name: &str, help: &str) -> std::fmt::Result {
+    return typed_header(out, name, "gauge", help);
+}
+
+fn typed_header(out: &mut String, name: &str, type_: &str, help: &str) -> std::fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, type_)?;
+    return Ok(());
+}
+
+impl Graph {
+    /// Renders provider, geom, and partition table metrics in the Prometheus text exposition
+    /// format.
+    ///
+    /// Provider metrics are labeled with the provider `name` and the `class` of the geom owning
+    /// it.  Providers without consumers (`Graph::orphan_providers()`) are included.  I/O counters
+    /// are exported for the providers with statistics from `Graph::attach_devstat()` or
+    /// `Graph::attach_geom_devstat()`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String cannot fail.
+        let _ = self.write_prometheus(&mut out);
+        return out;
+    }
+
+    fn prometheus_providers(&self) -> Vec<Provider<'_>> {
+        let mut by_id: BTreeMap<u64, Provider> = BTreeMap::new();
+        for (eid, edge) in &self.edges {
+            by_id.entry(eid.1).or_insert_with(|| Provider {
+                name: &edge.name,
+                class: &self.nodes[&edge.provider_geom].class,
+                mediasize: edge.mediasize,
+                sectorsize: edge.sectorsize,
+                mode: edge.mode,
+                withered: edge.withered,
+                devstat: edge.devstat.as_deref(),
+            });
+        }
+        for (pid, orphan) in &self.orphans {
+            by_id.entry(*pid).or_insert_with(|| Provider {
+                name: &orphan.name,
+                class: &self.nodes[&orphan.provider_geom].class,
+                mediasize: orphan.mediasize,
+                sectorsize: orphan.sectorsize,
+                mode: orphan.mode,
+                withered: orphan.withered,
+                devstat: None,
+            });
+        }
+        let mut result: Vec<_> = by_id.into_values().collect();
+        result.sort_by(|a, b| a.name.cmp(b.name));
+        return result;
+    }
+
+    fn write_prometheus(&self, out: &mut String) -> std::fmt::Result {
+        let providers = self.prometheus_providers();
+        let labels: Vec<String> = providers
+            .iter()
+            .map(|p| {
+                format!(
+                    "name=\"{}\",class=\"{}\"",
+                    escape(p.name),
+                    escape(p.class.name())
+                )
+            })
+            .collect();
+
+        header(
+            out,
+            "geom_provider_mediasize_bytes",
+            "Size of the provider, in bytes.",
+        )?;
+        for (p, l) in providers.iter().zip(&labels) {
+            writeln!(
+                out,
+                "geom_provider_mediasize_bytes{{{}}} {}",
+                l, p.mediasize
+            )?;
+        }
+        header(
+            out,
+            "geom_provider_sectorsize_bytes",
+            "Sector size of the provider, in bytes.",
+        )?;
+        for (p, l) in providers.iter().zip(&labels) {
+            writeln!(
+                out,
+                "geom_provider_sectorsize_bytes{{{}}} {}",
+                l, p.sectorsize
+            )?;
+        }
+        header(
+            out,
+            "geom_provider_access_count",
+            "Access reference counts of the provider, by kind.",
+        )?;
+        for (p, l) in providers.iter().zip(&labels) {
+            for (access, count) in &[
+                ("read", p.mode.read()),
+                ("write", p.mode.write()),
+                ("exclusive", p.mode.exclusive()),
+            ] {
+                writeln!(
+                    out,
+                    "geom_provider_access_count{{{},access=\"{}\"}} {}",
+                    l, access, count
+                )?;
+            }
+        }
+        header(
+            out,
+            "geom_provider_withered",
+            "Whether the provider is being torn down.",
+        )?;
+        for (p, l) in providers.iter().zip(&labels) {
+            writeln!(out, "geom_provider_withered{{{}}} {}", l, p.withered as u8)?;
+        }
+
+        self.write_prometheus_devstat(out, &providers, &labels)?;
+
+        header(out, "geom_geoms", "Number of geoms, by class.")?;
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for geom in self.nodes.values() {
+            *counts.entry(geom.class.name()).or_default() += 1;
+        }
+        for (class, count) in counts {
+            writeln!(out, "geom_geoms{{class=\"{}\"}} {}", escape(class), count)?;
+        }
+
+        header(
+            out,
+            "geom_part_table_corrupt",
+            "Whether the partition table is corrupt.",
+        )?;
+        for geom in self.nodes.values() {
+            if let Some(GeomMetadata::PART(m)) = geom.metadata.as_deref() {
+                writeln!(
+                    out,
+                    "geom_part_table_corrupt{{name=\"{}\",scheme=\"{}\"}} {}",
+                    escape(&geom.name),
+                    m.scheme.as_ref(),
+                    matches!(m.state, PartState::CORRUPT) as u8
+                )?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn write_prometheus_devstat(
+        &self,
+        out: &mut String,
+        providers: &[Provider],
+        labels: &[String],
+    ) -> std::fmt::Result {
+        let stats: Vec<(&DeviceStats, &String)> = providers
+            .iter()
+            .zip(labels)
+            .filter_map(|(p, l)| p.devstat.map(|s| (s, l)))
+            .collect();
+        if stats.is_empty() {
+            return Ok(());
+        }
+        let ops = |s: &DeviceStats| {
+            [
+                ("read", s.read),
+                ("write", s.write),
+                ("free", s.free),
+                ("other", s.other),
+            ]
+        };
+
+        typed_header(
+            out,
+            "geom_provider_bytes_total",
+            "counter",
+            "Bytes transferred by the device, by kind of operation.",
+        )?;
+        for (s, l) in &stats {
+            for (op, t) in &ops(s) {
+                writeln!(
+                    out,
+                    "geom_provider_bytes_total{{{},op=\"{}\"}} {}",
+                    l, op, t.bytes
+                )?;
+            }
+        }
+        typed_header(
+            out,
+            "geom_provider_operations_total",
+            "counter",
+            "Operations completed by the device, by kind.",
+        )?;
+        for (s, l) in &stats {
+            for (op, t) in &ops(s) {
+                writeln!(
+                    out,
+                    "geom_provider_operations_total{{{},op=\"{}\"}} {}",
+                    l, op, t.operations
+                )?;
+            }
+        }
+        typed_header(
+            out,
+            "geom_provider_duration_seconds_total",
+            "counter",
+            "Total time taken by the device's operations, by kind.",
+        )?;
+        for (s, l) in &stats {
+            for (op, t) in &ops(s) {
+                writeln!(
+                    out,
+                    "geom_provider_duration_seconds_total{{{},op=\"{}\"}} {}",
+                    l,
+                    op,
+                    t.duration.as_secs_f64()
+                )?;
+            }
+        }
+        typed_header(
+            out,
+            "geom_provider_busy_seconds_total",
+            "counter",
+            "Time the device has had operations outstanding.",
+        )?;
+        for (s, l) in &stats {
+            writeln!(
+                out,
+                "geom_provider_busy_seconds_total{{{}}} {}",
+                l,
+                s.busy_time.as_secs_f64()
+            )?;
+        }
+        header(
+            out,
+            "geom_provider_queue_length",
+            "Operations outstanding on the device.",
+        )?;
+        for (s, l) in &stats {
+            writeln!(
+                out,
+                "geom_provider_queue_length{{{}}} {}",
+                l, s.queue_length
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::DeviceStats;
+    use crate::{graph, raw};
+    use std::time::Duration;
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn sample() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let out = g.to_prometheus();
+        assert!(out.contains("# TYPE geom_provider_mediasize_bytes gauge\n"));
+        assert!(out
+            .contains("geom_provider_mediasize_bytes{name=\"nvd0p1\",class=\"PART\"} 209715200\n"));
+        assert!(out.contains(
+            "geom_provider_access_count{name=\"nvd0p2\",class=\"PART\",access=\"exclusive\"} 2\n"
+        ));
+        assert!(out.contains("geom_geoms{class=\"DISK\"} 3\n"));
+        assert!(out.contains("geom_part_table_corrupt{name=\"nvd0\",scheme=\"GPT\"} 0\n"));
+
+        // One sample per provider, even if it has several consumers.
+        let providers = out
+            .lines()
+            .filter(|l| l.starts_with("geom_provider_mediasize_bytes{"))
+            .count();
+        let mut ids: Vec<u64> = g.edges.keys().map(|eid| eid.1).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(providers, ids.len() + g.orphans.len());
+    }
+
+    #[test]
+    fn devstat() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let mut g = graph::decode_graph(&rawmesh).unwrap();
+        assert!(!g.to_prometheus().contains("geom_provider_bytes_total"));
+
+        let mut nvd0 = DeviceStats {
+            name: "nvd0".to_owned(),
+            queue_length: 2,
+            busy_time: Duration::from_millis(1500),
+            ..Default::default()
+        };
+        nvd0.read.bytes = 1 << 20;
+        nvd0.write.operations = 512;
+        g.attach_devstat(&[nvd0]);
+
+        let out = g.to_prometheus();
+        assert!(out.contains("# TYPE geom_provider_bytes_total counter\n"));
+        assert!(out.contains(
+            "geom_provider_bytes_total{name=\"nvd0\",class=\"DISK\",op=\"read\"} 1048576\n"
+        ));
+        assert!(out.contains(
+            "geom_provider_operations_total{name=\"nvd0\",class=\"DISK\",op=\"write\"} 512\n"
+        ));
+        assert!(
+            out.contains("geom_provider_busy_seconds_total{name=\"nvd0\",class=\"DISK\"} 1.5\n")
+        );
+        assert!(out.contains("geom_provider_queue_length{name=\"nvd0\",class=\"DISK\"} 2\n"));
+        assert!(!out.contains("name=\"nvd1\",class=\"DISK\",op="));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(super::escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,467 @@
+//! Text views of the graph.
+//!
+//! `Graph::render_tree()` prints one line per provider, like `geom -t`: the geom owning it
+//! (indented by depth), its class, and the provider name.  The consumers of each provider follow,
+//! indented further.  Geoms without providers (e.g., DEV or VFS) get a line of their own.
+//!
+//! `Graph::render_lsblk()` prints a table of providers in the style of Linux's `lsblk(8)`.
+use crate::graph::{Edge, EdgeMetadata, Geom, GeomClass, Graph, NodeId};
+use crate::size::ByteSize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Options for `Graph::render_tree()`.
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    /// Add a column with each provider's size
+    pub sizes: bool,
+    /// Add a column with each provider's mode
+    pub modes: bool,
+    /// Omit DEV geoms, which otherwise follow every provider
+    pub hide_dev: bool,
+}
+
+/// Options for `Graph::render_lsblk()`.
+#[derive(Clone, Debug, Default)]
+pub struct LsblkOptions {
+    /// Add a MOUNTPOINT column, from the mounts recorded by `Graph::attach_mounts()`.  Swap
+    /// devices are shown as `[SWAP]`.
+    pub mountpoints: bool,
+    /// List LABEL providers (e.g., `gpt/rootfs`) as children of the provider they label.
+    /// Otherwise, they are omitted, and their consumers are shown under the labeled provider.
+    pub labels: bool,
+}
+
+struct Row<'a> {
+    indent: usize,
+    geom: &'a Geom,
+    provider: Option<(&'a str, u64, String)>,
+}
+
+impl Graph {
+    /// Renders the graph as an indented tree, like `geom -t`, with optional extra columns.
+    ///
+    /// Each line shows a geom, its class, and one of its providers; consumers of the provider are
+    /// listed below it, indented by two more spaces.
+    pub fn render_tree(&self, opts: &TreeOptions) -> String {
+        let mut rows = Vec::new();
+        for (id, _) in self.roots_iter() {
+            self.tree_rows(&mut rows, opts, id, 0);
+        }
+
+        let geom_width = rows
+            .iter()
+            .map(|r| r.indent + r.geom.name.len())
+            .chain(std::iter::once("Geom".len()))
+            .max()
+            .unwrap_or(0);
+        let class_width = rows
+            .iter()
+            .map(|r| r.geom.class.name().len())
+            .chain(std::iter::once("Class".len()))
+            .max()
+            .unwrap_or(0);
+        let provider_width = rows
+            .iter()
+            .filter_map(|r| r.provider.as_ref().map(|p| p.0.len()))
+            .chain(std::iter::once("Provider".len()))
+            .max()
+            .unwrap_or(0);
+
+        let mut out = String::new();
+        let mut line = format!(
+            "{:gw$} {:cw$} {:pw$}",
+            "Geom",
+            "Class",
+            "Provider",
+            gw = geom_width,
+            cw = class_width,
+            pw = provider_width
+        );
+        if opts.sizes {
+            line.push_str(&format!(" {:>6}", "Size"));
+        }
+        if opts.modes {
+            line.push_str(" Mode");
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+
+        for row in &rows {
+            let mut line = format!(
+                "{:indent$}{:gw$} {:cw$} {:pw$}",
+                "",
+                row.geom.name,
+                row.geom.class.name(),
+                row.provider.as_ref().map_or("", |p| p.0),
+                indent = row.indent,
+                gw = geom_width - row.indent,
+                cw = class_width,
+                pw = provider_width
+            );
+            if let Some((_, mediasize, mode)) = &row.provider {
+                if opts.sizes {
+                    // Writing to a String cannot fail.
+                    let _ = write!(line, " {:>6}", ByteSize(*mediasize));
+                }
+                if opts.modes {
+                    let _ = write!(line, " {}", mode);
+                }
+            }
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        return out;
+    }
+
+    /// The providers of a geom, by id.  Several consumers may share a provider, so this keeps the
+    /// first `Edge` out of each.
+    fn providers_of(&self, id: &NodeId) -> Vec<(u64, &Edge)> {
+        let mut result: Vec<(u64, &Edge)> = Vec::new();
+        for (eid, edge) in self.child_edges_iter(id) {
+            if !result.iter().any(|(pid, _)| *pid == eid.1) {
+                result.push((eid.1, edge));
+            }
+        }
+        return result;
+    }
+
+    fn tree_rows<'a>(
+        &'a self,
+        rows: &mut Vec<Row<'a>>,
+        opts: &TreeOptions,
+        id: &NodeId,
+        indent: usize,
+    ) {
+        let geom = &self.nodes[id];
+        if opts.hide_dev && geom.class == GeomClass::DEV {
+            return;
+        }
+
+        let providers = self.providers_of(id);
+        let orphans = self
+            .orphans
+            .values()
+            .filter(|o| o.provider_geom == *id)
+            .collect::<Vec<_>>();
+        if providers.is_empty() && orphans.is_empty() {
+            rows.push(Row {
+                indent,
+                geom,
+                provider: None,
+            });
+            return;
+        }
+
+        for (pid, edge) in providers {
+            rows.push(Row {
+                indent,
+                geom,
+                provider: Some((&edge.name, edge.mediasize, edge.mode.to_string())),
+            });
+            for (eid, edge, _) in self.child_geoms_iter(id) {
+                if eid.1 == pid {
+                    self.tree_rows(rows, opts, &edge.consumer_geom, indent + 2);
+                }
+            }
+        }
+        for orphan in orphans {
+            rows.push(Row {
+                indent,
+                geom,
+                provider: Some((&orphan.name, orphan.mediasize, orphan.mode.to_string())),
+            });
+        }
+    }
+}
+
+/// A compact overview: `Graph::render_tree()` with sizes, without DEV geoms.
+impl std::fmt::Display for Graph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let opts = TreeOptions {
+            sizes: true,
+            modes: false,
+            hide_dev: true,
+        };
+        return f.write_str(&self.render_tree(&opts));
+    }
+}
+
+struct LsblkRow<'a> {
+    name: String,
+    class: &'a str,
+    size: String,
+    type_: String,
+    label: &'a str,
+    mountpoint: String,
+}
+
+impl Graph {
+    /// Renders the providers in the graph as a table in the style of `lsblk(8)`, with NAME, CLASS,
+    /// SIZE, TYPE, and LABEL columns (and MOUNTPOINT, if requested).
+    ///
+    /// Each root geom's providers (typically disks) are listed first, followed by the providers
+    /// built on them (partitions, `geli(8)` providers, ...), with tree lines in the NAME column.
+    /// TYPE is the partition type for partitions, `disk` for disks, and the lowercase class name
+    /// otherwise.  LABEL is the partition's label, if the scheme has them.
+    pub fn render_lsblk(&self, opts: &LsblkOptions) -> String {
+        let mut rows = Vec::new();
+        for (id, _) in self.roots_iter() {
+            for (pid, edge) in self.providers_of(id) {
+                self.lsblk_rows(&mut rows, opts, "", "", pid, edge);
+            }
+        }
+
+        let mut header = vec!["NAME", "CLASS", "SIZE", "TYPE", "LABEL"];
+        if opts.mountpoints {
+            header.push("MOUNTPOINT");
+        }
+        let cells: Vec<Vec<&str>> = std::iter::once(header.clone())
+            .chain(rows.iter().map(|r| {
+                vec![
+                    r.name.as_str(),
+                    r.class,
+                    r.size.as_str(),
+                    r.type_.as_str(),
+                    r.label,
+                    r.mountpoint.as_str(),
+                ]
+            }))
+            .map(|mut v| {
+                v.truncate(header.len());
+                v
+            })
+            .collect();
+        let widths: Vec<usize> = (0..header.len())
+            .map(|i| {
+                cells
+                    .iter()
+                    .map(|r| r[i].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut out = String::new();
+        for row in &cells {
+            let mut line = String::new();
+            for (i, cell) in row.iter().enumerate() {
+                // SIZE is right-aligned, as in lsblk(8).
+                if i == 2 {
+                    line.push_str(&format!("{:>w$} ", cell, w = widths[i]));
+                } else {
+                    // Pad by characters; the tree lines are multibyte.
+                    let pad = widths[i] - cell.chars().count();
+                    line.push_str(&format!("{}{:pad$} ", cell, "", pad = pad));
+                }
+            }
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        return out;
+    }
+
+    fn lsblk_rows<'a>(
+        &'a self,
+        rows: &mut Vec<LsblkRow<'a>>,
+        opts: &LsblkOptions,
+        branch: &str,
+        prefix: &str,
+        pid: u64,
+        edge: &'a Edge,
+    ) {
+        let class = &self.nodes[&edge.provider_geom].class;
+        let (type_, label) = match edge.metadata.as_deref() {
+            Some(EdgeMetadata::DISK(_)) => ("disk".to_owned(), ""),
+            Some(EdgeMetadata::PART(p)) => (p.type_.to_owned(), p.label.as_deref().unwrap_or("")),
+            _ => (class.name().to_lowercase(), ""),
+        };
+        let mountpoint = if opts.mountpoints {
+            self.lsblk_mountpoints(opts, pid, &edge.provider_geom)
+                .join(",")
+        } else {
+            String::new()
+        };
+        rows.push(LsblkRow {
+            name: format!("{}{}", branch, edge.name),
+            class: class.name(),
+            size: edge.size().to_string(),
+            type_,
+            label,
+            mountpoint,
+        });
+
+        let children = self.lsblk_children(opts, pid, &edge.provider_geom);
+        for (i, (cpid, cedge)) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let branch = format!("{}{}", prefix, if last { "└─" } else { "├─" });
+            let prefix = format!("{}{}", prefix, if last { "  " } else { "│ " });
+            self.lsblk_rows(rows, opts, &branch, &prefix, *cpid, cedge);
+        }
+    }
+
+    /// The providers of geoms consuming provider `pid` (of geom `id`).
+    fn lsblk_children(&self, opts: &LsblkOptions, pid: u64, id: &NodeId) -> Vec<(u64, &Edge)> {
+        let mut result: Vec<(u64, &Edge)> = Vec::new();
+        for (eid, edge, consumer) in self.child_geoms_iter(id) {
+            if eid.1 != pid {
+                continue;
+            }
+            for (cpid, cedge) in self.providers_of(&edge.consumer_geom) {
+                if consumer.class == GeomClass::LABEL && !opts.labels {
+                    result.extend(self.lsblk_children(opts, cpid, &edge.consumer_geom));
+                } else {
+                    result.push((cpid, cedge));
+                }
+            }
+        }
+        // A provider reached through several consumers (or labels) is listed once, where first seen.
+        let mut seen = BTreeSet::new();
+        result.retain(|(cpid, _)| seen.insert(*cpid));
+        return result;
+    }
+
+    /// Where provider `pid` (of geom `id`) is mounted, or used as swap.
+    fn lsblk_mountpoints(&self, opts: &LsblkOptions, pid: u64, id: &NodeId) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        for (eid, edge, consumer) in self.child_geoms_iter(id) {
+            if eid.1 != pid {
+                continue;
+            }
+            let found = match consumer.class {
+                GeomClass::DEV => consumer
+                    .mounts
+                    .iter()
+                    .map(|m| m.mountpoint.clone())
+                    .collect(),
+                GeomClass::SWAP => vec!["[SWAP]".to_owned()],
+                GeomClass::LABEL if !opts.labels => self
+                    .providers_of(&edge.consumer_geom)
+                    .into_iter()
+                    .flat_map(|(cpid, _)| self.lsblk_mountpoints(opts, cpid, &edge.consumer_geom))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for mountpoint in found {
+                if !result.contains(&mountpoint) {
+                    result.push(mountpoint);
+                }
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mounts::Mount;
+    use crate::render::{LsblkOptions, TreeOptions};
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn render_tree() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let out = g.render_tree(&Default::default());
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("Geom"));
+        assert!(lines[0].ends_with("Provider"));
+        assert!(lines.iter().all(|l| l.trim_end() == *l));
+
+        // Providers are followed by their consumers, indented.
+        let part = lines
+            .iter()
+            .position(|l| l.starts_with("  nvd0 ") && l.ends_with(" PART  nvd0p1"))
+            .unwrap();
+        assert!(lines[..part]
+            .iter()
+            .rev()
+            .find(|l| !l.starts_with("  "))
+            .unwrap()
+            .ends_with(" DISK  nvd0"));
+        assert!(lines[part + 1].starts_with("    nvd0p1 "));
+
+        let out = g.render_tree(&TreeOptions {
+            sizes: true,
+            modes: true,
+            hide_dev: false,
+        });
+        assert!(out.lines().next().unwrap().ends_with("Size Mode"));
+        assert!(out.lines().any(|l| l.split_whitespace().collect::<Vec<_>>()
+            == ["nvd0", "PART", "nvd0p1", "200M", "r0w0e0"]));
+    }
+
+    #[test]
+    fn render_lsblk() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let mut g = graph::decode_graph(&rawmesh).unwrap();
+        g.attach_mounts(&[Mount {
+            from: "/dev/gpt/freebsd-efi".to_owned(),
+            mountpoint: "/boot/efi".to_owned(),
+            fstype: "msdosfs".to_owned(),
+        }]);
+
+        let out = g.render_lsblk(&LsblkOptions {
+            mountpoints: true,
+            labels: false,
+        });
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0].split_whitespace().collect::<Vec<_>>(),
+            ["NAME", "CLASS", "SIZE", "TYPE", "LABEL", "MOUNTPOINT"]
+        );
+        assert!(out.contains("\nnvd0 "));
+        let efi = [
+            "└─nvd0p1",
+            "PART",
+            "200M",
+            "efi",
+            "freebsd-efi",
+            "/boot/efi",
+        ];
+        assert!(lines
+            .iter()
+            .any(|l| l.split_whitespace().collect::<Vec<_>>() == efi));
+        assert!(!out.contains("gptid/"));
+
+        // Providers reached through several skipped labels are listed once.
+        let mut names: Vec<&str> = lines[1..]
+            .iter()
+            .map(|l| l.split_whitespace().next().unwrap())
+            .map(|n| n.trim_start_matches(|c| "└├│─ ".contains(c)))
+            .collect();
+        let rows = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), rows);
+
+        let out = g.render_lsblk(&LsblkOptions {
+            mountpoints: false,
+            labels: true,
+        });
+        assert!(!out.contains("MOUNTPOINT"));
+        assert!(out.contains("gptid/"));
+    }
+
+    #[test]
+    fn display() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let out = g.to_string();
+        assert!(out.lines().next().unwrap().ends_with(" Size"));
+        assert!(!out.contains(" DEV"));
+        assert!(out.lines().any(
+            |l| l.split_whitespace().collect::<Vec<_>>() == ["nvd0", "PART", "nvd0p1", "200M"]
+        ));
+
+        let (id, _) = g
+            .find_by_class_name(&graph::GeomClass::PART, "nvd0")
+            .unwrap();
+        assert_eq!(g[*id].to_string(), "PART nvd0 (GPT)");
+        let (eid, _) = g.edges.iter().find(|(_, e)| e.name == "nvd0p1").unwrap();
+        assert_eq!(g[*eid].to_string(), "nvd0p1 200M r0w0e0 efi");
+    }
+}
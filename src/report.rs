@@ -0,0 +1,508 @@
+//! Combined storage reports.
+//!
+//! `Graph::full_report()` runs this library's analyses of a graph (integrity and device path
+//! lints, health checks, capacity, encryption coverage, and a disk inventory) and gathers their
+//! results in one `SystemStorageReport`, e.g., as the backend of a "storage health" page.  With
+//! the `serde` feature, the report can be serialized (e.g., to JSON).
+use crate::advisor::{DeviceUse, PathAdvice};
+use crate::graph::{EdgeMetadata, Geom, GeomClass, GeomMetadata, Graph, NodeId};
+use crate::subgraph::{Capacity, Tree};
+use crate::verify::IntegrityFinding;
+use std::collections::BTreeSet;
+
+/// The `State`s reported by RAID-like classes (MIRROR, RAID3, RAID, MULTIPATH, CONCAT, ...) when
+/// all is well.  Any other state is reported as `HealthIssue::Degraded`.
+pub(crate) const HEALTHY_STATES: &[&str] = &["ACTIVE", "COMPLETE", "OPTIMAL", "UP"];
+
+/// Which sections `Graph::full_report()` fills in.  The default is all of them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReportOptions {
+    /// Fill in `SystemStorageReport::lint`
+    pub lint: bool,
+    /// Fill in `SystemStorageReport::health`
+    pub health: bool,
+    /// Fill in `SystemStorageReport::capacity`
+    pub capacity: bool,
+    /// Fill in `SystemStorageReport::encryption`
+    pub encryption: bool,
+    /// Fill in `SystemStorageReport::inventory`
+    pub inventory: bool,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        return ReportOptions {
+            lint: true,
+            health: true,
+            capacity: true,
+            encryption: true,
+            inventory: true,
+        };
+    }
+}
+
+/// A problem with how the graph is put together or used; see `SystemStorageReport::lint`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum LintFinding {
+    /// An inconsistency in the graph itself; see `Graph::verify()`
+    Integrity(IntegrityFinding),
+    /// A filesystem, swap device, or ZFS vdev using a name subject to renumbering; see
+    /// `Graph::device_path_advice()`
+    UnstablePath(PathAdvice),
+}
+
+/// A sign of failing or degraded storage; see `SystemStorageReport::health`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum HealthIssue {
+    /// A partition table the kernel marks `CORRUPT`, e.g., one with a damaged GPT header; see
+    /// `Graph::corrupt_part_tables()`
+    CorruptPartTable { geom: NodeId, name: String },
+    /// A geom of a RAID-like class whose `State` is not a healthy one, e.g., a `DEGRADED` MIRROR
+    Degraded {
+        geom: NodeId,
+        class: String,
+        name: String,
+        state: String,
+    },
+    /// A suspended ELI geom, whose I/O is blocked until it is resumed
+    Suspended { geom: NodeId, name: String },
+    /// A provider being torn down, e.g., after its disk was detached
+    Withering { name: String },
+}
+
+/// The capacity of one tree of the graph; see `SystemStorageReport::capacity`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct TreeCapacity {
+    /// The names of the tree's roots, e.g., `["ada0"]`
+    pub roots: Vec<String>,
+    /// See `Tree::capacity()`
+    pub capacity: Capacity,
+}
+
+/// The ELI layer under some storage; see `EncryptionStatus::eli`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct EliLayer {
+    /// The ELI provider name, e.g., `"ada0p2.eli"`
+    pub name: String,
+    /// E.g., `"AES-XTS"`, if the ELI geom reported its config
+    pub encryption_algorithm: Option<String>,
+    /// The data encryption key length, in bits, if the ELI geom reported its config
+    pub key_length: Option<u64>,
+    /// The data authentication algorithm, if authentication is enabled
+    pub authentication_algorithm: Option<String>,
+}
+
+/// Whether a filesystem, swap device, or ZFS vdev is encrypted; see
+/// `SystemStorageReport::encryption`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct EncryptionStatus {
+    /// The geom using the storage: a VFS, SWAP, or ZFS::VDEV geom
+    pub geom: NodeId,
+    /// The provider it uses, e.g., `"ada0p2.eli"`
+    pub provider: String,
+    /// What the provider is used for
+    pub usage: DeviceUse,
+    /// The nearest ELI layer beneath the storage, or `None` if it is not encrypted
+    pub eli: Option<EliLayer>,
+}
+
+/// A provider of a root geom, typically a disk; see `SystemStorageReport::inventory`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DiskInventory {
+    /// The provider name, e.g., `"ada0"`
+    pub name: String,
+    /// The class of the root geom, e.g., `"DISK"` or `"MD"`
+    pub class: String,
+    /// The size, in bytes
+    pub mediasize: u64,
+    /// The sector size, in bytes
+    pub sectorsize: u64,
+    /// The disk's description (usually its model), for DISKs
+    pub descr: Option<String>,
+    /// The disk's serial number, for DISKs
+    pub ident: Option<String>,
+    /// The disk's rotation rate in RPM, for DISKs; zero for solid-state (or unknown)
+    pub rotationrate: Option<u64>,
+    /// The scheme of the partition table on the provider, if any, e.g., `"GPT"`
+    pub scheme: Option<String>,
+}
+
+/// The results of `Graph::full_report()`.  Sections not requested in the `ReportOptions` are
+/// empty.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SystemStorageReport {
+    /// Integrity problems and renumbering-prone device paths
+    pub lint: Vec<LintFinding>,
+    /// Corrupt partition tables, degraded volumes, suspended ELI geoms, and withering providers
+    pub health: Vec<HealthIssue>,
+    /// The capacity of each tree (see `Graph::trees()`)
+    pub capacity: Vec<TreeCapacity>,
+    /// Whether each filesystem, swap device, and ZFS vdev is encrypted
+    pub encryption: Vec<EncryptionStatus>,
+    /// The providers of the roots, e.g., the disks
+    pub inventory: Vec<DiskInventory>,
+}
+
+impl Graph {
+    /// Runs the analyses selected in `options` and gathers their results in one report.
+    ///
+    /// The capacity, the inventory, and the device path lints are computed for each root or tree
+    /// with `Graph::map_roots()`, so with the `rayon` feature they run in parallel.  The report is
+    /// the same either way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// fn myfoo(graph: &geom::Graph) {
+    ///     let report = graph.full_report(&Default::default());
+    ///     for issue in &report.health {
+    ///         println!("{:?}", issue);
+    ///     }
+    /// }
+    /// ```
+    pub fn full_report(&self, options: &ReportOptions) -> SystemStorageReport {
+        let mut report = SystemStorageReport::default();
+        if options.lint {
+            report.lint = self.lint();
+        }
+        if options.health {
+            report.health = self.health();
+        }
+        if options.capacity {
+            report.capacity = self.map_trees(|tree| TreeCapacity {
+                roots: tree.roots().map(|(_, g)| g.name.to_owned()).collect(),
+                capacity: tree.capacity(),
+            });
+        }
+        if options.encryption {
+            report.encryption = self.encryption();
+        }
+        if options.inventory {
+            report.inventory = self.inventory();
+        }
+        return report;
+    }
+
+    /// Runs `f` on each tree (see `Graph::trees()`), through `Graph::map_roots()`, and returns
+    /// the results in the order of `Graph::trees()`.
+    fn map_trees<R, F>(&self, f: F) -> Vec<R>
+    where
+        R: Send,
+        F: Fn(&Tree) -> R + Send + Sync,
+    {
+        let mut results: Vec<(NodeId, R)> = self
+            .map_roots(|id, _| {
+                // A tree with several roots is analyzed once, for the first.
+                let tree = self.tree(id);
+                if tree.roots().next().map(|(first, _)| first) != Some(id) {
+                    return None;
+                }
+                let lowest = *tree.members().next()?.0;
+                return Some((lowest, f(&tree)));
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        results.sort_by_key(|(lowest, _)| *lowest);
+        return results.into_iter().map(|(_, r)| r).collect();
+    }
+
+    fn lint(&self) -> Vec<LintFinding> {
+        let mut advice: Vec<PathAdvice> = self
+            .map_trees(|tree| {
+                return tree
+                    .members()
+                    .flat_map(|(id, _)| self.child_edges_iter(id))
+                    .filter_map(|(eid, edge)| self.edge_path_advice(eid, edge))
+                    .collect::<Vec<_>>();
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        advice.sort_by_key(|a| a.edge);
+        return self
+            .verify()
+            .into_iter()
+            .map(LintFinding::Integrity)
+            .chain(advice.into_iter().map(LintFinding::UnstablePath))
+            .collect();
+    }
+
+    fn health(&self) -> Vec<HealthIssue> {
+        let mut result: Vec<HealthIssue> = self
+            .corrupt_part_tables()
+            .into_iter()
+            .map(|(id, g)| HealthIssue::CorruptPartTable {
+                geom: *id,
+                name: g.name.to_owned(),
+            })
+            .collect();
+        for (id, geom) in &self.nodes {
+            if let Some(GeomMetadata::ELI {
+                state: Some(state), ..
+            }) = geom.metadata.as_deref()
+            {
+                if state == "SUSPENDED" {
+                    result.push(HealthIssue::Suspended {
+                        geom: *id,
+                        name: geom.name.to_owned(),
+                    });
+                }
+            } else if let Some(state) = geom.unknown_config.get("State") {
+                if !HEALTHY_STATES.contains(&state.as_str()) {
+                    result.push(HealthIssue::Degraded {
+                        geom: *id,
+                        class: geom.class.name().to_owned(),
+                        name: geom.name.to_owned(),
+                        state: state.to_owned(),
+                    });
+                }
+            }
+        }
+        let withering: BTreeSet<&str> = self
+            .edges
+            .values()
+            .filter(|e| e.withered)
+            .map(|e| e.name.as_str())
+            .chain(
+                self.orphans
+                    .values()
+                    .filter(|o| o.withered)
+                    .map(|o| o.name.as_str()),
+            )
+            .collect();
+        result.extend(withering.into_iter().map(|name| HealthIssue::Withering {
+            name: name.to_owned(),
+        }));
+        return result;
+    }
+
+    fn encryption(&self) -> Vec<EncryptionStatus> {
+        let mut result = Vec::new();
+        for (id, geom) in &self.nodes {
+            let usage = match (&geom.class, geom.metadata.as_deref()) {
+                (GeomClass::VFS, Some(GeomMetadata::VFS { fstype, .. })) => {
+                    DeviceUse::Filesystem(fstype.to_owned())
+                }
+                (GeomClass::SWAP, _) => DeviceUse::Swap,
+                (GeomClass::ZFSVDEV, _) => DeviceUse::ZfsVdev,
+                _ => continue,
+            };
+            for (eid, edge, provider) in self.parent_geoms_iter(id) {
+                let eli = std::iter::once((eid, edge, provider))
+                    .chain(self.ancestors_iter(&edge.provider_geom))
+                    .find(|(_, _, g)| g.class == GeomClass::ELI)
+                    .map(|(_, e, g)| match g.metadata.as_deref() {
+                        Some(GeomMetadata::ELI {
+                            encryption_algorithm,
+                            key_length,
+                            authentication_algorithm,
+                            ..
+                        }) => EliLayer {
+                            name: e.name.to_owned(),
+                            encryption_algorithm: Some(encryption_algorithm.to_owned()),
+                            key_length: Some(*key_length),
+                            authentication_algorithm: authentication_algorithm.to_owned(),
+                        },
+                        _ => EliLayer {
+                            name: e.name.to_owned(),
+                            encryption_algorithm: None,
+                            key_length: None,
+                            authentication_algorithm: None,
+                        },
+                    });
+                result.push(EncryptionStatus {
+                    geom: *id,
+                    provider: edge.name.to_owned(),
+                    usage: usage.clone(),
+                    eli,
+                });
+            }
+        }
+        return result;
+    }
+
+    fn inventory(&self) -> Vec<DiskInventory> {
+        return self
+            .map_roots(|id, root| self.root_inventory(id, root))
+            .into_iter()
+            .flatten()
+            .collect();
+    }
+
+    fn root_inventory(&self, id: &NodeId, root: &Geom) -> Vec<DiskInventory> {
+        let mut result = Vec::new();
+        // Several consumers may share a provider; list it once.
+        let mut seen = BTreeSet::new();
+        for (eid, edge) in self.child_edges_iter(id) {
+            if !seen.insert(eid.1) {
+                continue;
+            }
+            let disk = edge.metadata.as_deref().and_then(EdgeMetadata::as_disk);
+            let scheme = self
+                .child_geoms_iter(id)
+                .filter(|(peid, _, _)| peid.1 == eid.1)
+                .find_map(|(_, _, g)| match g.metadata.as_deref() {
+                    Some(GeomMetadata::PART(m)) => Some(m.scheme.as_ref().to_owned()),
+                    _ => None,
+                });
+            result.push(DiskInventory {
+                name: edge.name.to_owned(),
+                class: root.class.name().to_owned(),
+                mediasize: edge.mediasize,
+                sectorsize: edge.sectorsize,
+                descr: disk.map(|d| d.descr.to_owned()),
+                ident: disk.map(|d| d.ident.to_owned()),
+                rotationrate: disk.map(|d| d.rotationrate),
+                scheme,
+            });
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{HealthIssue, LintFinding, ReportOptions, TreeCapacity};
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn sample() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let report = g.full_report(&Default::default());
+        assert!(report.lint.is_empty());
+        assert!(report.health.is_empty());
+        assert_eq!(report.capacity.len(), g.trees().count());
+        let disks: Vec<_> = report
+            .inventory
+            .iter()
+            .filter(|d| d.class == "DISK")
+            .map(|d| (d.name.as_str(), d.scheme.as_deref()))
+            .collect();
+        assert!(disks.contains(&("nvd0", Some("GPT"))));
+        assert!(report
+            .inventory
+            .iter()
+            .all(|d| (d.class == "DISK") == d.descr.is_some()));
+        // The sample has filesystems and swap, none of them encrypted.
+        assert!(!report.encryption.is_empty());
+        assert!(report.encryption.iter().all(|e| e.eli.is_none()));
+
+        let none = ReportOptions {
+            lint: false,
+            health: false,
+            capacity: false,
+            encryption: false,
+            inventory: false,
+        };
+        assert_eq!(g.full_report(&none), Default::default());
+    }
+
+    #[test]
+    fn parallel() {
+        // A mirror joining two disks into one tree.
+        const MIRROR_XML: &str = r#"<class id="0x10">
+            <name>MIRROR</name>
+            <geom id="0x11">
+              <class ref="0x10"/>
+              <name>gm0</name>
+              <rank>3</rank>
+              <consumer id="0x12">
+                <geom ref="0x11"/>
+                <provider ref="0xfffff8001b62a900"/>
+                <mode>r0w0e0</mode>
+              </consumer>
+              <consumer id="0x13">
+                <geom ref="0x11"/>
+                <provider ref="0xfffff804947b5a00"/>
+                <mode>r0w0e0</mode>
+              </consumer>
+            </geom>
+          </class>
+        </mesh>"#;
+        let xml = SAMPLE_XML.replace("</mesh>", MIRROR_XML);
+        let mut g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        // Mount a filesystem by an unstable name.
+        let label = g
+            .edges
+            .values()
+            .find(|e| {
+                g.nodes[&e.consumer_geom].class == graph::GeomClass::VFS
+                    && g.nodes[&e.provider_geom].class == graph::GeomClass::LABEL
+            })
+            .unwrap()
+            .provider_geom;
+        g.nodes.get_mut(&label).unwrap().class = graph::GeomClass::DISK;
+
+        let report = g.full_report(&Default::default());
+        let capacity: Vec<_> = g
+            .trees()
+            .map(|tree| TreeCapacity {
+                roots: tree.roots().map(|(_, g)| g.name.to_owned()).collect(),
+                capacity: tree.capacity(),
+            })
+            .collect();
+        assert_eq!(report.capacity, capacity);
+        assert!(report.capacity.iter().any(|c| c.roots.len() == 2));
+        let lint: Vec<_> = g
+            .verify()
+            .into_iter()
+            .map(LintFinding::Integrity)
+            .chain(
+                g.device_path_advice()
+                    .into_iter()
+                    .map(LintFinding::UnstablePath),
+            )
+            .collect();
+        assert_eq!(report.lint, lint);
+        assert!(!report.lint.is_empty());
+        let inventory: Vec<_> = g
+            .roots_iter()
+            .flat_map(|(id, root)| g.root_inventory(id, root))
+            .collect();
+        assert_eq!(report.inventory, inventory);
+    }
+
+    #[test]
+    fn health() {
+        // A degraded mirror of two partitions, and a corrupt partition table.
+        const MIRROR_XML: &str = r#"<class id="0x10">
+            <name>MIRROR</name>
+            <geom id="0x11">
+              <class ref="0x10"/>
+              <name>gm0</name>
+              <rank>3</rank>
+              <config>
+                <State>DEGRADED</State>
+              </config>
+            </geom>
+          </class>
+        </mesh>"#;
+        let xml = SAMPLE_XML
+            .replacen("<state>OK</state>", "<state>CORRUPT</state>", 1)
+            .replace("</mesh>", MIRROR_XML);
+        let g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let health = g.full_report(&Default::default()).health;
+        assert_eq!(health.len(), 2);
+        assert!(matches!(&health[0], HealthIssue::CorruptPartTable { .. }));
+        assert_eq!(
+            health[1],
+            HealthIssue::Degraded {
+                geom: 0x11,
+                class: "MIRROR".to_owned(),
+                name: "gm0".to_owned(),
+                state: "DEGRADED".to_owned(),
+            }
+        );
+    }
+}
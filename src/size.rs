@@ -0,0 +1,171 @@
+//! Byte sizes.
+//!
+//! `ByteSize` formats a byte count the way `geom(8)` and `gpart(8)` do (`humanize_number(3)` with
+//! binary prefixes), e.g., `"932G"` or `"1.5M"`, and converts it to other units.  `Edge::size()`
+//! and friends wrap the sizes in the graph.
+use crate::graph::{Edge, OrphanProvider, PartInfo};
+
+/// A binary unit of size.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum SizeUnit {
+    B,
+    KiB,
+    MiB,
+    GiB,
+    TiB,
+    PiB,
+    EiB,
+}
+
+impl SizeUnit {
+    const ALL: [SizeUnit; 7] = [
+        Self::B,
+        Self::KiB,
+        Self::MiB,
+        Self::GiB,
+        Self::TiB,
+        Self::PiB,
+        Self::EiB,
+    ];
+
+    /// The number of bytes in one of this unit.
+    pub fn bytes(self) -> u64 {
+        return 1 << (10 * self as u32);
+    }
+
+    /// The `humanize_number(3)` suffix, e.g., `"G"` for `SizeUnit::GiB`.
+    pub fn suffix(self) -> &'static str {
+        return ["B", "K", "M", "G", "T", "P", "E"][self as usize];
+    }
+}
+
+/// A size in bytes.
+///
+/// `Display` abbreviates it to at most three significant digits and a suffix, like
+/// `humanize_number(3)`: `"512B"`, `"1.5M"`, `"466G"`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    /// The size, in bytes
+    pub fn bytes(self) -> u64 {
+        return self.0;
+    }
+
+    /// The size in `unit`s, e.g., `931.5` GiB.
+    pub fn in_unit(self, unit: SizeUnit) -> f64 {
+        return self.0 as f64 / unit.bytes() as f64;
+    }
+
+    /// The largest unit in which the size is at least one, or `SizeUnit::B` for zero.
+    pub fn unit(self) -> SizeUnit {
+        return *SizeUnit::ALL
+            .iter()
+            .rev()
+            .find(|u| self.0 >= u.bytes())
+            .unwrap_or(&SizeUnit::B);
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        return Self(bytes);
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut unit = self.unit();
+        let mut value = self.in_unit(unit);
+        // Rounding may carry into the next unit, e.g., 1023.9K.
+        if unit != SizeUnit::EiB && value.round() >= 1024.0 {
+            unit = SizeUnit::ALL[unit as usize + 1];
+            value = self.in_unit(unit);
+        }
+        let s = if unit == SizeUnit::B || value >= 9.95 {
+            format!("{:.0}{}", value, unit.suffix())
+        } else {
+            format!("{:.1}{}", value, unit.suffix())
+        };
+        // Honor width and alignment, e.g., `{:>6}`.
+        return f.pad(&s);
+    }
+}
+
+impl Edge {
+    /// The size of the provider, `mediasize`, as a `ByteSize`.
+    pub fn size(&self) -> ByteSize {
+        return ByteSize(self.mediasize);
+    }
+
+    /// The size of the provider, in `sectorsize` sectors.
+    pub fn sectors(&self) -> u64 {
+        return self.mediasize.checked_div(self.sectorsize).unwrap_or(0);
+    }
+
+    /// The size of the provider in `unit`s.
+    pub fn size_in(&self, unit: SizeUnit) -> f64 {
+        return self.size().in_unit(unit);
+    }
+}
+
+impl OrphanProvider {
+    /// The size of the provider, `mediasize`, as a `ByteSize`.
+    pub fn size(&self) -> ByteSize {
+        return ByteSize(self.mediasize);
+    }
+}
+
+impl PartInfo {
+    /// The size of the partition entry, `length`, as a `ByteSize`.
+    pub fn size(&self) -> ByteSize {
+        return ByteSize(self.length);
+    }
+
+    /// The size of the partition entry, in sectors of the partitioned provider.
+    pub fn sectors(&self) -> u64 {
+        return self.end - self.start + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph;
+    use crate::size::{ByteSize, SizeUnit};
+
+    #[test]
+    fn display() {
+        assert_eq!(ByteSize(0).to_string(), "0B");
+        assert_eq!(ByteSize(512).to_string(), "512B");
+        assert_eq!(ByteSize(1536 * 1024).to_string(), "1.5M");
+        assert_eq!(ByteSize(209715200).to_string(), "200M");
+        assert_eq!(ByteSize(500107862016).to_string(), "466G");
+        assert_eq!(ByteSize(8124087808).to_string(), "7.6G");
+        assert_eq!(ByteSize(1024 * 1024 - 1).to_string(), "1.0M");
+        assert_eq!(ByteSize(u64::MAX).to_string(), "16E");
+        assert_eq!(format!("{:>6}|", ByteSize(1024)), "  1.0K|");
+    }
+
+    #[test]
+    fn units() {
+        let s = ByteSize(1000204886016);
+        assert_eq!(s.unit(), SizeUnit::GiB);
+        assert!((s.in_unit(SizeUnit::GiB) - 931.51).abs() < 0.01);
+        assert_eq!(SizeUnit::MiB.bytes(), 1 << 20);
+        assert_eq!(ByteSize(0).unit(), SizeUnit::B);
+    }
+
+    #[test]
+    fn edges() {
+        let rawmesh = crate::raw::parse_xml(include_str!("test/fullsample.xml")).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (_, edge) = g.edges.iter().find(|(_, e)| e.name == "nvd0p1").unwrap();
+        assert_eq!(edge.size().to_string(), "200M");
+        assert_eq!(edge.sectors(), 409600);
+        assert_eq!(edge.size_in(SizeUnit::MiB), 200.0);
+        let part = edge.metadata.as_deref().unwrap().as_part().unwrap();
+        assert_eq!(part.sectors(), edge.sectors());
+        assert_eq!(part.size(), edge.size());
+    }
+}
@@ -0,0 +1,168 @@
+//! Binary `Graph` snapshots.
+//!
+//! `Graph::save()` writes a graph to a file, and `Graph::load()` reads it back, e.g., so a
+//! long-running monitor can pick up where it left off after a restart.  A snapshot is an 8-byte
+//! magic number, a little-endian `u32` format version, and the graph in CBOR (see the `serde`
+//! feature for what is included).
+use crate::{Error, Graph};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const MAGIC: &[u8; 8] = b"GEOMSNAP";
+
+/// The snapshot format version written by this library.  Snapshots of other versions are
+/// rejected by `Graph::load()`.
+pub const VERSION: u32 = 1;
+
+/// Distinguishes the temporary files of concurrent `Graph::save()`s within a process.
+static SAVES: AtomicUsize = AtomicUsize::new(0);
+
+impl Graph {
+    /// Writes a snapshot of the graph to `path`.
+    ///
+    /// The snapshot is written to a temporary file next to `path`, named uniquely so concurrent
+    /// saves don't collide, then renamed over it, so a crash never leaves a truncated snapshot
+    /// behind.  The temporary file is removed if the save fails.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            SAVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        let result = (|| -> Result<(), Error> {
+            let mut w = BufWriter::new(file);
+            self.write_snapshot(&mut w)?;
+            w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            std::fs::rename(&tmp, path)?;
+            return Ok(());
+        })();
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        return result;
+    }
+
+    /// Reads a snapshot written by `Graph::save()`.
+    ///
+    /// The graph is checked with `Graph::verify()` as it is loaded, so a damaged or hand-edited
+    /// snapshot is rejected with `Error::Snapshot` rather than breaking traversals later.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Graph, Error> {
+        return Graph::read_snapshot(BufReader::new(File::open(path)?));
+    }
+
+    /// Like `Graph::save()`, but to any writer.
+    pub fn write_snapshot<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        ciborium::ser::into_writer(self, w).map_err(|e| Error::Snapshot(e.to_string()))?;
+        return Ok(());
+    }
+
+    /// Like `Graph::load()`, but from any reader.
+    pub fn read_snapshot<R: Read>(mut r: R) -> Result<Graph, Error> {
+        let mut header = [0u8; 12];
+        r.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(Error::Snapshot("not a GEOM snapshot".to_owned()));
+        }
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&header[8..]);
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(Error::Snapshot(format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+        // Deserializing a `Graph` verifies it.
+        let graph: Graph =
+            ciborium::de::from_reader(&mut r).map_err(|e| Error::Snapshot(e.to_string()))?;
+        if r.read(&mut [0u8])? != 0 {
+            return Err(Error::Snapshot("trailing data after graph".to_owned()));
+        }
+        return Ok(graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, raw, Error};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn round_trip() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let mut buf = Vec::new();
+        g.write_snapshot(&mut buf).unwrap();
+        assert!(buf.starts_with(b"GEOMSNAP\x01\x00\x00\x00"));
+        let g2 = graph::Graph::read_snapshot(&buf[..]).unwrap();
+        assert_eq!(g2.nodes.len(), g.nodes.len());
+        assert_eq!(g2.edges.len(), g.edges.len());
+        assert_eq!(g2.names, g.names);
+        assert!(g2.verify().is_empty());
+
+        let path = std::env::temp_dir().join(format!("geom-snapshot-{}", std::process::id()));
+        g.save(&path).unwrap();
+        let g3 = graph::Graph::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(g3.part_uuids, g.part_uuids);
+    }
+
+    #[test]
+    fn bad_header() {
+        let err = graph::Graph::read_snapshot(&b"GEOMSNAP\x02\x00\x00\x00"[..]).unwrap_err();
+        assert!(matches!(err, Error::Snapshot(_)));
+        let err = graph::Graph::read_snapshot(&b"<mesh></mesh>"[..]).unwrap_err();
+        assert!(matches!(err, Error::Snapshot(_)));
+        let err = graph::Graph::read_snapshot(&b"GEOM"[..]).unwrap_err();
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+    #[test]
+    fn validation() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let mut g = graph::decode_graph(&rawmesh).unwrap();
+
+        let mut buf = Vec::new();
+        g.write_snapshot(&mut buf).unwrap();
+        buf.push(0);
+        let err = graph::Graph::read_snapshot(&buf[..]).unwrap_err();
+        assert!(matches!(err, Error::Snapshot(_)));
+
+        let id = *g.inedges.keys().next().unwrap();
+        g.nodes.remove(&id);
+        let mut buf = Vec::new();
+        g.write_snapshot(&mut buf).unwrap();
+        let err = graph::Graph::read_snapshot(&buf[..]).unwrap_err();
+        assert!(matches!(err, Error::Snapshot(m) if m.contains("inconsistent")));
+    }
+
+    #[test]
+    fn failed_save() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        // Renaming over a non-empty directory fails after the temporary file is written.
+        let dir = std::env::temp_dir().join(format!("geom-snapshot-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("busy")).unwrap();
+        assert!(g.save(&dir).is_err());
+        let parent = dir.parent().unwrap();
+        let prefix = dir.file_name().unwrap().to_str().unwrap().to_owned() + ".";
+        let leftovers = std::fs::read_dir(parent)
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                return name.to_str().unwrap().starts_with(&prefix);
+            })
+            .count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(leftovers, 0);
+    }
+}
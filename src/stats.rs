@@ -0,0 +1,1005 @@
+//! Per-device I/O statistics from `devstat(9)`.
+//!
+//! Disk drivers (`ada`, `da`, `nvd`, `md`, ...) count the transfers of each device they attach
+//! in a `struct devstat`, which the kernel exports, all at once, as the `kern.devstat.all`
+//! sysctl; this is what `iostat(8)` reads.  `get_devstat()` decodes it into `DeviceStats`, and
+//! `Graph::attach_devstat()` records each device's statistics on its provider's edges (e.g., on
+//! `ada0`), so the counters can be read alongside the topology.  GEOM also counts the I/O to
+//! each of its providers (e.g., `mirror/gm0`) in unnamed entries of the same sysctl; see
+//! `get_geom_devstat()` and `Graph::attach_geom_devstat()`.
+//!
+//! The counters are totals since the device attached; `StatsSampler` computes rates from
+//! successive snapshots.
+use crate::graph::{GeomClass, Graph, NodeId};
+use crate::Error;
+use std::collections::BTreeMap;
+use std::os::raw::{c_int, c_long, c_uint, c_void};
+use std::time::{Duration, Instant};
+
+/// `DEVSTAT_VERSION` from `<sys/devicestat.h>`; see the `kern.devstat.version` sysctl.
+const DEVSTAT_VERSION: i32 = 6;
+
+/// `DEVSTAT_NAME_LEN`
+const DEVSTAT_NAME_LEN: usize = 16;
+
+/// `devstat_trans_flags`: the kinds of transfer counted, indexing `bytes`, `operations`, and
+/// `duration`.
+const DEVSTAT_NO_DATA: usize = 0;
+const DEVSTAT_READ: usize = 1;
+const DEVSTAT_WRITE: usize = 2;
+const DEVSTAT_FREE: usize = 3;
+const DEVSTAT_N_TRANS_FLAGS: usize = 4;
+
+/// `time_t`, which is 32 bits only on i386.
+#[cfg(target_arch = "x86")]
+type TimeT = i32;
+#[cfg(not(target_arch = "x86"))]
+type TimeT = i64;
+
+/// `struct bintime`: seconds, and a binary fraction of a second.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Bintime {
+    sec: TimeT,
+    frac: u64,
+}
+
+impl Bintime {
+    fn duration(&self) -> Duration {
+        let nanos = ((self.frac >> 32) * 1_000_000_000) >> 32;
+        return Duration::new(self.sec.max(0) as u64, nanos as u32);
+    }
+}
+
+/// `struct devstat`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Devstat {
+    sequence0: c_uint,
+    allocated: c_int,
+    start_count: c_uint,
+    end_count: c_uint,
+    busy_from: Bintime,
+    dev_links: *const c_void,
+    device_number: u32,
+    device_name: [u8; DEVSTAT_NAME_LEN],
+    unit_number: c_int,
+    bytes: [u64; DEVSTAT_N_TRANS_FLAGS],
+    operations: [u64; DEVSTAT_N_TRANS_FLAGS],
+    duration: [Bintime; DEVSTAT_N_TRANS_FLAGS],
+    busy_time: Bintime,
+    creation_time: Bintime,
+    block_size: u32,
+    tag_types: [u64; 3],
+    flags: c_int,
+    device_type: c_int,
+    priority: c_int,
+    id: *const c_void,
+    sequence1: c_uint,
+}
+
+/// The counters of one kind of transfer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct TransferStats {
+    /// Bytes transferred
+    pub bytes: u64,
+    /// Completed operations
+    pub operations: u64,
+    /// The total time the operations took, from submission to completion
+    pub duration: Duration,
+}
+
+impl TransferStats {
+    /// The average time an operation took, if any completed.
+    pub fn latency(&self) -> Option<Duration> {
+        if self.operations == 0 {
+            return None;
+        }
+        return Some(self.duration.div_f64(self.operations as f64));
+    }
+}
+
+/// Average operation latencies, by kind of operation.  Each is `None` if no operations of that
+/// kind completed.
+///
+/// `devstat(9)` accumulates only the total time of each kind of operation, not a histogram, so
+/// these are means: a few very slow operations among many fast ones barely move them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Latency {
+    /// Reads
+    pub read: Option<Duration>,
+    /// Writes
+    pub write: Option<Duration>,
+    /// Deletes (TRIM)
+    pub free: Option<Duration>,
+    /// Operations without data, which are almost all cache flushes
+    pub flush: Option<Duration>,
+    /// Operations of every kind
+    pub all: Option<Duration>,
+}
+
+impl Latency {
+    fn of(
+        read: &TransferStats,
+        write: &TransferStats,
+        free: &TransferStats,
+        other: &TransferStats,
+    ) -> Latency {
+        let all = [read, write, free, other];
+        let total = TransferStats {
+            bytes: 0,
+            operations: all.iter().map(|t| t.operations).sum(),
+            duration: all.iter().map(|t| t.duration).sum(),
+        };
+        return Latency {
+            read: read.latency(),
+            write: write.latency(),
+            free: free.latency(),
+            flush: other.latency(),
+            all: total.latency(),
+        };
+    }
+
+    /// The slowest of the read, write, delete, and flush latencies.
+    pub fn max(&self) -> Option<Duration> {
+        return [self.read, self.write, self.free, self.flush]
+            .iter()
+            .flatten()
+            .max()
+            .copied();
+    }
+}
+
+/// The `devstat(9)` counters of a device, totals since it attached.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DeviceStats {
+    /// The device name, e.g., `"ada0"`, which is also the name of its GEOM provider
+    pub name: String,
+    /// Reads
+    pub read: TransferStats,
+    /// Writes
+    pub write: TransferStats,
+    /// Deletes (TRIM, `BIO_DELETE`)
+    pub free: TransferStats,
+    /// Operations without data, e.g., cache flushes (`BIO_FLUSH`)
+    pub other: TransferStats,
+    /// How long the device has had at least one operation outstanding
+    pub busy_time: Duration,
+    /// Operations started but not yet completed
+    pub queue_length: u32,
+    /// The device's block size, in bytes (zero if unknown)
+    pub block_size: u32,
+    /// When the device attached, as time since boot
+    pub creation_time: Duration,
+}
+
+impl DeviceStats {
+    fn from_devstat(ds: &Devstat) -> Option<DeviceStats> {
+        // Entries GEOM keeps for its own providers have no name or unit.
+        if ds.allocated == 0 || ds.unit_number < 0 {
+            return None;
+        }
+        let len = ds
+            .device_name
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(DEVSTAT_NAME_LEN);
+        let name = std::str::from_utf8(&ds.device_name[..len]).ok()?;
+        if name.is_empty() {
+            return None;
+        }
+        return Some(DeviceStats::named(
+            ds,
+            format!("{}{}", name, ds.unit_number),
+        ));
+    }
+
+    /// Decodes an entry GEOM keeps for one of its providers (or consumers), which is identified
+    /// by the address of the `struct g_provider` (or `g_consumer`), as is the provider in the
+    /// XML.  The result is unnamed.
+    fn from_geom_devstat(ds: &Devstat) -> Option<(u64, DeviceStats)> {
+        if ds.allocated == 0 || ds.unit_number >= 0 || ds.id.is_null() {
+            return None;
+        }
+        return Some((ds.id as usize as u64, DeviceStats::named(ds, String::new())));
+    }
+
+    fn named(ds: &Devstat, name: String) -> DeviceStats {
+        let transfer = |i: usize| TransferStats {
+            bytes: ds.bytes[i],
+            operations: ds.operations[i],
+            duration: ds.duration[i].duration(),
+        };
+        return DeviceStats {
+            name,
+            read: transfer(DEVSTAT_READ),
+            write: transfer(DEVSTAT_WRITE),
+            free: transfer(DEVSTAT_FREE),
+            other: transfer(DEVSTAT_NO_DATA),
+            busy_time: ds.busy_time.duration(),
+            queue_length: ds.start_count.wrapping_sub(ds.end_count),
+            block_size: ds.block_size,
+            creation_time: ds.creation_time.duration(),
+        };
+    }
+
+    /// The average operation latencies since the device attached.  See `DeviceRates::latency`
+    /// for recent latencies.
+    pub fn latency(&self) -> Latency {
+        return Latency::of(&self.read, &self.write, &self.free, &self.other);
+    }
+
+    /// Adds the counters of `other`, another device in the same volume, into these.  Transfers
+    /// and queue lengths are summed; `busy_time` becomes that of the busier device, and
+    /// `creation_time` that of the newer one.
+    fn accumulate(&mut self, other: &DeviceStats) {
+        for (t, o) in [
+            (&mut self.read, &other.read),
+            (&mut self.write, &other.write),
+            (&mut self.free, &other.free),
+            (&mut self.other, &other.other),
+        ] {
+            t.bytes = t.bytes.wrapping_add(o.bytes);
+            t.operations = t.operations.wrapping_add(o.operations);
+            t.duration += o.duration;
+        }
+        self.busy_time = self.busy_time.max(other.busy_time);
+        self.queue_length = self.queue_length.wrapping_add(other.queue_length);
+        self.block_size = self.block_size.max(other.block_size);
+        self.creation_time = self.creation_time.max(other.creation_time);
+    }
+
+    /// All completed operations, of any kind.
+    pub fn operations(&self) -> u64 {
+        return self.read.operations
+            + self.write.operations
+            + self.free.operations
+            + self.other.operations;
+    }
+}
+
+/// Decodes the value of `kern.devstat.all`: the `devstat_generation` (a `long`), followed by an
+/// array of `struct devstat`.
+fn decode_devstat(buf: &[u8]) -> Result<(i64, Vec<Devstat>), Error> {
+    let invalid = || -> Error {
+        let msg = "malformed kern.devstat.all";
+        return std::io::Error::new(std::io::ErrorKind::InvalidData, msg).into();
+    };
+    let header = std::mem::size_of::<c_long>();
+    let entry = std::mem::size_of::<Devstat>();
+    if buf.len() < header || !(buf.len() - header).is_multiple_of(entry) {
+        return Err(invalid());
+    }
+    let mut generation = [0u8; 8];
+    generation[..header].copy_from_slice(&buf[..header]);
+    let generation = i64::from_ne_bytes(generation);
+
+    let entries = buf[header..]
+        .chunks_exact(entry)
+        .map(|chunk| {
+            // SAFETY: `chunk` is exactly `size_of::<Devstat>()` bytes, and every bit pattern is a
+            // valid `Devstat` (the pointers are never dereferenced).
+            return unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const Devstat) };
+        })
+        .collect();
+    return Ok((generation, entries));
+}
+
+/// The generation and the named devices of `kern.devstat.all`.
+fn parse_devstat(buf: &[u8]) -> Result<(i64, Vec<DeviceStats>), Error> {
+    let (generation, entries) = decode_devstat(buf)?;
+    let devices = entries
+        .iter()
+        .filter_map(DeviceStats::from_devstat)
+        .collect();
+    return Ok((generation, devices));
+}
+
+/// The entries of `kern.devstat.all` GEOM keeps for its providers and consumers, by address.
+fn parse_geom_devstat(buf: &[u8]) -> Result<BTreeMap<u64, DeviceStats>, Error> {
+    let (_, entries) = decode_devstat(buf)?;
+    return Ok(entries
+        .iter()
+        .filter_map(DeviceStats::from_geom_devstat)
+        .collect());
+}
+
+#[cfg(target_os = "freebsd")]
+fn read_devstat() -> Result<Vec<u8>, Error> {
+    use sysctl::{CtlValue, Sysctl};
+
+    let version = sysctl::Ctl::new("kern.devstat.version")?.value()?;
+    if version != CtlValue::Int(DEVSTAT_VERSION) {
+        let msg = format!("unsupported devstat version {:?}", version);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg).into());
+    }
+    return match sysctl::Ctl::new("kern.devstat.all")?.value()? {
+        CtlValue::Struct(buf) | CtlValue::Node(buf) => Ok(buf),
+        _ => Ok(Vec::new()),
+    };
+}
+
+/// Returns the `devstat(9)` counters of every device on the running system.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn myfoo() -> Result<(), geom::Error> {
+///     for dev in geom::stats::get_devstat()? {
+///         println!("{}: {} bytes read", dev.name, dev.read.bytes);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn get_devstat() -> Result<Vec<DeviceStats>, Error> {
+    let (_, devices) = parse_devstat(&read_devstat()?)?;
+    return Ok(devices);
+}
+
+/// Returns the `devstat(9)` counters GEOM keeps for its own providers, by provider id (the
+/// second element of an `EdgeId`), for `Graph::attach_geom_devstat()`.  The counters are
+/// unnamed.
+///
+/// GEOM only counts I/O to providers while bit 0 of the `kern.geom.collectstats` sysctl is set
+/// (the default).  Consumers' counters (bit 1) are included, too, by consumer id.
+#[cfg(target_os = "freebsd")]
+pub fn get_geom_devstat() -> Result<BTreeMap<u64, DeviceStats>, Error> {
+    return parse_geom_devstat(&read_devstat()?);
+}
+
+/// The rates of one device over a sampling interval, as shown by `iostat -x`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DeviceRates {
+    /// The device name, e.g., `"ada0"`
+    pub name: String,
+    /// Megabytes (2^20 bytes) read per second
+    pub read_mbps: f64,
+    /// Megabytes (2^20 bytes) written per second
+    pub write_mbps: f64,
+    /// Reads completed per second
+    pub read_iops: f64,
+    /// Writes completed per second
+    pub write_iops: f64,
+    /// Operations of any kind (including deletes and flushes) completed per second
+    pub iops: f64,
+    /// The average latencies of the operations completed in the interval
+    pub latency: Latency,
+    /// The percentage of the interval the device had operations outstanding
+    pub busy_percent: f64,
+    /// Operations outstanding at the end of the interval
+    pub queue_length: u32,
+}
+
+/// The rates of every device between two samples, returned by `StatsSampler::sample_at()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct StatsSample {
+    /// The time since the previous sample
+    pub interval: Duration,
+    /// The devices present in both samples
+    pub devices: Vec<DeviceRates>,
+    /// The devices that appeared (or were replaced by a new device of the same name) since the
+    /// previous sample, which have rates only from the next one
+    pub arrived: Vec<String>,
+    /// The devices that disappeared since the previous sample
+    pub departed: Vec<String>,
+}
+
+impl StatsSample {
+    /// The rates of device `name`, if it was present in both samples.
+    pub fn device(&self, name: &str) -> Option<&DeviceRates> {
+        return self.devices.iter().find(|d| d.name == name);
+    }
+
+    /// The rates of each device with a provider in `graph`, by the `Geom` owning the provider
+    /// (e.g., the `GeomClass::DISK` geom `ada0`).
+    pub fn by_geom<'a>(&'a self, graph: &Graph) -> Vec<(NodeId, &'a DeviceRates)> {
+        return self
+            .devices
+            .iter()
+            .filter_map(|d| Some((graph.device_geom(&d.name)?, d)))
+            .collect();
+    }
+}
+
+/// Computes I/O rates from successive `devstat(9)` snapshots, like `iostat(8)`.
+///
+/// The sampler keeps the previous snapshot; each new one yields the rates of every device over
+/// the time between them.
+#[derive(Clone, Debug, Default)]
+pub struct StatsSampler {
+    latest: Option<(Instant, BTreeMap<String, DeviceStats>)>,
+}
+
+impl StatsSampler {
+    /// A sampler without a baseline.
+    pub fn new() -> StatsSampler {
+        return StatsSampler::default();
+    }
+
+    /// Samples the running system's counters now.  See `StatsSampler::sample_at()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// #[cfg(target_os = "freebsd")]
+    /// fn iostat() -> Result<(), geom::Error> {
+    ///     let mut sampler = geom::stats::StatsSampler::new();
+    ///     sampler.sample()?;
+    ///     loop {
+    ///         std::thread::sleep(std::time::Duration::from_secs(1));
+    ///         for dev in sampler.sample()?.unwrap().devices {
+    ///             println!("{}: {:.1} MB/s read", dev.name, dev.read_mbps);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    #[cfg(target_os = "freebsd")]
+    pub fn sample(&mut self) -> Result<Option<StatsSample>, Error> {
+        let stats = get_devstat()?;
+        return Ok(self.sample_at(Instant::now(), stats));
+    }
+
+    /// Records `stats`, taken at `time`, and returns the rates since the previous sample.  The
+    /// first sample is the baseline for later ones, and yields no rates; neither does a sample
+    /// taken no later than the previous one.
+    ///
+    /// Counters that wrap around between samples are handled.  A device whose creation time
+    /// changes has been replaced, so it is reported as arrived, as is a device not in the
+    /// previous sample.
+    pub fn sample_at(&mut self, time: Instant, stats: Vec<DeviceStats>) -> Option<StatsSample> {
+        let stats: BTreeMap<String, DeviceStats> =
+            stats.into_iter().map(|s| (s.name.clone(), s)).collect();
+        let previous = self.latest.replace((time, stats));
+        let (prevtime, prev) = previous?;
+        let (_, cur) = self.latest.as_ref().unwrap();
+        let interval = time.checked_duration_since(prevtime)?;
+        if interval.is_zero() {
+            return None;
+        }
+
+        let mut sample = StatsSample {
+            interval,
+            ..Default::default()
+        };
+        for (name, new) in cur {
+            match prev.get(name) {
+                Some(old) if old.creation_time == new.creation_time => {
+                    sample.devices.push(rates(old, new, interval));
+                }
+                _ => sample.arrived.push(name.clone()),
+            }
+        }
+        sample.departed = prev
+            .keys()
+            .filter(|name| !cur.contains_key(*name))
+            .cloned()
+            .collect();
+        return Some(sample);
+    }
+}
+
+/// The rates of a device that went from `old` to `new` over `interval`.
+fn rates(old: &DeviceStats, new: &DeviceStats, interval: Duration) -> DeviceRates {
+    let secs = interval.as_secs_f64();
+    let delta = |old: &TransferStats, new: &TransferStats| TransferStats {
+        bytes: new.bytes.wrapping_sub(old.bytes),
+        operations: new.operations.wrapping_sub(old.operations),
+        duration: new.duration.saturating_sub(old.duration),
+    };
+    let read = delta(&old.read, &new.read);
+    let write = delta(&old.write, &new.write);
+    let free = delta(&old.free, &new.free);
+    let other = delta(&old.other, &new.other);
+    let operations = read.operations + write.operations + free.operations + other.operations;
+    let busy = new.busy_time.saturating_sub(old.busy_time).as_secs_f64();
+
+    return DeviceRates {
+        name: new.name.clone(),
+        read_mbps: read.bytes as f64 / (1 << 20) as f64 / secs,
+        write_mbps: write.bytes as f64 / (1 << 20) as f64 / secs,
+        read_iops: read.operations as f64 / secs,
+        write_iops: write.operations as f64 / secs,
+        iops: operations as f64 / secs,
+        latency: Latency::of(&read, &write, &free, &other),
+        busy_percent: (100.0 * busy / secs).min(100.0),
+        queue_length: new.queue_length,
+    };
+}
+
+impl Graph {
+    /// Records each of `stats` in `Edge::devstat` of every edge out of the provider of the same
+    /// name (e.g., `ada0`).  Devices without a provider in the graph (e.g., `pass(4)` devices)
+    /// are ignored.
+    ///
+    /// Any previously attached statistics are replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// #[cfg(target_os = "freebsd")]
+    /// fn myfoo() -> Result<(), geom::Error> {
+    ///     let mut graph = geom::get_graph()?;
+    ///     graph.attach_devstat(&geom::stats::get_devstat()?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn attach_devstat(&mut self, stats: &[DeviceStats]) {
+        let by_name: BTreeMap<&str, &DeviceStats> =
+            stats.iter().map(|s| (s.name.as_str(), s)).collect();
+        for edge in self.edges.values_mut() {
+            edge.devstat = by_name
+                .get(edge.name.as_str())
+                .map(|s| Box::new((*s).clone()));
+        }
+    }
+
+    /// Records GEOM's own statistics for each provider (see `stats::get_geom_devstat()`), named
+    /// after it, in `Edge::devstat` of every edge out of it.  Providers which already have
+    /// statistics from `Graph::attach_devstat()` keep them, so call that first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// #[cfg(target_os = "freebsd")]
+    /// fn myfoo() -> Result<(), geom::Error> {
+    ///     let mut graph = geom::get_graph()?;
+    ///     graph.attach_devstat(&geom::stats::get_devstat()?);
+    ///     graph.attach_geom_devstat(&geom::stats::get_geom_devstat()?);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn attach_geom_devstat(&mut self, stats: &BTreeMap<u64, DeviceStats>) {
+        for ((_, provider), edge) in self.edges.iter_mut() {
+            if edge.devstat.is_some() {
+                continue;
+            }
+            if let Some(s) = stats.get(provider) {
+                edge.devstat = Some(Box::new(DeviceStats {
+                    name: edge.name.clone(),
+                    ..s.clone()
+                }));
+            }
+        }
+    }
+
+    /// The `Geom` owning the provider of device `name` (e.g., the `GeomClass::DISK` geom of
+    /// `ada0`), if any.
+    pub fn device_geom(&self, name: &str) -> Option<NodeId> {
+        return self
+            .edges
+            .values()
+            .map(|e| (e.name.as_str(), e.provider_geom))
+            .chain(
+                self.orphans
+                    .values()
+                    .map(|o| (o.name.as_str(), o.provider_geom)),
+            )
+            .find(|(n, _)| *n == name)
+            .map(|(_, id)| id);
+    }
+
+    /// The statistics of the geom `id`, named after it, from those attached with
+    /// `Graph::attach_devstat()` and `Graph::attach_geom_devstat()`.
+    ///
+    /// If the geom's own providers have statistics attached (e.g., GEOM's counters for
+    /// `mirror/gm0`, or the disk's for `ada0`), they are the geom's, summed over its providers.
+    /// Otherwise, they are rolled up from those of the providers it consumes, each the
+    /// provider's own statistics if attached, or else the roll-up of the geom underneath:
+    ///
+    /// - For a `GeomClass::MIRROR` geom, reads are summed, and the writes, deletes, and flushes
+    ///   are those of the member which has done the most of each, since each one goes to every
+    ///   member.
+    /// - For any other class, the counters are summed.  This is exact for geoms which pass each
+    ///   request to one provider (e.g., `PART` and `DEV`), and overcounts operations for those
+    ///   which split requests (e.g., `STRIPE`).
+    ///
+    /// The roll-up is of the devices' counters, so I/O on behalf of other geoms (e.g., to
+    /// another partition on the same disk) is included.  In either case, `busy_time` is that of
+    /// the busiest provider.  Feed successive results to a `StatsSampler` for the geom's rates.
+    ///
+    /// Returns `None` if there are no statistics for the geom or anything underneath it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// #[cfg(target_os = "freebsd")]
+    /// fn mirror_iostat() -> Result<(), geom::Error> {
+    ///     let mut sampler = geom::stats::StatsSampler::new();
+    ///     loop {
+    ///         let mut graph = geom::get_graph()?;
+    ///         graph.attach_devstat(&geom::stats::get_devstat()?);
+    ///         graph.attach_geom_devstat(&geom::stats::get_geom_devstat()?);
+    ///         let (id, _) = graph
+    ///             .find_by_class_name(&geom::GeomClass::MIRROR, "gm0")
+    ///             .unwrap();
+    ///         let stats = graph.aggregate_devstat(id).into_iter().collect();
+    ///         if let Some(sample) = sampler.sample_at(std::time::Instant::now(), stats) {
+    ///             println!("{:?}", sample.device("gm0"));
+    ///         }
+    ///         std::thread::sleep(std::time::Duration::from_secs(1));
+    ///     }
+    /// }
+    /// ```
+    pub fn aggregate_devstat(&self, id: &NodeId) -> Option<DeviceStats> {
+        let geom = self.nodes.get(id)?;
+        let mut result = self.rollup_devstat(id)?;
+        result.name = geom.name.clone();
+        return Some(result);
+    }
+
+    fn rollup_devstat(&self, id: &NodeId) -> Option<DeviceStats> {
+        let own: BTreeMap<&str, &DeviceStats> = self
+            .child_edges_iter(id)
+            .filter_map(|(_, e)| Some((e.name.as_str(), e.devstat.as_deref()?)))
+            .collect();
+        if !own.is_empty() {
+            let mut result = DeviceStats::default();
+            for stats in own.values() {
+                result.accumulate(stats);
+            }
+            return Some(result);
+        }
+
+        // Each provider underneath once, even if consumed twice.
+        let members: BTreeMap<u64, DeviceStats> = self
+            .parent_edges_iter(id)
+            .filter_map(|((_, provider), e)| {
+                let stats = match e.devstat.as_deref() {
+                    Some(s) => s.clone(),
+                    None => self.rollup_devstat(&e.provider_geom)?,
+                };
+                return Some((*provider, stats));
+            })
+            .collect();
+        if members.is_empty() {
+            return None;
+        }
+        let mut result = DeviceStats::default();
+        for stats in members.values() {
+            result.accumulate(stats);
+        }
+        if self.nodes[id].class == GeomClass::MIRROR {
+            let busiest = |f: fn(&DeviceStats) -> &TransferStats| {
+                return members
+                    .values()
+                    .map(f)
+                    .max_by_key(|t| t.operations)
+                    .copied()
+                    .unwrap_or_default();
+            };
+            result.write = busiest(|s| &s.write);
+            result.free = busiest(|s| &s.free);
+            result.other = busiest(|s| &s.other);
+        }
+        return Some(result);
+    }
+
+    /// The latencies since attach of each provider whose statistics have been attached with
+    /// `Graph::attach_devstat()` or `Graph::attach_geom_devstat()`, by the `Geom` owning it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    /// use std::time::Duration;
+    ///
+    /// #[cfg(target_os = "freebsd")]
+    /// fn slow_disks() -> Result<(), geom::Error> {
+    ///     let mut graph = geom::get_graph()?;
+    ///     graph.attach_devstat(&geom::stats::get_devstat()?);
+    ///     for (id, latency) in graph.device_latency() {
+    ///         if latency.max() > Some(Duration::from_millis(50)) {
+    ///             println!("{} is slow: {:?}", graph.nodes[&id].name, latency);
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn device_latency(&self) -> BTreeMap<NodeId, Latency> {
+        return self
+            .edges
+            .values()
+            .filter_map(|e| Some((e.provider_geom, e.devstat.as_ref()?.latency())))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::*;
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    fn devstat(name: &str, unit: c_int) -> Devstat {
+        let mut device_name = [0u8; DEVSTAT_NAME_LEN];
+        device_name[..name.len()].copy_from_slice(name.as_bytes());
+        let secs = |sec: TimeT, frac: u64| Bintime { sec, frac };
+        return Devstat {
+            sequence0: 7,
+            allocated: 1,
+            start_count: 12,
+            end_count: 10,
+            busy_from: Bintime::default(),
+            dev_links: std::ptr::null(),
+            device_number: 0,
+            device_name,
+            unit_number: unit,
+            bytes: [0, 1 << 20, 1 << 21, 1 << 30],
+            operations: [3, 256, 512, 1],
+            duration: [
+                secs(0, 1 << 63),
+                secs(1, 0),
+                secs(2, 1 << 62),
+                Bintime::default(),
+            ],
+            busy_time: secs(3, 0),
+            creation_time: secs(5, 0),
+            block_size: 512,
+            tag_types: [0; 3],
+            flags: 0,
+            device_type: 0,
+            priority: 0,
+            id: std::ptr::null(),
+            sequence1: 7,
+        };
+    }
+
+    /// Encodes `kern.devstat.all`.
+    pub(crate) fn encode(generation: c_long, entries: &[Devstat]) -> Vec<u8> {
+        let mut buf = generation.to_ne_bytes().to_vec();
+        for ds in entries {
+            // SAFETY: reading the bytes of a `repr(C)` value.
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    ds as *const Devstat as *const u8,
+                    std::mem::size_of::<Devstat>(),
+                )
+            };
+            buf.extend_from_slice(bytes);
+        }
+        return buf;
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn abi() {
+        assert_eq!(std::mem::size_of::<Devstat>(), 288);
+    }
+
+    #[test]
+    fn parse() {
+        let buf = encode(42, &[devstat("nvd", 0), devstat("", -1), devstat("ada", 0)]);
+        let (generation, devices) = parse_devstat(&buf).unwrap();
+        assert_eq!(generation, 42);
+        assert_eq!(devices.len(), 2);
+        let nvd0 = &devices[0];
+        assert_eq!(nvd0.name, "nvd0");
+        assert_eq!(nvd0.read.bytes, 1 << 20);
+        assert_eq!(nvd0.read.operations, 256);
+        assert_eq!(nvd0.read.duration, Duration::from_secs(1));
+        assert_eq!(nvd0.write.duration, Duration::from_millis(2250));
+        assert_eq!(nvd0.other.duration, Duration::from_millis(500));
+        assert_eq!(nvd0.free.bytes, 1 << 30);
+        assert_eq!(nvd0.operations(), 772);
+        assert_eq!(nvd0.busy_time, Duration::from_secs(3));
+        assert_eq!(nvd0.queue_length, 2);
+        assert_eq!(devices[1].name, "ada0");
+
+        assert!(parse_devstat(&buf[..buf.len() - 1]).is_err());
+        assert!(parse_devstat(&[]).is_err());
+
+        let mut provider = devstat("", -1);
+        provider.id = 0x14 as *const c_void;
+        let buf = encode(42, &[devstat("nvd", 0), devstat("", -1), provider]);
+        let providers = parse_geom_devstat(&buf).unwrap();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[&0x14].name, "");
+        assert_eq!(providers[&0x14].read, nvd0.read);
+    }
+
+    #[test]
+    fn attach() {
+        let mut g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let buf = encode(1, &[devstat("nvd", 0), devstat("pass", 0)]);
+        let (_, devices) = parse_devstat(&buf).unwrap();
+        g.attach_devstat(&devices);
+
+        let attached: Vec<_> = g.edges.values().filter(|e| e.devstat.is_some()).collect();
+        assert!(!attached.is_empty());
+        assert!(attached.iter().all(|e| e.name == "nvd0"));
+        assert_eq!(attached[0].devstat.as_ref().unwrap().write.operations, 512);
+
+        let latency = g.device_latency();
+        assert_eq!(latency.len(), 1);
+        let (id, l) = latency.iter().next().unwrap();
+        assert_eq!(g.nodes[id].class, graph::GeomClass::DISK);
+        assert_eq!(g.device_geom("nvd0"), Some(*id));
+        assert_eq!(g.device_geom("pass0"), None);
+        assert_eq!(l.read, Some(Duration::from_nanos(3_906_250)));
+        assert_eq!(l.write, Some(Duration::from_nanos(4_394_531)));
+        assert_eq!(l.free, Some(Duration::ZERO));
+        assert_eq!(l.flush, Some(Duration::from_nanos(166_666_667)));
+        assert_eq!(l.max(), l.flush);
+
+        g.attach_devstat(&[]);
+        assert!(g.edges.values().all(|e| e.devstat.is_none()));
+    }
+
+    #[test]
+    fn sampler() {
+        let t0 = Instant::now();
+        let stats = |name: &str, ops: u64, busy: u64, created: u64| DeviceStats {
+            name: name.to_owned(),
+            read: TransferStats {
+                bytes: ops.wrapping_mul(4096),
+                operations: ops,
+                duration: Duration::from_millis(ops),
+            },
+            busy_time: Duration::from_millis(busy),
+            creation_time: Duration::from_secs(created),
+            ..Default::default()
+        };
+
+        let mut s = StatsSampler::new();
+        let near = u64::MAX - 99;
+        assert!(s
+            .sample_at(t0, vec![stats("ada0", near, 0, 1), stats("da0", 0, 0, 1)])
+            .is_none());
+        assert!(s
+            .sample_at(t0, vec![stats("ada0", near, 0, 1), stats("da0", 0, 0, 1)])
+            .is_none());
+
+        // ada0's counters wrap; da0 is replaced, and nvd0 arrives.
+        let sample = s
+            .sample_at(
+                t0 + Duration::from_secs(2),
+                vec![
+                    stats("ada0", 156, 500, 1),
+                    stats("da0", 0, 0, 9),
+                    stats("nvd0", 7, 0, 9),
+                ],
+            )
+            .unwrap();
+        assert_eq!(sample.interval, Duration::from_secs(2));
+        assert_eq!(sample.arrived, vec!["da0", "nvd0"]);
+        assert!(sample.departed.is_empty());
+        let ada0 = sample.device("ada0").unwrap();
+        assert_eq!(ada0.read_iops, 128.0);
+        assert_eq!(ada0.iops, 128.0);
+        assert_eq!(ada0.read_mbps, 0.5);
+        assert_eq!(ada0.write_mbps, 0.0);
+        assert_eq!(ada0.busy_percent, 25.0);
+        assert!(sample.device("da0").is_none());
+
+        let sample = s
+            .sample_at(t0 + Duration::from_secs(3), vec![stats("nvd0", 17, 0, 9)])
+            .unwrap();
+        assert_eq!(sample.departed, vec!["ada0", "da0"]);
+        let nvd0 = sample.device("nvd0").unwrap();
+        assert_eq!(nvd0.iops, 10.0);
+        assert_eq!(nvd0.latency.read, Some(Duration::from_millis(1)));
+        assert_eq!(nvd0.latency.all, Some(Duration::from_millis(1)));
+        assert_eq!(nvd0.latency.write, None);
+    }
+
+    #[test]
+    fn aggregate() {
+        const MIRROR_XML: &str = r#"<class id="0x10">
+        <name>MIRROR</name>
+        <geom id="0x11">
+          <class ref="0x10"/>
+          <name>gm0</name>
+          <rank>3</rank>
+          <consumer id="0x12">
+            <geom ref="0x11"/>
+            <provider ref="0xfffff8001b62a900"/>
+            <mode>r0w0e0</mode>
+          </consumer>
+          <consumer id="0x13">
+            <geom ref="0x11"/>
+            <provider ref="0xfffff804947b5a00"/>
+            <mode>r0w0e0</mode>
+          </consumer>
+          <provider id="0x14">
+            <geom ref="0x11"/>
+            <mode>r0w0e0</mode>
+            <name>mirror/gm0</name>
+            <mediasize>1048576</mediasize>
+            <sectorsize>512</sectorsize>
+            <stripesize>0</stripesize>
+            <stripeoffset>0</stripeoffset>
+          </provider>
+        </geom>
+      </class>
+      <class id="0x20">
+        <name>NOP</name>
+        <geom id="0x21">
+          <class ref="0x20"/>
+          <name>mirror/gm0.nop</name>
+          <rank>4</rank>
+          <consumer id="0x22">
+            <geom ref="0x21"/>
+            <provider ref="0x14"/>
+            <mode>r0w0e0</mode>
+          </consumer>
+        </geom>
+      </class>
+    </mesh>"#;
+        let xml = SAMPLE_XML.replace("</mesh>", MIRROR_XML);
+        let mut g = graph::decode_graph(&raw::parse_xml(&xml).unwrap()).unwrap();
+        let (gm0, _) = g
+            .find_by_class_name(&graph::GeomClass::MIRROR, "gm0")
+            .unwrap();
+        let gm0 = *gm0;
+        assert_eq!(g.aggregate_devstat(&gm0), None);
+
+        let mut nvd1 = devstat("nvd", 1);
+        nvd1.busy_time.sec = 5;
+        nvd1.creation_time.sec = 9;
+        let buf = encode(1, &[devstat("nvd", 0), nvd1, devstat("ada", 0)]);
+        let (_, devices) = parse_devstat(&buf).unwrap();
+        g.attach_devstat(&devices);
+
+        let total = g.aggregate_devstat(&gm0).unwrap();
+        assert_eq!(total.name, "gm0");
+        assert_eq!(total.read.bytes, 2 << 20);
+        // Each write goes to both disks.
+        assert_eq!(total.write.operations, 512);
+        assert_eq!(total.write.duration, Duration::from_millis(2250));
+        assert_eq!(total.free.operations, 1);
+        assert_eq!(total.queue_length, 4);
+        assert_eq!(total.busy_time, Duration::from_secs(5));
+        assert_eq!(total.creation_time, Duration::from_secs(9));
+        let (latency, disk) = (total.latency(), devices[0].latency());
+        assert_eq!(latency.read, disk.read);
+        assert_eq!(latency.write, disk.write);
+        assert_eq!(latency.flush, disk.flush);
+
+        // A partition's DEV geom, and the disk itself, see just that disk.
+        for name in &["nvd0p4", "nvd0"] {
+            let (id, _) = g.find_by_class_name(&graph::GeomClass::DEV, name).unwrap();
+            let stats = g.aggregate_devstat(id).unwrap();
+            assert_eq!(stats.read, devices[0].read);
+        }
+        let (disk, _) = g
+            .find_by_class_name(&graph::GeomClass::DISK, "nvd0")
+            .unwrap();
+        assert_eq!(g.aggregate_devstat(disk).unwrap().name, "nvd0");
+        assert_eq!(g.aggregate_devstat(disk).unwrap().read, devices[0].read);
+
+        // GEOM's own counters for mirror/gm0 are gm0's; those for nvd0 don't replace the disk's.
+        let (nvd0, _) = g.edges.iter().find(|(_, e)| e.name == "nvd0").unwrap();
+        let mut gm0_provider = devstat("", -1);
+        gm0_provider.id = 0x14 as *const c_void;
+        gm0_provider.operations[DEVSTAT_WRITE] = 7;
+        let mut nvd0_provider = devstat("", -1);
+        nvd0_provider.id = nvd0.1 as usize as *const c_void;
+        nvd0_provider.operations[DEVSTAT_READ] = 9;
+        let buf = encode(1, &[gm0_provider, nvd0_provider]);
+        g.attach_geom_devstat(&parse_geom_devstat(&buf).unwrap());
+        let total = g.aggregate_devstat(&gm0).unwrap();
+        assert_eq!(total.name, "gm0");
+        assert_eq!(total.write.operations, 7);
+        assert_eq!(total.read.operations, 256);
+        assert_eq!(total.queue_length, 2);
+        let nvd0 = g.edges.values().find(|e| e.name == "nvd0").unwrap();
+        assert_eq!(nvd0.devstat.as_deref(), Some(&devices[0]));
+    }
+}
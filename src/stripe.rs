@@ -0,0 +1,193 @@
+//! Creating `gstripe(8)` volumes.
+//!
+//! A STRIPE geom interleaves its providers in `stripesize`-byte chunks into one provider,
+//! `stripe/<name>` (RAID0).  Each member contributes as much space as the smallest of them.  As
+//! with `concat`, a volume can be labeled, persisting on the providers, or created manually.
+use crate::concat::{check_volume, name_field};
+#[cfg(target_os = "freebsd")]
+use crate::ctl::CtlResult;
+use crate::ctl::{CtlError, GctlRequest, EINVAL};
+use crate::graph::Graph;
+use crate::Error;
+
+/// `G_STRIPE_MAGIC`
+const G_STRIPE_MAGIC: &str = "GEOM::STRIPE";
+/// `G_STRIPE_VERSION`, the metadata version, which the kernel also checks against each
+/// request's `version`
+const G_STRIPE_VERSION: u32 = 4;
+
+/// The default stripe size, in bytes, as in `gstripe(8)`.
+pub const DEFAULT_STRIPESIZE: u32 = 65536;
+
+/// Checks a new volume, as `check_volume()` does, and that `stripesize` is a power of two and a
+/// multiple of every provider's sector size.
+fn check_stripe<'a>(
+    graph: &Graph,
+    name: &str,
+    providers: &[&'a str],
+    stripesize: u32,
+) -> Result<Vec<(&'a str, u64, u64)>, Error> {
+    let members = check_volume(graph, "stripe", name, providers)?;
+    let bad = members
+        .iter()
+        .any(|(_, _, sectorsize)| !(stripesize as u64).is_multiple_of(*sectorsize));
+    if !stripesize.is_power_of_two() || bad {
+        return Err(CtlError::new(EINVAL, format!("Invalid stripe size {}", stripesize)).into());
+    }
+    return Ok(members);
+}
+
+/// Encodes `struct g_stripe_metadata` for member `no` of `all`.
+fn metadata(name: &str, id: u32, no: u16, all: u16, stripesize: u32, provsize: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(72);
+    buf.extend_from_slice(&name_field(G_STRIPE_MAGIC));
+    buf.extend_from_slice(&G_STRIPE_VERSION.to_le_bytes());
+    buf.extend_from_slice(&name_field(name));
+    buf.extend_from_slice(&id.to_le_bytes());
+    buf.extend_from_slice(&no.to_le_bytes());
+    buf.extend_from_slice(&all.to_le_bytes());
+    buf.extend_from_slice(&stripesize.to_le_bytes());
+    // `md_provider`, which is only set by `gstripe label -h`.
+    buf.extend_from_slice(&[0u8; 16]);
+    buf.extend_from_slice(&provsize.to_le_bytes());
+    return buf;
+}
+
+/// Returns the request to create the volume `stripe/<name>` from `providers`, in order, with
+/// `stripesize`-byte stripes (e.g., `DEFAULT_STRIPESIZE`), like `gstripe create -s`.  The volume
+/// is not recorded on the providers; see `stripe::label()`.
+///
+/// Fails as `concat::create_request()` does, or if `stripesize` is not a power of two multiple of
+/// each provider's sector size.
+pub fn create_request(
+    graph: &Graph,
+    name: &str,
+    providers: &[&str],
+    stripesize: u32,
+) -> Result<GctlRequest, Error> {
+    let members = check_stripe(graph, name, providers, stripesize)?;
+    let args: Vec<&str> = std::iter::once(name)
+        .chain(members.iter().map(|(p, _, _)| *p))
+        .collect();
+    return Ok(GctlRequest::new("STRIPE", "create")
+        .int("version", G_STRIPE_VERSION as i32)
+        .args(&args)
+        .number("stripesize", stripesize as i64));
+}
+
+/// Creates the volume `stripe/<name>` from `providers`, like `gstripe create`.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(target_os = "freebsd")]
+/// fn raid0() -> Result<(), geom::Error> {
+///     let stripesize = geom::stripe::DEFAULT_STRIPESIZE;
+///     let res = geom::stripe::create("fast", &["nvd0", "nvd1"], stripesize)?.refresh()?;
+///     let (_, stripe) = res
+///         .graph
+///         .find_by_class_name(&geom::GeomClass::STRIPE, "fast")
+///         .unwrap();
+///     println!("{:?}", stripe);
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+pub fn create(name: &str, providers: &[&str], stripesize: u32) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    create_request(&graph, name, providers, stripesize)?.issue()?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+/// Labels `providers` as the members of volume `stripe/<name>`, in order, like `gstripe label`.
+/// See `concat::label()`.
+#[cfg(target_os = "freebsd")]
+pub fn label(name: &str, providers: &[&str], stripesize: u32) -> Result<CtlResult, Error> {
+    let graph = crate::get_graph()?;
+    let members = check_stripe(&graph, name, providers, stripesize)?;
+    let id = crate::concat::random_id();
+    let args: Vec<&str> = std::iter::once(name)
+        .chain(members.iter().map(|(p, _, _)| *p))
+        .collect();
+    let req = GctlRequest::new("STRIPE", "label")
+        .args(&args)
+        .number("stripesize", stripesize as i64);
+    if !crate::ctl::check_policy(&req)? {
+        return Ok(CtlResult::unrefreshed(graph, None));
+    }
+    let all = members.len() as u16;
+    crate::ctl::audited(&req, || {
+        crate::concat::write_labels(&graph, &members, |no, provsize| {
+            return metadata(name, id, no, all, stripesize, provsize);
+        })?;
+        return Ok(crate::ctl::GctlResponse::default());
+    })?;
+    return Ok(CtlResult::unrefreshed(graph, None));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctl::{CtlErrorKind, ParamValue};
+    use crate::stripe::{create_request, metadata, DEFAULT_STRIPESIZE};
+    use crate::{graph, raw, Error};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn create() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let kind = |r: Result<_, Error>| match r {
+            Err(Error::Ctl(e)) => e.kind(),
+            _ => panic!(),
+        };
+
+        let req = create_request(&g, "fast", &["nvd0p4", "nvd1p2"], DEFAULT_STRIPESIZE).unwrap();
+        assert_eq!(req.class(), "STRIPE");
+        assert_eq!(req.get("version"), Some(&ParamValue::Int(4)));
+        assert_eq!(req.get("nargs"), Some(&ParamValue::Int(3)));
+        assert_eq!(req.get("arg2"), Some(&ParamValue::String("nvd1p2".into())));
+        assert_eq!(req.get("stripesize"), Some(&ParamValue::Number(65536)));
+
+        for stripesize in &[0, 256, 3 << 12] {
+            assert_eq!(
+                kind(create_request(
+                    &g,
+                    "fast",
+                    &["nvd0p4", "nvd1p2"],
+                    *stripesize
+                )),
+                CtlErrorKind::InvalidArgument
+            );
+        }
+        assert_eq!(
+            kind(create_request(&g, "fast", &["nvd9", "nvd1p2"], 4096)),
+            CtlErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn label() {
+        let md = metadata("fast", 7, 0, 2, 4096, 1 << 30);
+        assert_eq!(md.len(), 72);
+        assert_eq!(&md[..13], b"GEOM::STRIPE\0");
+        assert_eq!(&md[20..25], b"fast\0");
+        assert_eq!(&md[36..48], &[7, 0, 0, 0, 0, 0, 2, 0, 0, 0x10, 0, 0]);
+        assert_eq!(&md[64..], &(1u64 << 30).to_le_bytes());
+    }
+
+    #[test]
+    #[cfg(target_os = "freebsd")]
+    fn dry_run() {
+        let g = graph::decode_graph(&raw::parse_xml(SAMPLE_XML).unwrap()).unwrap();
+        let providers = ["nvd0p4", "nvd1p2"];
+        let (res, requests) = crate::dry_run_on(&g, || -> Result<(), Error> {
+            super::create("fast", &providers, DEFAULT_STRIPESIZE)?;
+            super::label("fast", &providers, DEFAULT_STRIPESIZE)?;
+            return Ok(());
+        });
+        res.unwrap();
+        let verbs: Vec<&str> = requests.iter().map(|r| r.verb()).collect();
+        assert_eq!(verbs, vec!["create", "label"]);
+    }
+}
@@ -2,8 +2,11 @@
 //! in the `geom::graph` module instead.
 extern crate serde;
 
-//use serde::{de::Error, Deserialize, Deserializer};
-use serde::Deserialize;
+use serde::{
+    de::{Error as _, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::collections::BTreeMap;
 
 use crate::Error;
 
@@ -12,7 +15,7 @@ use crate::Error;
 /// The mesh contains objects from various classes, called "geoms."  `Geom`s represent things like
 /// disks, or disk partitions, or device nodes under `/dev` on FreeBSD systems.  They are related
 /// by references called "consumers" and "providers."
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Mesh {
     #[serde(rename = "class", default)]
     pub classes: Vec<Class>,
@@ -20,7 +23,7 @@ pub struct Mesh {
 
 /// `Class` contains all of the objects ("geoms") and associated relationships ("consumers" and
 /// "providers") associated with the class.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Class {
     // Ideally, deserialize directly to u64.  However, neither of these works:
     //#[serde(with = "SerHex::<CompactPfx>")]
@@ -41,7 +44,7 @@ pub struct Class {
 /// depends on a lower-level (lower "`rank`") geom.  `Provider` edges indicate that this geom
 /// exposes an object to a higher-level object.  For example, a PART geom might "consume" a DISK
 /// geom ("ada0") and "provide" logical partition objects ("ada0p1", "ada0p2", etc.).
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Geom {
     pub id: String, // uintptr_t
     #[serde(rename = "class")]
@@ -49,6 +52,9 @@ pub struct Geom {
     pub name: String,
     pub rank: u64,
     pub config: Option<GeomConfig>,
+    /// Present (as `<wither/>`) if the geom is being torn down.
+    #[serde(default)]
+    pub wither: Option<()>,
     #[serde(rename = "consumer", default)]
     pub consumers: Vec<Consumer>,
     #[serde(rename = "provider", default)]
@@ -58,7 +64,7 @@ pub struct Geom {
 /// A `ClassRef` is just a logical pointer to a `Class`.
 ///
 /// `ClassRef::ref_` references the same namespace as `Class::id`.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct ClassRef {
     #[serde(rename = "ref")]
     pub ref_: String, // uintptr_t
@@ -66,8 +72,9 @@ pub struct ClassRef {
 
 /// A set of key-value metadata associated with a specific `Geom`.
 ///
-/// The semantics and available values vary depending on the class.
-#[derive(Debug, Deserialize, PartialEq)]
+/// The semantics and available values vary depending on the class.  Keys this library does not
+/// know about are preserved in `unknown`.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct GeomConfig {
     // PART
     pub scheme: Option<String>,
@@ -78,6 +85,69 @@ pub struct GeomConfig {
     pub fwheads: Option<u64>,
     pub state: Option<String>, // "OK"
     pub modified: Option<bool>,
+    // ELI
+    pub keys_total: Option<u64>,                  // KeysTotal
+    pub keys_allocated: Option<u64>,              // KeysAllocated
+    pub flags: Option<String>,                    // Flags: "BOOT, GELIBOOT" or "NONE"
+    pub used_key: Option<u64>,                    // UsedKey
+    pub version: Option<u64>,                     // Version
+    pub crypto: Option<String>,                   // Crypto
+    pub authentication_algorithm: Option<String>, // AuthenticationAlgorithm
+    pub key_length: Option<u64>,                  // KeyLength
+    pub encryption_algorithm: Option<String>,     // EncryptionAlgorithm
+    pub status: Option<String>, // State: "ACTIVE" (distinct from PART's lowercase "state")
+    // GATE
+    pub access: Option<String>, // "read-write"
+    pub timeout: Option<u64>,
+    pub info: Option<String>,
+    pub queue_count: Option<u64>,
+    pub queue_size: Option<u64>,
+    pub unit: Option<u64>,
+    pub read_provider: Option<String>,
+    pub read_offset: Option<u64>,
+    // CCD
+    pub interleave: Option<u64>,
+    // SCHED
+    pub algo: Option<String>,
+    /// Any other keys, by XML element name.
+    pub unknown: BTreeMap<String, String>,
+}
+
+impl<'de> Deserialize<'de> for GeomConfig {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let mut e = ConfigEntries::deserialize(d)?;
+        return Ok(GeomConfig {
+            scheme: e.string("scheme"),
+            entries: e.u64("entries").map_err(D::Error::custom)?,
+            first: e.u64("first").map_err(D::Error::custom)?,
+            last: e.u64("last").map_err(D::Error::custom)?,
+            fwsectors: e.u64("fwsectors").map_err(D::Error::custom)?,
+            fwheads: e.u64("fwheads").map_err(D::Error::custom)?,
+            state: e.string("state"),
+            modified: e.bool("modified").map_err(D::Error::custom)?,
+            keys_total: e.u64("KeysTotal").map_err(D::Error::custom)?,
+            keys_allocated: e.u64("KeysAllocated").map_err(D::Error::custom)?,
+            flags: e.string("Flags"),
+            used_key: e.u64("UsedKey").map_err(D::Error::custom)?,
+            version: e.u64("Version").map_err(D::Error::custom)?,
+            crypto: e.string("Crypto"),
+            authentication_algorithm: e.string("AuthenticationAlgorithm"),
+            key_length: e.u64("KeyLength").map_err(D::Error::custom)?,
+            encryption_algorithm: e.string("EncryptionAlgorithm"),
+            status: e.string("State"),
+            access: e.string("access"),
+            timeout: e.u64("timeout").map_err(D::Error::custom)?,
+            info: e.string("info"),
+            queue_count: e.u64("queue_count").map_err(D::Error::custom)?,
+            queue_size: e.u64("queue_size").map_err(D::Error::custom)?,
+            unit: e.u64("unit").map_err(D::Error::custom)?,
+            read_provider: e.string("read_provider"),
+            read_offset: e.u64("read_offset").map_err(D::Error::custom)?,
+            interleave: e.u64("interleave").map_err(D::Error::custom)?,
+            algo: e.string("algo"),
+            unknown: e.into_unknown(),
+        });
+    }
 }
 
 /// A pointer from one geom to a `Provider` of a lower-level geom.
@@ -86,7 +156,7 @@ pub struct GeomConfig {
 ///
 /// It is associated with the `Geom` with `id` equal to `geom_ref.ref_`, and points to the
 /// `Provider` with `id` equal to `provider_ref.ref_`.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Consumer {
     pub id: String, // uintptr_t
     #[serde(rename = "geom")]
@@ -101,7 +171,7 @@ pub struct Consumer {
 /// In the logical directed graph, it is an in-edge.
 ///
 /// It is associated with the `Geom` with `id` equal to `geom_ref.ref_`.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Provider {
     pub id: String, // uintptr_t
     #[serde(rename = "geom")]
@@ -112,15 +182,22 @@ pub struct Provider {
     pub sectorsize: u64,
     pub stripesize: u64,
     pub stripeoffset: u64,
+    /// Empty if the provider (or its geom) is withering, in which case the kernel omits it.
+    #[serde(default)]
     pub config: ProviderConfig,
+    /// Present (as `<wither/>`) if the provider is being torn down, e.g., after its disk was
+    /// detached, until its consumers close it.
+    #[serde(default)]
+    pub wither: Option<()>,
 }
 
 // Ideally this would be some enum type based on the Class, but, ya know.  (serde(flatten) / enum
 // interaction doesn't seem flawless at this time.)
 /// A set of key-value metadata associated with a specific `Provider`.
 ///
-/// The semantics and available values vary depending on the class.
-#[derive(Debug, Deserialize, PartialEq)]
+/// The semantics and available values vary depending on the class.  Keys this library does not
+/// know about are preserved in `unknown`.
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct ProviderConfig {
     // DISK
     pub fwheads: Option<u64>,
@@ -133,24 +210,54 @@ pub struct ProviderConfig {
     pub start: Option<u64>,
     pub end: Option<u64>,
     pub index: Option<u64>,
-    #[serde(rename = "type")]
-    pub type_: Option<String>,
+    pub type_: Option<String>, // type
     pub offset: Option<u64>,
     pub length: Option<u64>,
     pub label: Option<String>,
     pub rawtype: Option<String>,
     pub rawuuid: Option<String>,
     pub efimedia: Option<String>,
+    pub attrib: Vec<String>, // Repeated, e.g., "bootme", "active"
     // LABEL
     // index, length, offset shared with PART above
     pub seclength: Option<u64>,
     pub secoffset: Option<u64>,
+    /// Any other keys, by XML element name.
+    pub unknown: BTreeMap<String, String>,
+}
+
+impl<'de> Deserialize<'de> for ProviderConfig {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let mut e = ConfigEntries::deserialize(d)?;
+        return Ok(ProviderConfig {
+            fwheads: e.u64("fwheads").map_err(D::Error::custom)?,
+            fwsectors: e.u64("fwsectors").map_err(D::Error::custom)?,
+            rotationrate: e.string("rotationrate"),
+            ident: e.string("ident"),
+            lunid: e.string("lunid"),
+            descr: e.string("descr"),
+            start: e.u64("start").map_err(D::Error::custom)?,
+            end: e.u64("end").map_err(D::Error::custom)?,
+            index: e.u64("index").map_err(D::Error::custom)?,
+            type_: e.string("type"),
+            offset: e.u64("offset").map_err(D::Error::custom)?,
+            length: e.u64("length").map_err(D::Error::custom)?,
+            label: e.string("label"),
+            rawtype: e.string("rawtype"),
+            rawuuid: e.string("rawuuid"),
+            efimedia: e.string("efimedia"),
+            attrib: e.strings("attrib"),
+            seclength: e.u64("seclength").map_err(D::Error::custom)?,
+            secoffset: e.u64("secoffset").map_err(D::Error::custom)?,
+            unknown: e.into_unknown(),
+        });
+    }
 }
 
 /// A `GeomRef` is just a logical pointer to a `Geom`.
 ///
 /// `GeomRef::ref_` references the same namespace as `Geom::id`.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct GeomRef {
     #[serde(rename = "ref")]
     pub ref_: String, // uintptr_t
@@ -159,12 +266,97 @@ pub struct GeomRef {
 /// A `ProviderRef` is just a logical pointer to a `Provider`.
 ///
 /// `ProviderRef::ref_` references the same namespace as `Provider::id`.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct ProviderRef {
     #[serde(rename = "ref")]
     pub ref_: String, // uintptr_t
 }
 
+/// The children of a `<config>` element, in document order, as (element name, text) pairs.
+///
+/// Config sections are flat, but some keys may repeat, so we collect them into a list and let the
+/// `GeomConfig` and `ProviderConfig` deserializers pick out the keys they know about.  Whatever is
+/// left over is preserved as "unknown."
+struct ConfigEntries(Vec<(String, String)>);
+
+impl<'de> Deserialize<'de> for ConfigEntries {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = ConfigEntries;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "a config section")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(kv) = map.next_entry::<String, String>()? {
+                    entries.push(kv);
+                }
+                return Ok(ConfigEntries(entries));
+            }
+        }
+
+        return d.deserialize_map(EntriesVisitor);
+    }
+}
+
+impl ConfigEntries {
+    /// Removes and returns the value of the first `key` entry, if any.
+    fn string(&mut self, key: &str) -> Option<String> {
+        let pos = self.0.iter().position(|(k, _)| k == key)?;
+        return Some(self.0.remove(pos).1);
+    }
+
+    /// Removes and returns the values of every `key` entry, in document order.
+    fn strings(&mut self, key: &str) -> Vec<String> {
+        let mut result = Vec::new();
+        while let Some(v) = self.string(key) {
+            result.push(v);
+        }
+        return result;
+    }
+
+    /// Like `string()`, but parses the value as a decimal or `0x`-prefixed hexadecimal integer.
+    ///
+    /// Some kernels emit numeric values with surrounding whitespace, or in hexadecimal; both are
+    /// tolerated.  On failure, the error names the key and the raw value.
+    fn u64(&mut self, key: &str) -> Result<Option<u64>, String> {
+        let raw = match self.string(key) {
+            None => return Ok(None),
+            Some(raw) => raw,
+        };
+        let s = raw.trim();
+        let v = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse::<u64>().ok(),
+        };
+        return v
+            .map(Some)
+            .ok_or_else(|| format!("invalid value for {}: {:?}", key, raw));
+    }
+
+    /// Like `string()`, but parses the value as a boolean.
+    fn bool(&mut self, key: &str) -> Result<Option<bool>, String> {
+        let raw = match self.string(key) {
+            None => return Ok(None),
+            Some(raw) => raw,
+        };
+        return match raw.trim() {
+            "true" | "1" => Ok(Some(true)),
+            "false" | "0" => Ok(Some(false)),
+            _ => Err(format!("invalid value for {}: {:?}", key, raw)),
+        };
+    }
+
+    /// Consumes the remaining entries.  If a key repeats, the last value wins.
+    fn into_unknown(self) -> BTreeMap<String, String> {
+        return self.0.into_iter().collect();
+    }
+}
+
 /// Parse a GEOM XML string configuration into a geom::raw::Mesh structure.
 ///
 /// # Arguments
@@ -184,6 +376,205 @@ pub fn parse_xml(xml: &str) -> Result<Mesh, Error> {
     return Ok(quick_xml::de::from_str::<Mesh>(xml)?);
 }
 
+/// Escapes text or an attribute value for XML, as the kernel does (see `g_conf_cat_escaped()`).
+fn escape_xml(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(c),
+        }
+    }
+    return result;
+}
+
+/// Appends `<key>value</key>` to a config section, if `value` is present.
+fn config_entry<T: ToString>(entries: &mut Vec<(String, String)>, key: &str, value: &Option<T>) {
+    if let Some(v) = value {
+        entries.push((key.to_owned(), v.to_string()));
+    }
+}
+
+impl GeomConfig {
+    /// The inverse of the `Deserialize` impl: (element name, text) pairs, in kernel order where
+    /// known.  Unknown keys follow, sorted by name.
+    fn entries(&self) -> Vec<(String, String)> {
+        let mut e = Vec::new();
+        config_entry(&mut e, "scheme", &self.scheme);
+        config_entry(&mut e, "entries", &self.entries);
+        config_entry(&mut e, "first", &self.first);
+        config_entry(&mut e, "last", &self.last);
+        config_entry(&mut e, "fwsectors", &self.fwsectors);
+        config_entry(&mut e, "fwheads", &self.fwheads);
+        config_entry(&mut e, "state", &self.state);
+        config_entry(&mut e, "modified", &self.modified);
+        config_entry(&mut e, "KeysTotal", &self.keys_total);
+        config_entry(&mut e, "KeysAllocated", &self.keys_allocated);
+        config_entry(&mut e, "Flags", &self.flags);
+        config_entry(&mut e, "UsedKey", &self.used_key);
+        config_entry(&mut e, "Version", &self.version);
+        config_entry(&mut e, "Crypto", &self.crypto);
+        config_entry(
+            &mut e,
+            "AuthenticationAlgorithm",
+            &self.authentication_algorithm,
+        );
+        config_entry(&mut e, "KeyLength", &self.key_length);
+        config_entry(&mut e, "EncryptionAlgorithm", &self.encryption_algorithm);
+        config_entry(&mut e, "State", &self.status);
+        config_entry(&mut e, "access", &self.access);
+        config_entry(&mut e, "timeout", &self.timeout);
+        config_entry(&mut e, "info", &self.info);
+        config_entry(&mut e, "queue_count", &self.queue_count);
+        config_entry(&mut e, "queue_size", &self.queue_size);
+        config_entry(&mut e, "unit", &self.unit);
+        config_entry(&mut e, "read_provider", &self.read_provider);
+        config_entry(&mut e, "read_offset", &self.read_offset);
+        config_entry(&mut e, "interleave", &self.interleave);
+        config_entry(&mut e, "algo", &self.algo);
+        e.extend(
+            self.unknown
+                .iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned())),
+        );
+        return e;
+    }
+}
+
+impl ProviderConfig {
+    /// Like `GeomConfig::entries()`.
+    fn entries(&self) -> Vec<(String, String)> {
+        let mut e = Vec::new();
+        config_entry(&mut e, "fwheads", &self.fwheads);
+        config_entry(&mut e, "fwsectors", &self.fwsectors);
+        config_entry(&mut e, "rotationrate", &self.rotationrate);
+        config_entry(&mut e, "ident", &self.ident);
+        config_entry(&mut e, "lunid", &self.lunid);
+        config_entry(&mut e, "descr", &self.descr);
+        config_entry(&mut e, "start", &self.start);
+        config_entry(&mut e, "end", &self.end);
+        config_entry(&mut e, "index", &self.index);
+        config_entry(&mut e, "type", &self.type_);
+        config_entry(&mut e, "offset", &self.offset);
+        config_entry(&mut e, "length", &self.length);
+        config_entry(&mut e, "seclength", &self.seclength);
+        config_entry(&mut e, "secoffset", &self.secoffset);
+        for attrib in &self.attrib {
+            e.push(("attrib".to_owned(), attrib.to_owned()));
+        }
+        config_entry(&mut e, "label", &self.label);
+        config_entry(&mut e, "rawtype", &self.rawtype);
+        config_entry(&mut e, "rawuuid", &self.rawuuid);
+        config_entry(&mut e, "efimedia", &self.efimedia);
+        e.extend(
+            self.unknown
+                .iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned())),
+        );
+        return e;
+    }
+}
+
+fn write_config(out: &mut String, indent: &str, entries: &[(String, String)]) {
+    out.push_str(indent);
+    out.push_str("<config>\n");
+    for (k, v) in entries {
+        out.push_str(&format!("{}  <{}>{}</{}>\n", indent, k, escape_xml(v), k));
+    }
+    out.push_str(indent);
+    out.push_str("</config>\n");
+}
+
+/// Serialize a `Mesh` back into the XML format of the `kern.geom.confxml` `sysctl` node.
+///
+/// `parse_xml()` of the result yields an equal `Mesh`.  This can be used to build test fixtures,
+/// or to share a real dump after scrubbing it (e.g., clearing `ProviderConfig::ident`).  Config
+/// keys this library does not model are written after the others, so the output may not be
+/// byte-for-byte identical to the kernel's.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// let mesh = geom::raw::parse_xml("<mesh></mesh>").unwrap();
+/// assert_eq!(geom::raw::to_xml(&mesh), "<mesh>\n</mesh>\n");
+/// ```
+pub fn to_xml(mesh: &Mesh) -> String {
+    let mut out = String::from("<mesh>\n");
+    for class in &mesh.classes {
+        out.push_str(&format!("  <class id=\"{}\">\n", escape_xml(&class.id)));
+        out.push_str(&format!("    <name>{}</name>\n", escape_xml(&class.name)));
+        for geom in &class.geoms {
+            out.push_str(&format!("    <geom id=\"{}\">\n", escape_xml(&geom.id)));
+            out.push_str(&format!(
+                "      <class ref=\"{}\"/>\n",
+                escape_xml(&geom.class_ref.ref_)
+            ));
+            out.push_str(&format!("      <name>{}</name>\n", escape_xml(&geom.name)));
+            out.push_str(&format!("      <rank>{}</rank>\n", geom.rank));
+            if geom.wither.is_some() {
+                out.push_str("      <wither/>\n");
+            }
+            if let Some(config) = &geom.config {
+                write_config(&mut out, "      ", &config.entries());
+            }
+            for cp in &geom.consumers {
+                out.push_str(&format!("      <consumer id=\"{}\">\n", escape_xml(&cp.id)));
+                out.push_str(&format!(
+                    "        <geom ref=\"{}\"/>\n",
+                    escape_xml(&cp.geom_ref.ref_)
+                ));
+                out.push_str(&format!(
+                    "        <provider ref=\"{}\"/>\n",
+                    escape_xml(&cp.provider_ref.ref_)
+                ));
+                out.push_str(&format!("        <mode>{}</mode>\n", escape_xml(&cp.mode)));
+                out.push_str("      </consumer>\n");
+            }
+            for pp in &geom.providers {
+                out.push_str(&format!("      <provider id=\"{}\">\n", escape_xml(&pp.id)));
+                out.push_str(&format!(
+                    "        <geom ref=\"{}\"/>\n",
+                    escape_xml(&pp.geom_ref.ref_)
+                ));
+                out.push_str(&format!("        <mode>{}</mode>\n", escape_xml(&pp.mode)));
+                out.push_str(&format!("        <name>{}</name>\n", escape_xml(&pp.name)));
+                out.push_str(&format!(
+                    "        <mediasize>{}</mediasize>\n",
+                    pp.mediasize
+                ));
+                out.push_str(&format!(
+                    "        <sectorsize>{}</sectorsize>\n",
+                    pp.sectorsize
+                ));
+                out.push_str(&format!(
+                    "        <stripesize>{}</stripesize>\n",
+                    pp.stripesize
+                ));
+                out.push_str(&format!(
+                    "        <stripeoffset>{}</stripeoffset>\n",
+                    pp.stripeoffset
+                ));
+                if pp.wither.is_some() {
+                    out.push_str("        <wither/>\n");
+                } else {
+                    write_config(&mut out, "        ", &pp.config.entries());
+                }
+                out.push_str("      </provider>\n");
+            }
+            out.push_str("    </geom>\n");
+        }
+        out.push_str("  </class>\n");
+    }
+    out.push_str("</mesh>\n");
+    return out;
+}
+
 /// Returns a structure representing the raw GEOM mesh on the running system.
 ///
 /// # Examples
@@ -208,12 +599,13 @@ pub fn parse_xml(xml: &str) -> Result<Mesh, Error> {
 #[cfg(target_os = "freebsd")]
 pub fn get_mesh() -> Result<Mesh, Error> {
     let xml = crate::get_confxml()?;
-    return Ok(parse_xml(&xml)?);
+    return parse_xml(&xml);
 }
 
 #[cfg(test)]
 mod tests {
     use crate::structs;
+    use std::collections::BTreeMap;
 
     #[test]
     fn xml_mesh_basic() {
@@ -251,6 +643,86 @@ mod tests {
         assert_eq!(p.scheme.unwrap(), "GPT");
     }
 
+    #[test]
+    fn xml_geom_config_eli() {
+        let xml = r#"<config>
+                        <KeysTotal>1</KeysTotal>
+                        <KeysAllocated>1</KeysAllocated>
+                        <Flags>BOOT, GELIBOOT</Flags>
+                        <UsedKey>0</UsedKey>
+                        <Version>7</Version>
+                        <Crypto>accelerated software</Crypto>
+                        <KeyLength>256</KeyLength>
+                        <EncryptionAlgorithm>AES-XTS</EncryptionAlgorithm>
+                        <State>ACTIVE</State>
+                    </config>"#;
+        let p = quick_xml::de::from_str::<structs::GeomConfig>(xml).unwrap();
+        assert_eq!(p.version, Some(7));
+        assert_eq!(p.flags.unwrap(), "BOOT, GELIBOOT");
+        assert_eq!(p.encryption_algorithm.unwrap(), "AES-XTS");
+        assert_eq!(p.status.unwrap(), "ACTIVE");
+        assert_eq!(p.authentication_algorithm, None);
+        assert_eq!(p.state, None);
+    }
+
+    #[test]
+    fn xml_tolerant_numbers() {
+        let xml = r#"<config>
+                        <start> 40 </start>
+                        <end>0x1000</end>
+                        <index>
+                          2
+                        </index>
+                    </config>"#;
+        let p = quick_xml::de::from_str::<structs::ProviderConfig>(xml).unwrap();
+        assert_eq!(p.start, Some(40));
+        assert_eq!(p.end, Some(0x1000));
+        assert_eq!(p.index, Some(2));
+        assert_eq!(p.offset, None);
+
+        let xml = r#"<config><length>12k</length></config>"#;
+        let e = quick_xml::de::from_str::<structs::ProviderConfig>(xml).unwrap_err();
+        assert!(format!("{}", e).contains(r#"invalid value for length: "12k""#));
+    }
+
+    #[test]
+    fn xml_provider_config_attrib() {
+        let xml = r#"<config>
+                        <index>1</index>
+                        <attrib>bootme</attrib>
+                        <type>freebsd-boot</type>
+                        <attrib>bootonce</attrib>
+                    </config>"#;
+        let p = quick_xml::de::from_str::<structs::ProviderConfig>(xml).unwrap();
+        assert_eq!(p.attrib, vec!["bootme".to_owned(), "bootonce".to_owned()]);
+        assert!(p.unknown.is_empty());
+    }
+
+    #[test]
+    fn xml_unknown_config() {
+        let xml = r#"<config>
+                        <scheme>GPT</scheme>
+                        <frobnicate>yes</frobnicate>
+                        <Widgets>3</Widgets>
+                    </config>"#;
+        let p = quick_xml::de::from_str::<structs::GeomConfig>(xml).unwrap();
+        assert_eq!(p.scheme.unwrap(), "GPT");
+        assert_eq!(p.unknown.len(), 2);
+        assert_eq!(p.unknown["frobnicate"], "yes");
+        assert_eq!(p.unknown["Widgets"], "3");
+
+        let xml = r#"<config>
+                        <index>1</index>
+                        <sheep>many</sheep>
+                    </config>"#;
+        let p = quick_xml::de::from_str::<structs::ProviderConfig>(xml).unwrap();
+        assert_eq!(p.index, Some(1));
+        assert_eq!(
+            p.unknown.into_iter().collect::<Vec<_>>(),
+            vec![("sheep".into(), "many".into())]
+        );
+    }
+
     #[test]
     fn xml_consumer() {
         let xml = r#"<consumer id="0x123">
@@ -306,9 +778,11 @@ mod tests {
                 rawtype: None,
                 rawuuid: None,
                 efimedia: None,
+                attrib: vec![],
                 // LABEL fields
                 seclength: None,
                 secoffset: None,
+                unknown: BTreeMap::new(),
             }
         );
     }
@@ -376,7 +850,29 @@ mod tests {
                     fwheads: None,
                     state: None,
                     modified: None,
+                    keys_total: None,
+                    keys_allocated: None,
+                    flags: None,
+                    used_key: None,
+                    version: None,
+                    crypto: None,
+                    authentication_algorithm: None,
+                    key_length: None,
+                    encryption_algorithm: None,
+                    status: None,
+                    access: None,
+                    timeout: None,
+                    info: None,
+                    queue_count: None,
+                    queue_size: None,
+                    unit: None,
+                    read_provider: None,
+                    read_offset: None,
+                    interleave: None,
+                    algo: None,
+                    unknown: BTreeMap::new(),
                 }),
+                wither: None,
                 consumers: vec![],
                 providers: vec![],
             }
@@ -426,4 +922,50 @@ mod tests {
         assert_eq!(p.classes[8].name, "Flashmap");
         assert_eq!(p.classes[9].name, "MD");
     }
+
+    #[test]
+    fn xml_round_trip() {
+        let xml = include_str!("test/fullsample.xml");
+        let mesh = structs::parse_xml(xml).unwrap();
+        let out = structs::to_xml(&mesh);
+        assert_eq!(structs::parse_xml(&out).unwrap(), mesh);
+        // Stable once normalized.
+        assert_eq!(structs::to_xml(&structs::parse_xml(&out).unwrap()), out);
+    }
+
+    #[test]
+    fn xml_write_escaped() {
+        let xml = r#"<mesh>
+          <class id="0x1">
+            <name>DISK</name>
+            <geom id="0x2">
+              <class ref="0x1"/>
+              <name>da0</name>
+              <rank>1</rank>
+              <wither/>
+              <provider id="0x3">
+                <geom ref="0x2"/>
+                <mode>r0w0e0</mode>
+                <name>da0</name>
+                <mediasize>1024</mediasize>
+                <sectorsize>512</sectorsize>
+                <stripesize>0</stripesize>
+                <stripeoffset>0</stripeoffset>
+                <config>
+                  <descr>Tom &amp; Jerry &lt;USB&gt;</descr>
+                  <attrib>bootme</attrib>
+                  <attrib>bootonce</attrib>
+                  <frob>1</frob>
+                </config>
+              </provider>
+            </geom>
+          </class>
+        </mesh>"#;
+        let mesh = structs::parse_xml(xml).unwrap();
+        let out = structs::to_xml(&mesh);
+        assert!(out.contains("<descr>Tom &amp; Jerry &lt;USB&gt;</descr>"));
+        assert!(out.contains("<wither/>"));
+        assert!(out.contains("<frob>1</frob>"));
+        assert_eq!(structs::parse_xml(&out).unwrap(), mesh);
+    }
 }
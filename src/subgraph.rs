@@ -0,0 +1,512 @@
+//! Derived `Graph`s: pruned and extracted copies of a snapshot, and per-tree views of it.
+//!
+//! `prune_smaller_than()`, `subgraph()`, and `subtree()` return new, self-contained `Graph`s (with
+//! consistent indices; see `Graph::verify()`) rather than borrowing views, so the results can be
+//! traversed, rendered, or serialized like any decoded graph.  `trees()` borrows instead, for
+//! cheaply grouping a graph's geoms by the tree they belong to.
+use crate::graph::{Edge, EdgeId, Geom, GeomClass, Graph, Mode, NodeId};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+/// The class name of the summary geoms `Graph::prune_smaller_than()` adds in place of pruned
+/// providers, as `GeomClass::Other(ELIDED_CLASS)`.
+pub const ELIDED_CLASS: &str = "ELIDED";
+
+impl Graph {
+    pub(crate) fn remove_edge(&mut self, id: &EdgeId) -> Option<Edge> {
+        let edge = self.edges.remove(id)?;
+        for (index, node) in &mut [
+            (&mut self.inedges, edge.provider_geom),
+            (&mut self.outedges, edge.consumer_geom),
+        ] {
+            if let Some(v) = index.get_mut(node) {
+                v.retain(|e| e != id);
+                if v.is_empty() {
+                    index.remove(node);
+                }
+            }
+        }
+        return Some(edge);
+    }
+
+    pub(crate) fn insert_edge(&mut self, id: EdgeId, edge: Edge) {
+        self.inedges.entry(edge.provider_geom).or_default().push(id);
+        self.outedges
+            .entry(edge.consumer_geom)
+            .or_default()
+            .push(id);
+        self.edges.insert(id, edge);
+    }
+
+    /// Returns a copy of the connected tree containing `id`: every geom reachable from it by
+    /// following edges in either direction, and every edge between them.  E.g., for a partition's
+    /// DEV geom, the whole disk with all of its partitions, labels, and filesystems.
+    ///
+    /// Geoms spanning several disks (mirrors, stripes) join their disks into one tree.  An
+    /// unknown `id` yields an empty graph.
+    pub fn subgraph(&self, id: &NodeId) -> Graph {
+        return self.induced(&self.component(id));
+    }
+
+    /// Every geom connected to `id`, including `id` itself (if it exists).
+    pub(crate) fn component(&self, id: &NodeId) -> BTreeSet<NodeId> {
+        let mut members = BTreeSet::new();
+        let mut queue = vec![*id];
+        while let Some(node) = queue.pop() {
+            if !self.nodes.contains_key(&node) || !members.insert(node) {
+                continue;
+            }
+            queue.extend(self.child_edges_iter(&node).map(|(_, e)| e.consumer_geom));
+            queue.extend(self.parent_edges_iter(&node).map(|(_, e)| e.provider_geom));
+        }
+        return members;
+    }
+
+    /// The tree containing `id`.
+    pub(crate) fn tree(&self, id: &NodeId) -> Tree<'_> {
+        return Tree {
+            graph: self,
+            members: self.component(id),
+        };
+    }
+
+    /// Returns an `Iterator` over the connected trees of the graph (in the order of their lowest
+    /// `NodeId`), each a `Tree` view of its roots and members.
+    ///
+    /// Most trees have a single root, such as a DISK or MD; geoms spanning several disks (mirrors,
+    /// stripes) join their disks' trees into one with several roots.
+    pub fn trees(&self) -> Box<dyn Iterator<Item = Tree<'_>> + '_> {
+        let mut seen = BTreeSet::new();
+        Box::new(self.nodes.keys().filter_map(move |id| {
+            if seen.contains(id) {
+                return None;
+            }
+            let members = self.component(id);
+            seen.extend(members.iter().cloned());
+            return Some(Tree {
+                graph: self,
+                members,
+            });
+        }))
+    }
+
+    /// Returns a copy of the tree rooted at `id`: the geom itself, its descendents, and the edges
+    /// between them.  An unknown `id` yields an empty graph.
+    ///
+    /// Ranks are kept as they are, so unless `id` is a root, the result has no roots by
+    /// `roots_iter()`'s definition.
+    pub fn subtree(&self, id: &NodeId) -> Graph {
+        let mut members: BTreeSet<NodeId> = self
+            .descendants_with_depth_iter(id)
+            .map(|(_, _, e, _)| e.consumer_geom)
+            .collect();
+        if self.nodes.contains_key(id) {
+            members.insert(*id);
+        }
+        return self.induced(&members);
+    }
+
+    /// The graph of `members` and every edge between two of them.
+    pub(crate) fn induced(&self, members: &BTreeSet<NodeId>) -> Graph {
+        let mut result = Graph::new();
+        for id in members {
+            result.nodes.insert(*id, self.nodes[id].clone());
+        }
+        for (id, edge) in &self.edges {
+            if members.contains(&edge.consumer_geom) && members.contains(&edge.provider_geom) {
+                result.insert_edge(*id, edge.clone());
+            }
+        }
+        for (id, orphan) in &self.orphans {
+            if members.contains(&orphan.provider_geom) {
+                result.orphans.insert(*id, orphan.clone());
+            }
+        }
+        result.reindex();
+        return result;
+    }
+
+    /// Returns a copy of this graph without providers smaller than `bytes` (e.g., 512 KiB
+    /// `freebsd-boot` partitions), or anything that only consumes such providers.  Roots are always
+    /// kept.
+    ///
+    /// If `elide` is set, each geom that lost providers gets a single summary child of class
+    /// `GeomClass::Other(ELIDED_CLASS)`, named like `"3 elided"`, whose `Edge` has the combined
+    /// `mediasize` of the pruned providers, so that space totals still add up.  Summary geoms and
+    /// their edges have synthetic ids that do not occur in the kernel's mesh.
+    pub fn prune_smaller_than(&self, bytes: u64, elide: bool) -> Graph {
+        let mut result = self.clone();
+
+        // Per provider geom: the distinct pruned providers, and their sector size.
+        let mut pruned: BTreeMap<NodeId, (BTreeMap<u64, u64>, u64)> = BTreeMap::new();
+        let mut orphans = Vec::new();
+        let small: Vec<EdgeId> = self
+            .edges
+            .iter()
+            .filter(|(_, e)| e.mediasize < bytes)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &small {
+            let edge = result.remove_edge(id).unwrap();
+            let entry = pruned.entry(edge.provider_geom).or_default();
+            entry.0.insert(id.1, edge.mediasize);
+            entry.1 = edge.sectorsize;
+            orphans.push(edge.consumer_geom);
+        }
+
+        // Anything left consuming nothing was layered on pruned providers.
+        while let Some(node) = orphans.pop() {
+            if !result.nodes.contains_key(&node) || result.outedges.contains_key(&node) {
+                continue;
+            }
+            let children = result.inedges.get(&node).cloned().unwrap_or_default();
+            for id in &children {
+                let edge = result.remove_edge(id).unwrap();
+                orphans.push(edge.consumer_geom);
+            }
+            result.nodes.remove(&node);
+            pruned.remove(&node);
+        }
+
+        let nodes = &result.nodes;
+        result
+            .orphans
+            .retain(|_, o| o.mediasize >= bytes && nodes.contains_key(&o.provider_geom));
+
+        if elide {
+            let mut ids = SyntheticIds::new(self);
+            for (parent, (providers, sectorsize)) in &pruned {
+                let rank = result.nodes[parent].rank + 1;
+                let name = format!("{} elided", providers.len());
+                let (node, provider, consumer) = (ids.next(), ids.next(), ids.next());
+                result.nodes.insert(
+                    node,
+                    Geom {
+                        class: GeomClass::Other(ELIDED_CLASS.to_owned()),
+                        name: name.clone(),
+                        rank,
+                        metadata: None,
+                        unknown_config: BTreeMap::new(),
+                        raw_index: None,
+                        mounts: Vec::new(),
+                        withered: false,
+                    },
+                );
+                result.insert_edge(
+                    (consumer, provider),
+                    Edge {
+                        name,
+                        mode: Mode::from_str("r0w0e0").unwrap(),
+                        consumer_mode: Mode::from_str("r0w0e0").unwrap(),
+                        mediasize: providers.values().sum(),
+                        sectorsize: *sectorsize,
+                        stripesize: 0,
+                        stripeoffset: 0,
+                        metadata: None,
+                        unknown_config: BTreeMap::new(),
+                        consumer_geom: node,
+                        provider_geom: *parent,
+                        raw_index: None,
+                        withered: false,
+                        devstat: None,
+                    },
+                );
+            }
+        }
+
+        result.reindex();
+        return result;
+    }
+}
+
+/// Capacity roll-up of one `Tree`; see `Tree::capacity()`.  All sizes are in bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Capacity {
+    /// The total size of the roots' providers, e.g., of the disk(s)
+    pub media: u64,
+    /// The size of the partitions in partition tables directly on the roots.  (Partitions of
+    /// nested tables, e.g., a BSD label inside an MBR slice, are already counted here by their
+    /// enclosing slice.)
+    pub partitioned: u64,
+    /// The unallocated remainder of those partition tables, including space reserved for the
+    /// tables themselves.  Roots without a partition table contribute nothing.
+    pub slack: u64,
+    /// The size of providers of other, non-DEV geoms in the tree, such as LABEL, ELI, or MIRROR.
+    /// Each layer of a stack (e.g., a label on an ELI provider) is counted.
+    pub exposed: u64,
+}
+
+/// One connected tree of a `Graph`, as yielded by `Graph::trees()`.
+#[derive(Clone, Debug)]
+pub struct Tree<'a> {
+    graph: &'a Graph,
+    members: BTreeSet<NodeId>,
+}
+
+impl<'a> Tree<'a> {
+    /// Returns an `Iterator` which yields each `(&NodeId, &Geom)` at the top of the tree (those
+    /// consuming nothing), e.g., the DISK or MD geom(s).
+    pub fn roots(&self) -> Box<dyn Iterator<Item = (&'a NodeId, &'a Geom)> + '_> {
+        let graph = self.graph;
+        Box::new(
+            self.members()
+                .filter(move |(id, _)| graph.parent_edges_iter(id).len() == 0),
+        )
+    }
+
+    /// Returns an `Iterator` which yields each `(&NodeId, &Geom)` in the tree, in `NodeId` order.
+    pub fn members(&self) -> Box<dyn Iterator<Item = (&'a NodeId, &'a Geom)> + '_> {
+        let graph = self.graph;
+        Box::new(
+            self.members
+                .iter()
+                .map(move |id| graph.nodes.get_key_value(id).unwrap()),
+        )
+    }
+
+    /// Whether the geom `id` is in this tree.
+    pub fn contains(&self, id: &NodeId) -> bool {
+        return self.members.contains(id);
+    }
+
+    /// The number of geoms in the tree.
+    pub fn len(&self) -> usize {
+        return self.members.len();
+    }
+
+    /// Always false; trees have at least one geom.
+    pub fn is_empty(&self) -> bool {
+        return self.members.is_empty();
+    }
+
+    /// Rolls up the sizes of the tree's media, partitions, and other providers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// fn report(graph: &geom::Graph) {
+    ///     for tree in graph.trees() {
+    ///         let cap = tree.capacity();
+    ///         println!("{} bytes, {} unpartitioned", cap.media, cap.slack);
+    ///     }
+    /// }
+    /// ```
+    pub fn capacity(&self) -> Capacity {
+        let mut result = Capacity::default();
+        let roots: BTreeSet<&NodeId> = self.roots().map(|(id, _)| id).collect();
+        for (id, geom) in self.members() {
+            let size = self.provider_sizes(id).sum::<u64>();
+            if roots.contains(id) {
+                result.media += size;
+                continue;
+            }
+            match geom.class {
+                GeomClass::DEV => {}
+                GeomClass::PART => {
+                    let on_root = self
+                        .graph
+                        .parent_edges_iter(id)
+                        .any(|(_, e)| roots.contains(&e.provider_geom));
+                    let table = match self.graph.parent_edges_iter(id).next() {
+                        Some((_, e)) if on_root => e.mediasize,
+                        _ => continue,
+                    };
+                    result.partitioned += size;
+                    result.slack += table.saturating_sub(size);
+                }
+                _ => result.exposed += size,
+            }
+        }
+        return result;
+    }
+
+    /// The `mediasize` of each provider of the geom `id`, counting providers with several
+    /// consumers once.
+    fn provider_sizes(&self, id: &NodeId) -> impl Iterator<Item = u64> + 'a {
+        let mut seen = BTreeSet::new();
+        self.graph
+            .child_edges_iter(id)
+            .filter(move |(eid, _)| seen.insert(eid.1))
+            .map(|(_, e)| e.mediasize)
+    }
+}
+
+/// Allocates ids, counting down from `u64::MAX`, that are not used by any geom, provider, or
+/// consumer of a graph.
+struct SyntheticIds {
+    used: BTreeSet<u64>,
+    next: u64,
+}
+
+impl SyntheticIds {
+    fn new(graph: &Graph) -> Self {
+        let mut used: BTreeSet<u64> = graph.nodes.keys().cloned().collect();
+        for (c, p) in graph.edges.keys() {
+            used.insert(*c);
+            used.insert(*p);
+        }
+        return SyntheticIds {
+            used,
+            next: u64::MAX,
+        };
+    }
+
+    fn next(&mut self) -> u64 {
+        while self.used.contains(&self.next) {
+            self.next -= 1;
+        }
+        let id = self.next;
+        self.used.insert(id);
+        return id;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, raw, subgraph::ELIDED_CLASS};
+    use std::collections::BTreeSet;
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn extract() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let (dev, _) = g
+            .find_by_name("nvd1p2")
+            .find(|(_, n)| n.class == graph::GeomClass::DEV)
+            .unwrap();
+        let tree = g.subgraph(dev);
+        assert!(tree.verify().is_empty());
+        let roots: Vec<_> = tree.roots_iter().map(|(_, n)| n.name.clone()).collect();
+        assert_eq!(roots, vec!["nvd1".to_owned()]);
+        let (nvd1, _) = tree.roots_iter().next().unwrap();
+        assert_eq!(
+            tree.nodes.len(),
+            1 + g.descendants_with_depth_iter(nvd1).count()
+        );
+        assert!(tree.find_by_name("ffs.gpt-partition-2").next().is_some());
+        assert!(tree.find_by_name("nvd0").next().is_none());
+
+        // Rooted at the PART geom: no disk, nor the disk's own DEV node.
+        let (part, _) = g
+            .find_by_name("nvd1")
+            .find(|(_, n)| n.class == graph::GeomClass::PART)
+            .unwrap();
+        let sub = g.subtree(part);
+        assert!(sub.verify().is_empty());
+        assert_eq!(
+            sub.nodes.len(),
+            1 + g.descendants_with_depth_iter(part).count()
+        );
+        assert!(sub
+            .find_by_name("nvd1")
+            .all(|(_, n)| n.class == graph::GeomClass::PART));
+        assert_eq!(sub.edges.len(), g.descendants_with_depth_iter(part).count());
+
+        assert!(g.subgraph(&0).nodes.is_empty());
+        assert!(g.subtree(&0).nodes.is_empty());
+    }
+
+    #[test]
+    fn trees() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        let trees: Vec<_> = g.trees().collect();
+        // Every geom is in exactly one tree.
+        assert_eq!(trees.iter().map(|t| t.len()).sum::<usize>(), g.nodes.len());
+        for id in g.nodes.keys() {
+            assert_eq!(trees.iter().filter(|t| t.contains(id)).count(), 1);
+        }
+
+        let disks: Vec<_> = trees
+            .iter()
+            .filter(|t| t.roots().any(|(_, n)| n.class == graph::GeomClass::DISK))
+            .collect();
+        assert_eq!(disks.len(), 3);
+        for t in &disks {
+            let roots: Vec<_> = t.roots().collect();
+            assert_eq!(roots.len(), 1);
+            assert_eq!(
+                t.len(),
+                1 + g.descendants_with_depth_iter(roots[0].0).count()
+            );
+            assert!(t.members().any(|(_, n)| n.class == graph::GeomClass::PART));
+        }
+    }
+
+    #[test]
+    fn capacity() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        for tree in g.trees() {
+            let cap = tree.capacity();
+            assert!(cap.partitioned + cap.slack <= cap.media);
+        }
+
+        let (nvd0, _) = g
+            .find_by_class_name(&graph::GeomClass::DISK, "nvd0")
+            .unwrap();
+        let tree = g.trees().find(|t| t.contains(nvd0)).unwrap();
+        let cap = tree.capacity();
+        let media = g.child_edges_iter(nvd0).next().unwrap().1.mediasize;
+        let mut parts = BTreeSet::new();
+        for e in g.edges.values() {
+            if e.name.starts_with("nvd0p") {
+                parts.insert((e.name.clone(), e.mediasize));
+            }
+        }
+        assert_eq!(cap.media, media);
+        assert_eq!(cap.partitioned, parts.iter().map(|p| p.1).sum::<u64>());
+        assert_eq!(cap.slack, media - cap.partitioned);
+        // Labels of the partitions.
+        assert!(cap.exposed > 0);
+    }
+
+    #[test]
+    fn prune() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let has = |g: &graph::Graph, name: &str| g.edges.values().any(|e| e.name == name);
+
+        // nvd0p4 is a 256 KiB freebsd-boot partition, with two labels.
+        let pruned = g.prune_smaller_than(1024 * 1024, false);
+        assert!(pruned.verify().is_empty());
+        assert!(!has(&pruned, "nvd0p4"));
+        assert!(!has(&pruned, "gpt-partition-4"));
+        assert!(!has(&pruned, "gptid/aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa"));
+        assert!(has(&pruned, "nvd0p3"));
+        assert_eq!(pruned.roots_iter().count(), g.roots_iter().count());
+        assert!(pruned.find_by_name("gpt-partition-4").next().is_none());
+        // The original is untouched.
+        assert!(has(&g, "nvd0p4"));
+
+        let elided = g.prune_smaller_than(1024 * 1024, true);
+        assert!(elided.verify().is_empty());
+        let summaries: Vec<_> = elided
+            .edges
+            .values()
+            .filter(|e| {
+                elided.nodes[&e.consumer_geom].class == graph::GeomClass::Other(ELIDED_CLASS.into())
+            })
+            .collect();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].name, "1 elided");
+        assert_eq!(summaries[0].mediasize, 262144);
+        assert_eq!(
+            elided.nodes[&summaries[0].provider_geom].class,
+            graph::GeomClass::PART
+        );
+        assert_eq!(elided.nodes[&summaries[0].provider_geom].name, "nvd0");
+
+        // Nothing is that small.
+        let same = g.prune_smaller_than(512, true);
+        assert_eq!(same.nodes.len(), g.nodes.len());
+        assert_eq!(same.edges.len(), g.edges.len());
+    }
+}
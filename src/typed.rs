@@ -0,0 +1,361 @@
+//! Class-specific views of `Geom`s.
+//!
+//! `Geom::typed()` pairs a `Geom` with the metadata its class is known to carry, so a `match` on
+//! the result is checked against the classes rather than against `GeomMetadata` variants that a
+//! given class may or may not have.
+use crate::graph::{Geom, GeomClass, GeomMetadata, Mode, PartMetadata};
+use std::ops::Deref;
+
+/// A `Geom`, viewed according to its `GeomClass`; see `Geom::typed()`.
+///
+/// More classes may gain views in the future, so matches need a wildcard arm.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum TypedGeom<'a> {
+    /// A physical (or virtual) disk
+    Disk(DiskGeom<'a>),
+    /// A partition table
+    Part(PartGeom<'a>),
+    /// A `/dev` node
+    Dev(DevGeom<'a>),
+    /// A `glabel(8)` geom, providing labels for other providers
+    Label(LabelGeom<'a>),
+    /// A mounted filesystem
+    Vfs(VfsGeom<'a>),
+    /// A swap device in use
+    Swap(SwapGeom<'a>),
+    /// A `ccdconfig(8)` disk
+    Ccd(CcdGeom<'a>),
+    /// A `gsched(8)` I/O scheduler
+    Sched(SchedGeom<'a>),
+    /// A `geli(8)` encrypted provider
+    Eli(EliGeom<'a>),
+    /// A `geom_gate` device, serviced by a userspace daemon
+    Gate(GateGeom<'a>),
+    /// A `gmirror(8)` mirror
+    Mirror(MirrorGeom<'a>),
+    /// A ZFS vdev (`ZFSVDEV`) or volume (`ZFSZVOL`)
+    Zfs(ZfsGeom<'a>),
+    /// An `mdconfig(8)` memory disk
+    Md(MdGeom<'a>),
+    /// Any other class, or a geom lacking the metadata its class requires (e.g., a PART geom
+    /// without its table)
+    Other(&'a Geom),
+}
+
+// Each view dereferences to the underlying `Geom`, so `name`, `rank`, etc. remain at hand.
+macro_rules! geom_view {
+    ($name:ident) => {
+        impl<'a> Deref for $name<'a> {
+            type Target = Geom;
+
+            fn deref(&self) -> &Geom {
+                return self.geom;
+            }
+        }
+    };
+}
+
+/// A `GeomClass::DISK` geom.  Disk metadata is on its provider's `Edge`s; see
+/// `EdgeMetadata::as_disk()`.
+#[derive(Clone, Copy, Debug)]
+pub struct DiskGeom<'a> {
+    pub geom: &'a Geom,
+}
+geom_view!(DiskGeom);
+
+/// A `GeomClass::PART` geom, i.e., a partition table.
+#[derive(Clone, Copy, Debug)]
+pub struct PartGeom<'a> {
+    pub geom: &'a Geom,
+    /// The partition table metadata
+    pub table: &'a PartMetadata,
+}
+geom_view!(PartGeom);
+
+/// A `GeomClass::DEV` geom, i.e., a `/dev` node.
+#[derive(Clone, Copy, Debug)]
+pub struct DevGeom<'a> {
+    pub geom: &'a Geom,
+    /// The access held through the node by userland; see `GeomMetadata::DEV`.
+    pub mode: Mode,
+}
+geom_view!(DevGeom);
+
+/// A `GeomClass::LABEL` geom.  Label metadata is on its provider's `Edge`s; see
+/// `EdgeMetadata::as_label()`.
+#[derive(Clone, Copy, Debug)]
+pub struct LabelGeom<'a> {
+    pub geom: &'a Geom,
+}
+geom_view!(LabelGeom);
+
+/// A `GeomClass::VFS` geom, i.e., a mounted filesystem.
+#[derive(Clone, Copy, Debug)]
+pub struct VfsGeom<'a> {
+    pub geom: &'a Geom,
+    /// The filesystem type, e.g., `"ffs"`
+    pub fstype: &'a str,
+    /// The name of the provider mounted from
+    pub provider: &'a str,
+}
+geom_view!(VfsGeom);
+
+/// A `GeomClass::SWAP` geom.
+#[derive(Clone, Copy, Debug)]
+pub struct SwapGeom<'a> {
+    pub geom: &'a Geom,
+}
+geom_view!(SwapGeom);
+
+/// A `GeomClass::CCD` geom.
+#[derive(Clone, Copy, Debug)]
+pub struct CcdGeom<'a> {
+    pub geom: &'a Geom,
+    /// The number of component providers
+    pub components: u64,
+    /// The interleave factor, in sectors, if known
+    pub interleave: Option<u64>,
+}
+geom_view!(CcdGeom);
+
+/// A `GeomClass::SCHED` geom.
+#[derive(Clone, Copy, Debug)]
+pub struct SchedGeom<'a> {
+    pub geom: &'a Geom,
+    /// The scheduling algorithm, e.g., `"rr"`
+    pub algorithm: &'a str,
+}
+geom_view!(SchedGeom);
+
+/// A `GeomClass::ELI` geom.  The configuration is absent while the geom withers, and after
+/// `Graph::compact()`.
+#[derive(Clone, Copy, Debug)]
+pub struct EliGeom<'a> {
+    pub geom: &'a Geom,
+    /// E.g., `"AES-XTS"`, if known
+    pub encryption_algorithm: Option<&'a str>,
+    /// The data encryption key length, in bits, if known
+    pub key_length: Option<u64>,
+    /// The data authentication algorithm, if the `AUTH` flag is set
+    pub authentication_algorithm: Option<&'a str>,
+    /// `"ACTIVE"` or `"SUSPENDED"`, if reported
+    pub state: Option<&'a str>,
+}
+geom_view!(EliGeom);
+
+/// A `GeomClass::GATE` geom.  The configuration is absent after `Graph::compact()`.
+#[derive(Clone, Copy, Debug)]
+pub struct GateGeom<'a> {
+    pub geom: &'a Geom,
+    /// `"read-only"`, `"write-only"`, or `"read-write"`, if known
+    pub access: Option<&'a str>,
+    /// The daemon's description of the device, if known
+    pub info: Option<&'a str>,
+    /// The unit number, as in `/dev/ggate<unit>`, if known
+    pub unit: Option<u64>,
+}
+geom_view!(GateGeom);
+
+/// A `GeomClass::MIRROR` geom.
+#[derive(Clone, Copy, Debug)]
+pub struct MirrorGeom<'a> {
+    pub geom: &'a Geom,
+    /// E.g., `"COMPLETE"` or `"DEGRADED"`, if reported
+    pub state: Option<&'a str>,
+}
+geom_view!(MirrorGeom);
+
+/// A `GeomClass::ZFSVDEV` or `ZFSZVOL` geom.
+#[derive(Clone, Copy, Debug)]
+pub struct ZfsGeom<'a> {
+    pub geom: &'a Geom,
+    /// Whether this is a volume (`ZFSZVOL`), rather than a vdev consuming some provider
+    pub zvol: bool,
+}
+geom_view!(ZfsGeom);
+
+/// A `GeomClass::MD` geom, i.e., a memory disk.
+#[derive(Clone, Copy, Debug)]
+pub struct MdGeom<'a> {
+    pub geom: &'a Geom,
+}
+geom_view!(MdGeom);
+
+impl Geom {
+    /// Views this `Geom` according to its class.
+    pub fn typed(&self) -> TypedGeom<'_> {
+        let geom = self;
+        return match (&self.class, self.metadata.as_deref()) {
+            (GeomClass::DISK, _) => TypedGeom::Disk(DiskGeom { geom }),
+            (GeomClass::PART, Some(GeomMetadata::PART(table))) => {
+                TypedGeom::Part(PartGeom { geom, table })
+            }
+            (GeomClass::DEV, Some(GeomMetadata::DEV { mode })) => {
+                TypedGeom::Dev(DevGeom { geom, mode: *mode })
+            }
+            (GeomClass::LABEL, _) => TypedGeom::Label(LabelGeom { geom }),
+            (GeomClass::VFS, Some(GeomMetadata::VFS { fstype, provider })) => {
+                TypedGeom::Vfs(VfsGeom {
+                    geom,
+                    fstype,
+                    provider,
+                })
+            }
+            // `Graph::compact()` drops VFS metadata, which the kernel's name for the geom repeats.
+            (GeomClass::VFS, None) => match self.name.split_once('.') {
+                Some((fstype, provider)) => TypedGeom::Vfs(VfsGeom {
+                    geom,
+                    fstype,
+                    provider,
+                }),
+                None => TypedGeom::Other(geom),
+            },
+            (GeomClass::SWAP, _) => TypedGeom::Swap(SwapGeom { geom }),
+            (
+                GeomClass::CCD,
+                Some(GeomMetadata::CCD {
+                    components,
+                    interleave,
+                }),
+            ) => TypedGeom::Ccd(CcdGeom {
+                geom,
+                components: *components,
+                interleave: *interleave,
+            }),
+            (GeomClass::SCHED, Some(GeomMetadata::SCHED { algorithm })) => {
+                TypedGeom::Sched(SchedGeom { geom, algorithm })
+            }
+            (
+                GeomClass::ELI,
+                Some(GeomMetadata::ELI {
+                    encryption_algorithm,
+                    key_length,
+                    authentication_algorithm,
+                    state,
+                    ..
+                }),
+            ) => TypedGeom::Eli(EliGeom {
+                geom,
+                encryption_algorithm: Some(encryption_algorithm),
+                key_length: Some(*key_length),
+                authentication_algorithm: authentication_algorithm.as_deref(),
+                state: state.as_deref(),
+            }),
+            (GeomClass::ELI, _) => TypedGeom::Eli(EliGeom {
+                geom,
+                encryption_algorithm: None,
+                key_length: None,
+                authentication_algorithm: None,
+                state: self.unknown_config.get("State").map(String::as_str),
+            }),
+            (
+                GeomClass::GATE,
+                Some(GeomMetadata::GATE {
+                    access, info, unit, ..
+                }),
+            ) => TypedGeom::Gate(GateGeom {
+                geom,
+                access: Some(access),
+                info: Some(info),
+                unit: Some(*unit),
+            }),
+            (GeomClass::GATE, _) => TypedGeom::Gate(GateGeom {
+                geom,
+                access: None,
+                info: None,
+                unit: None,
+            }),
+            (GeomClass::MIRROR, _) => TypedGeom::Mirror(MirrorGeom {
+                geom,
+                state: self.unknown_config.get("State").map(String::as_str),
+            }),
+            (GeomClass::ZFSVDEV, _) => TypedGeom::Zfs(ZfsGeom { geom, zvol: false }),
+            (GeomClass::ZFSZVOL, _) => TypedGeom::Zfs(ZfsGeom { geom, zvol: true }),
+            (GeomClass::MD, _) => TypedGeom::Md(MdGeom { geom }),
+            _ => TypedGeom::Other(geom),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, raw, typed::TypedGeom, GeomClass};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn classes() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+
+        for geom in g.nodes.values() {
+            match geom.typed() {
+                TypedGeom::Disk(d) => assert_eq!(d.class, GeomClass::DISK),
+                TypedGeom::Part(p) => assert!(p.table.entries > 0),
+                TypedGeom::Dev(d) => assert_eq!(d.name, geom.name),
+                TypedGeom::Label(l) => assert_eq!(l.class, GeomClass::LABEL),
+                TypedGeom::Vfs(v) => assert!(geom.name.starts_with(v.fstype)),
+                TypedGeom::Swap(s) => assert_eq!(s.class, GeomClass::SWAP),
+                TypedGeom::Eli(e) => {
+                    assert_eq!(e.class, GeomClass::ELI);
+                    assert!(e.encryption_algorithm.is_some());
+                }
+                TypedGeom::Gate(gate) => assert_eq!(gate.class, GeomClass::GATE),
+                TypedGeom::Mirror(m) => assert_eq!(m.class, GeomClass::MIRROR),
+                TypedGeom::Zfs(z) => assert_eq!(z.zvol, z.class == GeomClass::ZFSZVOL),
+                TypedGeom::Md(m) => assert_eq!(m.class, GeomClass::MD),
+                TypedGeom::Ccd(_) | TypedGeom::Sched(_) => unreachable!(),
+                TypedGeom::Other(o) => assert!(!matches!(
+                    o.class,
+                    GeomClass::DISK
+                        | GeomClass::PART
+                        | GeomClass::DEV
+                        | GeomClass::LABEL
+                        | GeomClass::SWAP
+                        | GeomClass::ELI
+                        | GeomClass::GATE
+                        | GeomClass::MIRROR
+                        | GeomClass::ZFSVDEV
+                        | GeomClass::ZFSZVOL
+                        | GeomClass::MD
+                )),
+            }
+        }
+    }
+
+    #[test]
+    fn metadata_optional() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let mut geom = g.nodes.values().next().unwrap().clone();
+        geom.metadata = None;
+
+        geom.class = GeomClass::MIRROR;
+        geom.unknown_config
+            .insert("State".to_string(), "DEGRADED".to_string());
+        match geom.typed() {
+            TypedGeom::Mirror(m) => assert_eq!(m.state, Some("DEGRADED")),
+            other => panic!("{:?}", other),
+        }
+
+        // Compacted ELI and GATE geoms keep their class view.
+        geom.class = GeomClass::ELI;
+        match geom.typed() {
+            TypedGeom::Eli(e) => {
+                assert_eq!(e.encryption_algorithm, None);
+                assert_eq!(e.state, Some("DEGRADED"));
+            }
+            other => panic!("{:?}", other),
+        }
+        geom.class = GeomClass::GATE;
+        assert!(matches!(geom.typed(), TypedGeom::Gate(gate) if gate.unit.is_none()));
+
+        geom.class = GeomClass::ZFSZVOL;
+        assert!(matches!(geom.typed(), TypedGeom::Zfs(z) if z.zvol));
+        geom.class = GeomClass::MD;
+        assert!(matches!(geom.typed(), TypedGeom::Md(_)));
+
+        geom.class = GeomClass::PART;
+        assert!(matches!(geom.typed(), TypedGeom::Other(_)));
+    }
+}
@@ -0,0 +1,353 @@
+//! Incremental updates of a `Graph`.
+//!
+//! Re-fetching and decoding `kern.geom.confxml` on every change is expensive on systems with many
+//! geoms.  `Graph::apply_event()` and `Graph::apply_delta()` patch a graph in place instead, when
+//! the change is simple enough to be applied without the kernel's view of it; otherwise they
+//! leave the graph untouched and return `false`, and the caller should re-fetch it.
+use crate::diff::{self, Change, GraphDelta};
+use crate::graph::{EdgeId, GeomClass, Graph, NodeId, OrphanProvider};
+use crate::watch::GeomEvent;
+
+/// A `Change`, resolved against the graph it applies to.
+enum Op<'a> {
+    RemoveGeom(NodeId),
+    RemoveEdge(EdgeId),
+    SetGeomMetadata(NodeId, &'a Change),
+    SetConsumerMode(EdgeId, &'a Change),
+    /// Applies to every `Edge` (and `OrphanProvider`) of the named provider.
+    Provider(&'a Change),
+}
+
+impl Graph {
+    /// Whether a provider of this name exists, with or without consumers.
+    pub(crate) fn has_provider(&self, name: &str) -> bool {
+        return self.edges.values().any(|e| e.name == name)
+            || self.orphans.values().any(|o| o.name == name);
+    }
+
+    /// The size and sector size of the named provider, with or without consumers.
+    pub(crate) fn provider_size(&self, name: &str) -> Option<(u64, u64)> {
+        return self
+            .edges
+            .values()
+            .map(|e| (e.name.as_str(), e.mediasize, e.sectorsize))
+            .chain(
+                self.orphans
+                    .values()
+                    .map(|o| (o.name.as_str(), o.mediasize, o.sectorsize)),
+            )
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, m, s)| (m, s));
+    }
+
+    /// Removes an `Edge`, recording its provider as an orphan if this was its last consumer.
+    fn detach_edge(&mut self, id: &EdgeId) {
+        let edge = match self.remove_edge(id) {
+            Some(edge) => edge,
+            None => return,
+        };
+        let consumed = self.edges.keys().any(|eid| eid.1 == id.1);
+        if !consumed && self.nodes.contains_key(&edge.provider_geom) {
+            self.orphans.insert(
+                id.1,
+                OrphanProvider {
+                    name: edge.name,
+                    mode: edge.mode,
+                    mediasize: edge.mediasize,
+                    sectorsize: edge.sectorsize,
+                    provider_geom: edge.provider_geom,
+                    withered: edge.withered,
+                },
+            );
+        }
+    }
+
+    /// Removes a `Geom`, its providers, and the edges it consumes.
+    fn remove_geom(&mut self, id: &NodeId) {
+        let mut incident: Vec<EdgeId> = self.outedges.get(id).cloned().unwrap_or_default();
+        incident.extend(self.inedges.get(id).cloned().unwrap_or_default());
+        self.nodes.remove(id);
+        for eid in incident {
+            self.detach_edge(&eid);
+        }
+        self.orphans.retain(|_, o| o.provider_geom != *id);
+    }
+
+    /// Applies a `watch::GeomEvent` to the graph in place, if it can be, and returns whether it
+    /// was.  Returns `false`, leaving the graph untouched, if the event implies changes that it
+    /// does not describe, such as the size of a new provider; re-fetch the graph instead.
+    ///
+    /// Events applied in place:
+    ///
+    /// * `ProviderDestroyed`, for a provider consumed only by closed DEV geoms (e.g., a
+    ///   partition or label): the provider and its DEV geoms are removed, as is the geom that
+    ///   provided it if it has no other providers (e.g., a LABEL geom), unless it is a partition
+    ///   table.  (If the provider has other consumers, they are going away too, but their own
+    ///   events may not be delivered.)  Returns `false` for a provider not in the graph.
+    /// * `ProviderCreated`, for a provider already in the graph: nothing to do.
+    /// * DEVFS events: nothing to do.  GEOM providers' `/dev` nodes are reported by GEOM events
+    ///   too, and other `/dev` nodes are not part of the graph.
+    pub fn apply_event(&mut self, event: &GeomEvent) -> bool {
+        match event {
+            GeomEvent::ProviderDestroyed { name } => {
+                let edges: Vec<EdgeId> = self
+                    .edges
+                    .iter()
+                    .filter(|(_, e)| e.name == *name)
+                    .map(|(eid, _)| *eid)
+                    .collect();
+                let provider_geom = match edges.first() {
+                    Some(eid) => self.edges[eid].provider_geom,
+                    None => match self.orphans.values().find(|o| o.name == *name) {
+                        Some(orphan) => orphan.provider_geom,
+                        None => return false,
+                    },
+                };
+                let simple = edges.iter().all(|eid| {
+                    let edge = &self.edges[eid];
+                    return self.nodes[&edge.consumer_geom].class == GeomClass::DEV
+                        && !edge.consumer_mode.is_open()
+                        && !self.inedges.contains_key(&edge.consumer_geom);
+                });
+                if !simple {
+                    return false;
+                }
+                for eid in edges {
+                    if let Some(edge) = self.remove_edge(&eid) {
+                        self.nodes.remove(&edge.consumer_geom);
+                    }
+                }
+                self.orphans.retain(|_, o| o.name != *name);
+                // Partition tables outlive their last partition; other geoms go with it.
+                let empty = !self.inedges.contains_key(&provider_geom)
+                    && !self
+                        .orphans
+                        .values()
+                        .any(|o| o.provider_geom == provider_geom);
+                if empty
+                    && self
+                        .nodes
+                        .get(&provider_geom)
+                        .is_some_and(|g| g.class != GeomClass::PART)
+                {
+                    self.remove_geom(&provider_geom);
+                }
+                self.reindex();
+                return true;
+            }
+            GeomEvent::ProviderCreated { name } => return self.has_provider(name),
+            GeomEvent::DevfsCreated { .. } | GeomEvent::DevfsDestroyed { .. } => return true,
+            GeomEvent::MediaChange { .. }
+            | GeomEvent::SizeChange { .. }
+            | GeomEvent::AttributeChanged { .. } => return false,
+        }
+    }
+
+    /// Like `Graph::apply_event()`, but falls back to re-fetching the whole graph from the
+    /// running system.
+    #[cfg(target_os = "freebsd")]
+    pub fn apply_event_or_refresh(&mut self, event: &GeomEvent) -> Result<(), crate::Error> {
+        if !self.apply_event(event) {
+            *self = crate::get_graph()?;
+        }
+        return Ok(());
+    }
+
+    /// Applies a `GraphDelta` (computed against an equivalent graph, e.g., an earlier copy of
+    /// this one) in place, and returns whether it could be.
+    ///
+    /// Removals and changes are applied; additions (`Change::GeomAdded`, `Change::EdgeAdded`)
+    /// cannot be, as the delta does not describe the added geoms and edges in full.  If the
+    /// delta contains additions, or does not match the graph, it returns `false` and leaves the
+    /// graph untouched.
+    pub fn apply_delta(&mut self, delta: &GraphDelta) -> bool {
+        let keys = diff::geom_keys(self);
+        let ids: std::collections::BTreeMap<_, _> = keys.iter().map(|(id, k)| (k, *id)).collect();
+        let edge_id = |key: &diff::EdgeKey| -> Option<EdgeId> {
+            let consumer = ids.get(&key.consumer)?;
+            return self
+                .outedges
+                .get(consumer)?
+                .iter()
+                .find(|eid| self.edges[eid].name == key.provider)
+                .cloned();
+        };
+
+        let mut ops = Vec::new();
+        for change in &delta.changes {
+            let op = match change {
+                Change::GeomAdded(_) | Change::EdgeAdded(_) => return false,
+                Change::GeomRemoved(key) => match ids.get(key) {
+                    Some(id) => Op::RemoveGeom(*id),
+                    None => return false,
+                },
+                Change::GeomMetadata { geom, .. } => match ids.get(geom) {
+                    Some(id) => Op::SetGeomMetadata(*id, change),
+                    None => return false,
+                },
+                Change::EdgeRemoved(key) => match edge_id(key) {
+                    Some(eid) => Op::RemoveEdge(eid),
+                    None => return false,
+                },
+                Change::ConsumerMode { edge, .. } => match edge_id(edge) {
+                    Some(eid) => Op::SetConsumerMode(eid, change),
+                    None => return false,
+                },
+                Change::Mediasize { provider, .. }
+                | Change::Mode { provider, .. }
+                | Change::EdgeMetadata { provider, .. }
+                | Change::Withered { provider, .. } => {
+                    if !self.has_provider(provider) {
+                        return false;
+                    }
+                    Op::Provider(change)
+                }
+            };
+            ops.push(op);
+        }
+
+        for op in ops {
+            match op {
+                Op::RemoveGeom(id) => self.remove_geom(&id),
+                Op::RemoveEdge(eid) => self.detach_edge(&eid),
+                Op::SetGeomMetadata(id, Change::GeomMetadata { new, .. }) => {
+                    if let Some(geom) = self.nodes.get_mut(&id) {
+                        geom.metadata = new.clone();
+                    }
+                }
+                Op::SetConsumerMode(eid, Change::ConsumerMode { new, .. }) => {
+                    if let Some(edge) = self.edges.get_mut(&eid) {
+                        edge.consumer_mode = *new;
+                    }
+                }
+                Op::Provider(change) => self.apply_provider_change(change),
+                Op::SetGeomMetadata(..) | Op::SetConsumerMode(..) => unreachable!(),
+            }
+        }
+        self.reindex();
+        return true;
+    }
+
+    fn apply_provider_change(&mut self, change: &Change) {
+        let edges = self.edges.values_mut();
+        let orphans = self.orphans.values_mut();
+        match change {
+            Change::Mediasize { provider, new, .. } => {
+                edges
+                    .filter(|e| e.name == *provider)
+                    .for_each(|e| e.mediasize = *new);
+                orphans
+                    .filter(|o| o.name == *provider)
+                    .for_each(|o| o.mediasize = *new);
+            }
+            Change::Mode { provider, new, .. } => {
+                edges
+                    .filter(|e| e.name == *provider)
+                    .for_each(|e| e.mode = *new);
+                orphans
+                    .filter(|o| o.name == *provider)
+                    .for_each(|o| o.mode = *new);
+            }
+            Change::EdgeMetadata { provider, new, .. } => {
+                edges
+                    .filter(|e| e.name == *provider)
+                    .for_each(|e| e.metadata = new.clone());
+            }
+            Change::Withered { provider, withered } => {
+                edges
+                    .filter(|e| e.name == *provider)
+                    .for_each(|e| e.withered = *withered);
+                orphans
+                    .filter(|o| o.name == *provider)
+                    .for_each(|o| o.withered = *withered);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watch::GeomEvent;
+    use crate::{graph, raw};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    fn sample() -> graph::Graph {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        return graph::decode_graph(&rawmesh).unwrap();
+    }
+
+    #[test]
+    fn apply_event() {
+        let mut g = sample();
+        let orig = g.clone();
+
+        // The EFI partition's label is consumed by its DEV geom alone.
+        assert!(g.apply_event(&GeomEvent::ProviderDestroyed {
+            name: "msdosfs/EFI".to_owned()
+        }));
+        assert!(g.verify().is_empty());
+        assert!(g.edges.values().all(|e| e.name != "msdosfs/EFI"));
+        assert!(g
+            .find_by_class_name(&graph::GeomClass::DEV, "msdosfs/EFI")
+            .is_none());
+        // The LABEL geom providing it is gone too, as is its consumer of nvd0p1.
+        let labels = |g: &graph::Graph| {
+            g.nodes
+                .values()
+                .filter(|n| n.class == graph::GeomClass::LABEL && n.name == "nvd0p1")
+                .count()
+        };
+        assert_eq!((labels(&orig), labels(&g)), (3, 2));
+        assert_eq!(graph::Graph::diff(&orig, &g).changes.len(), 4);
+        assert!(!g.apply_event(&GeomEvent::ProviderDestroyed {
+            name: "msdosfs/EFI".to_owned()
+        }));
+
+        // nvd0p1 has a LABEL geom on it.
+        assert!(!g.apply_event(&GeomEvent::ProviderDestroyed {
+            name: "nvd0p1".to_owned()
+        }));
+        assert!(!g.apply_event(&GeomEvent::ProviderCreated {
+            name: "msdosfs/EFI".to_owned()
+        }));
+        assert!(g.apply_event(&GeomEvent::ProviderCreated {
+            name: "nvd0p2".to_owned()
+        }));
+        assert!(!g.apply_event(&GeomEvent::SizeChange {
+            name: "nvd0p2".to_owned()
+        }));
+    }
+
+    #[test]
+    fn apply_delta() {
+        let old = sample();
+        let mut new = old.clone();
+        for edge in new.edges.values_mut() {
+            if edge.name == "nvd0p2" {
+                edge.mediasize *= 2;
+            }
+        }
+        let (dev, _) = new
+            .find_by_class_name(&graph::GeomClass::DEV, "msdosfs/EFI")
+            .unwrap();
+        let dev = *dev;
+        new.nodes.remove(&dev);
+        for eid in new.outedges.remove(&dev).into_iter().flatten() {
+            new.remove_edge(&eid);
+        }
+
+        let delta = graph::Graph::diff(&old, &new);
+        let mut g = old.clone();
+        assert!(g.apply_delta(&delta));
+        assert!(graph::Graph::diff(&g, &new).is_empty());
+        assert!(g.verify().is_empty());
+        // msdosfs/EFI is left without consumers.
+        assert!(g.orphan_providers().any(|(_, o)| o.name == "msdosfs/EFI"));
+
+        // Additions cannot be applied.
+        let mut g = new.clone();
+        assert!(!g.apply_delta(&graph::Graph::diff(&new, &old)));
+        assert!(graph::Graph::diff(&g, &new).is_empty());
+    }
+}
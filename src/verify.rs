@@ -0,0 +1,169 @@
+//! Consistency checks for `geom::Graph`s.
+//!
+//! The traversal APIs assume that the `inedges` and `outedges` indices agree with `edges`, and
+//! that every edge's endpoints exist.  Decoding builds the indices from the edges themselves, but
+//! the access counts it records are only as consistent as the mesh it was given, which may have
+//! been edited or assembled by hand rather than taken from the kernel.  A graph may also change
+//! after it is decoded: all of its fields are public, `Graph::apply_event()` edits it in place,
+//! and snapshots may be loaded from elsewhere.
+use crate::graph::{EdgeId, Graph, Mode, NodeId};
+use std::collections::BTreeMap;
+
+/// A single inconsistency found by `Graph::verify()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum IntegrityFinding {
+    /// An `Edge` refers to a consumer `Geom` that is not in `nodes`.
+    MissingConsumer { edge: EdgeId, node: NodeId },
+    /// An `Edge` refers to a provider `Geom` that is not in `nodes`.
+    MissingProvider { edge: EdgeId, node: NodeId },
+    /// An `inedges` or `outedges` list refers to a `Geom` that is not in `nodes`.
+    DanglingIndex { node: NodeId },
+    /// An `inedges` or `outedges` list refers to an `Edge` that is not in `edges`.
+    MissingEdge { node: NodeId, edge: EdgeId },
+    /// `inedges[node]` contains an `Edge` whose `provider_geom` is some other node, or
+    /// `outedges[node]` contains one whose `consumer_geom` is some other node.
+    MisindexedEdge { node: NodeId, edge: EdgeId },
+    /// An `Edge` does not appear in its provider's `inedges` list.
+    UnindexedInedge { edge: EdgeId },
+    /// An `Edge` does not appear in its consumer's `outedges` list.
+    UnindexedOutedge { edge: EdgeId },
+    /// The `mode` of a provider (by provider id) is not the sum of its consumers'
+    /// `consumer_mode`s, or its `Edge`s disagree on it.
+    ModeMismatch {
+        provider: u64,
+        mode: Mode,
+        consumers: Mode,
+    },
+}
+
+fn is_indexed(index: &BTreeMap<NodeId, Vec<EdgeId>>, node: &NodeId, edgeid: &EdgeId) -> bool {
+    return index.get(node).is_some_and(|v| v.contains(edgeid));
+}
+
+impl Graph {
+    /// Checks that the `edges`, `inedges`, and `outedges` maps agree with each other, that
+    /// every `Edge` connects two `Geom`s present in `nodes`, and that each provider's access
+    /// `mode` is accounted for by its consumers.
+    ///
+    /// Returns every inconsistency found; an empty result means the graph is consistent.
+    pub fn verify(&self) -> Vec<IntegrityFinding> {
+        let mut findings = Vec::new();
+
+        for (edgeid, edge) in &self.edges {
+            if !self.nodes.contains_key(&edge.consumer_geom) {
+                findings.push(IntegrityFinding::MissingConsumer {
+                    edge: *edgeid,
+                    node: edge.consumer_geom,
+                });
+            }
+            if !self.nodes.contains_key(&edge.provider_geom) {
+                findings.push(IntegrityFinding::MissingProvider {
+                    edge: *edgeid,
+                    node: edge.provider_geom,
+                });
+            }
+            if !is_indexed(&self.inedges, &edge.provider_geom, edgeid) {
+                findings.push(IntegrityFinding::UnindexedInedge { edge: *edgeid });
+            }
+            if !is_indexed(&self.outedges, &edge.consumer_geom, edgeid) {
+                findings.push(IntegrityFinding::UnindexedOutedge { edge: *edgeid });
+            }
+        }
+
+        for (index, is_inedges) in &[(&self.inedges, true), (&self.outedges, false)] {
+            for (node, edgeids) in index.iter() {
+                if !self.nodes.contains_key(node) {
+                    findings.push(IntegrityFinding::DanglingIndex { node: *node });
+                }
+                for edgeid in edgeids {
+                    match self.edges.get(edgeid) {
+                        None => findings.push(IntegrityFinding::MissingEdge {
+                            node: *node,
+                            edge: *edgeid,
+                        }),
+                        Some(edge) => {
+                            let endpoint = if *is_inedges {
+                                edge.provider_geom
+                            } else {
+                                edge.consumer_geom
+                            };
+                            if endpoint != *node {
+                                findings.push(IntegrityFinding::MisindexedEdge {
+                                    node: *node,
+                                    edge: *edgeid,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Per provider: its mode (as recorded on each of its edges), and the sum of its consumers'.
+        let mut modes: BTreeMap<u64, (Mode, Mode, bool)> = BTreeMap::new();
+        for ((_, provider), edge) in &self.edges {
+            let entry = modes
+                .entry(*provider)
+                .or_insert((edge.mode, Mode::default(), true));
+            entry.1 = entry.1 + edge.consumer_mode;
+            entry.2 &= entry.0 == edge.mode;
+        }
+        for (provider, (mode, consumers, agree)) in modes {
+            if !agree || mode != consumers {
+                findings.push(IntegrityFinding::ModeMismatch {
+                    provider,
+                    mode,
+                    consumers,
+                });
+            }
+        }
+
+        return findings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graph, raw, verify::IntegrityFinding};
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn sample_is_consistent() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        assert_eq!(g.verify(), vec![]);
+    }
+
+    #[test]
+    fn corruption_is_found() {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let mut g = graph::decode_graph(&rawmesh).unwrap();
+
+        // Remove a DEV geom out from under its edge.
+        let (&edgeid, edge) = g
+            .edges
+            .iter()
+            .find(|(_, e)| {
+                e.name == "ada0p1" && g.nodes[&e.consumer_geom].class == graph::GeomClass::DEV
+            })
+            .unwrap();
+        let dev = edge.consumer_geom;
+        g.nodes.remove(&dev);
+        // And point an index at an edge that doesn't exist.
+        g.inedges.entry(dev).or_default().push((1, 2));
+
+        let findings = g.verify();
+        assert!(findings.contains(&IntegrityFinding::MissingConsumer {
+            edge: edgeid,
+            node: dev
+        }));
+        assert!(findings.contains(&IntegrityFinding::DanglingIndex { node: dev }));
+        assert!(findings.contains(&IntegrityFinding::MissingEdge {
+            node: dev,
+            edge: (1, 2)
+        }));
+        // Both the new inedges entry and the DEV geom's existing outedges entry dangle.
+        assert_eq!(findings.len(), 4);
+    }
+}
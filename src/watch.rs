@@ -0,0 +1,890 @@
+//! Notification of GEOM changes.
+//!
+//! `devd(8)` relays the kernel's device notifications to any process connected to its socket.  A
+//! `Watcher` connects to it and yields a `GeomEvent` as each GEOM provider (or `/dev` node) comes
+//! and goes, so callers need not poll `kern.geom.confxml` to notice, say, a USB stick being
+//! plugged in.
+//!
+//! Where `devd(8)` is not available (e.g., in a jail), `Watcher::poll()` instead re-fetches the
+//! graph periodically and reports what changed, as a `GraphDelta`.  Daemons built on a `Poller`
+//! can signal readiness and liveness to their supervisor; see `Poller::with_hooks()`.
+//!
+//! # Examples
+//!
+//! ```
+//! use freebsd_geom as geom;
+//!
+//! #[cfg(target_os = "freebsd")]
+//! fn myfoo() -> Result<(), geom::Error> {
+//!     for event in geom::watch::Watcher::devd()? {
+//!         if let geom::watch::GeomEvent::ProviderCreated { name } = event? {
+//!             println!("new provider: {}", name);
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//! ```
+use crate::devctl::{self, Message, Notify};
+use crate::diff::GraphDelta;
+use crate::{Error, Graph};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The `devd(8)` socket that delivers one notification per packet.
+pub const DEVD_SEQPACKET_PIPE: &str = "/var/run/devd.seqpacket.pipe";
+
+/// A change to the GEOM graph (or `/dev`), from a `devd(8)` notification.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum GeomEvent {
+    /// A provider was created (more precisely, its DEV geom created its `/dev` node), e.g.,
+    /// `da0` after a USB disk was attached, or `da0p1` once its partition table was tasted.
+    ProviderCreated { name: String },
+    /// A provider was destroyed, e.g., because its disk was detached.
+    ProviderDestroyed { name: String },
+    /// The media of a provider changed, e.g., a disc was inserted in `cd0`.
+    MediaChange { name: String },
+    /// The size of a provider changed, e.g., a `zvol` or `md(4)` was resized.
+    SizeChange { name: String },
+    /// A provider attribute changed, e.g., `GEOM::physpath`.
+    AttributeChanged { name: String, attribute: String },
+    /// A `/dev` node was created.  Not all of them belong to GEOM providers.
+    DevfsCreated { cdev: String },
+    /// A `/dev` node was destroyed.
+    DevfsDestroyed { cdev: String },
+}
+
+impl GeomEvent {
+    /// Picks out GEOM and DEVFS events from `devctl(4)` notifications.  Returns `None` for other
+    /// notifications.
+    pub fn from_notify(n: &Notify) -> Option<GeomEvent> {
+        let name = || n.get("cdev").map(|s| s.to_owned());
+        let event = match (&n.system[..], &n.subsystem[..], &n.kind[..]) {
+            ("GEOM", "DEV", "CREATE") => GeomEvent::ProviderCreated { name: name()? },
+            ("GEOM", "DEV", "DESTROY") => GeomEvent::ProviderDestroyed { name: name()? },
+            ("GEOM", "DEV", "MEDIACHANGE") => GeomEvent::MediaChange { name: name()? },
+            ("GEOM", "DEV", "SIZECHANGE") => GeomEvent::SizeChange { name: name()? },
+            ("GEOM", _, attr) if attr.starts_with("GEOM::") => GeomEvent::AttributeChanged {
+                name: name().or_else(|| n.get("devname").map(|s| s.to_owned()))?,
+                attribute: attr.to_owned(),
+            },
+            ("DEVFS", "CDEV", "CREATE") => GeomEvent::DevfsCreated { cdev: name()? },
+            ("DEVFS", "CDEV", "DESTROY") => GeomEvent::DevfsDestroyed { cdev: name()? },
+            _ => return None,
+        };
+        return Some(event);
+    }
+
+    /// Decodes a raw `devctl(4)` message, such as
+    /// `!system=GEOM subsystem=DEV type=CREATE cdev=da0`.  Returns `None` for anything that is
+    /// not a GEOM or DEVFS notification.
+    pub fn parse(msg: &str) -> Option<GeomEvent> {
+        return match devctl::parse(msg)? {
+            Message::Notify(n) => GeomEvent::from_notify(&n),
+            _ => None,
+        };
+    }
+}
+
+/// A connection to `devd(8)`'s seqpacket socket.
+#[cfg(target_os = "freebsd")]
+struct DevdSocket {
+    fd: std::os::unix::io::OwnedFd,
+}
+
+#[cfg(target_os = "freebsd")]
+impl DevdSocket {
+    fn connect(path: &std::path::Path, nonblocking: bool) -> Result<Self, Error> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+        let mut ty = libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC;
+        if nonblocking {
+            ty |= libc::SOCK_NONBLOCK;
+        }
+        let fd = unsafe { libc::socket(libc::PF_LOCAL, ty, 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+        let path = path.as_os_str().as_bytes();
+        if path.len() >= addr.sun_path.len() {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput).into());
+        }
+        for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+            *dst = *src as libc::c_char;
+        }
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        addr.sun_len = std::mem::size_of::<libc::sockaddr_un>() as u8;
+        let rc = unsafe {
+            libc::connect(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        return Ok(Self { fd });
+    }
+
+    /// Waits until a notification can be received, or `deadline` passes.  Returns whether one
+    /// can be.
+    fn wait(&self, deadline: Instant) -> std::io::Result<bool> {
+        use std::os::unix::io::AsRawFd;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // Round up, so as not to spin on sub-millisecond remainders.
+            let ms = remaining
+                .as_micros()
+                .div_ceil(1000)
+                .min(libc::c_int::MAX as u128);
+            let mut pfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let n = unsafe { libc::poll(&mut pfd, 1, ms as libc::c_int) };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            return Ok(n > 0);
+        }
+    }
+
+    /// Blocks until the next notification (or, if the socket is nonblocking, fails with
+    /// `ErrorKind::WouldBlock` if there is none).
+    fn recv(&self) -> std::io::Result<String> {
+        use std::os::unix::io::AsRawFd;
+
+        // devd(8) notifications are limited to a little under 8 kB.
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let n = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if n == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            return Ok(String::from_utf8_lossy(&buf[..n as usize]).into_owned());
+        }
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl std::os::unix::io::AsRawFd for DevdSocket {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        return self.fd.as_raw_fd();
+    }
+}
+
+/// Where a `Watcher` gets its `devctl(4)` messages.
+enum Source {
+    #[cfg(target_os = "freebsd")]
+    Devd(DevdSocket),
+    Messages(Box<dyn Iterator<Item = String> + Send>),
+}
+
+impl Source {
+    /// Returns the next message, or `None` if `deadline` passes first.  (`Source::Messages`
+    /// never blocks.)
+    #[cfg_attr(not(target_os = "freebsd"), allow(unused_variables))]
+    fn recv(&mut self, deadline: Option<Instant>) -> std::io::Result<Option<String>> {
+        match self {
+            #[cfg(target_os = "freebsd")]
+            Source::Devd(devd) => {
+                if let Some(deadline) = deadline {
+                    if !devd.wait(deadline)? {
+                        return Ok(None);
+                    }
+                }
+                return devd.recv().map(Some);
+            }
+            Source::Messages(messages) => {
+                return messages
+                    .next()
+                    .map(Some)
+                    .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+/// A callback registered with `Watcher::on_provider_removed()`.
+type RemovedFn = Box<dyn FnMut(&str) + Send>;
+
+/// Yields a `GeomEvent` for each GEOM (or DEVFS) notification, blocking until one arrives.
+///
+/// Iteration ends after an error (e.g., `devd(8)` exited); later calls to
+/// `Watcher::next_event()` fail with an `Error::Io` of kind `ErrorKind::NotConnected`.
+pub struct Watcher {
+    source: Option<Source>,
+    on_removed: Vec<(String, RemovedFn)>,
+}
+
+impl Watcher {
+    fn new(source: Source) -> Watcher {
+        return Watcher {
+            source: Some(source),
+            on_removed: Vec::new(),
+        };
+    }
+
+    /// Connects to `devd(8)` at `DEVD_SEQPACKET_PIPE`.
+    #[cfg(target_os = "freebsd")]
+    pub fn devd() -> Result<Watcher, Error> {
+        return Watcher::devd_at(DEVD_SEQPACKET_PIPE);
+    }
+
+    /// Connects to a `devd(8)` seqpacket socket at a non-default path.
+    #[cfg(target_os = "freebsd")]
+    pub fn devd_at<P: AsRef<std::path::Path>>(path: P) -> Result<Watcher, Error> {
+        let devd = DevdSocket::connect(path.as_ref(), false)?;
+        return Ok(Watcher::new(Source::Devd(devd)));
+    }
+
+    /// Watches raw `devctl(4)` messages obtained some other way, e.g., from one's own `devd(8)`
+    /// connection.  Once `messages` is exhausted, the watcher fails with
+    /// `ErrorKind::UnexpectedEof`, like a closed `devd(8)` connection.
+    pub fn from_messages<I>(messages: I) -> Watcher
+    where
+        I: IntoIterator<Item = String>,
+        I::IntoIter: Send + 'static,
+    {
+        return Watcher::new(Source::Messages(Box::new(messages.into_iter())));
+    }
+
+    /// Returns the next GEOM event, or `None` if `deadline` passes first.
+    fn next_event_before(&mut self, deadline: Option<Instant>) -> Result<Option<GeomEvent>, Error> {
+        loop {
+            let source = self.source.as_mut().ok_or_else(closed)?;
+            let msg = match source.recv(deadline) {
+                Ok(Some(msg)) => msg,
+                Ok(None) => return Ok(None),
+                Err(e) => {
+                    self.source = None;
+                    return Err(e.into());
+                }
+            };
+            if let Some(event) = GeomEvent::parse(&msg) {
+                if let GeomEvent::ProviderDestroyed { name } = &event {
+                    for (_, callback) in self.on_removed.iter_mut().filter(|(n, _)| n == name) {
+                        callback(name);
+                    }
+                }
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Blocks until the next GEOM event.
+    pub fn next_event(&mut self) -> Result<GeomEvent, Error> {
+        return self.next_event_before(None)?.ok_or(Error::GraphError);
+    }
+
+    /// Blocks until the provider `name` (e.g., `"da0p1"` or `"gpt/backup"`) is created, or
+    /// `timeout` passes.  Returns whether it was created.
+    ///
+    /// A provider that already exists when connected to `devd(8)` counts as created, so that
+    /// scripts need not race the device they are waiting for.  Other events received meanwhile
+    /// are discarded (after running any `Watcher::on_provider_removed()` callbacks).
+    pub fn wait_for_provider(&mut self, name: &str, timeout: Duration) -> Result<bool, Error> {
+        #[cfg(target_os = "freebsd")]
+        if let Some(Source::Devd(_)) = self.source {
+            if std::path::Path::new("/dev").join(name).exists() {
+                return Ok(true);
+            }
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.next_event_before(Some(deadline))? {
+                Some(GeomEvent::ProviderCreated { name: n }) if n == name => return Ok(true),
+                Some(_) => {}
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Calls `callback` with the provider name whenever the provider `name` is destroyed, e.g.,
+    /// to stop using a hot-plugged disk once it is detached.  Callbacks run as events are
+    /// received, i.e., while iterating the watcher or in `Watcher::wait_for_provider()`.
+    pub fn on_provider_removed<F>(&mut self, name: &str, callback: F)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.on_removed.push((name.to_owned(), Box::new(callback)));
+    }
+}
+
+impl Watcher {
+    /// Polls the running system's graph every `interval`, instead of listening to `devd(8)`.
+    #[cfg(target_os = "freebsd")]
+    pub fn poll(interval: Duration) -> Result<Poller, Error> {
+        return Poller::new(interval, crate::get_graph);
+    }
+}
+
+/// Fetches a graph.
+type FetchFn = Box<dyn FnMut() -> Result<Graph, Error> + Send>;
+
+/// Signals a service manager or supervisor as a `Poller` runs, so that daemons built on one need
+/// no plumbing of their own; see `Poller::with_hooks()` and `Pidfile`.
+pub trait ServiceHooks: Send {
+    /// Called once the first graph has been fetched, i.e., when the daemon is ready.
+    fn ready(&mut self, _graph: &Graph) -> Result<(), Error> {
+        return Ok(());
+    }
+
+    /// Called after each successful poll, whether or not anything changed, as a sign of life.
+    fn alive(&mut self, _graph: &Graph) -> Result<(), Error> {
+        return Ok(());
+    }
+}
+
+/// `ServiceHooks` maintaining a pid file, as `rc.subr(8)` scripts expect: it is written once the
+/// daemon is ready, its modification time is refreshed on each poll (for supervisors that watch
+/// for a stale file), and it is removed when the `Pidfile` is dropped.
+#[derive(Debug)]
+pub struct Pidfile {
+    path: std::path::PathBuf,
+    written: bool,
+}
+
+impl Pidfile {
+    /// A pid file at `path`, e.g., `"/var/run/mydaemon.pid"`.
+    pub fn new<P: Into<std::path::PathBuf>>(path: P) -> Pidfile {
+        return Pidfile {
+            path: path.into(),
+            written: false,
+        };
+    }
+}
+
+impl ServiceHooks for Pidfile {
+    fn ready(&mut self, _graph: &Graph) -> Result<(), Error> {
+        std::fs::write(&self.path, format!("{}\n", std::process::id()))?;
+        self.written = true;
+        return Ok(());
+    }
+
+    fn alive(&mut self, _graph: &Graph) -> Result<(), Error> {
+        let file = std::fs::OpenOptions::new().write(true).open(&self.path)?;
+        file.set_modified(std::time::SystemTime::now())?;
+        return Ok(());
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if self.written {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Watches for GEOM changes by periodically re-fetching the graph and comparing it with
+/// `Graph::diff()`; see `Watcher::poll()`.
+///
+/// Iterating yields a (non-empty) `GraphDelta` for each poll that found changes.  Iteration ends
+/// after an error; later calls to `Poller::next_delta()` fail with an `Error::Io` of kind
+/// `ErrorKind::NotConnected`.
+pub struct Poller {
+    interval: Duration,
+    fetch: Option<FetchFn>,
+    graph: Graph,
+    hooks: Option<Box<dyn ServiceHooks>>,
+}
+
+impl Poller {
+    /// Polls graphs from `fetch`, rather than the running system, e.g., for testing.  `fetch` is
+    /// called once immediately, for the initial graph.
+    pub fn new<F>(interval: Duration, mut fetch: F) -> Result<Poller, Error>
+    where
+        F: FnMut() -> Result<Graph, Error> + Send + 'static,
+    {
+        let graph = fetch()?;
+        return Ok(Poller {
+            interval,
+            fetch: Some(Box::new(fetch)),
+            graph,
+            hooks: None,
+        });
+    }
+
+    /// Signals `hooks` as the poller runs: `ready()` right away, as the first graph has been
+    /// fetched, then `alive()` after each poll.  If a hook fails, so does the poller.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use freebsd_geom as geom;
+    ///
+    /// #[cfg(target_os = "freebsd")]
+    /// fn daemon() -> Result<(), geom::Error> {
+    ///     let pidfile = geom::watch::Pidfile::new("/var/run/geomwatch.pid");
+    ///     let poller = geom::watch::Watcher::poll(std::time::Duration::from_secs(5))?;
+    ///     for delta in poller.with_hooks(pidfile)? {
+    ///         println!("{:?}", delta?);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_hooks<H: ServiceHooks + 'static>(mut self, mut hooks: H) -> Result<Poller, Error> {
+        hooks.ready(&self.graph)?;
+        self.hooks = Some(Box::new(hooks));
+        return Ok(self);
+    }
+
+    /// The most recently fetched graph.
+    pub fn graph(&self) -> &Graph {
+        return &self.graph;
+    }
+
+    /// Blocks until a poll finds changes, and returns them.
+    pub fn next_delta(&mut self) -> Result<GraphDelta, Error> {
+        loop {
+            if let Some(delta) = self.poll_once()? {
+                return Ok(delta);
+            }
+        }
+    }
+
+    /// Waits out one interval and polls; returns the changes, if any.
+    fn poll_once(&mut self) -> Result<Option<GraphDelta>, Error> {
+        let fetch = self.fetch.as_mut().ok_or_else(closed)?;
+        std::thread::sleep(self.interval);
+        let graph = match fetch() {
+            Ok(graph) => graph,
+            Err(e) => {
+                self.fetch = None;
+                return Err(e);
+            }
+        };
+        let delta = Graph::diff(&self.graph, &graph);
+        self.graph = graph;
+        if let Some(hooks) = &mut self.hooks {
+            if let Err(e) = hooks.alive(&self.graph) {
+                self.fetch = None;
+                return Err(e);
+            }
+        }
+        if delta.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(delta));
+    }
+
+    /// Calls `callback` with each change, and the graph after it, until it returns
+    /// `ControlFlow::Break` or polling fails.
+    pub fn run<F>(&mut self, mut callback: F) -> Result<(), Error>
+    where
+        F: FnMut(&GraphDelta, &Graph) -> ControlFlow<()>,
+    {
+        loop {
+            let delta = self.next_delta()?;
+            if callback(&delta, &self.graph).is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Polls on a new thread, which sends each change (or the error that stopped it) to the
+    /// returned `PollerThread`.  The thread exits within an interval of the `PollerThread` being
+    /// dropped, whether or not anything changed meanwhile.
+    pub fn spawn(mut self) -> PollerThread {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        std::thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                let res = match self.poll_once() {
+                    Ok(None) => continue,
+                    Ok(Some(delta)) => Ok(delta),
+                    Err(e) => Err(e),
+                };
+                let failed = res.is_err();
+                if tx.send(res).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        return PollerThread { rx, stop };
+    }
+}
+
+/// The receiving end of `Poller::spawn()`.  Dropping it stops the polling thread.
+pub struct PollerThread {
+    rx: Receiver<Result<GraphDelta, Error>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl PollerThread {
+    /// Blocks until the next change, or the error that stopped polling.  Fails once the polling
+    /// thread has exited and every result has been received.
+    pub fn recv(&self) -> Result<Result<GraphDelta, Error>, RecvError> {
+        return self.rx.recv();
+    }
+
+    /// Like `PollerThread::recv()`, but gives up after `timeout`.
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Result<GraphDelta, Error>, RecvTimeoutError> {
+        return self.rx.recv_timeout(timeout);
+    }
+
+    /// Returns a change (or error) if one is waiting, without blocking.
+    pub fn try_recv(&self) -> Result<Result<GraphDelta, Error>, TryRecvError> {
+        return self.rx.try_recv();
+    }
+}
+
+impl Iterator for PollerThread {
+    type Item = Result<GraphDelta, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        return self.rx.recv().ok();
+    }
+}
+
+impl Drop for PollerThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The error from a `Watcher` or `Poller` that has already failed.
+fn closed() -> Error {
+    let msg = "watcher stopped after an earlier error";
+    return std::io::Error::new(std::io::ErrorKind::NotConnected, msg).into();
+}
+
+impl Iterator for Poller {
+    type Item = Result<GraphDelta, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fetch.as_ref()?;
+        return Some(self.next_delta());
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<GeomEvent, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.source.as_ref()?;
+        return Some(self.next_event());
+    }
+}
+
+/// An asynchronous `Watcher`: a `Stream` of `GeomEvent`s, for use with `tokio`.
+///
+/// Like `Watcher`, the stream ends after an error.
+///
+/// # Examples
+///
+/// ```
+/// use freebsd_geom as geom;
+///
+/// #[cfg(all(target_os = "freebsd", feature = "tokio"))]
+/// async fn myfoo() -> Result<(), geom::Error> {
+///     use futures_core::Stream;
+///     use std::future::poll_fn;
+///     use std::pin::Pin;
+///
+///     let mut events = geom::watch::EventStream::devd()?;
+///     while let Some(event) = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await {
+///         println!("{:?}", event?);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg(all(target_os = "freebsd", feature = "tokio"))]
+pub struct EventStream {
+    devd: Option<tokio::io::unix::AsyncFd<DevdSocket>>,
+}
+
+#[cfg(all(target_os = "freebsd", feature = "tokio"))]
+impl EventStream {
+    /// Connects to `devd(8)` at `DEVD_SEQPACKET_PIPE`.  Must be called from within a `tokio`
+    /// runtime.
+    pub fn devd() -> Result<EventStream, Error> {
+        return EventStream::devd_at(DEVD_SEQPACKET_PIPE);
+    }
+
+    /// Connects to a `devd(8)` seqpacket socket at a non-default path.
+    pub fn devd_at<P: AsRef<std::path::Path>>(path: P) -> Result<EventStream, Error> {
+        let socket = DevdSocket::connect(path.as_ref(), true)?;
+        return Ok(EventStream {
+            devd: Some(tokio::io::unix::AsyncFd::new(socket)?),
+        });
+    }
+}
+
+#[cfg(all(target_os = "freebsd", feature = "tokio"))]
+impl futures_core::Stream for EventStream {
+    type Item = Result<GeomEvent, Error>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use std::task::Poll;
+
+        loop {
+            let devd = match &self.devd {
+                Some(devd) => devd,
+                None => return Poll::Ready(None),
+            };
+            let res = match devd.poll_read_ready(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(mut guard)) => match guard.try_io(|fd| fd.get_ref().recv()) {
+                    Ok(res) => res,
+                    // Spurious readiness; wait for the next.
+                    Err(_) => continue,
+                },
+                Poll::Ready(Err(e)) => Err(e),
+            };
+            match res {
+                Ok(msg) => {
+                    if let Some(event) = GeomEvent::parse(&msg) {
+                        return Poll::Ready(Some(Ok(event)));
+                    }
+                }
+                Err(e) => {
+                    self.devd = None;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::Change;
+    use crate::watch::{GeomEvent, Pidfile, Poller, ServiceHooks, Watcher};
+    use crate::{graph, raw, Error, Graph};
+    use std::ops::ControlFlow;
+    use std::time::Duration;
+    const SAMPLE_XML: &str = include_str!("test/fullsample.xml");
+
+    #[test]
+    fn parse_geom() {
+        assert_eq!(
+            GeomEvent::parse("!system=GEOM subsystem=DEV type=CREATE cdev=da0p1\n"),
+            Some(GeomEvent::ProviderCreated {
+                name: "da0p1".to_owned()
+            })
+        );
+        assert_eq!(
+            GeomEvent::parse("!system=GEOM subsystem=DEV type=DESTROY cdev=gpt/usbstick"),
+            Some(GeomEvent::ProviderDestroyed {
+                name: "gpt/usbstick".to_owned()
+            })
+        );
+        assert_eq!(
+            GeomEvent::parse("!system=GEOM subsystem=DEV type=MEDIACHANGE cdev=cd0"),
+            Some(GeomEvent::MediaChange {
+                name: "cd0".to_owned()
+            })
+        );
+        assert_eq!(
+            GeomEvent::parse("!system=GEOM subsystem=disk type=GEOM::physpath devname=da0"),
+            Some(GeomEvent::AttributeChanged {
+                name: "da0".to_owned(),
+                attribute: "GEOM::physpath".to_owned()
+            })
+        );
+        assert_eq!(
+            GeomEvent::parse("!system=DEVFS subsystem=CDEV type=DESTROY cdev=ttyU0"),
+            Some(GeomEvent::DevfsDestroyed {
+                cdev: "ttyU0".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_other() {
+        // Not notifications, or not about GEOM.
+        assert_eq!(
+            GeomEvent::parse("+umass0 at bus=0 sernum=\"123\" on uhub0"),
+            None
+        );
+        assert_eq!(
+            GeomEvent::parse("!system=IFNET subsystem=em0 type=LINK_UP"),
+            None
+        );
+        assert_eq!(
+            GeomEvent::parse("!system=GEOM subsystem=DEV type=CREATE"),
+            None
+        );
+        assert_eq!(GeomEvent::parse(""), None);
+    }
+
+    /// Serves the sample graph three times, then the sample with nvd0p1 grown, then fails.
+    fn poller() -> Poller {
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let mut grown = g.clone();
+        for edge in grown.edges.values_mut() {
+            if edge.name == "nvd0p1" {
+                edge.mediasize *= 2;
+            }
+        }
+        let mut graphs = vec![grown, g.clone(), g.clone(), g];
+        return Poller::new(Duration::ZERO, move || {
+            graphs.pop().ok_or(Error::GraphError)
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn poll() {
+        let mut p = poller();
+        let delta = p.next().unwrap().unwrap();
+        assert!(matches!(
+            &delta.changes[..],
+            [Change::Mediasize { provider, .. }] if provider == "nvd0p1"
+        ));
+        assert!(matches!(p.next(), Some(Err(Error::GraphError))));
+        assert!(p.next().is_none());
+
+        let mut calls = 0;
+        let res = poller().run(|delta, g| {
+            calls += 1;
+            assert_eq!(delta.changes.len(), 1);
+            assert!(g.edges.values().any(|e| e.mediasize == 2 * 209715200));
+            return ControlFlow::Break(());
+        });
+        assert!(res.is_ok());
+        assert_eq!(calls, 1);
+
+        let rx = poller().spawn();
+        assert!(rx.recv().unwrap().is_ok());
+        assert!(rx.recv().unwrap().is_err());
+        assert!(rx.recv().is_err());
+
+        let mut p = poller();
+        assert!(p.next().unwrap().is_ok());
+        assert!(p.next().unwrap().is_err());
+        let err = p.next_delta().unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == std::io::ErrorKind::NotConnected));
+    }
+
+    #[test]
+    fn spawn_stops() {
+        // A graph that never changes, so the thread never has anything to send.
+        let rawmesh = raw::parse_xml(SAMPLE_XML).unwrap();
+        let g = graph::decode_graph(&rawmesh).unwrap();
+        let polls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = polls.clone();
+        let p = Poller::new(Duration::from_millis(1), move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            return Ok(g.clone());
+        })
+        .unwrap();
+
+        let rx = p.spawn();
+        assert!(rx.recv_timeout(Duration::from_millis(20)).is_err());
+        drop(rx);
+        std::thread::sleep(Duration::from_millis(50));
+        let stopped = polls.load(std::sync::atomic::Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(polls.load(std::sync::atomic::Ordering::SeqCst), stopped);
+    }
+
+    #[test]
+    fn hooks() {
+        #[derive(Clone, Default)]
+        struct Counter(std::sync::Arc<std::sync::Mutex<(u32, u32)>>);
+        impl ServiceHooks for Counter {
+            fn ready(&mut self, _graph: &Graph) -> Result<(), Error> {
+                self.0.lock().unwrap().0 += 1;
+                return Ok(());
+            }
+            fn alive(&mut self, _graph: &Graph) -> Result<(), Error> {
+                self.0.lock().unwrap().1 += 1;
+                return Ok(());
+            }
+        }
+
+        let counter = Counter::default();
+        let mut p = poller().with_hooks(counter.clone()).unwrap();
+        assert_eq!(*counter.0.lock().unwrap(), (1, 0));
+        // Three polls: two unchanged, then the change.
+        assert!(p.next().unwrap().is_ok());
+        assert_eq!(*counter.0.lock().unwrap(), (1, 3));
+        assert!(p.next().unwrap().is_err());
+        assert_eq!(*counter.0.lock().unwrap(), (1, 3));
+
+        let path = std::env::temp_dir().join(format!("geom-watch-{}.pid", std::process::id()));
+        let mut p = poller().with_hooks(Pidfile::new(&path)).unwrap();
+        let pid = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(pid, format!("{}\n", std::process::id()));
+        std::fs::remove_file(&path).unwrap();
+        // A liveness failure stops the poller.
+        assert!(matches!(p.next(), Some(Err(Error::Io(_)))));
+        assert!(p.next().is_none());
+        drop(p);
+
+        let p = poller().with_hooks(Pidfile::new(&path)).unwrap();
+        assert!(path.exists());
+        drop(p);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn subscriptions() {
+        let messages = vec![
+            "!system=DEVFS subsystem=CDEV type=CREATE cdev=da0",
+            "!system=GEOM subsystem=DEV type=CREATE cdev=da0",
+            "!system=GEOM subsystem=DEV type=DESTROY cdev=da0p1",
+            "!system=GEOM subsystem=DEV type=CREATE cdev=da0p1",
+            "!system=GEOM subsystem=DEV type=DESTROY cdev=da0p1",
+        ];
+        let mut w = Watcher::from_messages(messages.into_iter().map(str::to_owned));
+        let (tx, rx) = std::sync::mpsc::channel();
+        w.on_provider_removed("da0p1", move |name| tx.send(name.to_owned()).unwrap());
+
+        assert!(w.wait_for_provider("da0p1", Duration::ZERO).unwrap());
+        assert_eq!(rx.try_recv().unwrap(), "da0p1");
+        assert!(rx.try_recv().is_err());
+
+        assert_eq!(
+            w.next().unwrap().unwrap(),
+            GeomEvent::ProviderDestroyed {
+                name: "da0p1".to_owned()
+            }
+        );
+        assert_eq!(rx.try_recv().unwrap(), "da0p1");
+        assert!(matches!(
+            w.wait_for_provider("da1", Duration::from_secs(1)),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+        assert!(w.next().is_none());
+    }
+}